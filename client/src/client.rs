@@ -1,33 +1,98 @@
-use crate::config::ClientConfig;
+use crate::config::{ClientConfig, TlsSettings};
 use common::{file_service_client::FileServiceClient, *};
+use sha2::{Digest, Sha256};
 use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::StreamExt;
-use tonic::transport::Channel;
-use tonic::Request;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+use tonic::{Request, Status, Streaming};
+
+/// Attaches the configured API key to every outgoing call.
+#[derive(Clone)]
+pub struct ApiKeyInterceptor {
+    authorization: Option<MetadataValue<Ascii>>,
+}
+
+impl ApiKeyInterceptor {
+    pub fn new(api_key: Option<&str>) -> Result<Self, FileServerError> {
+        let authorization = api_key
+            .map(|key| format!("Bearer {}", key).parse())
+            .transpose()
+            .map_err(|_| FileServerError::ConfigError("API key contains invalid characters".to_string()))?;
+
+        Ok(Self { authorization })
+    }
+}
+
+impl Interceptor for ApiKeyInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(authorization) = &self.authorization {
+            request.metadata_mut().insert("authorization", authorization.clone());
+        }
+        Ok(request)
+    }
+}
 
 pub struct FileServerClient {
-    client: FileServiceClient<Channel>,
+    client: FileServiceClient<InterceptedService<Channel, ApiKeyInterceptor>>,
     client_id: String,
+    retry_attempts: u32,
 }
 
 impl FileServerClient {
     pub async fn new(config: ClientConfig, client_id: String) -> Result<Self, FileServerError> {
         let endpoint = config.server_address();
-        let channel = Channel::from_shared(endpoint)
+        let mut endpoint = Channel::from_shared(endpoint)
             .map_err(|e| FileServerError::ConnectionFailed(e.to_string()))?
-            .timeout(Duration::from_secs(config.client.timeout_seconds))
+            .timeout(Duration::from_secs(config.client.timeout_seconds));
+
+        if let Some(tls) = &config.tls {
+            let tls_config = Self::tls_config(tls, &config.server.host)?;
+            endpoint = endpoint.tls_config(tls_config)
+                .map_err(|e| FileServerError::ConfigError(e.to_string()))?;
+        }
+
+        let channel = endpoint
             .connect()
             .await
             .map_err(|e| FileServerError::ConnectionFailed(e.to_string()))?;
 
-        let client = FileServiceClient::new(channel);
+        let interceptor = ApiKeyInterceptor::new(config.client.api_key.as_deref())?;
+        let client = FileServiceClient::with_interceptor(channel, interceptor);
 
         Ok(Self {
             client,
             client_id,
+            retry_attempts: config.client.retry_attempts,
         })
     }
 
+    pub fn retry_attempts(&self) -> u32 {
+        self.retry_attempts
+    }
+
+    fn tls_config(tls: &TlsSettings, host: &str) -> Result<ClientTlsConfig, FileServerError> {
+        let read_pem = |path: &str| {
+            std::fs::read(path)
+                .map_err(|e| FileServerError::ConfigError(format!("Failed to read TLS file '{}': {}", path, e)))
+        };
+
+        let mut tls_config = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(read_pem(&tls.ca_cert)?))
+            .domain_name(tls.domain.as_deref().unwrap_or(host));
+
+        if let (Some(cert), Some(key)) = (&tls.cert, &tls.key) {
+            tls_config = tls_config.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+        }
+
+        Ok(tls_config)
+    }
+
     pub async fn authenticate(&mut self) -> Result<ConnectResponse, FileServerError> {
         let request = Request::new(ConnectRequest {
             client_id: self.client_id.clone(),
@@ -52,13 +117,10 @@ impl FileServerClient {
         Ok(response.into_inner())
     }
 
-    pub async fn list(&mut self, path: &str) -> Result<Vec<FileEntry>, FileServerError> {
-        let request = Request::new(ListRequest {
-            path: path.to_string(),
-        });
-
-        let response = self.client.list(request).await?;
-        Ok(response.into_inner().entries)
+    /// Lists one page; pass `next_page_token` back in the request for the next
+    pub async fn list(&mut self, request: ListRequest) -> Result<ListResponse, FileServerError> {
+        let response = self.client.list(Request::new(request)).await?;
+        Ok(response.into_inner())
     }
 
     pub async fn read(&mut self, path: &str) -> Result<Vec<u8>, FileServerError> {
@@ -73,9 +135,13 @@ impl FileServerClient {
 
         while let Some(chunk) = stream.next().await {
             let chunk = chunk?;
+            verify_chunk(&chunk)?;
             data.extend_from_slice(&chunk.data);
             
             if chunk.is_last {
+                if let Some(expected) = &chunk.sha256 {
+                    verify_sha256(&data, expected)?;
+                }
                 break;
             }
         }
@@ -83,6 +149,17 @@ impl FileServerClient {
         Ok(data)
     }
 
+    /// Opens a read stream starting at `offset` and running to the end of the file
+    pub async fn read_from(&mut self, path: &str, offset: u64) -> Result<Streaming<DataChunk>, FileServerError> {
+        let request = Request::new(ReadRequest {
+            path: path.to_string(),
+            offset: Some(offset),
+            length: None,
+        });
+
+        let response = self.client.read(request).await?;
+        Ok(response.into_inner())
+    }
 
     pub async fn write(&mut self, path: &str, data: &[u8]) -> Result<WriteResponse, FileServerError> {
         let chunk_size = 64 * 1024; // 64KB chunks
@@ -96,6 +173,8 @@ impl FileServerClient {
                     data: chunk.to_vec(),
                     offset: (i * chunk_size) as u64,
                     is_last,
+                    crc32c: Some(crc32c::crc32c(chunk)),
+                    sha256: None,
                 }
             })
             .collect();
@@ -103,20 +182,251 @@ impl FileServerClient {
         let stream = tokio_stream::iter(chunks);
         let request = Request::new(stream);
 
-        let response = self.client.write(request).await?;
-        Ok(response.into_inner())
+        let response = self.client.write(request).await?.into_inner();
+
+        // Servers without checksum support leave the digest empty
+        if !response.sha256.is_empty() {
+            verify_sha256(data, &response.sha256)?;
+        }
+
+        Ok(response)
     }
 
     pub async fn write_text(&mut self, path: &str, text: &str) -> Result<WriteResponse, FileServerError> {
         self.write(path, text.as_bytes()).await
     }
 
-    pub async fn delete(&mut self, path: &str) -> Result<DeleteResponse, FileServerError> {
+    pub async fn delete(&mut self, path: &str, recursive: bool) -> Result<DeleteResponse, FileServerError> {
         let request = Request::new(DeleteRequest {
             path: path.to_string(),
+            recursive,
         });
 
         let response = self.client.delete(request).await?;
         Ok(response.into_inner())
     }
+
+    pub async fn begin_upload(&mut self, path: &str) -> Result<UploadSession, FileServerError> {
+        let request = Request::new(BeginUploadRequest {
+            path: path.to_string(),
+        });
+
+        let response = self.client.begin_upload(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn upload_status(&mut self, upload_id: &str) -> Result<UploadSession, FileServerError> {
+        let request = Request::new(UploadStatusRequest {
+            upload_id: upload_id.to_string(),
+        });
+
+        let response = self.client.get_upload_status(request).await?;
+        Ok(response.into_inner())
+    }
+
+    /// Sends the contents of a local file from `offset` onwards to an upload
+    /// session, reading it from disk as the stream is consumed.
+    pub async fn append_upload(&mut self, upload_id: &str, file_path: &str, offset: u64) -> Result<UploadSession, FileServerError> {
+        let chunk_size = 64 * 1024; // 64KB chunks
+        let mut file = tokio::fs::File::open(file_path).await?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+
+        let (tx, rx) = mpsc::channel(4);
+        let upload_id = upload_id.to_string();
+
+        tokio::spawn(async move {
+            let mut offset = offset;
+            loop {
+                let mut data = vec![0u8; chunk_size];
+                let n = match file.read(&mut data).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                data.truncate(n);
+
+                let chunk = UploadChunk {
+                    upload_id: upload_id.clone(),
+                    crc32c: Some(crc32c::crc32c(&data)),
+                    data,
+                    offset,
+                };
+                offset += n as u64;
+
+                if tx.send(chunk).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let request = Request::new(ReceiverStream::new(rx));
+        let response = self.client.append_upload(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn finish_upload(&mut self, upload_id: &str, sha256: &str) -> Result<WriteResponse, FileServerError> {
+        let request = Request::new(FinishUploadRequest {
+            upload_id: upload_id.to_string(),
+            sha256: sha256.to_string(),
+        });
+
+        let response = self.client.finish_upload(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn make_directory(&mut self, path: &str, parents: bool) -> Result<DirectoryResponse, FileServerError> {
+        let request = Request::new(MakeDirectoryRequest {
+            path: path.to_string(),
+            parents,
+        });
+
+        let response = self.client.make_directory(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn remove_directory(&mut self, path: &str, recursive: bool) -> Result<DirectoryResponse, FileServerError> {
+        let request = Request::new(RemoveDirectoryRequest {
+            path: path.to_string(),
+            recursive,
+        });
+
+        let response = self.client.remove_directory(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn move_path(&mut self, source: &str, destination: &str, overwrite: bool) -> Result<MoveResponse, FileServerError> {
+        let request = Request::new(MoveRequest {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            overwrite,
+        });
+
+        let response = self.client.r#move(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn copy(&mut self, source: &str, destination: &str, overwrite: bool) -> Result<Streaming<CopyProgress>, FileServerError> {
+        let request = Request::new(CopyRequest {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            overwrite,
+        });
+
+        let response = self.client.copy(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn symlink(&mut self, path: &str, target: &str, overwrite: bool) -> Result<LinkResponse, FileServerError> {
+        let request = Request::new(SymlinkRequest {
+            path: path.to_string(),
+            target: target.to_string(),
+            overwrite,
+        });
+
+        let response = self.client.symlink(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn link(&mut self, path: &str, target: &str, overwrite: bool) -> Result<LinkResponse, FileServerError> {
+        let request = Request::new(LinkRequest {
+            path: path.to_string(),
+            target: target.to_string(),
+            overwrite,
+        });
+
+        let response = self.client.link(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn read_link(&mut self, path: &str) -> Result<ReadLinkResponse, FileServerError> {
+        let request = Request::new(ReadLinkRequest {
+            path: path.to_string(),
+        });
+
+        let response = self.client.read_link(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn set_attributes(&mut self, request: SetAttributesRequest) -> Result<FileMetadata, FileServerError> {
+        let response = self.client.set_attributes(Request::new(request)).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn get_usage(&mut self, directory: &str) -> Result<UsageResponse, FileServerError> {
+        let request = Request::new(GetUsageRequest {
+            directory: directory.to_string(),
+        });
+
+        let response = self.client.get_usage(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn find(&mut self, request: FindRequest) -> Result<Streaming<FindResponse>, FileServerError> {
+        let response = self.client.find(Request::new(request)).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn grep(&mut self, request: GrepRequest) -> Result<Streaming<GrepResponse>, FileServerError> {
+        let response = self.client.grep(Request::new(request)).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn checksum(&mut self, path: &str) -> Result<ChecksumResponse, FileServerError> {
+        let request = Request::new(ChecksumRequest {
+            path: path.to_string(),
+        });
+
+        let response = self.client.checksum(request).await?;
+        Ok(response.into_inner())
+    }
+
+    pub async fn list_trash(&mut self, directory: &str) -> Result<Vec<TrashItem>, FileServerError> {
+        let request = Request::new(ListTrashRequest {
+            directory: directory.to_string(),
+        });
+
+        let response = self.client.list_trash(request).await?;
+        Ok(response.into_inner().items)
+    }
+
+    pub async fn restore_trash(&mut self, directory: &str, id: &str, overwrite: bool) -> Result<TrashResponse, FileServerError> {
+        let request = Request::new(RestoreTrashRequest {
+            directory: directory.to_string(),
+            id: id.to_string(),
+            overwrite,
+        });
+
+        let response = self.client.restore_trash(request).await?;
+        Ok(response.into_inner())
+    }
+
+    /// Purges one item, or everything the caller may delete if `id` is `None`
+    pub async fn purge_trash(&mut self, directory: &str, id: Option<&str>) -> Result<TrashResponse, FileServerError> {
+        let request = Request::new(PurgeTrashRequest {
+            directory: directory.to_string(),
+            id: id.unwrap_or_default().to_string(),
+        });
+
+        let response = self.client.purge_trash(request).await?;
+        Ok(response.into_inner())
+    }
+}
+
+/// Checks a received chunk against the CRC32C the server sent with it
+pub fn verify_chunk(chunk: &DataChunk) -> Result<(), FileServerError> {
+    match chunk.crc32c {
+        Some(expected) if crc32c::crc32c(&chunk.data) != expected => Err(FileServerError::ChecksumMismatch(
+            format!("CRC32C of chunk at offset {} in '{}'", chunk.offset, chunk.path)
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn verify_sha256(data: &[u8], expected: &str) -> Result<(), FileServerError> {
+    let actual = hex::encode(Sha256::digest(data));
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(FileServerError::ChecksumMismatch(format!(
+            "SHA-256 is {}, server reported {}", actual, expected
+        )));
+    }
+    Ok(())
 }
\ No newline at end of file
@@ -1,10 +1,13 @@
 use common::FileServerError;
 use serde::{Deserialize, Serialize};
 
+pub const API_KEY_ENV: &str = "FILESERVER_API_KEY";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientConfig {
     pub server: ServerSettings,
     pub client: ClientSettings,
+    pub tls: Option<TlsSettings>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,6 +20,20 @@ pub struct ServerSettings {
 pub struct ClientSettings {
     pub timeout_seconds: u64,
     pub retry_attempts: u32,
+    /// API key sent to the server; `FILESERVER_API_KEY` takes precedence
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsSettings {
+    /// PEM-encoded CA used to verify the server certificate
+    pub ca_cert: String,
+    /// PEM-encoded client certificate, required when the server enforces mutual TLS
+    pub cert: Option<String>,
+    /// PEM-encoded private key for `cert`
+    pub key: Option<String>,
+    /// Name to verify the server certificate against (defaults to `server.host`)
+    pub domain: Option<String>,
 }
 
 impl ClientConfig {
@@ -46,11 +63,31 @@ impl ClientConfig {
             return Err(FileServerError::ConfigError("Retry attempts cannot be 0".to_string()));
         }
 
+        if self.client.api_key.as_deref() == Some("") {
+            return Err(FileServerError::ConfigError("API key cannot be empty".to_string()));
+        }
+
+        if let Some(tls) = &self.tls {
+            if tls.cert.is_some() != tls.key.is_some() {
+                return Err(FileServerError::ConfigError(
+                    "tls.cert and tls.key must be set together".to_string()
+                ));
+            }
+        }
+
         Ok(())
     }
 
+    /// Applies settings supplied through the environment
+    pub fn apply_env_overrides(&mut self) {
+        if let Ok(api_key) = std::env::var(API_KEY_ENV) {
+            self.client.api_key = Some(api_key);
+        }
+    }
+
     pub fn server_address(&self) -> String {
-        format!("http://{}:{}", self.server.host, self.server.port)
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+        format!("{}://{}:{}", scheme, self.server.host, self.server.port)
     }
 }
 
@@ -88,12 +125,87 @@ retry_attempts = 5
             client: ClientSettings {
                 timeout_seconds: 30,
                 retry_attempts: 3,
+                api_key: None,
             },
+            tls: None,
         };
 
         assert_eq!(config.server_address(), "http://192.168.1.100:8080");
     }
 
+    #[test]
+    fn test_tls_config_parsing() {
+        let config_content = r#"
+[server]
+host = "files.internal"
+port = 50051
+
+[client]
+timeout_seconds = 30
+retry_attempts = 3
+
+[tls]
+ca_cert = "/etc/fileserver/ca.crt"
+cert = "/etc/fileserver/client.crt"
+key = "/etc/fileserver/client.key"
+        "#;
+
+        let config: ClientConfig = toml::from_str(config_content).unwrap();
+        let tls = config.tls.as_ref().unwrap();
+
+        assert_eq!(tls.ca_cert, "/etc/fileserver/ca.crt");
+        assert_eq!(tls.cert.as_deref(), Some("/etc/fileserver/client.crt"));
+        assert_eq!(tls.key.as_deref(), Some("/etc/fileserver/client.key"));
+        assert!(tls.domain.is_none());
+        assert_eq!(config.server_address(), "https://files.internal:50051");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_config_validation_empty_api_key() {
+        let config = ClientConfig {
+            server: ServerSettings {
+                host: "localhost".to_string(),
+                port: 8080,
+            },
+            client: ClientSettings {
+                timeout_seconds: 30,
+                retry_attempts: 3,
+                api_key: Some(String::new()),
+            },
+            tls: None,
+        };
+
+        let result = config.validate();
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("API key cannot be empty"));
+    }
+
+    #[test]
+    fn test_config_validation_tls_cert_without_key() {
+        let config = ClientConfig {
+            server: ServerSettings {
+                host: "localhost".to_string(),
+                port: 8080,
+            },
+            client: ClientSettings {
+                timeout_seconds: 30,
+                retry_attempts: 3,
+                api_key: None,
+            },
+            tls: Some(TlsSettings {
+                ca_cert: "ca.crt".to_string(),
+                cert: Some("client.crt".to_string()),
+                key: None,
+                domain: None,
+            }),
+        };
+
+        let result = config.validate();
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("tls.cert and tls.key must be set together"));
+    }
+
     #[test]
     fn test_config_validation_valid() {
         let config = ClientConfig {
@@ -104,7 +216,9 @@ retry_attempts = 5
             client: ClientSettings {
                 timeout_seconds: 30,
                 retry_attempts: 3,
+                api_key: None,
             },
+            tls: None,
         };
 
         assert!(config.validate().is_ok());
@@ -120,7 +234,9 @@ retry_attempts = 5
             client: ClientSettings {
                 timeout_seconds: 30,
                 retry_attempts: 3,
+                api_key: None,
             },
+            tls: None,
         };
 
         let result = config.validate();
@@ -138,7 +254,9 @@ retry_attempts = 5
             client: ClientSettings {
                 timeout_seconds: 30,
                 retry_attempts: 3,
+                api_key: None,
             },
+            tls: None,
         };
 
         let result = config.validate();
@@ -156,7 +274,9 @@ retry_attempts = 5
             client: ClientSettings {
                 timeout_seconds: 0,
                 retry_attempts: 3,
+                api_key: None,
             },
+            tls: None,
         };
 
         let result = config.validate();
@@ -174,7 +294,9 @@ retry_attempts = 5
             client: ClientSettings {
                 timeout_seconds: 30,
                 retry_attempts: 0,
+                api_key: None,
             },
+            tls: None,
         };
 
         let result = config.validate();
@@ -196,6 +318,7 @@ port = 12345
 [client]
 timeout_seconds = 120
 retry_attempts = 10
+api_key = "file-key"
         "#;
 
         std::fs::write(&config_file, config_content).unwrap();
@@ -206,6 +329,7 @@ retry_attempts = 10
         assert_eq!(config.server.port, 12345);
         assert_eq!(config.client.timeout_seconds, 120);
         assert_eq!(config.client.retry_attempts, 10);
+        assert_eq!(config.client.api_key.as_deref(), Some("file-key"));
 
         // Clean up
         std::fs::remove_file(&config_file).ok();
@@ -5,7 +5,8 @@ mod operations;
 use client::FileServerClient;
 use config::{ClientConfig, ServerSettings, ClientSettings};
 use operations::FileOperations;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use common::{EntryType, FindRequest, GrepRequest, ListRequest, SetAttributesRequest};
 use tracing::{error, info};
 
 #[derive(Parser)]
@@ -41,12 +42,218 @@ enum Commands {
     Connect,
     HealthCheck,
     Stat { path: String },
-    List { path: String },
+    List {
+        path: String,
+        /// Descend into subdirectories
+        #[arg(short, long)]
+        recursive: bool,
+        /// Levels to descend when recursive, 0 for no limit
+        #[arg(long, default_value = "0")]
+        max_depth: u32,
+        /// Only list entries matching this glob; may be repeated
+        #[arg(long)]
+        include: Vec<String>,
+        /// Skip entries, and directories' contents, matching this glob; may be repeated
+        #[arg(long)]
+        exclude: Vec<String>,
+        /// Entries fetched per request, 0 for the server's default
+        #[arg(long, default_value = "0")]
+        page_size: u32,
+    },
     Read { path: String },
     ReadText { path: String },
+    Download { path: String, file: String },
     Write { path: String, content: String },
     WriteFile { path: String, file: String },
-    Delete { path: String },
+    Upload {
+        path: String,
+        file: String,
+        /// Resume the upload session with this id
+        #[arg(long)]
+        resume: Option<String>,
+    },
+    Move {
+        source: String,
+        destination: String,
+        /// Replace the destination if it already exists
+        #[arg(long)]
+        overwrite: bool,
+    },
+    Copy {
+        source: String,
+        destination: String,
+        /// Replace the destination file if it already exists
+        #[arg(long)]
+        overwrite: bool,
+    },
+    Checksum { path: String },
+    Delete {
+        path: String,
+        /// Delete directories together with their contents
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    Mkdir {
+        path: String,
+        /// Create missing parent directories
+        #[arg(short, long)]
+        parents: bool,
+    },
+    Rmdir {
+        path: String,
+        /// Remove the directory together with its contents
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// Create a link on the server, like ln
+    Ln {
+        /// What the link points to: stored as given for a symbolic link,
+        /// a remote file for a hard link
+        target: String,
+        path: String,
+        /// Create a symbolic link instead of a hard link
+        #[arg(short, long)]
+        symbolic: bool,
+        /// Replace an existing file or link at the path
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Print the target of a symbolic link
+    Readlink { path: String },
+    /// Change the mode of a remote file, in octal or like chmod's u+x
+    Chmod { mode: String, path: String },
+    /// Change the owner and/or group of a remote file, as owner[:group] or :group
+    Chown { owner: String, path: String },
+    /// Set the access and modification times of an existing remote file
+    Touch {
+        path: String,
+        /// Unix time in seconds, now if not given
+        #[arg(long)]
+        time: Option<i64>,
+    },
+    /// Search a directory tree on the server
+    Find {
+        path: String,
+        /// Glob matched against entry names
+        #[arg(long, conflicts_with = "regex")]
+        name: Option<String>,
+        /// Regular expression matched against entry names
+        #[arg(long)]
+        regex: Option<String>,
+        /// Smallest size to match, e.g. 1G or 500K
+        #[arg(long, value_parser = parse_size)]
+        min_size: Option<u64>,
+        /// Largest size to match
+        #[arg(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+        /// Only match entries modified within this long, e.g. 7d or 12h
+        #[arg(long, value_parser = parse_age)]
+        newer: Option<u64>,
+        /// Only match entries last modified longer ago than this
+        #[arg(long, value_parser = parse_age)]
+        older: Option<u64>,
+        #[arg(long = "type", value_enum)]
+        entry_type: Option<FindType>,
+        /// Levels to search, 0 for no limit
+        #[arg(long, default_value = "0")]
+        max_depth: u32,
+        /// Stop after this many matches, 0 for the server's limit
+        #[arg(long, default_value = "0")]
+        limit: u32,
+    },
+    /// Search the contents of files on the server
+    Grep {
+        /// A file, or a directory whose files are searched
+        path: String,
+        pattern: String,
+        /// Treat the pattern as a regular expression
+        #[arg(short = 'E', long)]
+        regex: bool,
+        #[arg(short, long)]
+        ignore_case: bool,
+        /// Only search files whose names match this glob
+        #[arg(long)]
+        name: Option<String>,
+        /// Levels to search, 0 for no limit
+        #[arg(long, default_value = "0")]
+        max_depth: u32,
+        /// Stop after this many matching lines, 0 for the server's limit
+        #[arg(long, default_value = "0")]
+        limit: u32,
+    },
+    /// Show how much of a directory's quota is used
+    Usage { directory: String },
+    /// Manage the trash of a directory
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommands,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FindType {
+    File,
+    Dir,
+    Symlink,
+}
+
+impl From<FindType> for EntryType {
+    fn from(entry_type: FindType) -> Self {
+        match entry_type {
+            FindType::File => EntryType::File,
+            FindType::Dir => EntryType::Directory,
+            FindType::Symlink => EntryType::Symlink,
+        }
+    }
+}
+
+/// Parses a byte count with an optional K, M, G or T suffix (powers of 1024)
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("Invalid size '{}'", value))?;
+
+    let shift = match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("Unknown size unit in '{}'", value)),
+    };
+    number.checked_mul(1 << shift).ok_or_else(|| format!("Size '{}' is too large", value))
+}
+
+/// Parses a duration such as 30m, 12h, 7d or 2w into seconds
+fn parse_age(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, unit) = value.split_at(value.len().saturating_sub(1));
+    let number: u64 = number.parse().map_err(|_| format!("Invalid duration '{}'", value))?;
+
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Duration '{}' needs a unit: s, m, h, d or w", value)),
+    };
+    number.checked_mul(seconds).ok_or_else(|| format!("Duration '{}' is too long", value))
+}
+
+#[derive(Subcommand)]
+enum TrashCommands {
+    List { directory: String },
+    Restore {
+        directory: String,
+        id: String,
+        /// Replace a file that now exists at the original path
+        #[arg(short, long)]
+        overwrite: bool,
+    },
+    /// Permanently delete an item, or everything you may delete if no id is given
+    Purge { directory: String, id: Option<String> },
 }
 
 fn create_config_from_args(args: &Args) -> Result<ClientConfig, Box<dyn std::error::Error>> {
@@ -62,7 +269,9 @@ fn create_config_from_args(args: &Args) -> Result<ClientConfig, Box<dyn std::err
             client: ClientSettings {
                 timeout_seconds: args.timeout,
                 retry_attempts: args.retries,
+                api_key: None,
             },
+            tls: None,
         };
         
         config.validate()?;
@@ -112,7 +321,7 @@ fn create_config_from_args(args: &Args) -> Result<ClientConfig, Box<dyn std::err
     }
     
     // Error: neither CLI args nor config file available
-    return Err("Either provide --server and --port, or specify a config file with --config".into());
+    Err("Either provide --server and --port, or specify a config file with --config".into())
 }
 
 #[tokio::main]
@@ -121,7 +330,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let args = Args::parse();
     
-    let config = create_config_from_args(&args)?;
+    let mut config = create_config_from_args(&args)?;
+    config.apply_env_overrides();
+    config.validate()?;
     let client_id = format!("client-{}", uuid::Uuid::now_v7());
     let client = FileServerClient::new(config, client_id).await?;
     let mut operations = FileOperations::new(client);
@@ -139,8 +350,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             operations.stat(&path).await?;
             Ok(())
         }
-        Commands::List { path } => {
-            operations.list(&path).await?;
+        Commands::List { path, recursive, max_depth, include, exclude, page_size } => {
+            let request = ListRequest {
+                path,
+                recursive,
+                max_depth,
+                include,
+                exclude,
+                page_size,
+                page_token: String::new(),
+            };
+            operations.list(request).await?;
             Ok(())
         }
         Commands::Read { path } => {
@@ -152,6 +372,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             operations.read_text(&path).await?;
             Ok(())
         }
+        Commands::Download { path, file } => {
+            operations.download(&path, &file).await?;
+            Ok(())
+        }
         Commands::Write { path, content } => {
             operations.write(&path, &content).await?;
             Ok(())
@@ -160,10 +384,128 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             operations.write_file(&path, &file).await?;
             Ok(())
         }
-        Commands::Delete { path } => {
-            operations.delete(&path).await?;
+        Commands::Upload { path, file, resume } => {
+            operations.upload(&path, &file, resume.as_deref()).await?;
+            Ok(())
+        }
+        Commands::Move { source, destination, overwrite } => {
+            operations.move_path(&source, &destination, overwrite).await?;
+            Ok(())
+        }
+        Commands::Copy { source, destination, overwrite } => {
+            operations.copy(&source, &destination, overwrite).await?;
+            Ok(())
+        }
+        Commands::Checksum { path } => {
+            operations.checksum(&path).await?;
+            Ok(())
+        }
+        Commands::Delete { path, recursive } => {
+            operations.delete(&path, recursive).await?;
             Ok(())
         }
+        Commands::Mkdir { path, parents } => {
+            operations.make_directory(&path, parents).await?;
+            Ok(())
+        }
+        Commands::Rmdir { path, recursive } => {
+            operations.remove_directory(&path, recursive).await?;
+            Ok(())
+        }
+        Commands::Ln { target, path, symbolic, force } => {
+            operations.link(&target, &path, symbolic, force).await?;
+            Ok(())
+        }
+        Commands::Readlink { path } => {
+            operations.read_link(&path).await?;
+            Ok(())
+        }
+        Commands::Chmod { mode, path } => {
+            operations.chmod(&path, &mode).await?;
+            Ok(())
+        }
+        Commands::Chown { owner, path } => {
+            let (owner, group) = match owner.split_once(':') {
+                Some((owner, group)) => (owner.to_string(), group.to_string()),
+                None => (owner, String::new()),
+            };
+            let request = SetAttributesRequest {
+                path,
+                owner: Some(owner).filter(|owner| !owner.is_empty()),
+                group: Some(group).filter(|group| !group.is_empty()),
+                ..Default::default()
+            };
+            operations.set_attributes(request).await?;
+            Ok(())
+        }
+        Commands::Touch { path, time } => {
+            let nanos = match time {
+                Some(seconds) => seconds.saturating_mul(1_000_000_000),
+                None => std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos() as i64,
+            };
+            let request = SetAttributesRequest {
+                path,
+                atime_ns: Some(nanos),
+                mtime_ns: Some(nanos),
+                ..Default::default()
+            };
+            operations.set_attributes(request).await?;
+            Ok(())
+        }
+        Commands::Find { path, name, regex, min_size, max_size, newer, older, entry_type, max_depth, limit } => {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as i64;
+            let request = FindRequest {
+                path,
+                name_glob: name.unwrap_or_default(),
+                name_regex: regex.unwrap_or_default(),
+                min_size,
+                max_size,
+                modified_after: newer.map(|age| now - age as i64),
+                modified_before: older.map(|age| now - age as i64),
+                entry_type: entry_type.map_or(EntryType::Any, EntryType::from) as i32,
+                max_depth,
+                max_results: limit,
+            };
+            operations.find(request).await?;
+            Ok(())
+        }
+        Commands::Grep { path, pattern, regex, ignore_case, name, max_depth, limit } => {
+            let request = GrepRequest {
+                path,
+                pattern,
+                regex,
+                ignore_case,
+                name_glob: name.unwrap_or_default(),
+                max_depth,
+                max_matches: limit,
+            };
+            operations.grep(request).await?;
+            Ok(())
+        }
+        Commands::Usage { directory } => {
+            operations.usage(&directory).await?;
+            Ok(())
+        }
+        Commands::Trash { command } => match command {
+            TrashCommands::List { directory } => {
+                operations.list_trash(&directory).await?;
+                Ok(())
+            }
+            TrashCommands::Restore { directory, id, overwrite } => {
+                operations.restore_trash(&directory, &id, overwrite).await?;
+                Ok(())
+            }
+            TrashCommands::Purge { directory, id } => {
+                operations.purge_trash(&directory, id.as_deref()).await?;
+                Ok(())
+            }
+        },
     };
 
     if let Err(e) = result {
@@ -172,4 +514,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4K"), Ok(4096));
+        assert_eq!(parse_size("1G"), Ok(1 << 30));
+        assert_eq!(parse_size("2gb"), Ok(2 << 30));
+        assert!(parse_size("1X").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("90s"), Ok(90));
+        assert_eq!(parse_age("12h"), Ok(12 * 3600));
+        assert_eq!(parse_age("7d"), Ok(7 * 86400));
+        assert!(parse_age("7").is_err());
+        assert!(parse_age("d").is_err());
+    }
 }
\ No newline at end of file
@@ -1,5 +1,13 @@
-use crate::client::FileServerClient;
-use common::{FileServerError, FileEntry, FileMetadata, HealthStatus};
+use crate::client::{self, FileServerClient};
+use common::{FileServerError, FileMetadata, FileType, FindRequest, GrepRequest, HealthStatus, ListRequest, PosixAttributes, QuotaUsage, SetAttributesRequest, TrashItem};
+use indicatif::{ProgressBar, ProgressStyle};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio_stream::StreamExt;
+
+/// Symbolic links followed in a row before giving up on a path
+const MAX_LINK_HOPS: usize = 8;
 
 pub struct FileOperations {
     client: FileServerClient,
@@ -39,55 +47,55 @@ impl FileOperations {
         Ok(status)
     }
 
+    /// Prints a path's metadata: an `ls -l` line followed by the details
     pub async fn stat(&mut self, path: &str) -> Result<FileMetadata, FileServerError> {
         let metadata = self.client.stat(path).await?;
+        let posix = metadata.posix.clone().unwrap_or_default();
         
-        println!("File Information for '{}':", path);
-        println!("  Name: {}", metadata.name);
-        println!("  Size: {} bytes", metadata.size);
-        println!("  Type: {}", if metadata.is_directory { "Directory" } else { "File" });
-        println!("  Permissions: {}", metadata.permissions);
-        
-        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(metadata.modified_time as u64);
-        let created = std::time::UNIX_EPOCH + std::time::Duration::from_secs(metadata.created_time as u64);
-        
-        let modified_datetime = chrono::DateTime::<chrono::Utc>::from(modified);
-        let created_datetime = chrono::DateTime::<chrono::Utc>::from(created);
+        println!("{}", long_listing(&metadata.permissions, &posix, metadata.size, metadata.modified_time, &metadata.name));
+        println!("  Type: {}", type_description(posix.file_type()));
+        println!("  Mode: {:04o}", posix.mode);
+        println!("  Owner: {} ({})", name_or_id(&posix.owner, posix.uid), posix.uid);
+        println!("  Group: {} ({})", name_or_id(&posix.group, posix.gid), posix.gid);
+        println!("  Inode: {}  Links: {}", posix.inode, posix.link_count);
+        println!("  Access: {}", format_nanos(posix.atime_ns));
+        println!("  Modify: {}", format_nanos(posix.mtime_ns));
+        println!("  Change: {}", format_nanos(posix.ctime_ns));
         
-        println!("  Modified: {}", modified_datetime.format("%Y-%m-%d %H:%M:%S UTC"));
-        println!("  Created: {}", created_datetime.format("%Y-%m-%d %H:%M:%S UTC"));
+        match metadata.created_time {
+            Some(created) => {
+                let created = std::time::UNIX_EPOCH + std::time::Duration::from_secs(created as u64);
+                let created_datetime = chrono::DateTime::<chrono::Utc>::from(created);
+                println!("  Birth: {}", created_datetime.format("%Y-%m-%d %H:%M:%S UTC"));
+            }
+            None => println!("  Birth: -"),
+        }
         
         Ok(metadata)
     }
 
-    pub async fn list(&mut self, path: &str) -> Result<Vec<FileEntry>, FileServerError> {
-        let entries = self.client.list(path).await?;
+    /// Prints every entry matching `request`, fetching one page at a time.
+    /// Returns the number of entries listed.
+    pub async fn list(&mut self, mut request: ListRequest) -> Result<usize, FileServerError> {
+        println!("Directory listing for '{}':", request.path);
         
-        println!("Directory listing for '{}':", path);
-        println!("{:<30} {:<10} {:<15} {}", "Name", "Type", "Size", "Modified");
-        println!("{}", "-".repeat(70));
-        
-        for entry in &entries {
-            let file_type = if entry.is_directory { "Directory" } else { "File" };
-            let size = if entry.is_directory {
-                "-".to_string()
-            } else {
-                format!("{} bytes", entry.size)
-            };
+        let mut count = 0;
+        loop {
+            let page = self.client.list(request.clone()).await?;
             
-            let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(entry.modified_time as u64);
-            let datetime = chrono::DateTime::<chrono::Utc>::from(modified);
-            let modified_str = datetime.format("%Y-%m-%d %H:%M").to_string();
+            for entry in &page.entries {
+                let posix = entry.posix.clone().unwrap_or_default();
+                println!("{}", long_listing(&entry.permissions, &posix, entry.size, entry.modified_time, &entry.name));
+            }
+            count += page.entries.len();
             
-            println!("{:<30} {:<10} {:<15} {}", 
-                entry.name, 
-                file_type, 
-                size,
-                modified_str
-            );
+            if page.next_page_token.is_empty() {
+                break;
+            }
+            request.page_token = page.next_page_token;
         }
         
-        Ok(entries)
+        Ok(count)
     }
 
     pub async fn read(&mut self, path: &str) -> Result<Vec<u8>, FileServerError> {
@@ -115,6 +123,127 @@ impl FileOperations {
         }
     }
 
+    /// Downloads a remote file to a local path. An existing local file is
+    /// treated as a partial download and continued from its current size,
+    /// and dropped connections are resumed the same way.
+    pub async fn download(&mut self, path: &str, file_path: &str) -> Result<u64, FileServerError> {
+        let metadata = self.stat_target(path).await?;
+        if metadata.is_directory {
+            return Err(FileServerError::InvalidPath(format!("'{}' is a directory", path)));
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(file_path).await?;
+        let mut offset = file.metadata().await?.len();
+
+        if offset > metadata.size {
+            return Err(FileServerError::InvalidPath(format!(
+                "Local file '{}' is larger than '{}' ({} > {} bytes)",
+                file_path, path, offset, metadata.size
+            )));
+        }
+
+        let progress = ProgressBar::new(metadata.size);
+        progress.set_style(
+            ProgressStyle::with_template("{bytes}/{total_bytes} [{bar:40}] {bytes_per_sec}, eta {eta}")
+                .expect("progress template is valid")
+                .progress_chars("=> "),
+        );
+        progress.set_position(offset);
+        if offset > 0 {
+            progress.println(format!("Resuming download of '{}' at {} bytes", path, offset));
+        }
+
+        let mut failures = 0;
+        loop {
+            let start = offset;
+            match self.download_from(path, &mut file, &mut offset, &progress).await {
+                Ok(()) => break,
+                Err(e) => {
+                    // Only consecutive failures without progress count against the limit
+                    if offset > start {
+                        failures = 0;
+                    }
+                    if failures >= self.client.retry_attempts() {
+                        progress.abandon();
+                        return Err(e);
+                    }
+                    failures += 1;
+                    progress.println(format!(
+                        "Download interrupted at {} bytes: {}; retrying ({}/{})",
+                        offset, e, failures, self.client.retry_attempts()
+                    ));
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            }
+        }
+
+        file.sync_all().await?;
+        let local_size = file.metadata().await?.len();
+        if local_size != metadata.size {
+            progress.abandon();
+            return Err(FileServerError::IoError(std::io::Error::other(format!(
+                "Downloaded {} bytes but '{}' is {} bytes",
+                local_size, path, metadata.size
+            ))));
+        }
+
+        progress.finish();
+
+        // Covers data from earlier, interrupted runs as well as this one
+        let remote = self.client.checksum(path).await?;
+        let local = sha256_file(file_path)?;
+        if local != remote.sha256 {
+            return Err(FileServerError::ChecksumMismatch(format!(
+                "'{}' has SHA-256 {}, but '{}' has {}", file_path, local, path, remote.sha256
+            )));
+        }
+
+        println!("✓ Successfully downloaded {} bytes to '{}'", local_size, file_path);
+        println!("  SHA-256: {}", local);
+
+        Ok(local_size)
+    }
+
+    /// Streams the rest of a remote file into `file` from `offset`, which
+    /// tracks how much has been written even if the stream fails.
+    async fn download_from(
+        &mut self,
+        path: &str,
+        file: &mut tokio::fs::File,
+        offset: &mut u64,
+        progress: &ProgressBar,
+    ) -> Result<(), FileServerError> {
+        file.flush().await?;
+        file.seek(std::io::SeekFrom::Start(*offset)).await?;
+
+        let mut stream = self.client.read_from(path, *offset).await?;
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            client::verify_chunk(&chunk)?;
+            if chunk.offset != *offset {
+                return Err(FileServerError::ConnectionFailed(format!(
+                    "Expected data at offset {}, received offset {}", offset, chunk.offset
+                )));
+            }
+
+            file.write_all(&chunk.data).await?;
+            *offset += chunk.data.len() as u64;
+            progress.set_position(*offset);
+
+            if chunk.is_last {
+                file.flush().await?;
+                return Ok(());
+            }
+        }
+
+        Err(FileServerError::ConnectionFailed("Download ended before the last chunk".to_string()))
+    }
+
     pub async fn write(&mut self, path: &str, content: &str) -> Result<(), FileServerError> {
         let response = self.client.write_text(path, content).await?;
         
@@ -123,7 +252,7 @@ impl FileOperations {
             println!("  Message: {}", response.message);
         } else {
             return Err(FileServerError::IoError(
-                std::io::Error::new(std::io::ErrorKind::Other, response.message)
+                std::io::Error::other(response.message)
             ));
         }
         
@@ -132,23 +261,866 @@ impl FileOperations {
 
     pub async fn write_file(&mut self, path: &str, file_path: &str) -> Result<(), FileServerError> {
         let content = std::fs::read_to_string(file_path)
-            .map_err(|e| FileServerError::IoError(e))?;
+            .map_err(FileServerError::IoError)?;
         
         self.write(path, &content).await
     }
 
-    pub async fn delete(&mut self, path: &str) -> Result<(), FileServerError> {
-        let response = self.client.delete(path).await?;
+    pub async fn make_directory(&mut self, path: &str, parents: bool) -> Result<(), FileServerError> {
+        let response = self.client.make_directory(path, parents).await?;
+        
+        println!("✓ Successfully created '{}'", path);
+        println!("  Message: {}", response.message);
+        
+        Ok(())
+    }
+
+    pub async fn remove_directory(&mut self, path: &str, recursive: bool) -> Result<(), FileServerError> {
+        let response = self.client.remove_directory(path, recursive).await?;
+        
+        println!("✓ Successfully removed '{}'", path);
+        println!("  Message: {}", response.message);
+        
+        Ok(())
+    }
+
+    pub async fn move_path(&mut self, source: &str, destination: &str, overwrite: bool) -> Result<(), FileServerError> {
+        let response = self.client.move_path(source, destination, overwrite).await?;
+        
+        println!("✓ Successfully moved '{}' to '{}'", source, destination);
+        println!("  Message: {}", response.message);
+        
+        Ok(())
+    }
+
+    /// Creates `path` as a link to `target`: a symbolic link storing `target`
+    /// as given, or a hard link to the file at the remote path `target`
+    pub async fn link(&mut self, target: &str, path: &str, symbolic: bool, overwrite: bool) -> Result<(), FileServerError> {
+        let response = if symbolic {
+            self.client.symlink(path, target, overwrite).await?
+        } else {
+            self.client.link(path, target, overwrite).await?
+        };
+
+        println!("✓ Successfully linked '{}' to '{}'", path, target);
+        println!("  Message: {}", response.message);
+
+        Ok(())
+    }
+
+    pub async fn read_link(&mut self, path: &str) -> Result<(), FileServerError> {
+        let response = self.client.read_link(path).await?;
+
+        if response.resolved.is_empty() {
+            println!("{}", response.target);
+        } else {
+            println!("{} ({})", response.target, response.resolved);
+        }
+
+        Ok(())
+    }
+
+    /// Changes a file's mode, given in octal or symbolically like `chmod`
+    pub async fn chmod(&mut self, path: &str, mode: &str) -> Result<(), FileServerError> {
+        let current = if mode.chars().all(|c| c.is_digit(8)) {
+            0
+        } else {
+            self.stat_target(path).await?.posix.unwrap_or_default().mode
+        };
+        let mode = apply_mode(mode, current).map_err(FileServerError::InvalidPath)?;
+
+        self.set_attributes(SetAttributesRequest {
+            path: path.to_string(),
+            mode: Some(mode),
+            ..Default::default()
+        }).await
+    }
+
+    /// Changes attributes of a remote file and prints the result like `ls -l`
+    pub async fn set_attributes(&mut self, request: SetAttributesRequest) -> Result<(), FileServerError> {
+        let metadata = self.client.set_attributes(request).await?;
+        let posix = metadata.posix.unwrap_or_default();
+
+        println!("{}", long_listing(&metadata.permissions, &posix, metadata.size, metadata.modified_time, &metadata.name));
+
+        Ok(())
+    }
+
+    /// Shows how much of a directory's quota is used, in total and by the caller
+    pub async fn usage(&mut self, directory: &str) -> Result<(), FileServerError> {
+        let response = self.client.get_usage(directory).await?;
+
+        println!("Usage of '{}':", directory);
+        if let Some(total) = response.total {
+            print_quota_usage("Total", &total);
+        }
+        if let Some(client) = response.client {
+            print_quota_usage("Yours", &client);
+        }
+
+        Ok(())
+    }
+
+    /// Copies a file or directory tree on the server, showing its progress
+    pub async fn copy(&mut self, source: &str, destination: &str, overwrite: bool) -> Result<(), FileServerError> {
+        let mut stream = self.client.copy(source, destination, overwrite).await?;
+
+        let progress = ProgressBar::new(0);
+        progress.set_style(
+            ProgressStyle::with_template("{bytes}/{total_bytes} [{bar:40}] {bytes_per_sec} {msg}")
+                .expect("progress template is valid")
+                .progress_chars("=> "),
+        );
+
+        while let Some(update) = stream.next().await {
+            let update = match update {
+                Ok(update) => update,
+                Err(status) => {
+                    progress.abandon();
+                    return Err(status.into());
+                }
+            };
+
+            progress.set_length(update.bytes_total);
+            progress.set_position(update.bytes_copied);
+            progress.set_message(format!("{}/{} files", update.files_copied, update.files_total));
+
+            if update.done {
+                progress.finish();
+                println!(
+                    "✓ Successfully copied '{}' to '{}' ({} files, {} bytes)",
+                    source, destination, update.files_copied, update.bytes_copied
+                );
+                return Ok(());
+            }
+        }
+
+        progress.abandon();
+        Err(FileServerError::ConnectionFailed("Copy ended without completing".to_string()))
+    }
+
+    /// Prints the entries matching a search on the server as they arrive.
+    /// Returns the number of matches.
+    pub async fn find(&mut self, request: FindRequest) -> Result<usize, FileServerError> {
+        let path = request.path.clone();
+        let mut stream = self.client.find(request).await?;
+        let mut count = 0;
+        let mut truncated = false;
+        
+        while let Some(response) = stream.next().await {
+            let response = response?;
+            for entry in &response.entries {
+                let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(entry.modified_time as u64);
+                let datetime = chrono::DateTime::<chrono::Utc>::from(modified);
+                let size = if entry.is_directory { "-".to_string() } else { entry.size.to_string() };
+                
+                println!("{:>15}  {}  {}/{}", size, datetime.format("%Y-%m-%d %H:%M"), path.trim_end_matches('/'), entry.name);
+            }
+            count += response.entries.len();
+            truncated = response.truncated;
+        }
+        
+        if truncated {
+            println!("Stopped after {} matches; narrow the search to see the rest", count);
+        } else {
+            println!("{} matches", count);
+        }
+        
+        Ok(count)
+    }
+
+    /// Prints the lines matching a search of file contents on the server,
+    /// grep style. Returns the number of matching lines.
+    pub async fn grep(&mut self, request: GrepRequest) -> Result<usize, FileServerError> {
+        let mut stream = self.client.grep(request).await?;
+        let mut count = 0;
+        
+        while let Some(response) = stream.next().await {
+            let response = response?;
+            for grep_match in &response.matches {
+                println!("{}:{}:{}", grep_match.path, grep_match.line_number, grep_match.line);
+            }
+            count += response.matches.len();
+            
+            if response.files_scanned > 0 || response.truncated {
+                eprintln!(
+                    "{} matches in {} files ({} bytes scanned)",
+                    count, response.files_scanned, response.bytes_scanned
+                );
+                if response.truncated {
+                    eprintln!("Stopped at the server's search limits; narrow the search to see the rest");
+                }
+            }
+        }
+        
+        Ok(count)
+    }
+
+    pub async fn checksum(&mut self, path: &str) -> Result<String, FileServerError> {
+        let response = self.client.checksum(path).await?;
+        
+        println!("{}  {} ({} bytes)", response.sha256, path, response.size);
+        
+        Ok(response.sha256)
+    }
+
+    pub async fn delete(&mut self, path: &str, recursive: bool) -> Result<(), FileServerError> {
+        let response = self.client.delete(path, recursive).await?;
         
         if response.success {
             println!("✓ Successfully deleted '{}'", path);
             println!("  Message: {}", response.message);
+            if !response.trash_id.is_empty() {
+                println!("  Trash ID: {}", response.trash_id);
+            }
         } else {
             return Err(FileServerError::IoError(
-                std::io::Error::new(std::io::ErrorKind::Other, response.message)
+                std::io::Error::other(response.message)
             ));
         }
         
         Ok(())
     }
-}
\ No newline at end of file
+
+    pub async fn list_trash(&mut self, directory: &str) -> Result<Vec<TrashItem>, FileServerError> {
+        let items = self.client.list_trash(directory).await?;
+        
+        println!("Trash of '{}':", directory);
+        println!("{:<20} {:<20} {:<10} {:<15} {:<15} Original path", "ID", "Deleted", "Type", "Size", "Deleted by");
+        println!("{}", "-".repeat(100));
+        
+        for item in &items {
+            let file_type = if item.is_directory { "Directory" } else { "File" };
+            let size = if item.is_directory {
+                "-".to_string()
+            } else {
+                format!("{} bytes", item.size)
+            };
+            
+            let deleted = std::time::UNIX_EPOCH + std::time::Duration::from_secs(item.deleted_at as u64);
+            let datetime = chrono::DateTime::<chrono::Utc>::from(deleted);
+            
+            println!("{:<20} {:<20} {:<10} {:<15} {:<15} {}", 
+                item.id, 
+                datetime.format("%Y-%m-%d %H:%M").to_string(), 
+                file_type, 
+                size,
+                item.deleted_by,
+                item.original_path
+            );
+        }
+        
+        Ok(items)
+    }
+
+    pub async fn restore_trash(&mut self, directory: &str, id: &str, overwrite: bool) -> Result<(), FileServerError> {
+        let response = self.client.restore_trash(directory, id, overwrite).await?;
+        
+        println!("✓ Successfully restored '{}'", id);
+        println!("  Message: {}", response.message);
+        
+        Ok(())
+    }
+
+    pub async fn purge_trash(&mut self, directory: &str, id: Option<&str>) -> Result<(), FileServerError> {
+        let response = self.client.purge_trash(directory, id).await?;
+        
+        println!("✓ Successfully purged trash of '{}'", directory);
+        println!("  Message: {}", response.message);
+        
+        Ok(())
+    }
+
+    /// Uploads a local file through a resumable upload session. Interrupted
+    /// transfers are retried from the last byte the server committed; pass
+    /// the session id printed by an earlier run to resume it.
+    pub async fn upload(&mut self, path: &str, file_path: &str, resume: Option<&str>) -> Result<(), FileServerError> {
+        let file_size = std::fs::metadata(file_path)?.len();
+        let checksum = sha256_file(file_path)?;
+
+        let mut session = match resume {
+            Some(upload_id) => self.client.upload_status(upload_id).await?,
+            None => self.client.begin_upload(path).await?,
+        };
+
+        if session.path != path {
+            return Err(FileServerError::InvalidPath(format!(
+                "Upload session '{}' is for '{}'", session.upload_id, session.path
+            )));
+        }
+
+        println!("Upload session: {}", session.upload_id);
+        if session.committed_bytes > 0 {
+            println!("  Resuming at {} of {} bytes", session.committed_bytes, file_size);
+        }
+
+        let mut attempts = 0;
+        while session.committed_bytes < file_size {
+            let offset = session.committed_bytes;
+            match self.client.append_upload(&session.upload_id, file_path, offset).await {
+                Ok(updated) if updated.committed_bytes > offset => session = updated,
+                Ok(_) => {
+                    return Err(FileServerError::IoError(
+                        std::io::Error::other("Local file changed during upload")
+                    ));
+                }
+                Err(e) if attempts < self.client.retry_attempts() => {
+                    attempts += 1;
+                    println!("  Upload interrupted: {}", e);
+                    println!("  Retrying ({}/{})...", attempts, self.client.retry_attempts());
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+                    if let Ok(updated) = self.client.upload_status(&session.upload_id).await {
+                        session = updated;
+                    }
+                }
+                Err(e) => {
+                    println!("Upload failed, resume it with: --resume {}", session.upload_id);
+                    return Err(e);
+                }
+            }
+        }
+
+        if session.committed_bytes != file_size {
+            return Err(FileServerError::IoError(
+                std::io::Error::other("Local file changed during upload")
+            ));
+        }
+
+        let response = self.client.finish_upload(&session.upload_id, &checksum).await?;
+        println!("✓ Successfully uploaded {} bytes to '{}'", response.bytes_written, path);
+
+        Ok(())
+    }
+
+    /// Stats what `path` points to, following symbolic links the way the
+    /// server does when reading through them. Only relative targets can be
+    /// followed, as an absolute one names no remote path.
+    async fn stat_target(&mut self, path: &str) -> Result<FileMetadata, FileServerError> {
+        let mut path = path.to_string();
+        for _ in 0..MAX_LINK_HOPS {
+            let metadata = self.client.stat(&path).await?;
+            let posix = metadata.posix.clone().unwrap_or_default();
+            if posix.file_type() != FileType::Symlink {
+                return Ok(metadata);
+            }
+            if posix.symlink_target.starts_with('/') {
+                return Err(FileServerError::InvalidPath(format!(
+                    "'{}' links to '{}', which is not a remote path", path, posix.symlink_target
+                )));
+            }
+
+            let mut components: Vec<&str> = path.split('/').collect();
+            components.pop();
+            for component in posix.symlink_target.split('/') {
+                match component {
+                    "" | "." => {}
+                    ".." => {
+                        components.pop();
+                    }
+                    component => components.push(component),
+                }
+            }
+            path = components.join("/");
+        }
+        Err(FileServerError::InvalidPath(format!("Too many levels of symbolic links at '{}'", path)))
+    }
+}
+
+fn sha256_file(file_path: &str) -> Result<String, FileServerError> {
+    let mut file = std::fs::File::open(file_path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Renders an entry the way `ls -l --time-style=long-iso` does
+/// Applies a `chmod` mode to the current permission bits. The mode is
+/// either octal (`755`) or symbolic clauses like `u+x,go-w` or `a=r`.
+fn apply_mode(spec: &str, current: u32) -> Result<u32, String> {
+    let invalid = || format!("Invalid mode '{}'", spec);
+
+    if !spec.is_empty() && spec.chars().all(|c| c.is_digit(8)) {
+        return u32::from_str_radix(spec, 8).ok().filter(|mode| *mode <= 0o7777).ok_or_else(invalid);
+    }
+
+    let mut mode = current & 0o7777;
+    for clause in spec.split(',') {
+        let split = clause.find(['+', '-', '=']).ok_or_else(invalid)?;
+        let (who, operation) = clause.split_at(split);
+
+        let mut classes = 0;
+        for c in who.chars() {
+            classes |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => return Err(invalid()),
+            };
+        }
+        if classes == 0 {
+            classes = 0o7777;
+        }
+
+        let mut bits = 0;
+        for c in operation[1..].chars() {
+            bits |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                's' => 0o6000,
+                't' => 0o1000,
+                _ => return Err(invalid()),
+            };
+        }
+        bits &= classes;
+
+        match &operation[..1] {
+            "+" => mode |= bits,
+            "-" => mode &= !bits,
+            _ => mode = (mode & !classes) | bits,
+        }
+    }
+
+    Ok(mode)
+}
+
+fn long_listing(permissions: &str, posix: &PosixAttributes, size: u64, modified_time: i64, name: &str) -> String {
+    let size = match posix.file_type() {
+        FileType::BlockDevice | FileType::CharDevice => format!("{}, {}", posix.device_major, posix.device_minor),
+        _ => size.to_string(),
+    };
+    
+    let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(modified_time as u64);
+    let datetime = chrono::DateTime::<chrono::Utc>::from(modified);
+    
+    let mut line = format!(
+        "{} {:>3} {:<8} {:<8} {:>10} {} {}",
+        permissions,
+        posix.link_count,
+        name_or_id(&posix.owner, posix.uid),
+        name_or_id(&posix.group, posix.gid),
+        size,
+        datetime.format("%Y-%m-%d %H:%M"),
+        name
+    );
+    if !posix.symlink_target.is_empty() {
+        line.push_str(" -> ");
+        line.push_str(&posix.symlink_target);
+    }
+    line
+}
+
+fn print_quota_usage(label: &str, usage: &QuotaUsage) {
+    println!("  {}: {}, {}", label,
+        usage_of(usage.used_bytes, usage.max_bytes, "bytes"),
+        usage_of(usage.used_files, usage.max_files, "files"));
+}
+
+/// e.g. `93 of 100 bytes (93%)`, or `93 bytes` without a limit
+fn usage_of(used: u64, max: Option<u64>, unit: &str) -> String {
+    match max {
+        Some(max) => format!("{} of {} {} ({}%)", used, max, unit, used.saturating_mul(100) / max.max(1)),
+        None => format!("{} {}", used, unit),
+    }
+}
+
+fn name_or_id(name: &str, id: u32) -> String {
+    if name.is_empty() { id.to_string() } else { name.to_string() }
+}
+
+fn type_description(file_type: FileType) -> &'static str {
+    match file_type {
+        FileType::Regular => "regular file",
+        FileType::Directory => "directory",
+        FileType::Symlink => "symbolic link",
+        FileType::Fifo => "fifo",
+        FileType::Socket => "socket",
+        FileType::BlockDevice => "block special file",
+        FileType::CharDevice => "character special file",
+        FileType::Unknown => "unknown",
+    }
+}
+
+fn format_nanos(nanos: i64) -> String {
+    chrono::DateTime::from_timestamp_nanos(nanos).format("%Y-%m-%d %H:%M:%S%.9f UTC").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ClientConfig, ClientSettings, ServerSettings};
+    use common::file_service_server::{FileService, FileServiceServer};
+    use common::*;
+    use std::collections::VecDeque;
+    use std::path::PathBuf;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tokio_stream::Stream;
+    use tonic::{Request, Response, Status, Streaming};
+
+    type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+    /// Large enough for tonic to send every chunk on its own rather than
+    /// batch them, so chunks before a failure reach the client
+    const TEST_CHUNK_SIZE: usize = 64 * 1024;
+
+    /// Serves one file, `remote/file.bin`, and takes uploads. Streams can be
+    /// made to fail part way to see the client resume them.
+    #[derive(Default)]
+    struct TestServer {
+        content: Vec<u8>,
+        /// Size reported by Stat, if not the size of `content`
+        stat_size: Option<u64>,
+        /// Checksum reported, if not the one of `content`
+        checksum: Option<String>,
+        /// Symbolic links Stat describes, as (path, target)
+        links: Vec<(&'static str, &'static str)>,
+        /// Bytes each of the next Reads sends before failing
+        cut_reads: Mutex<VecDeque<usize>>,
+        /// Offsets Reads were asked to start at
+        read_offsets: Mutex<Vec<u64>>,
+        /// Chunks the next append takes before failing
+        cut_append_after: Mutex<Option<usize>>,
+        uploaded: Mutex<Vec<u8>>,
+    }
+
+    fn upload_session(committed_bytes: u64) -> UploadSession {
+        UploadSession {
+            upload_id: "session".to_string(),
+            path: "remote/upload.bin".to_string(),
+            committed_bytes,
+            expires_in_seconds: 3600,
+        }
+    }
+
+    /// The service with the calls the tests use, every other one answering
+    /// UNIMPLEMENTED
+    macro_rules! test_service {
+        ($($name:ident($request:ty) -> $response:ty;)*) => {
+            #[tonic::async_trait]
+            impl FileService for TestServer {
+                type ReadStream = ResponseStream<DataChunk>;
+                type CopyStream = ResponseStream<CopyProgress>;
+                type FindStream = ResponseStream<FindResponse>;
+                type GrepStream = ResponseStream<GrepResponse>;
+
+                async fn stat(&self, request: Request<StatRequest>) -> Result<Response<FileMetadata>, Status> {
+                    let path = request.into_inner().path;
+                    if let Some((_, target)) = self.links.iter().find(|(link, _)| *link == path) {
+                        let posix = PosixAttributes {
+                            file_type: FileType::Symlink as i32,
+                            symlink_target: target.to_string(),
+                            ..Default::default()
+                        };
+                        return Ok(Response::new(FileMetadata { size: target.len() as u64, posix: Some(posix), ..Default::default() }));
+                    }
+                    Ok(Response::new(FileMetadata {
+                        name: "file.bin".to_string(),
+                        size: self.stat_size.unwrap_or(self.content.len() as u64),
+                        ..Default::default()
+                    }))
+                }
+
+                async fn read(&self, request: Request<ReadRequest>) -> Result<Response<Self::ReadStream>, Status> {
+                    let offset = request.into_inner().offset.unwrap_or(0);
+                    self.read_offsets.lock().unwrap().push(offset);
+                    let cut = self.cut_reads.lock().unwrap().pop_front();
+
+                    let data = &self.content[offset as usize..];
+                    let mut chunks: Vec<Result<DataChunk, Status>> = data
+                        .chunks(TEST_CHUNK_SIZE)
+                        .enumerate()
+                        .map(|(i, chunk)| DataChunk {
+                            path: "remote/file.bin".to_string(),
+                            data: chunk.to_vec(),
+                            offset: offset + (i * TEST_CHUNK_SIZE) as u64,
+                            is_last: (i + 1) * TEST_CHUNK_SIZE >= data.len(),
+                            crc32c: Some(crc32c::crc32c(chunk)),
+                            sha256: None,
+                        })
+                        .map(Ok)
+                        .collect();
+                    if let Some(cut) = cut {
+                        chunks.truncate(cut / TEST_CHUNK_SIZE);
+                        chunks.push(Err(Status::unavailable("connection reset")));
+                    }
+                    Ok(Response::new(Box::pin(tokio_stream::iter(chunks))))
+                }
+
+                async fn checksum(&self, _: Request<ChecksumRequest>) -> Result<Response<ChecksumResponse>, Status> {
+                    Ok(Response::new(ChecksumResponse {
+                        path: "remote/file.bin".to_string(),
+                        size: self.content.len() as u64,
+                        sha256: self.checksum.clone().unwrap_or_else(|| hex::encode(Sha256::digest(&self.content))),
+                    }))
+                }
+
+                async fn begin_upload(&self, _: Request<BeginUploadRequest>) -> Result<Response<UploadSession>, Status> {
+                    Ok(Response::new(upload_session(0)))
+                }
+
+                async fn get_upload_status(&self, _: Request<UploadStatusRequest>) -> Result<Response<UploadSession>, Status> {
+                    Ok(Response::new(upload_session(self.uploaded.lock().unwrap().len() as u64)))
+                }
+
+                async fn append_upload(&self, request: Request<Streaming<UploadChunk>>) -> Result<Response<UploadSession>, Status> {
+                    let mut stream = request.into_inner();
+                    let cut = self.cut_append_after.lock().unwrap().take();
+                    let mut chunks = 0;
+
+                    while let Some(chunk) = stream.next().await {
+                        if cut == Some(chunks) {
+                            return Err(Status::unavailable("connection reset"));
+                        }
+                        let chunk = chunk?;
+                        let mut uploaded = self.uploaded.lock().unwrap();
+                        if chunk.offset != uploaded.len() as u64 {
+                            return Err(Status::failed_precondition("Chunk offset does not match"));
+                        }
+                        uploaded.extend_from_slice(&chunk.data);
+                        chunks += 1;
+                    }
+                    Ok(Response::new(upload_session(self.uploaded.lock().unwrap().len() as u64)))
+                }
+
+                async fn finish_upload(&self, request: Request<FinishUploadRequest>) -> Result<Response<WriteResponse>, Status> {
+                    let uploaded = self.uploaded.lock().unwrap();
+                    let sha256 = hex::encode(Sha256::digest(&*uploaded));
+                    if request.into_inner().sha256 != sha256 {
+                        return Err(Status::data_loss("Checksum mismatch"));
+                    }
+                    Ok(Response::new(WriteResponse {
+                        success: true,
+                        message: "File written successfully".to_string(),
+                        bytes_written: uploaded.len() as u64,
+                        sha256,
+                    }))
+                }
+
+                $(
+                    async fn $name(&self, _: Request<$request>) -> Result<Response<$response>, Status> {
+                        Err(Status::unimplemented(stringify!($name)))
+                    }
+                )*
+            }
+        };
+    }
+
+    test_service! {
+        authenticate(ConnectRequest) -> ConnectResponse;
+        health_check(Empty) -> HealthStatus;
+        list(ListRequest) -> ListResponse;
+        write(Streaming<DataChunk>) -> WriteResponse;
+        delete(DeleteRequest) -> DeleteResponse;
+        r#move(MoveRequest) -> MoveResponse;
+        copy(CopyRequest) -> Self::CopyStream;
+        make_directory(MakeDirectoryRequest) -> DirectoryResponse;
+        remove_directory(RemoveDirectoryRequest) -> DirectoryResponse;
+        list_trash(ListTrashRequest) -> ListTrashResponse;
+        restore_trash(RestoreTrashRequest) -> TrashResponse;
+        purge_trash(PurgeTrashRequest) -> TrashResponse;
+        find(FindRequest) -> Self::FindStream;
+        grep(GrepRequest) -> Self::GrepStream;
+        symlink(SymlinkRequest) -> LinkResponse;
+        link(LinkRequest) -> LinkResponse;
+        read_link(ReadLinkRequest) -> ReadLinkResponse;
+        set_attributes(SetAttributesRequest) -> FileMetadata;
+        get_usage(GetUsageRequest) -> UsageResponse;
+    }
+
+    async fn connect(server: &Arc<TestServer>) -> FileOperations {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(FileServiceServer::from_arc(Arc::clone(server)))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let config = ClientConfig {
+            server: ServerSettings { host: "127.0.0.1".to_string(), port },
+            client: ClientSettings { timeout_seconds: 10, retry_attempts: 2, api_key: None },
+            tls: None,
+        };
+        FileOperations::new(FileServerClient::new(config, "test".to_string()).await.unwrap())
+    }
+
+    fn test_content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn local_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fileserver_client_test_{}_{}", uuid::Uuid::now_v7(), name))
+    }
+
+    #[test]
+    fn test_long_listing() {
+        let posix = PosixAttributes {
+            file_type: FileType::Symlink as i32,
+            link_count: 1,
+            uid: 1000,
+            gid: 1000,
+            owner: "alice".to_string(),
+            symlink_target: "target.txt".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            long_listing("lrwxrwxrwx", &posix, 10, 0, "link"),
+            "lrwxrwxrwx   1 alice    1000             10 1970-01-01 00:00 link -> target.txt"
+        );
+
+        let device = PosixAttributes {
+            file_type: FileType::CharDevice as i32,
+            device_major: 1,
+            device_minor: 3,
+            ..Default::default()
+        };
+        assert!(long_listing("crw-rw-rw-", &device, 0, 0, "null").contains("      1, 3 "));
+    }
+
+    #[test]
+    fn test_usage_of() {
+        assert_eq!(usage_of(93, Some(100), "bytes"), "93 of 100 bytes (93%)");
+        assert_eq!(usage_of(12, None, "files"), "12 files");
+    }
+
+    #[test]
+    fn test_apply_mode() {
+        assert_eq!(apply_mode("755", 0o600), Ok(0o755));
+        assert_eq!(apply_mode("0644", 0o777), Ok(0o644));
+        assert_eq!(apply_mode("+x", 0o644), Ok(0o755));
+        assert_eq!(apply_mode("u+x", 0o644), Ok(0o744));
+        assert_eq!(apply_mode("go-w", 0o666), Ok(0o644));
+        assert_eq!(apply_mode("u=rw,go=r", 0o777), Ok(0o644));
+        assert_eq!(apply_mode("o=", 0o755), Ok(0o750));
+        assert_eq!(apply_mode("+t", 0o777), Ok(0o1777));
+        assert_eq!(apply_mode("a-x", 0o100755), Ok(0o644));
+
+        assert!(apply_mode("10000", 0).is_err());
+        assert!(apply_mode("u", 0o644).is_err());
+        assert!(apply_mode("z+x", 0o644).is_err());
+        assert!(apply_mode("u+q", 0o644).is_err());
+        assert!(apply_mode("", 0o644).is_err());
+    }
+
+    #[test]
+    fn test_format_nanos() {
+        assert_eq!(format_nanos(1_500_000_000_123_456_789), "2017-07-14 02:40:00.123456789 UTC");
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_partial_file_and_dropped_streams() {
+        let content = test_content(10 * TEST_CHUNK_SIZE);
+        let server = Arc::new(TestServer { content: content.clone(), ..Default::default() });
+        server.cut_reads.lock().unwrap().push_back(5 * TEST_CHUNK_SIZE);
+        let mut operations = connect(&server).await;
+
+        // A local file is taken for an earlier, interrupted download
+        let local = local_path("partial.bin");
+        std::fs::write(&local, &content[..2 * TEST_CHUNK_SIZE]).unwrap();
+
+        let size = operations.download("remote/file.bin", local.to_str().unwrap()).await.unwrap();
+        assert_eq!(size, content.len() as u64);
+        assert_eq!(std::fs::read(&local).unwrap(), content);
+        // The dropped stream is picked up where it broke off
+        assert_eq!(*server.read_offsets.lock().unwrap(), [2 * TEST_CHUNK_SIZE as u64, 7 * TEST_CHUNK_SIZE as u64]);
+
+        std::fs::remove_file(&local).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_verifies_size_and_checksum() {
+        let content = test_content(3 * TEST_CHUNK_SIZE);
+        let local = local_path("download.bin");
+
+        // More than the file holds locally is not a partial download
+        let server = Arc::new(TestServer { content: content.clone(), ..Default::default() });
+        let mut operations = connect(&server).await;
+        std::fs::write(&local, test_content(4 * TEST_CHUNK_SIZE)).unwrap();
+        let err = operations.download("remote/file.bin", local.to_str().unwrap()).await.unwrap_err();
+        assert!(err.to_string().contains("is larger than"), "{}", err);
+        assert!(server.read_offsets.lock().unwrap().is_empty());
+
+        // A stream that ends short of the size the file had
+        std::fs::remove_file(&local).unwrap();
+        let server = Arc::new(TestServer { content: content.clone(), stat_size: Some(4 * TEST_CHUNK_SIZE as u64), ..Default::default() });
+        let err = connect(&server).await.download("remote/file.bin", local.to_str().unwrap()).await.unwrap_err();
+        assert!(err.to_string().contains(&format!("Downloaded {} bytes but", content.len())), "{}", err);
+
+        // Data that does not match the checksum of the remote file
+        std::fs::remove_file(&local).unwrap();
+        let server = Arc::new(TestServer { content, checksum: Some("0".repeat(64)), ..Default::default() });
+        let err = connect(&server).await.download("remote/file.bin", local.to_str().unwrap()).await.unwrap_err();
+        assert!(matches!(err, FileServerError::ChecksumMismatch(_)), "{}", err);
+
+        std::fs::remove_file(&local).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_follows_links_to_their_target() {
+        let content = test_content(2 * TEST_CHUNK_SIZE);
+        let links = vec![("remote/latest.bin", "old/../file.bin"), ("remote/passwd", "/etc/passwd")];
+        let server = Arc::new(TestServer { content: content.clone(), links, ..Default::default() });
+        let mut operations = connect(&server).await;
+        let local = local_path("latest.bin");
+
+        // The size checked against is the target's, not the link's
+        operations.download("remote/latest.bin", local.to_str().unwrap()).await.unwrap();
+        assert_eq!(std::fs::read(&local).unwrap(), content);
+        std::fs::remove_file(&local).unwrap();
+
+        let err = operations.download("remote/passwd", local.to_str().unwrap()).await.unwrap_err();
+        assert!(err.to_string().contains("not a remote path"), "{}", err);
+
+        std::fs::remove_file(&local).ok();
+    }
+
+    #[tokio::test]
+    async fn test_download_gives_up_after_retries_without_progress() {
+        let server = Arc::new(TestServer { content: test_content(TEST_CHUNK_SIZE), ..Default::default() });
+        // Every read fails before sending anything
+        server.cut_reads.lock().unwrap().extend([0; 3]);
+        let mut operations = connect(&server).await;
+        let local = local_path("missing.bin");
+
+        let err = operations.download("remote/file.bin", local.to_str().unwrap()).await.unwrap_err();
+        assert!(err.to_string().contains("connection reset"), "{}", err);
+        // The first attempt and both retries
+        assert_eq!(server.read_offsets.lock().unwrap().len(), 3);
+
+        std::fs::remove_file(&local).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_upload_resumes_from_committed_bytes() {
+        let server = Arc::new(TestServer::default());
+        *server.cut_append_after.lock().unwrap() = Some(2);
+        let mut operations = connect(&server).await;
+
+        // Several of the client's 64 KiB chunks, with the stream failing after two
+        let content = test_content(200 * 1024);
+        let local = local_path("upload.bin");
+        std::fs::write(&local, &content).unwrap();
+
+        operations.upload("remote/upload.bin", local.to_str().unwrap(), None).await.unwrap();
+        assert_eq!(*server.uploaded.lock().unwrap(), content);
+
+        // A session for another path is not continued
+        let err = operations.upload("remote/other.bin", local.to_str().unwrap(), Some("session")).await.unwrap_err();
+        assert!(err.to_string().contains("is for 'remote/upload.bin'"), "{}", err);
+
+        std::fs::remove_file(&local).unwrap();
+    }
+}
@@ -8,6 +8,12 @@ pub enum FileServerError {
     #[error("File not found: {0}")]
     FileNotFound(String),
     
+    #[error("Already exists: {0}")]
+    AlreadyExists(String),
+    
+    #[error("Directory not empty: {0}")]
+    DirectoryNotEmpty(String),
+    
     #[error("Connection failed: {0}")]
     ConnectionFailed(String),
     
@@ -17,14 +23,30 @@ pub enum FileServerError {
     #[error("Configuration error: {0}")]
     ConfigError(String),
     
+    #[error("Checksum mismatch: {0}")]
+    ChecksumMismatch(String),
+    
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+    
+    #[error("Rejected by file policy: {0}")]
+    PolicyViolation(String),
+    
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
     
     #[error("gRPC error: {0}")]
-    GrpcError(#[from] tonic::Status),
+    GrpcError(Box<tonic::Status>),
     
     #[error("TOML parsing error: {0}")]
     TomlError(#[from] toml::de::Error),
 }
 
+// Boxed, as a `Status` would make every result carrying this error large
+impl From<tonic::Status> for FileServerError {
+    fn from(status: tonic::Status) -> Self {
+        FileServerError::GrpcError(Box::new(status))
+    }
+}
+
 pub type Result<T> = std::result::Result<T, FileServerError>;
\ No newline at end of file
@@ -0,0 +1,569 @@
+use crate::auth::CallerIdentity;
+use crate::config::AuditConfig;
+use chrono::{DateTime, SecondsFormat, Utc};
+use common::FileServerError;
+use std::ffi::CString;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::Write as _;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Instant;
+use tonic::body::BoxBody;
+use tonic::codegen::http::{self, HeaderMap};
+use tonic::codegen::{Body, BoxFuture, Bytes, Service};
+use tonic::server::NamedService;
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+use tonic::{Code, Status};
+
+tokio::task_local! {
+    /// The call being served by the current task
+    static CALL: CallAudit;
+}
+
+/// What a call did, filled in as it is served. Tasks a call spawns take a
+/// clone along to keep counting.
+#[derive(Clone, Default)]
+pub struct CallAudit(Arc<Mutex<CallDetails>>);
+
+#[derive(Default)]
+struct CallDetails {
+    client: Option<String>,
+    cert_subject: Option<String>,
+    /// Virtual paths in the order the call named them, the source first
+    paths: Vec<String>,
+    resolved: Vec<PathBuf>,
+    bytes: u64,
+    /// Failure a call reported in its response rather than as its status
+    failure: Option<String>,
+}
+
+impl CallAudit {
+    pub fn add_bytes(&self, bytes: usize) {
+        self.0.lock().unwrap().bytes += bytes as u64;
+    }
+}
+
+/// The call being served, to hand to tasks that keep working on it
+pub fn current() -> Option<CallAudit> {
+    CALL.try_with(CallAudit::clone).ok()
+}
+
+fn with_current(f: impl FnOnce(&mut CallDetails)) {
+    let _ = CALL.try_with(|call| f(&mut call.0.lock().unwrap()));
+}
+
+/// Records who made the current call
+pub fn identify(identity: &CallerIdentity) {
+    with_current(|details| {
+        details.client = identity.client_name.clone();
+        details.cert_subject = identity.cert_subject.clone();
+    });
+}
+
+/// Records a virtual path named by the current call
+pub fn path(path: &str) {
+    with_current(|details| details.paths.push(path.to_string()));
+}
+
+/// Records where a path of the current call led on disk
+pub fn resolved(path: &Path) {
+    with_current(|details| details.resolved.push(path.to_path_buf()));
+}
+
+/// Counts data the current call transferred
+pub fn add_bytes(bytes: usize) {
+    with_current(|details| details.bytes += bytes as u64);
+}
+
+/// Records that the current call failed, for calls that answer with a
+/// success flag instead of an error status
+pub fn failed(message: &str) {
+    with_current(|details| details.failure = Some(message.to_string()));
+}
+
+/// One finished call, as written to the audit log
+struct Record<'a> {
+    timestamp: DateTime<Utc>,
+    peer_ip: Option<IpAddr>,
+    operation: &'a str,
+    details: &'a CallDetails,
+    status: Option<&'a Status>,
+    duration_ms: f64,
+}
+
+impl Record<'_> {
+    fn failed(&self) -> bool {
+        self.status.is_none_or(|status| status.code() != Code::Ok) || self.details.failure.is_some()
+    }
+
+    fn to_json(&self) -> String {
+        let details = self.details;
+        let mut json = String::from("{");
+        field(&mut json, "timestamp", Some(&self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)));
+        field(&mut json, "client", details.client.as_deref());
+        field(&mut json, "cert_subject", details.cert_subject.as_deref());
+        field(&mut json, "peer_ip", self.peer_ip.map(|ip| ip.to_string()).as_deref());
+        field(&mut json, "operation", Some(self.operation));
+        field(&mut json, "path", details.paths.first().map(String::as_str));
+        field(&mut json, "resolved_path", details.resolved.first().map(|p| p.to_string_lossy()).as_deref());
+        field(&mut json, "target_path", details.paths.get(1).map(String::as_str));
+        field(&mut json, "resolved_target_path", details.resolved.get(1).map(|p| p.to_string_lossy()).as_deref());
+        let _ = write!(json, "\"bytes\":{},", details.bytes);
+
+        // A call whose response never completed was cut short by the client
+        let code = self.status.map_or(Code::Cancelled, Status::code);
+        let (outcome, message) = match (&details.failure, code) {
+            (Some(failure), Code::Ok) => ("FAILED", Some(failure.as_str())),
+            _ => (code_name(code), self.status.map(Status::message).filter(|message| !message.is_empty())),
+        };
+        field(&mut json, "outcome", Some(outcome));
+        field(&mut json, "error", message);
+        let _ = write!(json, "\"duration_ms\":{:.3}}}", self.duration_ms);
+        json
+    }
+}
+
+/// Appends `"name":value,` with the value as a JSON string, or null
+fn field(json: &mut String, name: &str, value: Option<&str>) {
+    let _ = write!(json, "\"{}\":", name);
+    match value {
+        Some(value) => escape_into(json, value),
+        None => json.push_str("null"),
+    }
+    json.push(',');
+}
+
+fn escape_into(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+fn code_name(code: Code) -> &'static str {
+    match code {
+        Code::Ok => "OK",
+        Code::Cancelled => "CANCELLED",
+        Code::Unknown => "UNKNOWN",
+        Code::InvalidArgument => "INVALID_ARGUMENT",
+        Code::DeadlineExceeded => "DEADLINE_EXCEEDED",
+        Code::NotFound => "NOT_FOUND",
+        Code::AlreadyExists => "ALREADY_EXISTS",
+        Code::PermissionDenied => "PERMISSION_DENIED",
+        Code::ResourceExhausted => "RESOURCE_EXHAUSTED",
+        Code::FailedPrecondition => "FAILED_PRECONDITION",
+        Code::Aborted => "ABORTED",
+        Code::OutOfRange => "OUT_OF_RANGE",
+        Code::Unimplemented => "UNIMPLEMENTED",
+        Code::Internal => "INTERNAL",
+        Code::Unavailable => "UNAVAILABLE",
+        Code::DataLoss => "DATA_LOSS",
+        Code::Unauthenticated => "UNAUTHENTICATED",
+    }
+}
+
+/// Records waiting to be written before further ones are dropped
+const AUDIT_QUEUE_LEN: usize = 4096;
+
+/// Destination of the audit records. Records are written by a thread of
+/// their own, so calls never wait on the disk or syslog. Should it fall that
+/// far behind, records are dropped and the gap is noted in the log.
+pub struct AuditLog {
+    records: mpsc::SyncSender<(String, bool)>,
+    /// Records dropped since the writer last caught up
+    dropped: Arc<AtomicU64>,
+}
+
+impl AuditLog {
+    /// Opens the log file, before privileges are dropped. Rotating it later
+    /// needs its directory to be writable by the user the server runs as.
+    pub fn open(config: &AuditConfig) -> Result<Self, FileServerError> {
+        let mut file = config.path
+            .as_ref()
+            .map(|path| RotatingFile::open(PathBuf::from(path), config.max_file_size, config.max_files))
+            .transpose()?;
+
+        let syslog = config.syslog;
+        if syslog {
+            // SAFETY: the identity is a static C string, which openlog
+            // requires as it keeps the pointer
+            unsafe { libc::openlog(c"fileserver".as_ptr(), libc::LOG_PID | libc::LOG_NDELAY, libc::LOG_AUTHPRIV) };
+        }
+
+        let (records, received) = mpsc::sync_channel::<(String, bool)>(AUDIT_QUEUE_LEN);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer_dropped = Arc::clone(&dropped);
+        std::thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || {
+                let mut write = |line: &str, failed: bool| {
+                    if let Some(file) = file.as_mut() {
+                        if let Err(e) = file.write_line(line) {
+                            tracing::error!("Failed to write audit log: {}", e);
+                        }
+                    }
+                    if syslog {
+                        send_to_syslog(line, failed);
+                    }
+                };
+
+                for (line, failed) in received {
+                    let dropped = writer_dropped.swap(0, Ordering::Relaxed);
+                    if dropped > 0 {
+                        tracing::error!("Audit log fell behind, {} records were dropped", dropped);
+                        write(&gap_record(Utc::now(), dropped), true);
+                    }
+                    write(&line, failed);
+                }
+            })?;
+
+        Ok(Self { records, dropped })
+    }
+
+    fn record(&self, record: &Record) {
+        if let Err(mpsc::TrySendError::Full(_)) = self.records.try_send((record.to_json(), record.failed())) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Notes in the log that records were dropped before this point
+fn gap_record(timestamp: DateTime<Utc>, dropped: u64) -> String {
+    format!(
+        r#"{{"timestamp":"{}","dropped_records":{}}}"#,
+        timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+        dropped
+    )
+}
+
+fn send_to_syslog(line: &str, failed: bool) {
+    let priority = if failed { libc::LOG_WARNING } else { libc::LOG_INFO };
+    // JSON escapes every control character, so the line has no NUL in it
+    if let Ok(line) = CString::new(line) {
+        // SAFETY: both arguments are NUL-terminated, and the fixed format
+        // string consumes exactly the one string argument passed
+        unsafe { libc::syslog(priority, c"%s".as_ptr(), line.as_ptr()) };
+    }
+}
+
+/// A log file that is moved aside to `<path>.1`, shifting older ones up to
+/// `<path>.<max_files>`, once it would grow past `max_size`
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: u32,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: u32) -> Result<Self, FileServerError> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size, max_size, max_files })
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(format!("{}\n", line).as_bytes())?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let rotated = |n: u32| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", n));
+            PathBuf::from(path)
+        };
+
+        for n in (1..self.max_files).rev() {
+            match std::fs::rename(rotated(n), rotated(n + 1)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        std::fs::rename(&self.path, rotated(1))?;
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Wraps a gRPC service to write an audit record for each of its calls,
+/// once the response has been sent in full
+#[derive(Clone)]
+pub struct Audited<S> {
+    inner: S,
+    log: Option<Arc<AuditLog>>,
+}
+
+impl<S> Audited<S> {
+    pub fn new(inner: S, log: Option<Arc<AuditLog>>) -> Self {
+        Self { inner, log }
+    }
+}
+
+impl<S: NamedService> NamedService for Audited<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, B> Service<http::Request<B>> for Audited<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let Some(log) = &self.log else {
+            return Box::pin(self.inner.call(request));
+        };
+
+        let mut call = PendingCall {
+            log: Arc::clone(log),
+            timestamp: Utc::now(),
+            started: Instant::now(),
+            operation: request.uri().path().rsplit('/').next().unwrap_or_default().to_string(),
+            peer_ip: peer_ip(&request),
+            audit: CallAudit::default(),
+            status: None,
+        };
+
+        // The interceptor runs right away, the handler as the future is polled
+        let audit = call.audit.clone();
+        let response = CALL.sync_scope(audit.clone(), || self.inner.call(request));
+
+        Box::pin(CALL.scope(audit, async move {
+            let response = response.await?;
+
+            // Failures before any message are sent as headers alone
+            call.status = Status::from_header_map(response.headers());
+            Ok(response.map(|body| AuditedBody { inner: body, call: Some(call) }.boxed_unsync()))
+        }))
+    }
+}
+
+fn peer_ip<B>(request: &http::Request<B>) -> Option<IpAddr> {
+    let extensions = request.extensions();
+    extensions.get::<TcpConnectInfo>()
+        .or_else(|| extensions.get::<TlsConnectInfo<TcpConnectInfo>>().map(TlsConnectInfo::get_ref))
+        .and_then(TcpConnectInfo::remote_addr)
+        .map(|addr| addr.ip().to_canonical())
+}
+
+/// A call whose record is written once its response is done with
+struct PendingCall {
+    log: Arc<AuditLog>,
+    timestamp: DateTime<Utc>,
+    started: Instant,
+    operation: String,
+    peer_ip: Option<IpAddr>,
+    audit: CallAudit,
+    status: Option<Status>,
+}
+
+impl Drop for PendingCall {
+    fn drop(&mut self) {
+        let details = self.audit.0.lock().unwrap();
+        self.log.record(&Record {
+            timestamp: self.timestamp,
+            peer_ip: self.peer_ip,
+            operation: &self.operation,
+            details: &details,
+            status: self.status.as_ref(),
+            duration_ms: self.started.elapsed().as_secs_f64() * 1000.0,
+        });
+    }
+}
+
+/// Response body that picks the outcome of a call up from its trailers
+struct AuditedBody {
+    inner: BoxBody,
+    call: Option<PendingCall>,
+}
+
+impl Body for AuditedBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Status>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<Option<HeaderMap>, Status>> {
+        let trailers = ready!(Pin::new(&mut self.inner).poll_trailers(cx));
+        if let (Ok(Some(trailers)), Some(call)) = (&trailers, self.call.as_mut()) {
+            call.status = call.status.take().or_else(|| Status::from_header_map(trailers));
+        }
+        // The response is complete, the record need not wait for the body
+        // to be dropped
+        self.call.take();
+        Poll::Ready(trailers)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fileserver_audit_test_{}", uuid::Uuid::now_v7()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_record_json() {
+        let details = CallDetails {
+            client: Some("backup".to_string()),
+            cert_subject: None,
+            paths: vec!["docs/a \"quoted\"\nname.txt".to_string(), "docs/b.txt".to_string()],
+            resolved: vec![PathBuf::from("/srv/docs/a.txt")],
+            bytes: 42,
+            failure: None,
+        };
+        let status = Status::not_found("No such file: a\\b");
+        let record = Record {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 5_000_000).unwrap(),
+            peer_ip: Some("10.1.2.3".parse().unwrap()),
+            operation: "Move",
+            details: &details,
+            status: Some(&status),
+            duration_ms: 1.5,
+        };
+
+        assert!(record.failed());
+        assert_eq!(
+            record.to_json(),
+            concat!(
+                r#"{"timestamp":"2023-11-14T22:13:20.005Z","client":"backup","cert_subject":null,"#,
+                r#""peer_ip":"10.1.2.3","operation":"Move","path":"docs/a \"quoted\"\nname.txt","#,
+                r#""resolved_path":"/srv/docs/a.txt","target_path":"docs/b.txt","resolved_target_path":null,"#,
+                r#""bytes":42,"outcome":"NOT_FOUND","error":"No such file: a\\b","duration_ms":1.500}"#,
+            )
+        );
+
+        let ok = Status::new(Code::Ok, "");
+        let record = Record { status: Some(&ok), ..record };
+        assert!(!record.failed());
+        assert!(record.to_json().contains(r#""outcome":"OK","error":null"#));
+
+        let cancelled = Record { status: None, ..record };
+        assert!(cancelled.to_json().contains(r#""outcome":"CANCELLED""#));
+
+        let failing = CallDetails { failure: Some("No such file".to_string()), ..Default::default() };
+        let failed = Record { details: &failing, ..record };
+        assert!(failed.failed());
+        assert!(failed.to_json().contains(r#""outcome":"FAILED","error":"No such file""#));
+
+        let mut json = String::new();
+        escape_into(&mut json, "bell\u{7}");
+        assert_eq!(json, r#""bell\u0007""#);
+    }
+
+    #[test]
+    fn test_full_queue_drops_records() {
+        let (records, received) = mpsc::sync_channel(2);
+        let log = AuditLog { records, dropped: Arc::new(AtomicU64::new(0)) };
+        let details = CallDetails::default();
+        let record = Record {
+            timestamp: Utc::now(),
+            peer_ip: None,
+            operation: "Stat",
+            details: &details,
+            status: None,
+            duration_ms: 0.0,
+        };
+
+        for _ in 0..5 {
+            log.record(&record);
+        }
+        assert_eq!(received.try_iter().count(), 2);
+        assert_eq!(log.dropped.load(Ordering::Relaxed), 3);
+
+        let gap = gap_record(DateTime::from_timestamp(1_700_000_000, 0).unwrap(), 3);
+        assert_eq!(gap, r#"{"timestamp":"2023-11-14T22:13:20.000Z","dropped_records":3}"#);
+    }
+
+    #[test]
+    fn test_rotation_keeps_max_files() {
+        let dir = temp_dir();
+        let path = dir.join("audit.log");
+        let mut file = RotatingFile::open(path.clone(), 20, 2).unwrap();
+
+        for line in ["first line", "second line", "third line", "fourth line"] {
+            file.write_line(line).unwrap();
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("audit.log"), "fourth line\n");
+        assert_eq!(read("audit.log.1"), "third line\n");
+        assert_eq!(read("audit.log.2"), "second line\n");
+        assert!(!dir.join("audit.log.3").exists());
+
+        // Appends to an existing file, counting its size
+        let mut file = RotatingFile::open(path, 30, 2).unwrap();
+        file.write_line("fifth line").unwrap();
+        assert_eq!(read("audit.log"), "fourth line\nfifth line\n");
+        file.write_line("sixth line").unwrap();
+        assert_eq!(read("audit.log"), "sixth line\n");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_details_are_collected_within_a_call() {
+        // Outside of a call there is nothing to record to
+        path("docs/ignored.txt");
+        assert!(current().is_none());
+
+        let audit = CallAudit::default();
+        CALL.scope(audit.clone(), async {
+            path("docs/report.pdf");
+            resolved(Path::new("/srv/docs/report.pdf"));
+            add_bytes(10);
+
+            let spawned = current().unwrap();
+            tokio::spawn(async move { spawned.add_bytes(5) }).await.unwrap();
+        })
+        .await;
+
+        let details = audit.0.lock().unwrap();
+        assert_eq!(details.paths, ["docs/report.pdf"]);
+        assert_eq!(details.resolved, [PathBuf::from("/srv/docs/report.pdf")]);
+        assert_eq!(details.bytes, 15);
+    }
+}
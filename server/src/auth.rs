@@ -1,18 +1,56 @@
-use crate::config::ServerConfig;
+use crate::config::{DirectoryConfig, Operation, ServerConfig};
+use crate::file_handler;
+use crate::tls::peer_certificate_subject;
 use common::FileServerError;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
 use std::net::IpAddr;
+use std::sync::Arc;
+use tonic::service::Interceptor;
 use tonic::{Request, Status};
 
+const API_KEY_HASH_SCHEME: &str = "sha256";
+const API_KEY_SALT_LEN: usize = 16;
+
 pub struct AuthService {
     pub config: ServerConfig,
 }
 
+/// Who is calling, as established by the transport. Attached to every
+/// request by `AuthInterceptor`.
+#[derive(Debug, Clone)]
+pub struct CallerIdentity {
+    pub ip: IpAddr,
+    pub cert_subject: Option<String>,
+    /// Name of the configured client whose API key was presented
+    pub client_name: Option<String>,
+}
+
+impl CallerIdentity {
+    /// Names ACL rules can refer to this caller by
+    pub fn names(&self) -> Vec<&str> {
+        self.client_name.iter().chain(self.cert_subject.iter()).map(String::as_str).collect()
+    }
+}
+
+impl std::fmt::Display for CallerIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.client_name, &self.cert_subject) {
+            (Some(name), _) => write!(f, "{} from {}", name, self.ip),
+            (None, Some(subject)) => write!(f, "{} from {}", subject, self.ip),
+            (None, None) => write!(f, "anonymous from {}", self.ip),
+        }
+    }
+}
+
+// Checks fail with the `Status` the interceptor answers with
+#[allow(clippy::result_large_err)]
 impl AuthService {
     pub fn new(config: ServerConfig) -> Self {
         Self { config }
     }
 
-    pub fn authorize_connection(&self, request: &Request<()>) -> Result<(), Status> {
+    pub fn authorize_connection<T>(&self, request: &Request<T>) -> Result<CallerIdentity, Status> {
         let client_ip = self.extract_client_ip(request)?;
         
         if !self.config.is_ip_allowed(&client_ip) {
@@ -20,36 +58,138 @@ impl AuthService {
                 format!("IP address {} is not allowed to connect", client_ip)
             ));
         }
+
+        let cert_subject = peer_certificate_subject(request);
+        self.authorize_certificate_subject(cert_subject.as_deref())?;
+
+        let client_name = self.authenticate_api_key(request)?;
         
-        Ok(())
+        Ok(CallerIdentity {
+            ip: client_ip,
+            cert_subject,
+            client_name,
+        })
+    }
+
+    /// Verifies the `authorization: Bearer <key>` metadata against the
+    /// configured clients. Returns `None` when token auth is disabled.
+    pub fn authenticate_api_key<T>(&self, request: &Request<T>) -> Result<Option<String>, Status> {
+        if !self.config.token_auth_enabled() {
+            return Ok(None);
+        }
+
+        let api_key = request.metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("API key required"))?;
+
+        self.config.clients
+            .iter()
+            .find(|client| verify_api_key(api_key, &client.api_key_hash))
+            .map(|client| Some(client.name.clone()))
+            .ok_or_else(|| Status::unauthenticated("Invalid API key"))
     }
 
-    pub fn check_directory_access(&self, dir_name: &str, operation: &str) -> Result<String, FileServerError> {
+    pub fn authorize_certificate_subject(&self, subject: Option<&str>) -> Result<(), Status> {
+        let allowed_subjects = match &self.config.tls {
+            Some(tls) if !tls.allowed_subjects.is_empty() => &tls.allowed_subjects,
+            _ => return Ok(()),
+        };
+
+        match subject {
+            Some(subject) if allowed_subjects.iter().any(|s| s == subject) => Ok(()),
+            Some(subject) => Err(Status::permission_denied(
+                format!("Client certificate '{}' is not allowed to connect", subject)
+            )),
+            None => Err(Status::unauthenticated("Client certificate required")),
+        }
+    }
+
+    pub fn check_directory_access(
+        &self,
+        identity: &CallerIdentity,
+        dir_name: &str,
+        file_path: &str,
+        operation: Operation,
+    ) -> Result<String, FileServerError> {
         let directory = self.config.get_directory(dir_name)
             .ok_or_else(|| FileServerError::PermissionDenied(
                 format!("Directory '{}' not found", dir_name)
             ))?;
 
-        match (operation, directory.permissions.as_str()) {
-            ("read", "read-only") | ("read", "read-write") => Ok(directory.path.clone()),
-            ("write", "read-write") => Ok(directory.path.clone()),
-            ("write", "read-only") => Err(FileServerError::PermissionDenied(
-                format!("Write operation not allowed on read-only directory '{}'", dir_name)
-            )),
-            _ => Err(FileServerError::PermissionDenied(
-                format!("Invalid operation '{}' on directory '{}'", operation, dir_name)
-            )),
+        if !directory.allows(operation) {
+            let verb = match operation {
+                Operation::Read => "Read",
+                Operation::Write => "Write",
+                Operation::Delete => "Delete",
+            };
+            return Err(FileServerError::PermissionDenied(
+                format!("{} operation not allowed on read-only directory '{}'", verb, dir_name)
+            ));
+        }
+
+        if directory.acl.is_empty() || self.acl_grants(identity, directory, file_path).contains(&operation) {
+            return Ok(directory.path.clone());
+        }
+
+        Err(FileServerError::PermissionDenied(
+            format!("{} access to '{}/{}' denied for {}", operation, dir_name, file_path, identity)
+        ))
+    }
+
+    /// Like `check_directory_access`, for a whole tree: the operation must
+    /// also be granted under every more specific ACL rule below `file_path`
+    pub fn check_tree_access(
+        &self,
+        identity: &CallerIdentity,
+        dir_name: &str,
+        file_path: &str,
+        operation: Operation,
+    ) -> Result<String, FileServerError> {
+        let base_path = self.check_directory_access(identity, dir_name, file_path, operation)?;
+        let below = self.config.get_directory(dir_name)
+            .into_iter()
+            .flat_map(|directory| &directory.acl)
+            .filter(|rule| rule.lies_below(file_path));
+        for rule in below {
+            self.check_directory_access(identity, dir_name, &rule.path, operation)?;
         }
+        Ok(base_path)
     }
 
-    fn extract_client_ip(&self, request: &Request<()>) -> Result<IpAddr, Status> {
+    /// Operations granted to the caller on `file_path` by the most specific
+    /// ACL rules covering it.
+    fn acl_grants(&self, identity: &CallerIdentity, directory: &DirectoryConfig, file_path: &str) -> Vec<Operation> {
+        let depth = match directory.acl.iter().filter(|r| r.covers(file_path)).map(|r| r.prefix_depth()).max() {
+            Some(depth) => depth,
+            None => return Vec::new(),
+        };
+
+        let names = identity.names();
+        directory.acl
+            .iter()
+            .filter(|r| r.covers(file_path) && r.prefix_depth() == depth)
+            .filter(|r| r.matches_caller(&names, &identity.ip))
+            .flat_map(|r| r.allow.iter().copied())
+            .collect()
+    }
+
+    /// Whether the caller has any access to the directory, at any path
+    pub fn can_see_directory(&self, identity: &CallerIdentity, directory: &DirectoryConfig) -> bool {
+        let names = identity.names();
+        directory.acl.is_empty()
+            || directory.acl.iter().any(|r| !r.allow.is_empty() && r.matches_caller(&names, &identity.ip))
+    }
+
+    fn extract_client_ip<T>(&self, request: &Request<T>) -> Result<IpAddr, Status> {
         let remote_addr = request.remote_addr();
         
         match remote_addr {
-            Some(addr) => Ok(addr.ip()),
+            Some(addr) => Ok(addr.ip().to_canonical()),
             None => {
-                // Fallback to localhost for local development when remote_addr is not available
-                Ok("127.0.0.1".parse().unwrap())
+                // Fail closed: without a peer address the allowlist cannot be checked
+                Err(Status::permission_denied("Unable to determine client address"))
             }
         }
     }
@@ -67,14 +207,91 @@ impl AuthService {
             ));
         }
 
+        if path.split('/').any(|component| file_handler::is_upload_temp(component.as_ref())) {
+            return Err(FileServerError::InvalidPath(
+                "Names of upload temporary files are reserved".to_string()
+            ));
+        }
+
         Ok(())
     }
 }
 
+/// Hashes an API key with a random salt, in the format stored in
+/// `[[clients]].api_key_hash`.
+pub fn hash_api_key(api_key: &str) -> String {
+    let mut salt = [0u8; API_KEY_SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    format_api_key_hash(api_key, &salt)
+}
+
+fn format_api_key_hash(api_key: &str, salt: &[u8]) -> String {
+    let digest = Sha256::new()
+        .chain_update(salt)
+        .chain_update(api_key.as_bytes())
+        .finalize();
+    format!("{}:{}:{}", API_KEY_HASH_SCHEME, hex::encode(salt), hex::encode(digest))
+}
+
+fn parse_api_key_hash(stored: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut parts = stored.splitn(3, ':');
+    if parts.next()? != API_KEY_HASH_SCHEME {
+        return None;
+    }
+
+    let salt = hex::decode(parts.next()?).ok()?;
+    let digest = hex::decode(parts.next()?).ok()?;
+    if salt.is_empty() || digest.len() != 32 {
+        return None;
+    }
+
+    Some((salt, digest))
+}
+
+pub fn is_valid_api_key_hash(stored: &str) -> bool {
+    parse_api_key_hash(stored).is_some()
+}
+
+pub fn verify_api_key(api_key: &str, stored: &str) -> bool {
+    let Some((salt, expected)) = parse_api_key_hash(stored) else {
+        return false;
+    };
+
+    let actual = Sha256::new()
+        .chain_update(&salt)
+        .chain_update(api_key.as_bytes())
+        .finalize();
+
+    // Constant-time comparison so the digest cannot be probed byte by byte
+    actual.iter().zip(expected.iter()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Runs the connection checks in front of every RPC, using the peer address
+/// recorded by the transport.
+#[derive(Clone)]
+pub struct AuthInterceptor {
+    auth: Arc<AuthService>,
+}
+
+impl AuthInterceptor {
+    pub fn new(auth: Arc<AuthService>) -> Self {
+        Self { auth }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let identity = self.auth.authorize_connection(&request)?;
+        crate::audit::identify(&identity);
+        request.extensions_mut().insert(identity);
+        Ok(request)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{ServerConfig, ServerSettings, DirectoryConfig};
+    use crate::config::{AclRule, ServerConfig, ServerSettings, DirectoryConfig, FollowSymlinks};
     use std::fs;
 
     fn create_test_config() -> ServerConfig {
@@ -93,43 +310,209 @@ mod tests {
                 allowed_ips: vec!["127.0.0.1".to_string(), "192.168.1.0/24".to_string()],
                 user: None,
                 group: None,
+                upload_session_timeout_seconds: 3600,
+                max_upload_sessions_per_client: 16,
+                max_find_results: 10_000,
+                max_grep_matches: 1000,
+                max_grep_bytes: 1024 * 1024 * 1024,
+                max_grep_entries: 1_000_000,
+                max_connections: None,
+                max_concurrent_streams: None,
+                concurrency_limit_per_connection: None,
+                keepalive_interval_seconds: None,
+                keepalive_timeout_seconds: None,
+                max_receive_message_size: None,
+                max_send_message_size: None,
+                max_concurrent_transfers: None,
+                transfer_queue_timeout_seconds: 30,
             },
             directories: vec![
                 DirectoryConfig {
                     name: "docs".to_string(),
                     path: docs_dir.to_string_lossy().to_string(),
                     permissions: "read-only".to_string(),
+                    acl: vec![],
+                    allow_recursive_delete: true,
+                    trash: None,
+                    follow_symlinks: FollowSymlinks::WithinRoot,
+                    allow_links: false,
+                    allow_set_attributes: false,
+                    quota: None,
+                    file_policy: None,
                 },
                 DirectoryConfig {
                     name: "workspace".to_string(),
                     path: workspace_dir.to_string_lossy().to_string(),
                     permissions: "read-write".to_string(),
+                    acl: vec![],
+                    allow_recursive_delete: true,
+                    trash: None,
+                    follow_symlinks: FollowSymlinks::WithinRoot,
+                    allow_links: false,
+                    allow_set_attributes: false,
+                    quota: None,
+                    file_policy: None,
                 },
             ],
+            limits: None,
+            audit: None,
+            tls: None,
+            clients: vec![],
+        }
+    }
+
+    fn local_identity() -> CallerIdentity {
+        local_identity_at("127.0.0.1")
+    }
+
+    fn local_identity_at(ip: &str) -> CallerIdentity {
+        CallerIdentity {
+            ip: ip.parse().unwrap(),
+            cert_subject: None,
+            client_name: None,
+        }
+    }
+
+    fn named_identity(name: &str, ip: &str) -> CallerIdentity {
+        CallerIdentity {
+            ip: ip.parse().unwrap(),
+            cert_subject: None,
+            client_name: Some(name.to_string()),
         }
     }
 
     fn cleanup_test_dirs(config: &ServerConfig) {
         if let Some(dir_config) = config.directories.first() {
             if let Some(parent) = std::path::Path::new(&dir_config.path).parent() {
-                if let Some(grandparent) = parent.parent() {
-                    fs::remove_dir_all(grandparent).ok();
-                }
+                fs::remove_dir_all(parent).ok();
             }
         }
     }
 
+    #[test]
+    fn test_authorize_connection_without_peer_address() {
+        let config = create_test_config();
+        let auth = AuthService::new(config.clone());
+
+        let result = auth.authorize_connection(&Request::new(()));
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[test]
+    fn test_interceptor_rejects_missing_peer_address() {
+        let config = create_test_config();
+        let mut interceptor = AuthInterceptor::new(Arc::new(AuthService::new(config.clone())));
+
+        let result = interceptor.call(Request::new(()));
+        assert!(result.is_err());
+        assert!(result.unwrap_err().message().contains("Unable to determine client address"));
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[test]
+    fn test_certificate_subject_authorization() {
+        let mut config = create_test_config();
+        let auth = AuthService::new(config.clone());
+
+        // Without an allowlist any (or no) certificate is accepted
+        assert!(auth.authorize_certificate_subject(None).is_ok());
+        assert!(auth.authorize_certificate_subject(Some("CN=anyone")).is_ok());
+
+        config.tls = Some(crate::config::TlsConfig {
+            cert: "server.crt".to_string(),
+            key: "server.key".to_string(),
+            client_ca: Some("ca.crt".to_string()),
+            allowed_subjects: vec!["CN=backup-agent".to_string()],
+        });
+        let auth = AuthService::new(config.clone());
+
+        assert!(auth.authorize_certificate_subject(Some("CN=backup-agent")).is_ok());
+
+        let result = auth.authorize_certificate_subject(Some("CN=intruder"));
+        assert_eq!(result.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+        let result = auth.authorize_certificate_subject(None);
+        assert_eq!(result.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[test]
+    fn test_api_key_hash_roundtrip() {
+        let stored = hash_api_key("s3cret");
+
+        assert!(stored.starts_with("sha256:"));
+        assert!(is_valid_api_key_hash(&stored));
+        assert!(verify_api_key("s3cret", &stored));
+        assert!(!verify_api_key("s3cret ", &stored));
+        assert!(!verify_api_key("", &stored));
+
+        // Salts are random, so the same key never hashes the same way twice
+        assert_ne!(stored, hash_api_key("s3cret"));
+    }
+
+    #[test]
+    fn test_api_key_hash_format() {
+        assert!(!is_valid_api_key_hash("s3cret"));
+        assert!(!is_valid_api_key_hash("md5:00:00"));
+        assert!(!is_valid_api_key_hash("sha256:zz:00"));
+        assert!(!is_valid_api_key_hash("sha256:0011:0011"));
+        assert!(!verify_api_key("s3cret", "s3cret"));
+    }
+
+    #[test]
+    fn test_authenticate_api_key() {
+        let mut config = create_test_config();
+        let auth = AuthService::new(config.clone());
+
+        // Token auth is disabled when no clients are configured
+        assert_eq!(auth.authenticate_api_key(&Request::new(())).unwrap(), None);
+
+        config.clients = vec![
+            crate::config::ApiClientConfig {
+                name: "backup-agent".to_string(),
+                api_key_hash: hash_api_key("backup-key"),
+            },
+            crate::config::ApiClientConfig {
+                name: "ci".to_string(),
+                api_key_hash: hash_api_key("ci-key"),
+            },
+        ];
+        let auth = AuthService::new(config.clone());
+
+        let with_key = |key: &str| {
+            let mut request = Request::new(());
+            request.metadata_mut().insert("authorization", format!("Bearer {}", key).parse().unwrap());
+            request
+        };
+
+        assert_eq!(auth.authenticate_api_key(&with_key("ci-key")).unwrap(), Some("ci".to_string()));
+        assert_eq!(auth.authenticate_api_key(&with_key("backup-key")).unwrap(), Some("backup-agent".to_string()));
+
+        let result = auth.authenticate_api_key(&with_key("wrong-key"));
+        assert_eq!(result.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+        let result = auth.authenticate_api_key(&Request::new(()));
+        assert_eq!(result.unwrap_err().message(), "API key required");
+
+        cleanup_test_dirs(&config);
+    }
+
     #[test]
     fn test_directory_access_read_operations() {
         let config = create_test_config();
         let auth = AuthService::new(config.clone());
 
         // Test read access to read-only directory
-        let result = auth.check_directory_access("docs", "read");
+        let result = auth.check_directory_access(&local_identity(), "docs", "", Operation::Read);
         assert!(result.is_ok());
 
         // Test read access to read-write directory
-        let result = auth.check_directory_access("workspace", "read");
+        let result = auth.check_directory_access(&local_identity(), "workspace", "", Operation::Read);
         assert!(result.is_ok());
 
         cleanup_test_dirs(&config);
@@ -141,23 +524,128 @@ mod tests {
         let auth = AuthService::new(config.clone());
 
         // Test write access to read-only directory (should fail)
-        let result = auth.check_directory_access("docs", "write");
+        let result = auth.check_directory_access(&local_identity(), "docs", "", Operation::Write);
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("Write operation not allowed"));
 
         // Test write access to read-write directory (should succeed)
-        let result = auth.check_directory_access("workspace", "write");
+        let result = auth.check_directory_access(&local_identity(), "workspace", "", Operation::Write);
         assert!(result.is_ok());
 
         cleanup_test_dirs(&config);
     }
 
+    #[test]
+    fn test_directory_access_delete_operations() {
+        let config = create_test_config();
+        let auth = AuthService::new(config.clone());
+
+        let result = auth.check_directory_access(&local_identity(), "docs", "file.txt", Operation::Delete);
+        assert!(result.unwrap_err().to_string().contains("Delete operation not allowed"));
+
+        let result = auth.check_directory_access(&local_identity(), "workspace", "file.txt", Operation::Delete);
+        assert!(result.is_ok());
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[test]
+    fn test_directory_access_acl() {
+        let mut config = create_test_config();
+        config.directories[1].acl = vec![
+            AclRule {
+                path: String::new(),
+                clients: vec!["*".to_string()],
+                ips: vec![],
+                allow: vec![Operation::Read],
+            },
+            AclRule {
+                path: String::new(),
+                clients: vec!["team-a".to_string()],
+                ips: vec!["192.168.1.0/24".to_string()],
+                allow: vec![Operation::Read, Operation::Write],
+            },
+            AclRule {
+                path: "finance".to_string(),
+                clients: vec!["finance".to_string()],
+                ips: vec![],
+                allow: vec![Operation::Read, Operation::Write, Operation::Delete],
+            },
+        ];
+        let auth = AuthService::new(config.clone());
+
+        let team_a = named_identity("team-a", "10.0.0.1");
+        let finance = named_identity("finance", "10.0.0.2");
+        let lan_host = local_identity_at("192.168.1.50");
+        let anyone = named_identity("ops", "10.0.0.3");
+
+        let check = |identity: &CallerIdentity, path: &str, op: Operation| {
+            auth.check_directory_access(identity, "workspace", path, op).is_ok()
+        };
+
+        // Root rules: everyone reads, team-a and the LAN range may also write
+        assert!(check(&anyone, "notes.txt", Operation::Read));
+        assert!(!check(&anyone, "notes.txt", Operation::Write));
+        assert!(check(&team_a, "notes.txt", Operation::Write));
+        assert!(check(&lan_host, "notes.txt", Operation::Write));
+        assert!(!check(&team_a, "notes.txt", Operation::Delete));
+
+        // The more specific finance/ rule replaces the root rules entirely
+        assert!(check(&finance, "finance/q3.csv", Operation::Delete));
+        assert!(!check(&team_a, "finance/q3.csv", Operation::Read));
+        assert!(!check(&anyone, "finance", Operation::Read));
+        assert!(check(&anyone, "finance-public/q3.csv", Operation::Read));
+
+        // Directories without ACLs keep their directory-wide permissions
+        assert!(auth.check_directory_access(&anyone, "docs", "", Operation::Read).is_ok());
+
+        let result = auth.check_directory_access(&team_a, "workspace", "finance/q3.csv", Operation::Read);
+        assert!(result.unwrap_err().to_string().contains("read access to 'workspace/finance/q3.csv' denied for team-a"));
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[test]
+    fn test_read_only_directory_caps_acl_grants() {
+        let mut config = create_test_config();
+        config.directories[0].acl = vec![AclRule {
+            path: String::new(),
+            clients: vec!["*".to_string()],
+            ips: vec![],
+            allow: vec![Operation::Read, Operation::Write],
+        }];
+        let auth = AuthService::new(config.clone());
+
+        let result = auth.check_directory_access(&local_identity(), "docs", "file.txt", Operation::Write);
+        assert!(result.unwrap_err().to_string().contains("Write operation not allowed on read-only directory"));
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[test]
+    fn test_can_see_directory() {
+        let mut config = create_test_config();
+        config.directories[1].acl = vec![AclRule {
+            path: "finance".to_string(),
+            clients: vec!["finance".to_string()],
+            ips: vec![],
+            allow: vec![Operation::Read],
+        }];
+        let auth = AuthService::new(config.clone());
+
+        assert!(auth.can_see_directory(&local_identity(), &config.directories[0]));
+        assert!(!auth.can_see_directory(&local_identity(), &config.directories[1]));
+        assert!(auth.can_see_directory(&named_identity("finance", "10.0.0.1"), &config.directories[1]));
+
+        cleanup_test_dirs(&config);
+    }
+
     #[test]
     fn test_directory_access_nonexistent_directory() {
         let config = create_test_config();
         let auth = AuthService::new(config.clone());
 
-        let result = auth.check_directory_access("nonexistent", "read");
+        let result = auth.check_directory_access(&local_identity(), "nonexistent", "", Operation::Read);
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("Directory 'nonexistent' not found"));
 
@@ -183,6 +671,11 @@ mod tests {
         assert!(auth.validate_path("/etc/passwd").is_err());
         assert!(auth.validate_path("\\Windows\\System32").is_err());
 
+        // Names of upload temporary files
+        assert!(auth.validate_path(".file.txt.0123456789abcdef.upload").is_err());
+        assert!(auth.validate_path("subdir/.file.txt.0123456789abcdef.upload/x").is_err());
+        assert!(auth.validate_path(".file.txt.upload").is_ok());
+
         cleanup_test_dirs(&config);
     }
 
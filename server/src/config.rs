@@ -1,5 +1,6 @@
 use common::FileServerError;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::IpAddr;
 use std::path::PathBuf;
 use ipnet::IpNet;
@@ -8,6 +9,13 @@ use ipnet::IpNet;
 pub struct ServerConfig {
     pub server: ServerSettings,
     pub directories: Vec<DirectoryConfig>,
+    /// Rate limits on transfers
+    pub limits: Option<LimitsConfig>,
+    /// Record of every call made to the server
+    pub audit: Option<AuditConfig>,
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub clients: Vec<ApiClientConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,13 +24,320 @@ pub struct ServerSettings {
     pub allowed_ips: Vec<String>,
     pub user: Option<String>,
     pub group: Option<String>,
+    /// Idle time after which an unfinished upload session is discarded
+    #[serde(default = "default_upload_session_timeout")]
+    pub upload_session_timeout_seconds: u64,
+    /// Upload sessions one client may have open at once
+    #[serde(default = "default_max_upload_sessions_per_client")]
+    pub max_upload_sessions_per_client: usize,
+    /// Most entries a single Find request may return
+    #[serde(default = "default_max_find_results")]
+    pub max_find_results: u32,
+    /// Most matching lines a single Grep request may return
+    #[serde(default = "default_max_grep_matches")]
+    pub max_grep_matches: u32,
+    /// Most file content a single Grep request may read
+    #[serde(default = "default_max_grep_bytes")]
+    pub max_grep_bytes: u64,
+    /// Most directory entries a single Grep request may look at
+    #[serde(default = "default_max_grep_entries")]
+    pub max_grep_entries: u64,
+    /// Most connections served at once; further clients wait to be accepted
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    /// Most concurrent HTTP/2 streams, that is calls, on one connection
+    #[serde(default)]
+    pub max_concurrent_streams: Option<u32>,
+    /// Most calls of one connection handled at once; the rest are queued
+    #[serde(default)]
+    pub concurrency_limit_per_connection: Option<usize>,
+    /// Interval of HTTP/2 pings checking that idle connections are alive
+    #[serde(default)]
+    pub keepalive_interval_seconds: Option<u64>,
+    /// Time to wait for a ping to be answered before closing the connection
+    #[serde(default)]
+    pub keepalive_timeout_seconds: Option<u64>,
+    /// Largest message accepted from clients (tonic's default is 4 MiB)
+    #[serde(default)]
+    pub max_receive_message_size: Option<usize>,
+    /// Largest message sent to clients
+    #[serde(default)]
+    pub max_send_message_size: Option<usize>,
+    /// Most Read, Write and upload streams served at once across all clients
+    #[serde(default)]
+    pub max_concurrent_transfers: Option<usize>,
+    /// How long a transfer waits for one of the `max_concurrent_transfers`
+    /// slots before it is refused; 0 refuses it right away
+    #[serde(default = "default_transfer_queue_timeout")]
+    pub transfer_queue_timeout_seconds: u64,
 }
 
+fn default_upload_session_timeout() -> u64 {
+    3600
+}
+
+fn default_max_upload_sessions_per_client() -> usize {
+    16
+}
+
+fn default_max_find_results() -> u32 {
+    10_000
+}
+
+fn default_max_grep_matches() -> u32 {
+    1000
+}
+
+fn default_max_grep_bytes() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_max_grep_entries() -> u64 {
+    1_000_000
+}
+
+fn default_transfer_queue_timeout() -> u64 {
+    30
+}
+
+/// Smallest message size limit, leaving room for a data chunk and for
+/// batches of find and grep results
+const MIN_MESSAGE_SIZE: usize = 1024 * 1024;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DirectoryConfig {
     pub name: String,
     pub path: String,
     pub permissions: String,
+    /// Access rules; without any, `permissions` applies to every caller
+    #[serde(default)]
+    pub acl: Vec<AclRule>,
+    /// Whether directories can be deleted together with their contents
+    #[serde(default = "default_allow_recursive_delete")]
+    pub allow_recursive_delete: bool,
+    /// Move deleted items to a recycle bin instead of removing them
+    pub trash: Option<TrashConfig>,
+    /// Which symbolic links may be followed when resolving paths
+    #[serde(default)]
+    pub follow_symlinks: FollowSymlinks,
+    /// Whether clients may create symbolic and hard links
+    #[serde(default)]
+    pub allow_links: bool,
+    /// Whether clients may change modes, times and ownership
+    #[serde(default)]
+    pub allow_set_attributes: bool,
+    /// Limits on what the directory may hold
+    pub quota: Option<QuotaConfig>,
+    /// Which files clients may write
+    pub file_policy: Option<FilePolicyConfig>,
+}
+
+fn default_allow_recursive_delete() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FollowSymlinks {
+    /// Refuse paths that go through a symbolic link
+    Never,
+    /// Follow links whose target stays inside the directory
+    #[default]
+    WithinRoot,
+    /// Follow every link, wherever it leads
+    Always,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashConfig {
+    /// Days a deleted item is kept before it is purged
+    #[serde(default = "default_trash_retention_days")]
+    pub retention_days: u64,
+}
+
+fn default_trash_retention_days() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaConfig {
+    #[serde(flatten)]
+    pub limits: QuotaLimits,
+    /// Limits on the files each client has written, by client name or
+    /// certificate subject; "*" applies to clients not listed
+    #[serde(default)]
+    pub clients: HashMap<String, QuotaLimits>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaLimits {
+    /// Total size of the files, in bytes
+    pub max_bytes: Option<u64>,
+    /// Number of files, directories and links
+    pub max_files: Option<u64>,
+}
+
+impl QuotaConfig {
+    /// Limits for the files written by `client`, if any apply
+    pub fn client_limits(&self, client: &str) -> Option<&QuotaLimits> {
+        self.clients.get(client).or_else(|| self.clients.get("*"))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FilePolicyConfig {
+    /// Largest file that may be written, in bytes
+    pub max_file_size: Option<u64>,
+    /// Extensions files must have, e.g. ["jpg", "pdf"]; any if empty
+    #[serde(default)]
+    pub allowed_extensions: Vec<String>,
+    /// Extensions files may not have
+    #[serde(default)]
+    pub denied_extensions: Vec<String>,
+    /// Check that a file's first bytes match its extension
+    #[serde(default)]
+    pub sniff_content: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Read,
+    Write,
+    Delete,
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operation::Read => write!(f, "read"),
+            Operation::Write => write!(f, "write"),
+            Operation::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+/// Grants operations under a path prefix of a directory to a set of callers.
+/// For a given path only the rules with the longest matching prefix apply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AclRule {
+    /// Path prefix inside the directory; empty for the whole directory
+    #[serde(default)]
+    pub path: String,
+    /// Client names or certificate subjects; "*" matches any caller
+    #[serde(default)]
+    pub clients: Vec<String>,
+    /// Caller IP addresses or CIDR ranges
+    #[serde(default)]
+    pub ips: Vec<String>,
+    pub allow: Vec<Operation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM-encoded server certificate chain
+    pub cert: String,
+    /// PEM-encoded private key for `cert`
+    pub key: String,
+    /// PEM-encoded CA used to verify client certificates; enables mutual TLS
+    pub client_ca: Option<String>,
+    /// Client certificate subjects allowed to connect (requires `client_ca`)
+    #[serde(default)]
+    pub allowed_subjects: Vec<String>,
+}
+
+/// Where the audit log goes: a JSON line per call, to a file rotated by size,
+/// to syslog, or both
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// File the records are appended to
+    pub path: Option<String>,
+    /// Size at which the file is rotated to `<path>.1`
+    #[serde(default = "default_audit_max_file_size")]
+    pub max_file_size: u64,
+    /// Rotated files kept, `<path>.1` being the most recent
+    #[serde(default = "default_audit_max_files")]
+    pub max_files: u32,
+    /// Also send the records to the system log
+    #[serde(default)]
+    pub syslog: bool,
+}
+
+fn default_audit_max_file_size() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_audit_max_files() -> u32 {
+    5
+}
+
+impl AuditConfig {
+    fn validate(&self) -> Result<(), FileServerError> {
+        if self.path.is_none() && !self.syslog {
+            return Err(FileServerError::ConfigError("The audit log needs a path or syslog".to_string()));
+        }
+        if self.max_file_size == 0 || self.max_files == 0 {
+            return Err(FileServerError::ConfigError("Audit log rotation settings cannot be 0".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Token-bucket limits on the Read and Write streams of each caller. Callers
+/// are told apart by client name or certificate subject, or by IP address
+/// when they have neither.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LimitsConfig {
+    #[serde(flatten)]
+    pub rates: RateLimits,
+    /// Limits replacing the ones above for particular clients, by client
+    /// name or certificate subject
+    #[serde(default)]
+    pub clients: HashMap<String, RateLimits>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimits {
+    /// Read and Write calls a caller may start per second
+    pub requests_per_second: Option<u32>,
+    /// Calls that may be started at once after a quiet spell; defaults to
+    /// `requests_per_second`
+    pub burst: Option<u32>,
+    /// Bytes per second sent to a caller across its Read streams
+    pub read_bytes_per_second: Option<u64>,
+    /// Bytes per second accepted from a caller across its Write streams
+    pub write_bytes_per_second: Option<u64>,
+}
+
+impl LimitsConfig {
+    /// Limits for the caller known by `name`, or by its IP address if `None`
+    pub fn rates_for(&self, name: Option<&str>) -> &RateLimits {
+        name.and_then(|name| self.clients.get(name)).unwrap_or(&self.rates)
+    }
+}
+
+impl RateLimits {
+    fn validate(&self) -> Result<(), FileServerError> {
+        let zero = self.requests_per_second == Some(0)
+            || self.burst == Some(0)
+            || self.read_bytes_per_second == Some(0)
+            || self.write_bytes_per_second == Some(0);
+        if zero {
+            return Err(FileServerError::ConfigError("Rate limits cannot be 0".to_string()));
+        }
+        if self.burst.is_some() && self.requests_per_second.is_none() {
+            return Err(FileServerError::ConfigError("A burst requires requests_per_second".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// A named client that authenticates with an API key. Generate the hash
+/// with `fileserver-server --hash-api-key`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiClientConfig {
+    pub name: String,
+    pub api_key_hash: String,
 }
 
 impl ServerConfig {
@@ -40,6 +355,47 @@ impl ServerConfig {
             return Err(FileServerError::ConfigError("Port cannot be 0".to_string()));
         }
 
+        if self.server.upload_session_timeout_seconds == 0 {
+            return Err(FileServerError::ConfigError("Upload session timeout cannot be 0".to_string()));
+        }
+
+        if self.server.max_upload_sessions_per_client == 0 {
+            return Err(FileServerError::ConfigError("Maximum upload sessions per client cannot be 0".to_string()));
+        }
+
+        if self.server.max_find_results == 0 {
+            return Err(FileServerError::ConfigError("Maximum find results cannot be 0".to_string()));
+        }
+
+        if self.server.max_grep_matches == 0 || self.server.max_grep_bytes == 0 || self.server.max_grep_entries == 0 {
+            return Err(FileServerError::ConfigError("Grep limits cannot be 0".to_string()));
+        }
+
+        let server = &self.server;
+        let zero_limit = server.max_connections == Some(0)
+            || server.max_concurrent_streams == Some(0)
+            || server.concurrency_limit_per_connection == Some(0)
+            || server.max_concurrent_transfers == Some(0);
+        if zero_limit {
+            return Err(FileServerError::ConfigError("Concurrency limits cannot be 0".to_string()));
+        }
+
+        if server.keepalive_interval_seconds == Some(0) || server.keepalive_timeout_seconds == Some(0) {
+            return Err(FileServerError::ConfigError("Keepalive settings cannot be 0".to_string()));
+        }
+        if server.keepalive_timeout_seconds.is_some() && server.keepalive_interval_seconds.is_none() {
+            return Err(FileServerError::ConfigError(
+                "A keepalive timeout requires keepalive_interval_seconds".to_string()
+            ));
+        }
+
+        let message_sizes = [server.max_receive_message_size, server.max_send_message_size];
+        if message_sizes.iter().flatten().any(|size| *size < MIN_MESSAGE_SIZE) {
+            return Err(FileServerError::ConfigError(format!(
+                "Message size limits must be at least {} bytes", MIN_MESSAGE_SIZE
+            )));
+        }
+
         for ip_str in &self.server.allowed_ips {
             if !Self::is_valid_ip_or_cidr(ip_str) {
                 return Err(FileServerError::ConfigError(
@@ -48,6 +404,39 @@ impl ServerConfig {
             }
         }
 
+        if let Some(tls) = &self.tls {
+            tls.validate()?;
+        }
+
+        if let Some(audit) = &self.audit {
+            audit.validate()?;
+        }
+
+        if let Some(limits) = &self.limits {
+            limits.rates.validate()?;
+            for rates in limits.clients.values() {
+                rates.validate()?;
+            }
+        }
+
+        for (i, client) in self.clients.iter().enumerate() {
+            if client.name.is_empty() {
+                return Err(FileServerError::ConfigError("Client name cannot be empty".to_string()));
+            }
+
+            if self.clients[..i].iter().any(|c| c.name == client.name) {
+                return Err(FileServerError::ConfigError(
+                    format!("Duplicate client name: {}", client.name)
+                ));
+            }
+
+            if !crate::auth::is_valid_api_key_hash(&client.api_key_hash) {
+                return Err(FileServerError::ConfigError(
+                    format!("Invalid api_key_hash for client '{}'", client.name)
+                ));
+            }
+        }
+
         for dir in &self.directories {
             let path = PathBuf::from(&dir.path);
             if !path.exists() {
@@ -62,17 +451,80 @@ impl ServerConfig {
                     format!("Invalid permissions '{}'. Must be 'read-only' or 'read-write'", dir.permissions)
                 )),
             }
+
+            for rule in &dir.acl {
+                rule.validate(&dir.name)?;
+            }
+
+            if let Some(trash) = &dir.trash {
+                if dir.permissions != "read-write" {
+                    return Err(FileServerError::ConfigError(
+                        format!("Trash requires a read-write directory: {}", dir.name)
+                    ));
+                }
+                if trash.retention_days == 0 {
+                    return Err(FileServerError::ConfigError(
+                        format!("Trash retention cannot be 0 days: {}", dir.name)
+                    ));
+                }
+            }
+
+            if dir.allow_links && dir.permissions != "read-write" {
+                return Err(FileServerError::ConfigError(
+                    format!("Links require a read-write directory: {}", dir.name)
+                ));
+            }
+
+            if dir.allow_set_attributes && dir.permissions != "read-write" {
+                return Err(FileServerError::ConfigError(
+                    format!("Setting attributes requires a read-write directory: {}", dir.name)
+                ));
+            }
+
+            if let Some(quota) = &dir.quota {
+                if dir.permissions != "read-write" {
+                    return Err(FileServerError::ConfigError(
+                        format!("Quotas require a read-write directory: {}", dir.name)
+                    ));
+                }
+                let limits = std::iter::once(&quota.limits).chain(quota.clients.values());
+                if limits.flat_map(|l| [l.max_bytes, l.max_files]).any(|max| max == Some(0)) {
+                    return Err(FileServerError::ConfigError(
+                        format!("Quota limits cannot be 0: {}", dir.name)
+                    ));
+                }
+            }
+
+            if let Some(policy) = &dir.file_policy {
+                if dir.permissions != "read-write" {
+                    return Err(FileServerError::ConfigError(
+                        format!("File policies require a read-write directory: {}", dir.name)
+                    ));
+                }
+                if policy.max_file_size == Some(0) {
+                    return Err(FileServerError::ConfigError(
+                        format!("Maximum file size cannot be 0: {}", dir.name)
+                    ));
+                }
+                let normalize = |extension: &String| extension.trim_start_matches('.').to_lowercase();
+                let denied: Vec<String> = policy.denied_extensions.iter().map(normalize).collect();
+                if let Some(extension) = policy.allowed_extensions.iter().map(normalize).find(|e| denied.contains(e)) {
+                    return Err(FileServerError::ConfigError(
+                        format!("Extension '{}' is both allowed and denied: {}", extension, dir.name)
+                    ));
+                }
+            }
         }
 
         Ok(())
     }
 
     fn is_valid_ip_or_cidr(ip_str: &str) -> bool {
-        if let Ok(_) = ip_str.parse::<IpAddr>() {
+        if ip_str.parse::<IpAddr>().is_ok() {
             return true;
         }
         
-        if let Ok(_) = ip_str.parse::<IpNet>() {
+        if ip_str.parse::<IpNet>().is_ok() {
             return true;
         }
         
@@ -94,11 +546,107 @@ impl ServerConfig {
         false
     }
 
+    pub fn token_auth_enabled(&self) -> bool {
+        !self.clients.is_empty()
+    }
+
     pub fn get_directory(&self, name: &str) -> Option<&DirectoryConfig> {
         self.directories.iter().find(|d| d.name == name)
     }
 }
 
+impl DirectoryConfig {
+    /// Operations the directory permits at all, regardless of ACLs
+    pub fn allows(&self, operation: Operation) -> bool {
+        match operation {
+            Operation::Read => true,
+            Operation::Write | Operation::Delete => self.permissions == "read-write",
+        }
+    }
+}
+
+impl AclRule {
+    fn validate(&self, dir_name: &str) -> Result<(), FileServerError> {
+        if self.clients.is_empty() && self.ips.is_empty() {
+            return Err(FileServerError::ConfigError(
+                format!("ACL rule for '{}/{}' must list clients or ips", dir_name, self.path)
+            ));
+        }
+
+        if self.path.starts_with('/') || self.path.split('/').any(|c| c == "..") {
+            return Err(FileServerError::ConfigError(
+                format!("Invalid ACL path '{}' in directory '{}'", self.path, dir_name)
+            ));
+        }
+
+        for ip_str in &self.ips {
+            if !ServerConfig::is_valid_ip_or_cidr(ip_str) {
+                return Err(FileServerError::ConfigError(
+                    format!("Invalid IP address or CIDR in ACL: {}", ip_str)
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Number of path components in the prefix, used to pick the most specific rules
+    pub fn prefix_depth(&self) -> usize {
+        self.path.split('/').filter(|c| !c.is_empty()).count()
+    }
+
+    /// Whether `file_path` (relative to the directory root) lies under this rule's prefix
+    pub fn covers(&self, file_path: &str) -> bool {
+        let mut path = file_path.split('/').filter(|c| !c.is_empty());
+        self.path
+            .split('/')
+            .filter(|c| !c.is_empty())
+            .all(|prefix| path.next() == Some(prefix))
+    }
+
+    /// Whether this rule's prefix lies strictly below `file_path`, governing
+    /// part of the tree there
+    pub fn lies_below(&self, file_path: &str) -> bool {
+        let mut prefix = self.path.split('/').filter(|c| !c.is_empty());
+        file_path.split('/').filter(|c| !c.is_empty()).all(|c| prefix.next() == Some(c)) && prefix.next().is_some()
+    }
+
+    pub fn matches_caller(&self, names: &[&str], ip: &IpAddr) -> bool {
+        let name_match = self.clients.iter().any(|c| c == "*" || names.contains(&c.as_str()));
+        let ip_match = self.ips.iter().any(|allowed| {
+            if let Ok(allowed_ip) = allowed.parse::<IpAddr>() {
+                allowed_ip == *ip
+            } else if let Ok(net) = allowed.parse::<IpNet>() {
+                net.contains(ip)
+            } else {
+                false
+            }
+        });
+        name_match || ip_match
+    }
+}
+
+impl TlsConfig {
+    pub fn validate(&self) -> Result<(), FileServerError> {
+        let files = [Some(&self.cert), Some(&self.key), self.client_ca.as_ref()];
+        for file in files.into_iter().flatten() {
+            if !PathBuf::from(file).is_file() {
+                return Err(FileServerError::ConfigError(
+                    format!("TLS file does not exist: {}", file)
+                ));
+            }
+        }
+
+        if !self.allowed_subjects.is_empty() && self.client_ca.is_none() {
+            return Err(FileServerError::ConfigError(
+                "tls.allowed_subjects requires tls.client_ca to be set".to_string()
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -122,10 +670,17 @@ permissions = "read-only"
         
         assert_eq!(config.server.port, 8080);
         assert_eq!(config.server.allowed_ips, vec!["127.0.0.1", "192.168.1.0/24"]);
+        assert_eq!(config.server.upload_session_timeout_seconds, 3600);
+        assert_eq!(config.server.max_upload_sessions_per_client, 16);
+        assert_eq!(config.server.max_find_results, 10_000);
+        assert_eq!(config.server.max_grep_matches, 1000);
+        assert_eq!(config.server.max_grep_bytes, 1024 * 1024 * 1024);
+        assert_eq!(config.server.max_grep_entries, 1_000_000);
         assert_eq!(config.directories.len(), 1);
         assert_eq!(config.directories[0].name, "test_dir");
         assert_eq!(config.directories[0].path, "/tmp");
         assert_eq!(config.directories[0].permissions, "read-only");
+        assert!(config.directories[0].allow_recursive_delete);
     }
 
     #[test]
@@ -136,8 +691,27 @@ permissions = "read-only"
                 allowed_ips: vec!["127.0.0.1".to_string(), "192.168.1.0/24".to_string()],
                 user: None,
                 group: None,
+                upload_session_timeout_seconds: 3600,
+                max_upload_sessions_per_client: 16,
+                max_find_results: 10_000,
+                max_grep_matches: 1000,
+                max_grep_bytes: 1024 * 1024 * 1024,
+                max_grep_entries: 1_000_000,
+                max_connections: None,
+                max_concurrent_streams: None,
+                concurrency_limit_per_connection: None,
+                keepalive_interval_seconds: None,
+                keepalive_timeout_seconds: None,
+                max_receive_message_size: None,
+                max_send_message_size: None,
+                max_concurrent_transfers: None,
+                transfer_queue_timeout_seconds: 30,
             },
             directories: vec![],
+            limits: None,
+            audit: None,
+            tls: None,
+            clients: vec![],
         };
 
         // Test localhost
@@ -161,19 +735,54 @@ permissions = "read-only"
                 allowed_ips: vec!["127.0.0.1".to_string()],
                 user: None,
                 group: None,
+                upload_session_timeout_seconds: 3600,
+                max_upload_sessions_per_client: 16,
+                max_find_results: 10_000,
+                max_grep_matches: 1000,
+                max_grep_bytes: 1024 * 1024 * 1024,
+                max_grep_entries: 1_000_000,
+                max_connections: None,
+                max_concurrent_streams: None,
+                concurrency_limit_per_connection: None,
+                keepalive_interval_seconds: None,
+                keepalive_timeout_seconds: None,
+                max_receive_message_size: None,
+                max_send_message_size: None,
+                max_concurrent_transfers: None,
+                transfer_queue_timeout_seconds: 30,
             },
             directories: vec![
                 DirectoryConfig {
                     name: "docs".to_string(),
                     path: "/tmp/docs".to_string(),
                     permissions: "read-only".to_string(),
+                    acl: vec![],
+                    allow_recursive_delete: true,
+                    trash: None,
+                    follow_symlinks: FollowSymlinks::WithinRoot,
+                    allow_links: false,
+                    allow_set_attributes: false,
+                    quota: None,
+                    file_policy: None,
                 },
                 DirectoryConfig {
                     name: "workspace".to_string(),
                     path: "/tmp/workspace".to_string(),
                     permissions: "read-write".to_string(),
+                    acl: vec![],
+                    allow_recursive_delete: true,
+                    trash: None,
+                    follow_symlinks: FollowSymlinks::WithinRoot,
+                    allow_links: false,
+                    allow_set_attributes: false,
+                    quota: None,
+                    file_policy: None,
                 },
             ],
+            limits: None,
+            audit: None,
+            tls: None,
+            clients: vec![],
         };
 
         assert!(config.get_directory("docs").is_some());
@@ -192,8 +801,27 @@ permissions = "read-only"
                 allowed_ips: vec!["127.0.0.1".to_string()],
                 user: None,
                 group: None,
+                upload_session_timeout_seconds: 3600,
+                max_upload_sessions_per_client: 16,
+                max_find_results: 10_000,
+                max_grep_matches: 1000,
+                max_grep_bytes: 1024 * 1024 * 1024,
+                max_grep_entries: 1_000_000,
+                max_connections: None,
+                max_concurrent_streams: None,
+                concurrency_limit_per_connection: None,
+                keepalive_interval_seconds: None,
+                keepalive_timeout_seconds: None,
+                max_receive_message_size: None,
+                max_send_message_size: None,
+                max_concurrent_transfers: None,
+                transfer_queue_timeout_seconds: 30,
             },
             directories: vec![],
+            limits: None,
+            audit: None,
+            tls: None,
+            clients: vec![],
         };
 
         let result = config.validate();
@@ -213,12 +841,39 @@ permissions = "read-only"
                 allowed_ips: vec!["127.0.0.1".to_string()],
                 user: None,
                 group: None,
+                upload_session_timeout_seconds: 3600,
+                max_upload_sessions_per_client: 16,
+                max_find_results: 10_000,
+                max_grep_matches: 1000,
+                max_grep_bytes: 1024 * 1024 * 1024,
+                max_grep_entries: 1_000_000,
+                max_connections: None,
+                max_concurrent_streams: None,
+                concurrency_limit_per_connection: None,
+                keepalive_interval_seconds: None,
+                keepalive_timeout_seconds: None,
+                max_receive_message_size: None,
+                max_send_message_size: None,
+                max_concurrent_transfers: None,
+                transfer_queue_timeout_seconds: 30,
             },
             directories: vec![DirectoryConfig {
                 name: "test".to_string(),
                 path: temp_dir.to_string_lossy().to_string(),
                 permissions: "invalid".to_string(),
+                acl: vec![],
+                allow_recursive_delete: true,
+                trash: None,
+                follow_symlinks: FollowSymlinks::WithinRoot,
+                allow_links: false,
+                allow_set_attributes: false,
+                quota: None,
+                file_policy: None,
             }],
+            limits: None,
+            audit: None,
+            tls: None,
+            clients: vec![],
         };
 
         let result = config.validate();
@@ -229,6 +884,489 @@ permissions = "read-only"
         fs::remove_dir_all(&temp_dir).ok();
     }
 
+    #[test]
+    fn test_trash_config() {
+        let config_content = r#"
+[server]
+port = 8080
+allowed_ips = ["127.0.0.1"]
+
+[[directories]]
+name = "workspace"
+path = "/tmp"
+permissions = "read-write"
+
+[directories.trash]
+
+[[directories]]
+name = "archive"
+path = "/tmp"
+permissions = "read-only"
+
+[directories.trash]
+retention_days = 7
+        "#;
+
+        let config: ServerConfig = toml::from_str(config_content).unwrap();
+        assert_eq!(config.directories[0].trash.as_ref().unwrap().retention_days, 30);
+        assert_eq!(config.directories[1].trash.as_ref().unwrap().retention_days, 7);
+
+        let result = config.validate();
+        assert!(result.unwrap_err().to_string().contains("Trash requires a read-write directory"));
+    }
+
+    #[test]
+    fn test_symlink_config() {
+        let config_content = r#"
+[server]
+port = 8080
+allowed_ips = ["127.0.0.1"]
+
+[[directories]]
+name = "workspace"
+path = "/tmp"
+permissions = "read-write"
+
+[[directories]]
+name = "archive"
+path = "/tmp"
+permissions = "read-only"
+follow_symlinks = "never"
+        "#;
+
+        let config: ServerConfig = toml::from_str(config_content).unwrap();
+        assert_eq!(config.directories[0].follow_symlinks, FollowSymlinks::WithinRoot);
+        assert_eq!(config.directories[1].follow_symlinks, FollowSymlinks::Never);
+
+        let invalid = config_content.replace("\"never\"", "\"sometimes\"");
+        assert!(toml::from_str::<ServerConfig>(&invalid).is_err());
+
+        let mut config = config;
+        assert!(!config.directories[0].allow_links);
+        config.directories[0].allow_links = true;
+        assert!(config.validate().is_ok());
+        config.directories[1].allow_links = true;
+        assert!(config.validate().unwrap_err().to_string().contains("Links require a read-write directory"));
+    }
+
+    #[test]
+    fn test_quota_config() {
+        let config_content = r#"
+[server]
+port = 8080
+allowed_ips = ["127.0.0.1"]
+
+[[directories]]
+name = "uploads"
+path = "/tmp"
+permissions = "read-write"
+
+[directories.quota]
+max_bytes = 1073741824
+max_files = 10000
+
+[directories.quota.clients]
+"ci-runner" = { max_bytes = 104857600 }
+"*" = { max_bytes = 10485760, max_files = 100 }
+        "#;
+
+        let config: ServerConfig = toml::from_str(config_content).unwrap();
+        let quota = config.directories[0].quota.as_ref().unwrap();
+        assert_eq!(quota.limits, QuotaLimits { max_bytes: Some(1 << 30), max_files: Some(10_000) });
+        assert_eq!(quota.client_limits("ci-runner").unwrap().max_bytes, Some(100 << 20));
+        assert_eq!(quota.client_limits("ci-runner").unwrap().max_files, None);
+        assert_eq!(quota.client_limits("alice").unwrap().max_files, Some(100));
+        assert!(config.validate().is_ok());
+
+        let zero = config_content.replace("max_files = 100 ", "max_files = 0 ");
+        let config: ServerConfig = toml::from_str(&zero).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("Quota limits cannot be 0"));
+
+        let read_only = config_content.replace("\"read-write\"", "\"read-only\"");
+        let config: ServerConfig = toml::from_str(&read_only).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("Quotas require a read-write directory"));
+    }
+
+    #[test]
+    fn test_file_policy_config() {
+        let config_content = r#"
+[server]
+port = 8080
+allowed_ips = ["127.0.0.1"]
+
+[[directories]]
+name = "uploads"
+path = "/tmp"
+permissions = "read-write"
+
+[directories.file_policy]
+max_file_size = 52428800
+allowed_extensions = ["jpg", "png", "pdf"]
+sniff_content = true
+        "#;
+
+        let config: ServerConfig = toml::from_str(config_content).unwrap();
+        let policy = config.directories[0].file_policy.as_ref().unwrap();
+        assert_eq!(policy.max_file_size, Some(50 << 20));
+        assert_eq!(policy.allowed_extensions, ["jpg", "png", "pdf"]);
+        assert!(policy.denied_extensions.is_empty());
+        assert!(policy.sniff_content);
+        assert!(config.validate().is_ok());
+
+        let conflicting = config_content.replace("sniff_content", "denied_extensions = [\".PNG\"]\nsniff_content");
+        let config: ServerConfig = toml::from_str(&conflicting).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("Extension 'png' is both allowed and denied"));
+
+        let read_only = config_content.replace("\"read-write\"", "\"read-only\"");
+        let config: ServerConfig = toml::from_str(&read_only).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("File policies require a read-write directory"));
+    }
+
+    #[test]
+    fn test_concurrency_settings() {
+        let config_content = r#"
+[server]
+port = 8080
+allowed_ips = ["127.0.0.1"]
+max_connections = 100
+max_concurrent_streams = 32
+concurrency_limit_per_connection = 16
+keepalive_interval_seconds = 30
+keepalive_timeout_seconds = 10
+max_receive_message_size = 8388608
+max_concurrent_transfers = 64
+
+[[directories]]
+name = "data"
+path = "/tmp"
+permissions = "read-only"
+        "#;
+
+        let config: ServerConfig = toml::from_str(config_content).unwrap();
+        assert_eq!(config.server.max_connections, Some(100));
+        assert_eq!(config.server.max_concurrent_streams, Some(32));
+        assert_eq!(config.server.concurrency_limit_per_connection, Some(16));
+        assert_eq!(config.server.keepalive_interval_seconds, Some(30));
+        assert_eq!(config.server.keepalive_timeout_seconds, Some(10));
+        assert_eq!(config.server.max_receive_message_size, Some(8 << 20));
+        assert_eq!(config.server.max_send_message_size, None);
+        assert_eq!(config.server.max_concurrent_transfers, Some(64));
+        assert_eq!(config.server.transfer_queue_timeout_seconds, 30);
+        assert!(config.validate().is_ok());
+
+        let zero = config_content.replace("max_concurrent_streams = 32", "max_concurrent_streams = 0");
+        let config: ServerConfig = toml::from_str(&zero).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("Concurrency limits cannot be 0"));
+
+        let no_interval = config_content.replace("keepalive_interval_seconds = 30\n", "");
+        let config: ServerConfig = toml::from_str(&no_interval).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("requires keepalive_interval_seconds"));
+
+        let tiny = config_content.replace("8388608", "65536");
+        let config: ServerConfig = toml::from_str(&tiny).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("Message size limits must be at least"));
+    }
+
+    #[test]
+    fn test_audit_config() {
+        let config_content = r#"
+[server]
+port = 8080
+allowed_ips = ["127.0.0.1"]
+
+[[directories]]
+name = "data"
+path = "/tmp"
+permissions = "read-only"
+
+[audit]
+path = "/var/log/fileserver/audit.log"
+max_files = 10
+        "#;
+
+        let config: ServerConfig = toml::from_str(config_content).unwrap();
+        let audit = config.audit.as_ref().unwrap();
+        assert_eq!(audit.path.as_deref(), Some("/var/log/fileserver/audit.log"));
+        assert_eq!(audit.max_file_size, 100 << 20);
+        assert_eq!(audit.max_files, 10);
+        assert!(!audit.syslog);
+        assert!(config.validate().is_ok());
+
+        let syslog_only = config_content.replace("path = \"/var/log/fileserver/audit.log\"", "syslog = true");
+        let config: ServerConfig = toml::from_str(&syslog_only).unwrap();
+        assert!(config.audit.as_ref().unwrap().path.is_none());
+        assert!(config.validate().is_ok());
+
+        let nowhere = config_content.replace("path = \"/var/log/fileserver/audit.log\"", "");
+        let config: ServerConfig = toml::from_str(&nowhere).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("needs a path or syslog"));
+
+        let no_files = config_content.replace("max_files = 10", "max_files = 0");
+        let config: ServerConfig = toml::from_str(&no_files).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("rotation settings cannot be 0"));
+    }
+
+    #[test]
+    fn test_limits_config() {
+        let config_content = r#"
+[server]
+port = 8080
+allowed_ips = ["127.0.0.1"]
+
+[[directories]]
+name = "data"
+path = "/tmp"
+permissions = "read-only"
+
+[limits]
+requests_per_second = 5
+burst = 20
+read_bytes_per_second = 10485760
+
+[limits.clients.backup]
+write_bytes_per_second = 52428800
+        "#;
+
+        let config: ServerConfig = toml::from_str(config_content).unwrap();
+        let limits = config.limits.as_ref().unwrap();
+        assert_eq!(limits.rates.requests_per_second, Some(5));
+        assert_eq!(limits.rates.burst, Some(20));
+        assert_eq!(limits.rates.read_bytes_per_second, Some(10 << 20));
+        assert_eq!(limits.rates.write_bytes_per_second, None);
+        assert_eq!(limits.rates_for(None), &limits.rates);
+        assert_eq!(limits.rates_for(Some("ci")), &limits.rates);
+        assert_eq!(limits.rates_for(Some("backup")).write_bytes_per_second, Some(50 << 20));
+        assert_eq!(limits.rates_for(Some("backup")).requests_per_second, None);
+        assert!(config.validate().is_ok());
+
+        let zero = config_content.replace("write_bytes_per_second = 52428800", "write_bytes_per_second = 0");
+        let config: ServerConfig = toml::from_str(&zero).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("Rate limits cannot be 0"));
+
+        let burst_only = config_content.replace("requests_per_second = 5\n", "");
+        let config: ServerConfig = toml::from_str(&burst_only).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("A burst requires requests_per_second"));
+    }
+
+    #[test]
+    fn test_tls_config_parsing() {
+        let config_content = r#"
+[server]
+port = 8080
+allowed_ips = ["127.0.0.1"]
+
+[tls]
+cert = "/etc/fileserver/server.crt"
+key = "/etc/fileserver/server.key"
+client_ca = "/etc/fileserver/ca.crt"
+allowed_subjects = ["CN=backup-agent"]
+
+[[directories]]
+name = "test_dir"
+path = "/tmp"
+permissions = "read-only"
+        "#;
+
+        let config: ServerConfig = toml::from_str(config_content).unwrap();
+        let tls = config.tls.unwrap();
+
+        assert_eq!(tls.cert, "/etc/fileserver/server.crt");
+        assert_eq!(tls.key, "/etc/fileserver/server.key");
+        assert_eq!(tls.client_ca.as_deref(), Some("/etc/fileserver/ca.crt"));
+        assert_eq!(tls.allowed_subjects, vec!["CN=backup-agent"]);
+    }
+
+    #[test]
+    fn test_config_validation_missing_tls_files() {
+        let config = ServerConfig {
+            server: ServerSettings {
+                port: 8080,
+                allowed_ips: vec!["127.0.0.1".to_string()],
+                user: None,
+                group: None,
+                upload_session_timeout_seconds: 3600,
+                max_upload_sessions_per_client: 16,
+                max_find_results: 10_000,
+                max_grep_matches: 1000,
+                max_grep_bytes: 1024 * 1024 * 1024,
+                max_grep_entries: 1_000_000,
+                max_connections: None,
+                max_concurrent_streams: None,
+                concurrency_limit_per_connection: None,
+                keepalive_interval_seconds: None,
+                keepalive_timeout_seconds: None,
+                max_receive_message_size: None,
+                max_send_message_size: None,
+                max_concurrent_transfers: None,
+                transfer_queue_timeout_seconds: 30,
+            },
+            directories: vec![],
+            limits: None,
+            audit: None,
+            tls: Some(TlsConfig {
+                cert: "/nonexistent/server.crt".to_string(),
+                key: "/nonexistent/server.key".to_string(),
+                client_ca: None,
+                allowed_subjects: vec![],
+            }),
+            clients: vec![],
+        };
+
+        let result = config.validate();
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("TLS file does not exist"));
+    }
+
+    #[test]
+    fn test_clients_config_parsing() {
+        let config_content = r#"
+[server]
+port = 8080
+allowed_ips = ["127.0.0.1"]
+
+[[clients]]
+name = "backup-agent"
+api_key_hash = "sha256:00112233445566778899aabbccddeeff:0000000000000000000000000000000000000000000000000000000000000000"
+
+[[directories]]
+name = "test_dir"
+path = "/tmp"
+permissions = "read-only"
+        "#;
+
+        let config: ServerConfig = toml::from_str(config_content).unwrap();
+
+        assert_eq!(config.clients.len(), 1);
+        assert_eq!(config.clients[0].name, "backup-agent");
+        assert!(config.token_auth_enabled());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_config_validation_clients() {
+        let mut config = ServerConfig {
+            server: ServerSettings {
+                port: 8080,
+                allowed_ips: vec!["127.0.0.1".to_string()],
+                user: None,
+                group: None,
+                upload_session_timeout_seconds: 3600,
+                max_upload_sessions_per_client: 16,
+                max_find_results: 10_000,
+                max_grep_matches: 1000,
+                max_grep_bytes: 1024 * 1024 * 1024,
+                max_grep_entries: 1_000_000,
+                max_connections: None,
+                max_concurrent_streams: None,
+                concurrency_limit_per_connection: None,
+                keepalive_interval_seconds: None,
+                keepalive_timeout_seconds: None,
+                max_receive_message_size: None,
+                max_send_message_size: None,
+                max_concurrent_transfers: None,
+                transfer_queue_timeout_seconds: 30,
+            },
+            directories: vec![],
+            limits: None,
+            audit: None,
+            tls: None,
+            clients: vec![ApiClientConfig {
+                name: "ci".to_string(),
+                api_key_hash: "plaintext-key".to_string(),
+            }],
+        };
+
+        let result = config.validate();
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Invalid api_key_hash for client 'ci'"));
+
+        config.clients[0].api_key_hash = crate::auth::hash_api_key("secret");
+        config.clients.push(config.clients[0].clone());
+
+        let result = config.validate();
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Duplicate client name: ci"));
+    }
+
+    #[test]
+    fn test_acl_config_parsing() {
+        let config_content = r#"
+[server]
+port = 8080
+allowed_ips = ["127.0.0.1"]
+
+[[directories]]
+name = "uploads"
+path = "/tmp"
+permissions = "read-write"
+
+[[directories.acl]]
+clients = ["*"]
+allow = ["read"]
+
+[[directories.acl]]
+path = "finance/"
+clients = ["finance-team"]
+ips = ["10.20.0.0/16"]
+allow = ["read", "write", "delete"]
+        "#;
+
+        let config: ServerConfig = toml::from_str(config_content).unwrap();
+        let acl = &config.directories[0].acl;
+
+        assert_eq!(acl.len(), 2);
+        assert_eq!(acl[0].path, "");
+        assert_eq!(acl[0].allow, vec![Operation::Read]);
+        assert_eq!(acl[1].clients, vec!["finance-team"]);
+        assert_eq!(acl[1].allow, vec![Operation::Read, Operation::Write, Operation::Delete]);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_acl_rule_matching() {
+        let rule = AclRule {
+            path: "finance/".to_string(),
+            clients: vec!["finance-team".to_string()],
+            ips: vec!["10.20.0.0/16".to_string()],
+            allow: vec![Operation::Read],
+        };
+
+        assert_eq!(rule.prefix_depth(), 1);
+        assert!(rule.covers("finance"));
+        assert!(rule.covers("finance/q3/report.csv"));
+        assert!(!rule.covers("finance2/report.csv"));
+        assert!(!rule.covers(""));
+
+        assert!(rule.lies_below(""));
+        assert!(!rule.lies_below("finance"));
+        assert!(!rule.lies_below("finance/q3"));
+        assert!(!rule.lies_below("fin"));
+
+        let outside: IpAddr = "192.168.1.1".parse().unwrap();
+        let inside: IpAddr = "10.20.1.1".parse().unwrap();
+        assert!(rule.matches_caller(&["finance-team"], &outside));
+        assert!(rule.matches_caller(&[], &inside));
+        assert!(!rule.matches_caller(&["ops"], &outside));
+    }
+
+    #[test]
+    fn test_acl_rule_validation() {
+        let mut rule = AclRule {
+            path: String::new(),
+            clients: vec![],
+            ips: vec![],
+            allow: vec![Operation::Read],
+        };
+        assert!(rule.validate("docs").unwrap_err().to_string().contains("must list clients or ips"));
+
+        rule.ips = vec!["not-an-ip".to_string()];
+        assert!(rule.validate("docs").unwrap_err().to_string().contains("Invalid IP address or CIDR in ACL"));
+
+        rule.ips = vec!["10.0.0.0/8".to_string()];
+        rule.path = "../etc".to_string();
+        assert!(rule.validate("docs").unwrap_err().to_string().contains("Invalid ACL path"));
+    }
+
     #[test]
     fn test_is_valid_ip_or_cidr() {
         assert!(ServerConfig::is_valid_ip_or_cidr("127.0.0.1"));
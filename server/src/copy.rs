@@ -0,0 +1,358 @@
+use common::{CopyProgress, FileServerError};
+use nix::errno::Errno;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Largest amount of data copied between progress reports
+const COPY_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+// FICLONE from linux/fs.h: share the source's extents instead of copying data
+nix::ioctl_write_int!(ficlone, 0x94, 9);
+
+enum EntryKind {
+    Directory,
+    File,
+    /// A link and its target, read when the copy is planned so the link
+    /// made is the one checked
+    Symlink(PathBuf),
+}
+
+struct CopyEntry {
+    relative: PathBuf,
+    kind: EntryKind,
+}
+
+/// A copy of a file or directory tree, walked up front so progress can be
+/// reported against totals. The copy is blocking and meant to run on the
+/// blocking thread pool.
+pub struct CopyJob {
+    source: PathBuf,
+    destination: PathBuf,
+    entries: Vec<CopyEntry>,
+    files_total: u64,
+    bytes_total: u64,
+}
+
+impl CopyJob {
+    /// Walks `source`. Symbolic links are copied as links, never followed.
+    /// Entries for which `include` returns false, given their path below
+    /// `source`, are left out along with everything below them.
+    pub fn plan(source: &Path, destination: &Path, include: impl Fn(&Path, bool) -> bool) -> Result<Self, FileServerError> {
+        let metadata = fs::symlink_metadata(source)
+            .map_err(|_| FileServerError::FileNotFound(source.to_string_lossy().to_string()))?;
+
+        let mut job = Self {
+            source: source.to_path_buf(),
+            destination: destination.to_path_buf(),
+            entries: Vec::new(),
+            files_total: 0,
+            bytes_total: 0,
+        };
+        job.add(PathBuf::new(), &metadata)?;
+
+        // Directories are listed before their contents, so they can be
+        // created in order
+        let mut pending = Vec::new();
+        if metadata.is_dir() {
+            pending.push(PathBuf::new());
+        }
+
+        while let Some(relative) = pending.pop() {
+            let mut children = fs::read_dir(job.source_path(&relative))?
+                .map(|entry| entry.map(|e| relative.join(e.file_name())))
+                .collect::<Result<Vec<_>, _>>()?;
+            children.sort();
+
+            for child in children {
+                let metadata = fs::symlink_metadata(job.source_path(&child))?;
+                if !include(&child, metadata.is_dir()) {
+                    continue;
+                }
+                if metadata.is_dir() {
+                    pending.push(child.clone());
+                }
+                job.add(child, &metadata)?;
+            }
+        }
+
+        Ok(job)
+    }
+
+    pub fn files_total(&self) -> u64 {
+        self.files_total
+    }
+
+    /// Files, directories and links to be copied
+    pub fn entries_total(&self) -> u64 {
+        self.entries.len() as u64
+    }
+
+    /// The symbolic links to be made, as their paths below the destination
+    /// and their targets
+    pub fn links(&self) -> Vec<(PathBuf, PathBuf)> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match &entry.kind {
+                EntryKind::Symlink(target) => Some((entry.relative.clone(), target.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn bytes_total(&self) -> u64 {
+        self.bytes_total
+    }
+
+    /// Performs the copy, calling `report` after every file and every
+    /// `COPY_CHUNK_SIZE` bytes. Returning `false` from `report` cancels the
+    /// copy. On failure nothing is left at the destination.
+    pub fn run(self, mut report: impl FnMut(&CopyProgress) -> bool) -> Result<CopyProgress, FileServerError> {
+        let mut progress = CopyProgress {
+            files_total: self.files_total,
+            bytes_total: self.bytes_total,
+            ..Default::default()
+        };
+
+        // A single file goes through a temporary file, so an existing
+        // destination is replaced atomically and never left half-written
+        if let [CopyEntry { kind: EntryKind::File, .. }] = self.entries.as_slice() {
+            progress.current_path = self.source.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let temp = temp_path(&self.destination);
+            let result = copy_file(&self.source, &temp, &mut progress, &mut report)
+                .and_then(|()| Ok(fs::rename(&temp, &self.destination)?));
+            if result.is_err() {
+                let _ = fs::remove_file(&temp);
+            }
+            result?;
+        } else {
+            // Only files can replace an existing destination, which also
+            // makes it safe to remove the destination after a failure
+            if fs::symlink_metadata(&self.destination).is_ok() {
+                return Err(FileServerError::AlreadyExists(self.destination.to_string_lossy().to_string()));
+            }
+
+            if let Err(e) = self.copy_entries(&mut progress, &mut report) {
+                let _ = fs::remove_dir_all(&self.destination).or_else(|_| fs::remove_file(&self.destination));
+                return Err(e);
+            }
+        }
+
+        if let Some(parent) = self.destination.parent() {
+            File::open(parent)?.sync_all()?;
+        }
+
+        Ok(progress)
+    }
+
+    fn copy_entries(&self, progress: &mut CopyProgress, report: &mut impl FnMut(&CopyProgress) -> bool) -> Result<(), FileServerError> {
+        for entry in &self.entries {
+            let from = self.source_path(&entry.relative);
+            let to = join(&self.destination, &entry.relative);
+            progress.current_path = entry.relative.to_string_lossy().to_string();
+
+            match &entry.kind {
+                EntryKind::Directory => {
+                    fs::create_dir(&to)?;
+                    fs::set_permissions(&to, fs::metadata(&from)?.permissions())?;
+                }
+                EntryKind::Symlink(target) => std::os::unix::fs::symlink(target, &to)?,
+                EntryKind::File => copy_file(&from, &to, progress, report)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn source_path(&self, relative: &Path) -> PathBuf {
+        join(&self.source, relative)
+    }
+
+    fn add(&mut self, relative: PathBuf, metadata: &fs::Metadata) -> Result<(), FileServerError> {
+        let kind = if metadata.is_dir() {
+            EntryKind::Directory
+        } else if metadata.file_type().is_symlink() {
+            EntryKind::Symlink(fs::read_link(self.source_path(&relative))?)
+        } else {
+            self.files_total += 1;
+            self.bytes_total += metadata.len();
+            EntryKind::File
+        };
+        self.entries.push(CopyEntry { relative, kind });
+        Ok(())
+    }
+}
+
+/// Joins without adding a trailing slash for the root of the copy
+fn join(base: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() {
+        base.to_path_buf()
+    } else {
+        base.join(relative)
+    }
+}
+
+fn temp_path(destination: &Path) -> PathBuf {
+    let name = destination.file_name().unwrap_or_default().to_string_lossy();
+    destination.with_file_name(format!(".{}.{:016x}.copy", name, rand::random::<u64>()))
+}
+
+/// Copies one file, preferring a reflink, then `copy_file_range`, then a
+/// plain read/write loop, and carries over the source's permission bits.
+fn copy_file(
+    from: &Path,
+    to: &Path,
+    progress: &mut CopyProgress,
+    report: &mut impl FnMut(&CopyProgress) -> bool,
+) -> Result<(), FileServerError> {
+    let mut source = File::open(from)?;
+    let metadata = source.metadata()?;
+    let mut destination = File::options().write(true).create_new(true).open(to)?;
+
+    let mut on_copied = |n: u64, progress: &mut CopyProgress| {
+        progress.bytes_copied += n;
+        if report(progress) {
+            Ok(())
+        } else {
+            Err(FileServerError::IoError(std::io::Error::new(std::io::ErrorKind::Interrupted, "Copy cancelled")))
+        }
+    };
+
+    // SAFETY: both descriptors are open for the duration of the call
+    let cloned = unsafe { ficlone(destination.as_raw_fd(), source.as_raw_fd() as _) }.is_ok();
+
+    if cloned {
+        on_copied(metadata.len(), progress)?;
+    } else {
+        let mut use_copy_file_range = true;
+        let mut buffer = Vec::new();
+        let mut copied = 0u64;
+
+        loop {
+            let n = if use_copy_file_range {
+                match nix::fcntl::copy_file_range(&source, None, &destination, None, COPY_CHUNK_SIZE) {
+                    Ok(n) => n,
+                    // Not supported for this pair of files; nothing was copied yet
+                    Err(Errno::EXDEV | Errno::ENOSYS | Errno::EOPNOTSUPP | Errno::EINVAL) if copied == 0 => {
+                        use_copy_file_range = false;
+                        continue;
+                    }
+                    Err(e) => return Err(std::io::Error::from(e).into()),
+                }
+            } else {
+                buffer.resize(COPY_CHUNK_SIZE, 0);
+                let n = source.read(&mut buffer)?;
+                destination.write_all(&buffer[..n])?;
+                n
+            };
+
+            if n == 0 {
+                break;
+            }
+            copied += n as u64;
+            on_copied(n as u64, progress)?;
+        }
+    }
+
+    destination.set_permissions(metadata.permissions())?;
+    destination.sync_all()?;
+    progress.files_copied += 1;
+    on_copied(0, progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn test_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("copy_test_{}", uuid::Uuid::now_v7()));
+        fs::create_dir_all(dir.join("source/nested")).unwrap();
+        fs::write(dir.join("source/a.txt"), "Alpha").unwrap();
+        fs::write(dir.join("source/nested/b.txt"), "Bravo!").unwrap();
+        std::os::unix::fs::symlink("a.txt", dir.join("source/link")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_copy_tree() {
+        let dir = test_dir();
+        fs::set_permissions(dir.join("source/a.txt"), fs::Permissions::from_mode(0o640)).unwrap();
+
+        let job = CopyJob::plan(&dir.join("source"), &dir.join("copy"), |_, _| true).unwrap();
+        assert_eq!(job.files_total(), 2);
+        assert_eq!(job.bytes_total(), 11);
+        assert_eq!(job.entries_total(), 5);
+        assert_eq!(job.links(), [(PathBuf::from("link"), PathBuf::from("a.txt"))]);
+
+        let mut reports = Vec::new();
+        let progress = job.run(|p| {
+            reports.push(p.clone());
+            true
+        }).unwrap();
+
+        assert_eq!(progress.files_copied, 2);
+        assert_eq!(progress.bytes_copied, 11);
+        assert_eq!(reports.last().unwrap().files_copied, 2);
+        assert_eq!(fs::read_to_string(dir.join("copy/a.txt")).unwrap(), "Alpha");
+        assert_eq!(fs::read_to_string(dir.join("copy/nested/b.txt")).unwrap(), "Bravo!");
+        assert_eq!(fs::read_link(dir.join("copy/link")).unwrap(), Path::new("a.txt"));
+        assert_eq!(fs::metadata(dir.join("copy/a.txt")).unwrap().permissions().mode() & 0o777, 0o640);
+
+        // The source is untouched
+        assert_eq!(fs::read_to_string(dir.join("source/a.txt")).unwrap(), "Alpha");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_leaves_out_excluded_entries() {
+        let dir = test_dir();
+
+        let job = CopyJob::plan(&dir.join("source"), &dir.join("copy"), |path, _| path != Path::new("nested")).unwrap();
+        assert_eq!(job.files_total(), 1);
+        job.run(|_| true).unwrap();
+
+        assert!(dir.join("copy/a.txt").exists());
+        assert!(!dir.join("copy/nested").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_copy_single_file_replaces_destination() {
+        let dir = test_dir();
+        fs::write(dir.join("existing.txt"), "Old content").unwrap();
+
+        let job = CopyJob::plan(&dir.join("source/nested/b.txt"), &dir.join("existing.txt"), |_, _| true).unwrap();
+        job.run(|_| true).unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("existing.txt")).unwrap(), "Bravo!");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cancelled_copy_leaves_nothing_behind() {
+        let dir = test_dir();
+
+        let job = CopyJob::plan(&dir.join("source"), &dir.join("copy"), |_, _| true).unwrap();
+        assert!(job.run(|_| false).is_err());
+        assert!(!dir.join("copy").exists());
+
+        let job = CopyJob::plan(&dir.join("source/a.txt"), &dir.join("single.txt"), |_, _| true).unwrap();
+        assert!(job.run(|_| false).is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_plan_missing_source() {
+        let dir = test_dir();
+        let result = CopyJob::plan(&dir.join("missing"), &dir.join("copy"), |_, _| true);
+        assert!(matches!(result, Err(FileServerError::FileNotFound(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
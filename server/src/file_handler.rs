@@ -1,17 +1,175 @@
-use common::{FileServerError, FileMetadata, FileEntry};
-use std::path::Path;
+use crate::copy::CopyJob;
+use crate::metadata::{self, OwnerNames};
+use crate::policy::PolicyGuard;
+use crate::quota::{self, Reservation};
+use crate::walk::{self, ListOptions, ListPage};
+use common::{FileServerError, FileMetadata};
+use rand::Rng;
+use nix::sys::stat::{utimensat, UtimensatFlags};
+use nix::sys::time::TimeSpec;
+use sha2::{Digest, Sha256};
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use tokio::fs as async_fs;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, AsyncSeekExt};
 
+pub const READ_CHUNK_SIZE: usize = 64 * 1024; // 64KB chunks
+
+/// Hex digits of the random part of an upload's temporary file name
+const UPLOAD_TEMP_SUFFIX_LEN: usize = 16;
+
 pub struct FileHandler;
 
+/// An in-progress upload. Dropping it without calling `commit` removes the
+/// temporary file, so aborted uploads never leave partial data behind.
+pub struct UploadFile {
+    file: async_fs::File,
+    temp_path: PathBuf,
+    final_path: PathBuf,
+    limits: UploadLimits,
+    committed: bool,
+}
+
+/// What an upload has to stay within
+#[derive(Default)]
+pub struct UploadLimits {
+    /// Room in the directory's quota
+    pub quota: Option<Reservation>,
+    /// The directory's rules on which files may be written
+    pub policy: Option<PolicyGuard>,
+}
+
+impl UploadFile {
+    #[cfg(test)]
+    pub fn temp_path(&self) -> &Path {
+        &self.temp_path
+    }
+
+    /// Writes `data` at `offset` in the file being uploaded. Fails without
+    /// writing anything if the chunk breaks the file policy or the file
+    /// would grow beyond its quota.
+    pub async fn write_chunk(&mut self, offset: u64, data: &[u8]) -> Result<(), FileServerError> {
+        if let Some(policy) = self.limits.policy.as_mut() {
+            policy.check_chunk(offset, data)?;
+        }
+        if let Some(reservation) = self.limits.quota.as_mut() {
+            reservation.grow_to(offset.saturating_add(data.len() as u64))?;
+        }
+
+        self.file.seek(std::io::SeekFrom::Start(offset)).await?;
+        self.file.write_all(data).await?;
+        Ok(())
+    }
+
+    /// Flushes the data written so far to disk without finishing the upload
+    pub async fn sync(&mut self) -> Result<(), FileServerError> {
+        self.file.sync_data().await?;
+        Ok(())
+    }
+
+    /// Hex-encoded SHA-256 of the data written so far
+    pub async fn sha256(&mut self) -> Result<String, FileServerError> {
+        self.file.flush().await?;
+        let (_, digest) = sha256_file(&self.temp_path).await?;
+        Ok(digest)
+    }
+
+    /// Flushes the data to disk and atomically moves it into place. Returns
+    /// the size of the file, which chunks written out of order or over each
+    /// other make different from the data received.
+    pub async fn commit(mut self) -> Result<u64, FileServerError> {
+        if let Some(policy) = &self.limits.policy {
+            policy.finish()?;
+        }
+
+        self.file.sync_all().await?;
+        let size = self.file.metadata().await?.len();
+        if let Some(owner) = self.limits.quota.as_ref().and_then(Reservation::owner) {
+            quota::set_owner(&self.temp_path, owner);
+        }
+        async_fs::rename(&self.temp_path, &self.final_path).await?;
+        self.committed = true;
+        if let Some(reservation) = self.limits.quota.take() {
+            reservation.commit();
+        }
+
+        // Persist the rename itself
+        if let Some(parent) = self.final_path.parent() {
+            async_fs::File::open(parent).await?.sync_all().await?;
+        }
+
+        Ok(size)
+    }
+}
+
+impl Drop for UploadFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Reads a byte range of a file in `READ_CHUNK_SIZE` pieces.
+pub struct ChunkedReader {
+    file: async_fs::File,
+    offset: u64,
+    remaining: u64,
+}
+
+impl ChunkedReader {
+    /// File offset of the next chunk
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Bytes left in the requested range
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    /// Reads the next chunk, or `None` once the range is exhausted. A file
+    /// that shrank while being read ends the range early, with `None` in
+    /// place of the partial chunk.
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, FileServerError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+
+        let to_read = self.remaining.min(READ_CHUNK_SIZE as u64) as usize;
+        let mut buffer = vec![0u8; to_read];
+        let mut filled = 0;
+
+        while filled < to_read {
+            let n = self.file.read(&mut buffer[filled..]).await?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+
+        if filled < to_read {
+            self.remaining = 0;
+            return Ok(None);
+        }
+        self.remaining -= filled as u64;
+        self.offset += filled as u64;
+
+        Ok(Some(buffer))
+    }
+}
+
 impl FileHandler {
     pub fn new() -> Self {
         Self
     }
 
+    /// Describes `full_path` itself; a symbolic link is described as a link,
+    /// along with its target, rather than what it points to
     pub async fn stat(&self, full_path: &Path) -> Result<FileMetadata, FileServerError> {
-        let metadata = async_fs::metadata(full_path).await?;
+        let metadata = async_fs::symlink_metadata(full_path).await?;
         
         let name = full_path.file_name()
             .and_then(|n| n.to_str())
@@ -24,131 +182,463 @@ impl FileHandler {
             .as_secs() as i64;
 
         let created_time = metadata.created()
-            .unwrap_or(std::time::SystemTime::UNIX_EPOCH)
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs() as i64;
+            .ok()
+            .map(|created| created.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs() as i64);
+
+        let posix = metadata::posix_attributes(full_path, &metadata, &mut OwnerNames::default());
 
         Ok(FileMetadata {
             name,
             size: metadata.len(),
             is_directory: metadata.is_dir(),
-            permissions: if metadata.is_dir() { "dir".to_string() } else { "file".to_string() },
+            permissions: metadata::mode_string(posix.file_type(), posix.mode),
             modified_time,
             created_time,
+            posix: Some(posix),
         })
     }
 
-    pub async fn list_directory(&self, full_path: &Path) -> Result<Vec<FileEntry>, FileServerError> {
+    /// Lists a page of the entries below `full_path`; see `walk::list`
+    pub async fn list_directory(
+        &self,
+        full_path: &Path,
+        options: ListOptions,
+        visible: impl Fn(&Path, bool) -> bool + Send + 'static,
+    ) -> Result<ListPage, FileServerError> {
         if !full_path.is_dir() {
             return Err(FileServerError::InvalidPath("Path is not a directory".to_string()));
         }
 
-        let mut entries = Vec::new();
-        let mut dir = async_fs::read_dir(full_path).await?;
+        let full_path = full_path.to_path_buf();
+        tokio::task::spawn_blocking(move || walk::list(&full_path, &options, visible))
+            .await
+            .map_err(|e| FileServerError::IoError(std::io::Error::other(e)))?
+    }
 
-        while let Some(entry) = dir.next_entry().await? {
-            let metadata = entry.metadata().await?;
-            let name = entry.file_name().to_string_lossy().to_string();
+    /// Opens `full_path` for a chunked read of the given byte range. Only one
+    /// chunk is held in memory at a time, whatever the size of the range.
+    pub async fn open_reader(&self, full_path: &Path, offset: Option<u64>, length: Option<u64>) -> Result<ChunkedReader, FileServerError> {
+        if !full_path.is_file() {
+            return Err(FileServerError::InvalidPath("Path is not a file".to_string()));
+        }
 
-            let modified_time = metadata.modified()?
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs() as i64;
+        let mut file = async_fs::File::open(full_path).await?;
+        
+        let file_size = file.metadata().await?.len();
+        let start = offset.unwrap_or(0).min(file_size);
+        let end = length.map(|len| start.saturating_add(len)).unwrap_or(file_size).min(file_size);
 
-            entries.push(FileEntry {
-                name,
-                is_directory: metadata.is_dir(),
-                size: metadata.len(),
-                modified_time,
-                permissions: if metadata.is_dir() { "dir".to_string() } else { "file".to_string() },
-            });
-        }
+        file.seek(std::io::SeekFrom::Start(start)).await?;
 
-        entries.sort_by(|a, b| {
-            match (a.is_directory, b.is_directory) {
-                (true, false) => std::cmp::Ordering::Less,
-                (false, true) => std::cmp::Ordering::Greater,
-                _ => a.name.cmp(&b.name),
-            }
-        });
+        Ok(ChunkedReader {
+            file,
+            offset: start,
+            remaining: end - start,
+        })
+    }
 
-        Ok(entries)
+    /// Starts an upload to `full_path`. Data goes to a hidden temporary file
+    /// next to the target and only replaces it once the upload is committed.
+    /// Every chunk written is checked against `limits`.
+    pub async fn begin_upload(&self, full_path: &Path, limits: UploadLimits) -> Result<UploadFile, FileServerError> {
+        let parent = full_path.parent()
+            .ok_or_else(|| FileServerError::InvalidPath("Path has no parent directory".to_string()))?;
+        let file_name = full_path.file_name()
+            .ok_or_else(|| FileServerError::InvalidPath("Path has no file name".to_string()))?;
+
+        async_fs::create_dir_all(parent).await?;
+
+        let suffix: u64 = rand::thread_rng().gen();
+        let temp_path = parent.join(format!(
+            ".{}.{:0width$x}.upload",
+            file_name.to_string_lossy(),
+            suffix,
+            width = UPLOAD_TEMP_SUFFIX_LEN
+        ));
+
+        let file = async_fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path).await?;
+
+        Ok(UploadFile {
+            file,
+            temp_path,
+            final_path: full_path.to_path_buf(),
+            limits,
+            committed: false,
+        })
     }
 
-    pub async fn read_file(&self, full_path: &Path, offset: Option<u64>, length: Option<u64>) -> Result<Vec<u8>, FileServerError> {
+    /// Size and hex-encoded SHA-256 of a file
+    pub async fn checksum(&self, full_path: &Path) -> Result<(u64, String), FileServerError> {
         if !full_path.is_file() {
             return Err(FileServerError::InvalidPath("Path is not a file".to_string()));
         }
 
-        let mut file = async_fs::File::open(full_path).await?;
-        
-        let file_size = file.metadata().await?.len();
-        let start = offset.unwrap_or(0);
-        let end = length.map(|len| start + len).unwrap_or(file_size);
+        sha256_file(full_path).await
+    }
 
-        if start >= file_size {
-            return Ok(Vec::new());
+    /// Deletes a file or symbolic link, or a directory. Directories must be
+    /// empty unless `recursive` is set.
+    pub async fn delete_file(&self, full_path: &Path, recursive: bool) -> Result<(), FileServerError> {
+        let metadata = async_fs::symlink_metadata(full_path).await
+            .map_err(|_| FileServerError::FileNotFound(full_path.to_string_lossy().to_string()))?;
+
+        if metadata.is_dir() {
+            self.remove_directory(full_path, recursive).await
+        } else {
+            async_fs::remove_file(full_path).await?;
+            Ok(())
         }
+    }
 
-        let actual_end = end.min(file_size);
-        let bytes_to_read = (actual_end - start) as usize;
+    /// Checks that `full_path` exists and may be deleted, returning its
+    /// metadata. Directories must be empty unless `recursive` is set.
+    pub async fn check_removable(&self, full_path: &Path, recursive: bool) -> Result<std::fs::Metadata, FileServerError> {
+        let metadata = async_fs::symlink_metadata(full_path).await
+            .map_err(|_| FileServerError::FileNotFound(full_path.to_string_lossy().to_string()))?;
 
-        let mut buffer = vec![0u8; bytes_to_read];
-        file.seek(std::io::SeekFrom::Start(start)).await?;
-        file.read_exact(&mut buffer).await?;
+        if metadata.is_dir() && !recursive && async_fs::read_dir(full_path).await?.next_entry().await?.is_some() {
+            return Err(FileServerError::DirectoryNotEmpty(
+                full_path.to_string_lossy().to_string()
+            ));
+        }
 
-        Ok(buffer)
+        Ok(metadata)
     }
 
-    pub async fn write_file(&self, full_path: &Path, data: &[u8], offset: Option<u64>) -> Result<u64, FileServerError> {
-        if let Some(parent) = full_path.parent() {
-            async_fs::create_dir_all(parent).await?;
+    /// Creates a directory, and its missing parents if `parents` is set
+    pub async fn make_directory(&self, full_path: &Path, parents: bool) -> Result<(), FileServerError> {
+        if async_fs::symlink_metadata(full_path).await.is_ok() {
+            return Err(FileServerError::AlreadyExists(
+                full_path.to_string_lossy().to_string()
+            ));
         }
 
-        let mut file = if offset.is_some() && full_path.exists() {
-            async_fs::OpenOptions::new()
-                .write(true)
-                .open(full_path).await?
+        if parents {
+            async_fs::create_dir_all(full_path).await?;
         } else {
-            async_fs::File::create(full_path).await?
-        };
-
-        if let Some(pos) = offset {
-            file.seek(std::io::SeekFrom::Start(pos)).await?;
+            async_fs::create_dir(full_path).await.map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => FileServerError::FileNotFound(
+                    "Parent directory does not exist".to_string()
+                ),
+                _ => e.into(),
+            })?;
         }
 
-        file.write_all(data).await?;
-        file.sync_all().await?;
-
-        Ok(data.len() as u64)
+        Ok(())
     }
 
-    pub async fn delete_file(&self, full_path: &Path) -> Result<(), FileServerError> {
-        if !full_path.exists() {
-            return Err(FileServerError::FileNotFound(
-                full_path.to_string_lossy().to_string()
-            ));
+    /// Removes a directory, which must be empty unless `recursive` is set
+    pub async fn remove_directory(&self, full_path: &Path, recursive: bool) -> Result<(), FileServerError> {
+        let metadata = async_fs::symlink_metadata(full_path).await
+            .map_err(|_| FileServerError::FileNotFound(full_path.to_string_lossy().to_string()))?;
+
+        if !metadata.is_dir() {
+            return Err(FileServerError::InvalidPath("Path is not a directory".to_string()));
         }
 
-        if full_path.is_file() {
-            async_fs::remove_file(full_path).await?;
-        } else if full_path.is_dir() {
+        if recursive {
             async_fs::remove_dir_all(full_path).await?;
         } else {
+            async_fs::remove_dir(full_path).await.map_err(|e| match e.kind() {
+                std::io::ErrorKind::DirectoryNotEmpty => FileServerError::DirectoryNotEmpty(
+                    full_path.to_string_lossy().to_string()
+                ),
+                _ => e.into(),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves a file or directory. A plain `rename` is used where possible;
+    /// between filesystems the data is copied and the source removed, which
+    /// for a directory is a recursive delete and needs `recursive_delete`.
+    pub async fn move_path(
+        &self,
+        source: &Path,
+        destination: &Path,
+        overwrite: bool,
+        recursive_delete: bool,
+    ) -> Result<(), FileServerError> {
+        let source_metadata = self.check_transfer(source, destination, overwrite).await?;
+
+        if let Some(parent) = destination.parent() {
+            async_fs::create_dir_all(parent).await?;
+        }
+
+        match async_fs::rename(source, destination).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                if source_metadata.is_dir() && !recursive_delete {
+                    return Err(FileServerError::PermissionDenied(
+                        "Moving a directory across filesystems deletes it recursively, which is disabled".to_string()
+                    ));
+                }
+                self.copy_and_remove(source, destination, source_metadata.is_dir()).await
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Copies a file or directory tree, without the entries `include`
+    /// rejects. The returned job does the actual copying and should be run
+    /// with `spawn_blocking`.
+    pub async fn plan_copy(
+        &self,
+        source: &Path,
+        destination: &Path,
+        overwrite: bool,
+        include: impl Fn(&Path, bool) -> bool + Send + 'static,
+    ) -> Result<CopyJob, FileServerError> {
+        self.check_transfer(source, destination, overwrite).await?;
+
+        if let Some(parent) = destination.parent() {
+            async_fs::create_dir_all(parent).await?;
+        }
+
+        let (source, destination) = (source.to_path_buf(), destination.to_path_buf());
+        tokio::task::spawn_blocking(move || CopyJob::plan(&source, &destination, include))
+            .await
+            .map_err(|e| FileServerError::IoError(std::io::Error::other(e)))?
+    }
+
+    /// Creates `full_path` as a link. With `overwrite` an existing file or
+    /// link is replaced: the new link is made beside it and renamed over it,
+    /// so the path never goes missing.
+    pub async fn create_link(&self, target: LinkTarget<'_>, full_path: &Path, overwrite: bool) -> Result<(), FileServerError> {
+        if let LinkTarget::Hard(source) = target {
+            let metadata = async_fs::symlink_metadata(source).await
+                .map_err(|_| FileServerError::FileNotFound(source.to_string_lossy().to_string()))?;
+            if metadata.is_dir() {
+                return Err(FileServerError::InvalidPath("Hard links cannot point to directories".to_string()));
+            }
+        }
+
+        let existing = async_fs::symlink_metadata(full_path).await.ok();
+        match &existing {
+            Some(_) if !overwrite => {
+                return Err(FileServerError::AlreadyExists(full_path.to_string_lossy().to_string()));
+            }
+            Some(metadata) if metadata.is_dir() => {
+                return Err(FileServerError::InvalidPath("Only files and links can be replaced by a link".to_string()));
+            }
+            _ => {}
+        }
+
+        let parent = full_path.parent()
+            .ok_or_else(|| FileServerError::InvalidPath("Path has no parent directory".to_string()))?;
+        let file_name = full_path.file_name()
+            .ok_or_else(|| FileServerError::InvalidPath("Path has no file name".to_string()))?;
+        async_fs::create_dir_all(parent).await?;
+
+        if existing.is_none() {
+            target.create(full_path).await?;
+            return Ok(());
+        }
+
+        let suffix: u64 = rand::thread_rng().gen();
+        let temp_path = parent.join(format!(".{}.{:016x}.link", file_name.to_string_lossy(), suffix));
+        target.create(&temp_path).await?;
+
+        // Renaming a hard link over another link to the same file does
+        // nothing, so the temporary link may still be there afterwards
+        let renamed = async_fs::rename(&temp_path, full_path).await;
+        async_fs::remove_file(&temp_path).await.ok();
+        renamed?;
+        Ok(())
+    }
+
+    /// Target of the symbolic link at `full_path`, as stored in the link
+    pub async fn read_link(&self, full_path: &Path) -> Result<PathBuf, FileServerError> {
+        let metadata = async_fs::symlink_metadata(full_path).await
+            .map_err(|_| FileServerError::FileNotFound(full_path.to_string_lossy().to_string()))?;
+        if !metadata.file_type().is_symlink() {
+            return Err(FileServerError::InvalidPath("Path is not a symbolic link".to_string()));
+        }
+
+        Ok(async_fs::read_link(full_path).await?)
+    }
+
+    /// Changes the attributes of `full_path`, following symbolic links.
+    /// Ownership goes first, since a chown may clear setuid and setgid bits.
+    pub async fn set_attributes(&self, full_path: &Path, changes: AttributeChanges) -> Result<(), FileServerError> {
+        async_fs::metadata(full_path).await
+            .map_err(|_| FileServerError::FileNotFound(full_path.to_string_lossy().to_string()))?;
+
+        let full_path = full_path.to_path_buf();
+        tokio::task::spawn_blocking(move || -> Result<(), FileServerError> {
+            if changes.uid.is_some() || changes.gid.is_some() {
+                std::os::unix::fs::chown(&full_path, changes.uid, changes.gid)?;
+            }
+            if let Some(mode) = changes.mode {
+                std::fs::set_permissions(&full_path, std::fs::Permissions::from_mode(mode))?;
+            }
+            if changes.atime_ns.is_some() || changes.mtime_ns.is_some() {
+                utimensat(
+                    None,
+                    &full_path,
+                    &timespec(changes.atime_ns),
+                    &timespec(changes.mtime_ns),
+                    UtimensatFlags::FollowSymlink,
+                ).map_err(std::io::Error::from)?;
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|e| FileServerError::IoError(std::io::Error::other(e)))?
+    }
+
+    /// Common checks for moving or copying `source` to `destination`.
+    /// Returns the metadata of the source.
+    async fn check_transfer(&self, source: &Path, destination: &Path, overwrite: bool) -> Result<std::fs::Metadata, FileServerError> {
+        let source_metadata = async_fs::symlink_metadata(source).await
+            .map_err(|_| FileServerError::FileNotFound(source.to_string_lossy().to_string()))?;
+
+        if destination.starts_with(source) {
             return Err(FileServerError::InvalidPath(
-                "Path is neither a file nor a directory".to_string()
+                "Cannot move or copy a path into itself".to_string()
             ));
         }
 
+        if let Ok(existing) = async_fs::symlink_metadata(destination).await {
+            if !overwrite {
+                return Err(FileServerError::AlreadyExists(
+                    destination.to_string_lossy().to_string()
+                ));
+            }
+            if existing.is_dir() || !source_metadata.is_file() {
+                return Err(FileServerError::InvalidPath(
+                    "Only files can replace an existing destination".to_string()
+                ));
+            }
+        }
+
+        Ok(source_metadata)
+    }
+
+    /// Fallback for moves between filesystems
+    async fn copy_and_remove(&self, source: &Path, destination: &Path, is_dir: bool) -> Result<(), FileServerError> {
+        let job = {
+            let (source, destination) = (source.to_path_buf(), destination.to_path_buf());
+            tokio::task::spawn_blocking(move || CopyJob::plan(&source, &destination, |_, _| true)?.run(|_| true))
+        };
+        job.await.map_err(|e| FileServerError::IoError(std::io::Error::other(e)))??;
+
+        if is_dir {
+            async_fs::remove_dir_all(source).await?;
+        } else {
+            async_fs::remove_file(source).await?;
+        }
         Ok(())
     }
 }
 
+/// Whether `name` is that of a temporary file `FileHandler::begin_upload`
+/// writes to, `.{name}.{random hex}.upload`. Clients may not use such names,
+/// so these files are known to belong to uploads.
+pub fn is_upload_temp(name: &OsStr) -> bool {
+    let Some(rest) = name.as_bytes().strip_prefix(b".").and_then(|rest| rest.strip_suffix(b".upload")) else {
+        return false;
+    };
+    let Some(split) = rest.len().checked_sub(UPLOAD_TEMP_SUFFIX_LEN + 1).filter(|&split| split > 0) else {
+        return false;
+    };
+    rest[split] == b'.' && rest[split + 1..].iter().all(u8::is_ascii_hexdigit)
+}
+
+/// Removes the temporary files of uploads below `root`, without following
+/// symbolic links. At startup these are left over from uploads that were in
+/// progress when the server stopped. Returns how many were removed.
+pub fn remove_stale_uploads(root: &Path) -> Result<usize, FileServerError> {
+    let mut removed = 0;
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(directory) = pending.pop() {
+        for entry in std::fs::read_dir(&directory)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(entry.path());
+            } else if file_type.is_file() && is_upload_temp(&entry.file_name()) {
+                std::fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Attributes to change on a file; those left as `None` are kept
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AttributeChanges {
+    pub mode: Option<u32>,
+    pub atime_ns: Option<i64>,
+    pub mtime_ns: Option<i64>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+/// A time for `utimensat`, or one telling it to leave the time alone
+fn timespec(nanos: Option<i64>) -> TimeSpec {
+    match nanos {
+        Some(nanos) => TimeSpec::new(nanos.div_euclid(1_000_000_000), nanos.rem_euclid(1_000_000_000)),
+        None => TimeSpec::UTIME_OMIT,
+    }
+}
+
+/// What a new link points to
+#[derive(Clone, Copy)]
+pub enum LinkTarget<'a> {
+    /// A symbolic link storing this path
+    Symbolic(&'a Path),
+    /// A hard link to this file
+    Hard(&'a Path),
+}
+
+impl LinkTarget<'_> {
+    async fn create(self, link: &Path) -> Result<(), FileServerError> {
+        let created = match self {
+            LinkTarget::Symbolic(target) => async_fs::symlink(target, link).await,
+            LinkTarget::Hard(source) => async_fs::hard_link(source, link).await,
+        };
+
+        created.map_err(|e| match e.kind() {
+            std::io::ErrorKind::CrossesDevices => FileServerError::InvalidPath(
+                "Hard links cannot cross filesystems".to_string()
+            ),
+            _ => e.into(),
+        })
+    }
+}
+
+async fn sha256_file(path: &Path) -> Result<(u64, String), FileServerError> {
+    let mut file = async_fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; READ_CHUNK_SIZE];
+    let mut size = 0;
+
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        size += n as u64;
+    }
+
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use common::FileType;
     use std::fs;
+    use std::os::unix::fs::MetadataExt;
     use tokio::fs as async_fs;
     use uuid::Uuid;
 
@@ -189,7 +679,8 @@ mod tests {
         assert!(!metadata.is_directory);
         assert_eq!(metadata.size, 13); // "Hello, World!" length
         assert!(metadata.modified_time > 0);
-        assert!(metadata.created_time > 0);
+        assert!(metadata.created_time.unwrap() > 0);
+        assert!(metadata.permissions.starts_with("-rw"));
 
         cleanup_test_environment(&test_dir).await;
     }
@@ -210,6 +701,25 @@ mod tests {
         cleanup_test_environment(&test_dir).await;
     }
 
+    #[tokio::test]
+    async fn test_stat_symlink() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+        let link = test_dir.join("link");
+        std::os::unix::fs::symlink("subdir", &link).unwrap();
+
+        let metadata = handler.stat(&link).await.unwrap();
+        assert_eq!(metadata.name, "link");
+        assert!(!metadata.is_directory);
+        assert_eq!(metadata.size, 6);
+        assert!(metadata.permissions.starts_with('l'));
+        let posix = metadata.posix.unwrap();
+        assert_eq!(posix.file_type(), FileType::Symlink);
+        assert_eq!(posix.symlink_target, "subdir");
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
     #[tokio::test]
     async fn test_stat_nonexistent_file() {
         let test_dir = create_test_environment().await;
@@ -222,15 +732,19 @@ mod tests {
         cleanup_test_environment(&test_dir).await;
     }
 
+    fn list_options() -> ListOptions {
+        ListOptions::from_request(&common::ListRequest::default()).unwrap()
+    }
+
     #[tokio::test]
     async fn test_list_directory() {
         let test_dir = create_test_environment().await;
         let handler = FileHandler::new();
 
-        let result = handler.list_directory(&test_dir).await;
+        let result = handler.list_directory(&test_dir, list_options(), |_, _| true).await;
         assert!(result.is_ok());
 
-        let entries = result.unwrap();
+        let entries = result.unwrap().entries;
         assert_eq!(entries.len(), 2); // test_file.txt and subdir
 
         // Check directory comes first (sorted)
@@ -251,22 +765,31 @@ mod tests {
         let handler = FileHandler::new();
         let nonexistent = test_dir.join("nonexistent");
 
-        let result = handler.list_directory(&nonexistent).await;
+        let result = handler.list_directory(&nonexistent, list_options(), |_, _| true).await;
         assert!(result.is_err());
 
         cleanup_test_environment(&test_dir).await;
     }
 
+    async fn read_all(reader: &mut ChunkedReader) -> Vec<u8> {
+        let mut content = Vec::new();
+        while let Some(chunk) = reader.next_chunk().await.unwrap() {
+            assert!(chunk.len() <= READ_CHUNK_SIZE);
+            content.extend_from_slice(&chunk);
+        }
+        content
+    }
+
     #[tokio::test]
     async fn test_read_file() {
         let test_dir = create_test_environment().await;
         let handler = FileHandler::new();
         let test_file = test_dir.join("test_file.txt");
 
-        let result = handler.read_file(&test_file, None, None).await;
+        let result = handler.open_reader(&test_file, None, None).await;
         assert!(result.is_ok());
 
-        let content = result.unwrap();
+        let content = read_all(&mut result.unwrap()).await;
         assert_eq!(content, b"Hello, World!");
 
         cleanup_test_environment(&test_dir).await;
@@ -279,15 +802,87 @@ mod tests {
         let test_file = test_dir.join("test_file.txt");
 
         // Read "World" from "Hello, World!"
-        let result = handler.read_file(&test_file, Some(7), Some(5)).await;
+        let result = handler.open_reader(&test_file, Some(7), Some(5)).await;
         assert!(result.is_ok());
 
-        let content = result.unwrap();
+        let mut reader = result.unwrap();
+        assert_eq!(reader.offset(), 7);
+        assert_eq!(reader.remaining(), 5);
+
+        let content = read_all(&mut reader).await;
         assert_eq!(content, b"World");
 
         cleanup_test_environment(&test_dir).await;
     }
 
+    #[tokio::test]
+    async fn test_read_file_range_past_end() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+        let test_file = test_dir.join("test_file.txt");
+
+        let mut reader = handler.open_reader(&test_file, Some(100), Some(5)).await.unwrap();
+        assert_eq!(reader.remaining(), 0);
+        assert!(reader.next_chunk().await.unwrap().is_none());
+
+        let mut reader = handler.open_reader(&test_file, Some(7), Some(u64::MAX)).await.unwrap();
+        assert_eq!(read_all(&mut reader).await, b"World!");
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_read_large_file_in_chunks() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+        let large_file = test_dir.join("large.bin");
+
+        let data: Vec<u8> = (0..READ_CHUNK_SIZE * 2 + 10).map(|i| (i % 251) as u8).collect();
+        fs::write(&large_file, &data).unwrap();
+
+        let mut reader = handler.open_reader(&large_file, None, None).await.unwrap();
+        let mut sizes = Vec::new();
+        let mut content = Vec::new();
+        while let Some(chunk) = reader.next_chunk().await.unwrap() {
+            sizes.push(chunk.len());
+            content.extend_from_slice(&chunk);
+        }
+
+        assert_eq!(sizes, vec![READ_CHUNK_SIZE, READ_CHUNK_SIZE, 10]);
+        assert_eq!(content, data);
+        assert_eq!(reader.offset(), data.len() as u64);
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_read_file_that_shrinks() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+        let large_file = test_dir.join("large.bin");
+        fs::write(&large_file, vec![7u8; READ_CHUNK_SIZE * 2]).unwrap();
+
+        let mut reader = handler.open_reader(&large_file, None, None).await.unwrap();
+        assert_eq!(reader.next_chunk().await.unwrap().unwrap().len(), READ_CHUNK_SIZE);
+
+        fs::OpenOptions::new().write(true).open(&large_file).unwrap().set_len(READ_CHUNK_SIZE as u64 + 10).unwrap();
+        assert!(reader.next_chunk().await.unwrap().is_none());
+        assert_eq!(reader.remaining(), 0);
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_read_directory_fails() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+
+        let result = handler.open_reader(&test_dir.join("subdir"), None, None).await;
+        assert!(result.is_err());
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
     #[tokio::test]
     async fn test_write_file() {
         let test_dir = create_test_environment().await;
@@ -295,9 +890,18 @@ mod tests {
         let new_file = test_dir.join("new_file.txt");
 
         let data = b"New file content";
-        let result = handler.write_file(&new_file, data, None).await;
+        let mut upload = handler.begin_upload(&new_file, UploadLimits::default()).await.unwrap();
+        upload.write_chunk(0, data).await.unwrap();
+
+        // Nothing is visible at the target until the upload is committed
+        assert!(upload.temp_path().exists());
+        assert!(!new_file.exists());
+
+        let temp_path = upload.temp_path().to_path_buf();
+        let result = upload.commit().await;
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), data.len() as u64);
+        assert!(!temp_path.exists());
 
         // Verify file was written
         let written_content = fs::read(&new_file).unwrap();
@@ -312,15 +916,75 @@ mod tests {
         let handler = FileHandler::new();
         let test_file = test_dir.join("test_file.txt");
 
-        // Write "RUST" at offset 7 (replacing "World")
-        let data = b"RUST";
-        let result = handler.write_file(&test_file, data, Some(7)).await;
-        assert!(result.is_ok());
+        // Chunks land at their offsets, even when they arrive out of order
+        let mut upload = handler.begin_upload(&test_file, UploadLimits::default()).await.unwrap();
+        upload.write_chunk(7, b"RUST").await.unwrap();
+        upload.write_chunk(0, b"Hello, ").await.unwrap();
+        upload.write_chunk(7, b"RU").await.unwrap();
 
-        // Verify the content
+        // The size of the file is reported, not the data received
+        assert_eq!(upload.commit().await.unwrap(), 11);
+
+        // Verify the content replaced the original file
         let content = fs::read(&test_file).unwrap();
         let content_str = String::from_utf8(content).unwrap();
-        assert!(content_str.contains("Hello, RUST"));
+        assert_eq!(content_str, "Hello, RUST");
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_upload_is_cleaned_up() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+        let test_file = test_dir.join("test_file.txt");
+
+        let mut upload = handler.begin_upload(&test_file, UploadLimits::default()).await.unwrap();
+        upload.write_chunk(0, b"partial").await.unwrap();
+        let temp_path = upload.temp_path().to_path_buf();
+        assert!(temp_path.exists());
+
+        drop(upload);
+
+        assert!(!temp_path.exists());
+        assert_eq!(fs::read(&test_file).unwrap(), b"Hello, World!");
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_upload_temp_names_are_recognised() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+
+        let upload = handler.begin_upload(&test_dir.join("subdir/report.txt"), UploadLimits::default()).await.unwrap();
+        assert!(is_upload_temp(upload.temp_path().file_name().unwrap()));
+        for name in [".report.upload", "report.0123456789abcdef.upload", "..0123456789abcdef.upload", ".report.0123456789abcdeg.upload"] {
+            assert!(!is_upload_temp(OsStr::new(name)), "{}", name);
+        }
+
+        // Left behind by a server that stopped mid-upload
+        let temp_path = upload.temp_path().to_path_buf();
+        std::mem::forget(upload);
+        fs::write(test_dir.join(".notes.upload"), "kept").unwrap();
+        assert_eq!(remove_stale_uploads(&test_dir).unwrap(), 1);
+        assert!(!temp_path.exists());
+        assert!(test_dir.join(".notes.upload").exists());
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_upload_creates_parent_directories() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+        let nested = test_dir.join("a/b/c.txt");
+
+        let mut upload = handler.begin_upload(&nested, UploadLimits::default()).await.unwrap();
+        upload.write_chunk(0, b"nested").await.unwrap();
+        upload.commit().await.unwrap();
+
+        assert_eq!(fs::read(&nested).unwrap(), b"nested");
 
         cleanup_test_environment(&test_dir).await;
     }
@@ -334,7 +998,7 @@ mod tests {
         // Ensure file exists before deletion
         assert!(test_file.exists());
 
-        let result = handler.delete_file(&test_file).await;
+        let result = handler.delete_file(&test_file, false).await;
         assert!(result.is_ok());
 
         // Verify file was deleted
@@ -352,7 +1016,12 @@ mod tests {
         // Ensure directory exists before deletion
         assert!(test_subdir.exists());
 
-        let result = handler.delete_file(&test_subdir).await;
+        // A directory with contents is only deleted when asked to
+        let result = handler.delete_file(&test_subdir, false).await;
+        assert!(matches!(result, Err(FileServerError::DirectoryNotEmpty(_))));
+        assert!(test_subdir.join("nested_file.txt").exists());
+
+        let result = handler.delete_file(&test_subdir, true).await;
         assert!(result.is_ok());
 
         // Verify directory was deleted
@@ -361,16 +1030,190 @@ mod tests {
         cleanup_test_environment(&test_dir).await;
     }
 
+    #[tokio::test]
+    async fn test_move_file() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+        let source = test_dir.join("test_file.txt");
+        let destination = test_dir.join("moved/renamed.txt");
+
+        handler.move_path(&source, &destination, false, true).await.unwrap();
+        assert!(!source.exists());
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "Hello, World!");
+
+        // Existing files are only replaced when asked to
+        async_fs::write(&source, "Replacement").await.unwrap();
+        let result = handler.move_path(&source, &destination, false, true).await;
+        assert!(matches!(result, Err(FileServerError::AlreadyExists(_))));
+        handler.move_path(&source, &destination, true, true).await.unwrap();
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "Replacement");
+
+        let result = handler.move_path(&source, &destination, true, true).await;
+        assert!(matches!(result, Err(FileServerError::FileNotFound(_))));
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_move_directory_into_itself() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+        let source = test_dir.join("subdir");
+
+        let result = handler.move_path(&source, &source.join("inner"), false, true).await;
+        assert!(matches!(result, Err(FileServerError::InvalidPath(_))));
+        assert!(source.join("nested_file.txt").exists());
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_copy_and_remove_fallback() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+        async_fs::create_dir_all(test_dir.join("subdir/deeper")).await.unwrap();
+        async_fs::write(test_dir.join("subdir/deeper/leaf.txt"), "Leaf").await.unwrap();
+        async_fs::symlink("nested_file.txt", test_dir.join("subdir/link")).await.unwrap();
+
+        let destination = test_dir.join("copied_file.txt");
+        handler.copy_and_remove(&test_dir.join("test_file.txt"), &destination, false).await.unwrap();
+        assert!(!test_dir.join("test_file.txt").exists());
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "Hello, World!");
+
+        let destination = test_dir.join("copied_dir");
+        handler.copy_and_remove(&test_dir.join("subdir"), &destination, true).await.unwrap();
+        assert!(!test_dir.join("subdir").exists());
+        assert_eq!(fs::read_to_string(destination.join("nested_file.txt")).unwrap(), "Nested content");
+        assert_eq!(fs::read_to_string(destination.join("deeper/leaf.txt")).unwrap(), "Leaf");
+        assert_eq!(fs::read_link(destination.join("link")).unwrap(), Path::new("nested_file.txt"));
+
+        // Only the copies remain, no temporary files
+        let names: Vec<_> = fs::read_dir(&test_dir).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names.len(), 2, "unexpected entries: {:?}", names);
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_make_and_remove_directory() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+        let nested = test_dir.join("a/b/c");
+
+        let result = handler.make_directory(&nested, false).await;
+        assert!(matches!(result, Err(FileServerError::FileNotFound(_))));
+        handler.make_directory(&nested, true).await.unwrap();
+        assert!(nested.is_dir());
+
+        let result = handler.make_directory(&nested, true).await;
+        assert!(matches!(result, Err(FileServerError::AlreadyExists(_))));
+
+        let result = handler.remove_directory(&test_dir.join("test_file.txt"), false).await;
+        assert!(matches!(result, Err(FileServerError::InvalidPath(_))));
+        let result = handler.remove_directory(&test_dir.join("a"), false).await;
+        assert!(matches!(result, Err(FileServerError::DirectoryNotEmpty(_))));
+
+        handler.remove_directory(&nested, false).await.unwrap();
+        assert!(!nested.exists());
+        handler.remove_directory(&test_dir.join("a"), true).await.unwrap();
+        assert!(!test_dir.join("a").exists());
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_symlink_keeps_target() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+        let link = test_dir.join("link");
+        async_fs::symlink(test_dir.join("subdir"), &link).await.unwrap();
+
+        handler.delete_file(&link, true).await.unwrap();
+        assert!(!link.exists());
+        assert!(test_dir.join("subdir/nested_file.txt").exists());
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
     #[tokio::test]
     async fn test_delete_nonexistent_file() {
         let test_dir = create_test_environment().await;
         let handler = FileHandler::new();
         let nonexistent = test_dir.join("nonexistent.txt");
 
-        let result = handler.delete_file(&nonexistent).await;
+        let result = handler.delete_file(&nonexistent, false).await;
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("File not found"));
 
         cleanup_test_environment(&test_dir).await;
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_create_and_replace_links() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+        fs::create_dir_all(test_dir.join("releases/41")).unwrap();
+        fs::create_dir_all(test_dir.join("releases/42")).unwrap();
+        let current = test_dir.join("current");
+
+        handler.create_link(LinkTarget::Symbolic(Path::new("releases/41")), &current, false).await.unwrap();
+        assert_eq!(handler.read_link(&current).await.unwrap(), Path::new("releases/41"));
+
+        let result = handler.create_link(LinkTarget::Symbolic(Path::new("releases/42")), &current, false).await;
+        assert!(matches!(result, Err(FileServerError::AlreadyExists(_))));
+        handler.create_link(LinkTarget::Symbolic(Path::new("releases/42")), &current, true).await.unwrap();
+        assert_eq!(handler.read_link(&current).await.unwrap(), Path::new("releases/42"));
+
+        let result = handler.create_link(LinkTarget::Symbolic(Path::new("releases/42")), &test_dir.join("subdir"), true).await;
+        assert!(matches!(result, Err(FileServerError::InvalidPath(_))));
+
+        let source = test_dir.join("subdir/nested_file.txt");
+        let hard = test_dir.join("links/hard.txt");
+        handler.create_link(LinkTarget::Hard(&source), &hard, false).await.unwrap();
+        assert_eq!(fs::metadata(&source).unwrap().nlink(), 2);
+
+        // Replacing a hard link with one to the same file leaves nothing behind
+        handler.create_link(LinkTarget::Hard(&source), &hard, true).await.unwrap();
+        assert_eq!(fs::read_dir(test_dir.join("links")).unwrap().count(), 1);
+
+        let result = handler.create_link(LinkTarget::Hard(&test_dir.join("subdir")), &test_dir.join("dir_link"), false).await;
+        assert!(matches!(result, Err(FileServerError::InvalidPath(_))));
+        assert!(matches!(handler.read_link(&hard).await, Err(FileServerError::InvalidPath(_))));
+
+        cleanup_test_environment(&test_dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_set_attributes() {
+        let test_dir = create_test_environment().await;
+        let handler = FileHandler::new();
+        let script = test_dir.join("test_file.txt");
+
+        let changes = AttributeChanges {
+            mode: Some(0o750),
+            mtime_ns: Some(1_600_000_000_123_456_789),
+            ..Default::default()
+        };
+        let before = fs::metadata(&script).unwrap();
+        handler.set_attributes(&script, changes).await.unwrap();
+
+        let after = fs::metadata(&script).unwrap();
+        assert_eq!(after.mode() & 0o7777, 0o750);
+        assert_eq!((after.mtime(), after.mtime_nsec()), (1_600_000_000, 123_456_789));
+        assert_eq!((after.atime(), after.atime_nsec()), (before.atime(), before.atime_nsec()));
+
+        // Times before the epoch are valid too
+        let changes = AttributeChanges { atime_ns: Some(-1_500_000_000), ..Default::default() };
+        handler.set_attributes(&script, changes).await.unwrap();
+        let after = fs::metadata(&script).unwrap();
+        assert_eq!((after.atime(), after.atime_nsec()), (-2, 500_000_000));
+        assert_eq!(after.mode() & 0o7777, 0o750);
+
+        let result = handler.set_attributes(&test_dir.join("missing"), AttributeChanges::default()).await;
+        assert!(matches!(result, Err(FileServerError::FileNotFound(_))));
+
+        cleanup_test_environment(&test_dir).await;
+    }
+}
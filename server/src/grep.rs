@@ -0,0 +1,302 @@
+use common::{FileServerError, GrepMatch};
+use regex::bytes::{Regex, RegexBuilder};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Files with a NUL byte in their first this many bytes are taken as binary
+const BINARY_SNIFF_BYTES: usize = 8 * 1024;
+/// Longest line held in memory; longer lines are searched in pieces
+const MAX_LINE_BYTES: u64 = 64 * 1024;
+/// Longest line sent back with a match, in characters
+const MAX_MATCH_LINE_CHARS: usize = 1024;
+/// Limit on the compiled size of a pattern, so it cannot exhaust memory
+const MAX_PATTERN_SIZE: usize = 1024 * 1024;
+/// Most of a long line's piece searched again with the next piece; matches
+/// longer than this may be missed where they cross a piece boundary
+const MAX_OVERLAP_BYTES: usize = 16 * 1024;
+
+/// A literal or regular expression searched for line by line
+pub struct GrepPattern {
+    regex: Regex,
+    /// Bytes at the end of a long line's piece a match running on into the
+    /// next piece can start in
+    overlap: usize,
+}
+
+impl GrepPattern {
+    pub fn new(pattern: &str, is_regex: bool, ignore_case: bool) -> Result<Self, FileServerError> {
+        if pattern.is_empty() {
+            return Err(FileServerError::InvalidPath("Search pattern cannot be empty".to_string()));
+        }
+
+        let source = if is_regex { pattern.to_string() } else { regex::escape(pattern) };
+        let regex = RegexBuilder::new(&source)
+            .case_insensitive(ignore_case)
+            .size_limit(MAX_PATTERN_SIZE)
+            .build()
+            .map_err(|e| FileServerError::InvalidPath(format!("Invalid regex '{}': {}", pattern, e)))?;
+
+        let overlap = regex_syntax::ParserBuilder::new()
+            .case_insensitive(ignore_case)
+            .utf8(false)
+            .build()
+            .parse(&source)
+            .ok()
+            .and_then(|hir| hir.properties().maximum_len())
+            .map_or(MAX_OVERLAP_BYTES, |len| len.saturating_sub(1).min(MAX_OVERLAP_BYTES));
+
+        Ok(Self { regex, overlap })
+    }
+}
+
+/// What is left of a search's limits on bytes read and matches returned
+pub struct GrepBudget {
+    max_bytes: u64,
+    bytes_left: u64,
+    matches_left: usize,
+    exhausted: bool,
+}
+
+impl GrepBudget {
+    pub fn new(max_bytes: u64, max_matches: usize) -> Self {
+        Self {
+            max_bytes,
+            bytes_left: max_bytes,
+            matches_left: max_matches,
+            exhausted: false,
+        }
+    }
+
+    /// Whether the search stopped with more left to search
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    pub fn bytes_scanned(&self) -> u64 {
+        self.max_bytes - self.bytes_left
+    }
+}
+
+/// Searches one file, calling `found` with each matching line until it
+/// returns false. Binary files are skipped, as is anything but a regular
+/// file by the time it is opened. Returns whether the search should carry
+/// on with other files.
+pub fn grep_file(
+    full_path: &Path,
+    relative: &str,
+    pattern: &GrepPattern,
+    budget: &mut GrepBudget,
+    mut found: impl FnMut(GrepMatch) -> bool,
+) -> Result<bool, FileServerError> {
+    let Some(file) = open_regular(full_path)? else {
+        return Ok(true);
+    };
+    let mut reader = BufReader::with_capacity(MAX_LINE_BYTES as usize, file);
+
+    let start = reader.fill_buf()?;
+    if start[..start.len().min(BINARY_SNIFF_BYTES)].contains(&0) {
+        return Ok(true);
+    }
+
+    let mut line = Vec::new();
+    let mut line_number = 1;
+    let mut complete = true;
+    let mut reported = false;
+
+    loop {
+        if reader.fill_buf()?.is_empty() {
+            return Ok(true);
+        }
+        if budget.bytes_left == 0 {
+            budget.exhausted = true;
+            return Ok(false);
+        }
+
+        // A long line is searched piece by piece, each behind the end of the
+        // one before so matches across the boundary are found, and reported
+        // once however many pieces match
+        if complete {
+            line.clear();
+            reported = false;
+        } else {
+            line.drain(..line.len().saturating_sub(pattern.overlap));
+        }
+        let limit = MAX_LINE_BYTES.min(budget.bytes_left);
+        let n = (&mut reader).take(limit).read_until(b'\n', &mut line)?;
+        budget.bytes_left -= n as u64;
+
+        complete = line.last() == Some(&b'\n');
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+
+        if !reported && pattern.regex.is_match(text) {
+            if budget.matches_left == 0 {
+                budget.exhausted = true;
+                return Ok(false);
+            }
+            budget.matches_left -= 1;
+            reported = true;
+
+            let grep_match = GrepMatch {
+                path: relative.to_string(),
+                line_number,
+                line: String::from_utf8_lossy(text).chars().take(MAX_MATCH_LINE_CHARS).collect(),
+            };
+            if !found(grep_match) {
+                return Ok(false);
+            }
+        }
+
+        if complete {
+            line_number += 1;
+        }
+    }
+}
+
+/// Opens `full_path` if it is a regular file. The file may have been
+/// replaced since it was found, so a link there is not followed, and a FIFO
+/// cannot hold up the open.
+fn open_regular(full_path: &Path) -> Result<Option<File>, FileServerError> {
+    let file = match File::options().read(true).custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK).open(full_path) {
+        Ok(file) => file,
+        Err(e) if e.raw_os_error() == Some(libc::ELOOP) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(file.metadata()?.is_file().then_some(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn test_file(content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("grep_test_{}", uuid::Uuid::now_v7()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn grep(content: &[u8], pattern: &GrepPattern, budget: &mut GrepBudget) -> (Vec<(u64, String)>, bool) {
+        let path = test_file(content);
+        let mut matches = Vec::new();
+        let carry_on = grep_file(&path, "test.log", pattern, budget, |m| {
+            matches.push((m.line_number, m.line));
+            true
+        }).unwrap();
+        fs::remove_file(&path).unwrap();
+        (matches, carry_on)
+    }
+
+    const LOG: &[u8] = b"GET /a request_id=abc123\r\nGET /b request_id=def456\nPOST /c request_id=ABC123\n";
+
+    #[test]
+    fn test_literal_and_regex_patterns() {
+        let literal = GrepPattern::new("abc123", false, false).unwrap();
+        let (matches, carry_on) = grep(LOG, &literal, &mut GrepBudget::new(1024, 10));
+        assert_eq!(matches, [(1, "GET /a request_id=abc123".to_string())]);
+        assert!(carry_on);
+
+        let ignore_case = GrepPattern::new("abc123", false, true).unwrap();
+        let (matches, _) = grep(LOG, &ignore_case, &mut GrepBudget::new(1024, 10));
+        assert_eq!(matches.iter().map(|m| m.0).collect::<Vec<_>>(), [1, 3]);
+
+        // Literals are not interpreted as regular expressions
+        let dot = GrepPattern::new("/.", false, false).unwrap();
+        assert!(grep(LOG, &dot, &mut GrepBudget::new(1024, 10)).0.is_empty());
+
+        let regex = GrepPattern::new("^(GET|POST) /[bc]", true, false).unwrap();
+        let (matches, _) = grep(LOG, &regex, &mut GrepBudget::new(1024, 10));
+        assert_eq!(matches.iter().map(|m| m.0).collect::<Vec<_>>(), [2, 3]);
+
+        assert!(GrepPattern::new("(", true, false).is_err());
+        assert!(GrepPattern::new("", false, false).is_err());
+    }
+
+    #[test]
+    fn test_binary_files_are_skipped() {
+        let pattern = GrepPattern::new("request", false, false).unwrap();
+        let mut binary = LOG.to_vec();
+        binary.insert(10, 0);
+
+        let mut budget = GrepBudget::new(1024, 10);
+        let (matches, carry_on) = grep(&binary, &pattern, &mut budget);
+        assert!(matches.is_empty());
+        assert!(carry_on);
+        assert_eq!(budget.bytes_scanned(), 0);
+    }
+
+    #[test]
+    fn test_links_are_not_followed() {
+        let pattern = GrepPattern::new("request", false, false).unwrap();
+        let target = test_file(LOG);
+        let link = target.with_extension("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let mut budget = GrepBudget::new(1024, 10);
+        let carry_on = grep_file(&link, "test.log", &pattern, &mut budget, |_| panic!("searched through a link")).unwrap();
+        assert!(carry_on);
+        assert_eq!(budget.bytes_scanned(), 0);
+
+        fs::remove_file(&link).unwrap();
+        fs::remove_file(&target).unwrap();
+    }
+
+    #[test]
+    fn test_limits_stop_the_search() {
+        let pattern = GrepPattern::new("request_id", false, false).unwrap();
+
+        let mut budget = GrepBudget::new(1024, 2);
+        let (matches, carry_on) = grep(LOG, &pattern, &mut budget);
+        assert_eq!(matches.len(), 2);
+        assert!(!carry_on);
+        assert!(budget.is_exhausted());
+
+        // Exactly enough matches is not a truncated search
+        let mut budget = GrepBudget::new(1024, 3);
+        assert!(grep(LOG, &pattern, &mut budget).1);
+        assert!(!budget.is_exhausted());
+        assert_eq!(budget.bytes_scanned(), LOG.len() as u64);
+
+        let mut budget = GrepBudget::new(30, 10);
+        let (matches, carry_on) = grep(LOG, &pattern, &mut budget);
+        assert_eq!(matches.len(), 1);
+        assert!(!carry_on);
+        assert!(budget.is_exhausted());
+        assert_eq!(budget.bytes_scanned(), 30);
+    }
+
+    #[test]
+    fn test_long_lines_are_searched_in_pieces() {
+        let mut content = vec![b'x'; 2 * MAX_LINE_BYTES as usize + 2000];
+        content.extend_from_slice(b"needle\nsecond line needle\n");
+        let pattern = GrepPattern::new("needle", false, false).unwrap();
+
+        let (matches, _) = grep(&content, &pattern, &mut GrepBudget::new(u64::MAX, 10));
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].0, 1);
+        assert_eq!(matches[0].1.chars().count(), MAX_MATCH_LINE_CHARS);
+        assert_eq!(matches[1], (2, "second line needle".to_string()));
+    }
+
+    #[test]
+    fn test_matches_across_pieces_of_a_line() {
+        // The needle straddles the end of the first piece, and the line has
+        // another match in each of the pieces after
+        let mut content = vec![b'x'; MAX_LINE_BYTES as usize - 3];
+        content.extend_from_slice(b"needle");
+        content.extend(vec![b'x'; MAX_LINE_BYTES as usize]);
+        content.extend_from_slice(b"needle needle\nlast\n");
+
+        for pattern in [GrepPattern::new("needle", false, false), GrepPattern::new("ne+dle", true, false)] {
+            let (matches, _) = grep(&content, &pattern.unwrap(), &mut GrepBudget::new(u64::MAX, 10));
+            assert_eq!(matches.iter().map(|m| m.0).collect::<Vec<_>>(), [1]);
+        }
+
+        let straddling = &content[..MAX_LINE_BYTES as usize + 3];
+        let (matches, _) = grep(straddling, &GrepPattern::new("needle", false, false).unwrap(), &mut GrepBudget::new(u64::MAX, 10));
+        assert_eq!(matches.len(), 1);
+    }
+}
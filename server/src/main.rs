@@ -1,16 +1,31 @@
+mod audit;
 mod auth;
 mod config;
+mod copy;
 mod file_handler;
+mod grep;
+mod metadata;
+mod policy;
 mod privilege;
+mod quota;
+mod rate_limit;
+mod resolve;
 mod service;
+mod tls;
+mod trash;
+mod transport;
+mod upload;
+mod walk;
 
+use audit::AuditLog;
 use auth::AuthService;
 use config::ServerConfig;
 use privilege::PrivilegeManager;
 use service::FileServiceImpl;
-use common::file_service_server::FileServiceServer;
 use clap::Parser;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
 use tonic::transport::Server;
 use tracing::info;
 
@@ -21,6 +36,10 @@ struct Args {
     /// Path to configuration file
     #[arg(short, long, default_value = "/etc/fileserver.toml")]
     config: String,
+
+    /// Read an API key from stdin, print its salted hash for `[[clients]]` and exit
+    #[arg(long)]
+    hash_api_key: bool,
 }
 
 #[tokio::main]
@@ -28,10 +47,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt::init();
 
     let args = Args::parse();
+
+    if args.hash_api_key {
+        let mut api_key = String::new();
+        std::io::stdin().read_line(&mut api_key)?;
+        let api_key = api_key.trim_end_matches(['\r', '\n']);
+        if api_key.is_empty() {
+            return Err("API key cannot be empty".into());
+        }
+        println!("{}", auth::hash_api_key(api_key));
+        return Ok(());
+    }
     
     info!("Loading configuration from: {}", args.config);
     let config = ServerConfig::load_from_file(&args.config)?;
     
+    // Load TLS material before dropping privileges, the key is usually root-only
+    let tls_config = config.tls.as_ref()
+        .map(tls::load_server_tls_config)
+        .transpose()?;
+
+    // The audit log file may also only be writable by root
+    let audit_log = config.audit.as_ref()
+        .map(AuditLog::open)
+        .transpose()?
+        .map(Arc::new);
+
     // Handle privilege dropping if user/group specified
     let privilege_manager = PrivilegeManager::new();
     privilege_manager.validate_user_group(
@@ -47,8 +88,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr: SocketAddr = format!("0.0.0.0:{}", config.server.port).parse()?;
     info!("Starting fileserver on {}", addr);
     
-    let auth_service = AuthService::new(config.clone());
-    let file_service = FileServiceImpl::new(auth_service);
+    let auth_service = Arc::new(AuthService::new(config.clone()));
+    let file_service = FileServiceImpl::new(Arc::clone(&auth_service))?;
+    file_service.spawn_background_tasks();
     
     info!("Configured directories:");
     for dir in &config.directories {
@@ -57,9 +99,28 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     
     info!("Allowed IPs: {:?}", config.server.allowed_ips);
 
-    Server::builder()
-        .add_service(FileServiceServer::new(file_service))
-        .serve(addr)
+    if let Some(audit) = &config.audit {
+        info!("Audit log: {} (syslog: {})", audit.path.as_deref().unwrap_or("none"), audit.syslog);
+    }
+
+    if config.token_auth_enabled() {
+        let names: Vec<&str> = config.clients.iter().map(|c| c.name.as_str()).collect();
+        info!("API key authentication enabled for clients: {:?}", names);
+    }
+
+    let mut server = transport::configure(Server::builder(), &config.server);
+    if let Some(tls_config) = tls_config {
+        match config.tls.as_ref().and_then(|t| t.client_ca.as_ref()) {
+            Some(_) => info!("TLS enabled, client certificates required"),
+            None => info!("TLS enabled"),
+        }
+        server = server.tls_config(tls_config)?;
+    }
+
+    let listener = TcpListener::bind(addr).await?;
+    server
+        .add_service(transport::file_service(file_service, auth_service, audit_log, &config.server))
+        .serve_with_incoming(transport::incoming(listener, config.server.max_connections))
         .await?;
 
     Ok(())
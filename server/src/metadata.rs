@@ -0,0 +1,197 @@
+use common::{FileType, PosixAttributes};
+use nix::unistd::{Gid, Group, Uid, User};
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+/// Resolves uids and gids to names, remembering the answers since a listing
+/// tends to ask about the same few owners over and over
+#[derive(Default)]
+pub struct OwnerNames {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl OwnerNames {
+    /// Empty if the uid has no entry in the user database
+    pub fn user(&mut self, uid: u32) -> String {
+        self.users
+            .entry(uid)
+            .or_insert_with(|| {
+                User::from_uid(Uid::from_raw(uid)).ok().flatten().map(|user| user.name).unwrap_or_default()
+            })
+            .clone()
+    }
+
+    /// Empty if the gid has no entry in the group database
+    pub fn group(&mut self, gid: u32) -> String {
+        self.groups
+            .entry(gid)
+            .or_insert_with(|| {
+                Group::from_gid(Gid::from_raw(gid)).ok().flatten().map(|group| group.name).unwrap_or_default()
+            })
+            .clone()
+    }
+}
+
+/// POSIX attributes of the file at `path` described by `metadata`. The link
+/// target is filled in when `metadata` is that of a symbolic link.
+pub fn posix_attributes(path: &Path, metadata: &Metadata, names: &mut OwnerNames) -> PosixAttributes {
+    let file_type = file_type(metadata);
+    let symlink_target = if file_type == FileType::Symlink {
+        fs::read_link(path).map(|target| target.to_string_lossy().to_string()).unwrap_or_default()
+    } else {
+        String::new()
+    };
+    let (device_major, device_minor) = match file_type {
+        FileType::BlockDevice | FileType::CharDevice => split_device(metadata.rdev()),
+        _ => (0, 0),
+    };
+
+    PosixAttributes {
+        file_type: file_type as i32,
+        mode: metadata.mode() & 0o7777,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        owner: names.user(metadata.uid()),
+        group: names.group(metadata.gid()),
+        inode: metadata.ino(),
+        link_count: metadata.nlink(),
+        symlink_target,
+        device_major,
+        device_minor,
+        atime_ns: nanos(metadata.atime(), metadata.atime_nsec()),
+        mtime_ns: nanos(metadata.mtime(), metadata.mtime_nsec()),
+        ctime_ns: nanos(metadata.ctime(), metadata.ctime_nsec()),
+    }
+}
+
+pub fn file_type(metadata: &Metadata) -> FileType {
+    let file_type = metadata.file_type();
+    if file_type.is_file() {
+        FileType::Regular
+    } else if file_type.is_dir() {
+        FileType::Directory
+    } else if file_type.is_symlink() {
+        FileType::Symlink
+    } else if file_type.is_fifo() {
+        FileType::Fifo
+    } else if file_type.is_socket() {
+        FileType::Socket
+    } else if file_type.is_block_device() {
+        FileType::BlockDevice
+    } else if file_type.is_char_device() {
+        FileType::CharDevice
+    } else {
+        FileType::Unknown
+    }
+}
+
+/// Renders a file type and mode the way `ls -l` does, e.g. `drwxr-xr-x`
+pub fn mode_string(file_type: FileType, mode: u32) -> String {
+    let type_char = match file_type {
+        FileType::Regular => '-',
+        FileType::Directory => 'd',
+        FileType::Symlink => 'l',
+        FileType::Fifo => 'p',
+        FileType::Socket => 's',
+        FileType::BlockDevice => 'b',
+        FileType::CharDevice => 'c',
+        FileType::Unknown => '?',
+    };
+
+    // The execute slot of each class doubles as setuid, setgid and sticky
+    let special = [(0o4000, 's', 'S'), (0o2000, 's', 'S'), (0o1000, 't', 'T')];
+    let mut rendered = String::with_capacity(10);
+    rendered.push(type_char);
+
+    for (class, (special_bit, set, unset)) in special.into_iter().enumerate() {
+        let bits = mode >> (6 - 3 * class);
+        rendered.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        rendered.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        rendered.push(match (bits & 0o1 != 0, mode & special_bit != 0) {
+            (true, true) => set,
+            (false, true) => unset,
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+
+    rendered
+}
+
+fn nanos(seconds: i64, nanoseconds: i64) -> i64 {
+    seconds.saturating_mul(1_000_000_000).saturating_add(nanoseconds)
+}
+
+/// Major and minor numbers of a Linux device number
+fn split_device(rdev: u64) -> (u32, u32) {
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & !0xff);
+    (major as u32, minor as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_mode_string() {
+        assert_eq!(mode_string(FileType::Regular, 0o644), "-rw-r--r--");
+        assert_eq!(mode_string(FileType::Directory, 0o755), "drwxr-xr-x");
+        assert_eq!(mode_string(FileType::Regular, 0o4755), "-rwsr-xr-x");
+        assert_eq!(mode_string(FileType::Regular, 0o2644), "-rw-r-Sr--");
+        assert_eq!(mode_string(FileType::Directory, 0o1777), "drwxrwxrwt");
+        assert_eq!(mode_string(FileType::Symlink, 0o777), "lrwxrwxrwx");
+        assert_eq!(mode_string(FileType::CharDevice, 0o666), "crw-rw-rw-");
+    }
+
+    #[test]
+    fn test_posix_attributes() {
+        let dir = std::env::temp_dir().join(format!("metadata_test_{}", uuid::Uuid::now_v7()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file.txt");
+        fs::write(&file, "Hello").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+        fs::hard_link(&file, dir.join("hard.txt")).unwrap();
+        std::os::unix::fs::symlink("file.txt", dir.join("link")).unwrap();
+        let _socket = std::os::unix::net::UnixListener::bind(dir.join("socket")).unwrap();
+        let mut names = OwnerNames::default();
+
+        let metadata = fs::metadata(&file).unwrap();
+        let attributes = posix_attributes(&file, &metadata, &mut names);
+        assert_eq!(attributes.file_type(), FileType::Regular);
+        assert_eq!(attributes.mode, 0o640);
+        assert_eq!(attributes.link_count, 2);
+        assert_eq!(attributes.inode, metadata.ino());
+        assert_eq!(attributes.uid, nix::unistd::getuid().as_raw());
+        assert!(attributes.symlink_target.is_empty());
+        assert_eq!(attributes.mtime_ns / 1_000_000_000, metadata.mtime());
+
+        let link = dir.join("link");
+        let attributes = posix_attributes(&link, &fs::symlink_metadata(&link).unwrap(), &mut names);
+        assert_eq!(attributes.file_type(), FileType::Symlink);
+        assert_eq!(attributes.symlink_target, "file.txt");
+
+        let socket = dir.join("socket");
+        let attributes = posix_attributes(&socket, &fs::metadata(&socket).unwrap(), &mut names);
+        assert_eq!(attributes.file_type(), FileType::Socket);
+
+        let null = Path::new("/dev/null");
+        let attributes = posix_attributes(null, &fs::metadata(null).unwrap(), &mut names);
+        assert_eq!(attributes.file_type(), FileType::CharDevice);
+        assert_eq!((attributes.device_major, attributes.device_minor), (1, 3));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_owner_names() {
+        let mut names = OwnerNames::default();
+        assert_eq!(names.user(0), "root");
+        assert_eq!(names.group(0), "root");
+        assert_eq!(names.user(u32::MAX - 1), "");
+    }
+}
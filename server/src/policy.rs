@@ -0,0 +1,344 @@
+use crate::config::FilePolicyConfig;
+use common::FileServerError;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Bytes at the start of a file that are enough to tell its type
+const SNIFF_LEN: usize = 16;
+
+/// A file type recognizable by the bytes it starts with
+struct Signature {
+    description: &'static str,
+    extensions: &'static [&'static str],
+    /// Byte strings that must appear at the given offsets
+    magic: &'static [(usize, &'static [u8])],
+    /// Programs are refused under any extension they do not belong to
+    executable: bool,
+}
+
+const fn signature(
+    description: &'static str,
+    extensions: &'static [&'static str],
+    magic: &'static [(usize, &'static [u8])],
+) -> Signature {
+    Signature { description, extensions, magic, executable: false }
+}
+
+const SIGNATURES: &[Signature] = &[
+    signature("a PNG image", &["png"], &[(0, b"\x89PNG\r\n\x1a\n")]),
+    signature("a JPEG image", &["jpg", "jpeg"], &[(0, b"\xff\xd8\xff")]),
+    signature("a GIF image", &["gif"], &[(0, b"GIF87a")]),
+    signature("a GIF image", &["gif"], &[(0, b"GIF89a")]),
+    signature("a WebP image", &["webp"], &[(0, b"RIFF"), (8, b"WEBP")]),
+    signature("a TIFF image", &["tif", "tiff"], &[(0, b"II*\x00")]),
+    signature("a TIFF image", &["tif", "tiff"], &[(0, b"MM\x00*")]),
+    signature("a BMP image", &["bmp"], &[(0, b"BM")]),
+    signature("a PDF document", &["pdf"], &[(0, b"%PDF-")]),
+    signature("a ZIP archive", &["zip", "jar", "apk", "docx", "xlsx", "pptx", "odt", "ods", "odp"], &[(0, b"PK\x03\x04")]),
+    signature("a ZIP archive", &["zip", "jar", "apk", "docx", "xlsx", "pptx", "odt", "ods", "odp"], &[(0, b"PK\x05\x06")]),
+    signature("a gzip archive", &["gz", "tgz"], &[(0, b"\x1f\x8b")]),
+    Signature { description: "an ELF executable", extensions: &["so", "o"], magic: &[(0, b"\x7fELF")], executable: true },
+    Signature { description: "a Windows executable", extensions: &["exe", "dll", "sys"], magic: &[(0, b"MZ")], executable: true },
+];
+
+impl Signature {
+    fn matches(&self, header: &[u8]) -> bool {
+        self.magic
+            .iter()
+            .all(|(offset, magic)| header.get(*offset..offset + magic.len()) == Some(*magic))
+    }
+}
+
+/// Which files may be written to one configured directory
+#[derive(Debug)]
+pub struct FilePolicy {
+    max_file_size: Option<u64>,
+    allowed: HashSet<String>,
+    denied: HashSet<String>,
+    sniff_content: bool,
+}
+
+impl FilePolicy {
+    pub fn new(config: &FilePolicyConfig) -> Self {
+        let normalize = |extensions: &[String]| {
+            extensions.iter().map(|e| e.trim_start_matches('.').to_lowercase()).collect()
+        };
+
+        Self {
+            max_file_size: config.max_file_size,
+            allowed: normalize(&config.allowed_extensions),
+            denied: normalize(&config.denied_extensions),
+            sniff_content: config.sniff_content,
+        }
+    }
+
+    /// Refuses a file name whose extension is denied, or not among the
+    /// allowed ones
+    pub fn check_name(&self, path: &Path) -> Result<(), FileServerError> {
+        let extension = extension(path);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+        if !self.allowed.is_empty() && !self.allowed.contains(&extension) {
+            let mut allowed: Vec<&str> = self.allowed.iter().map(String::as_str).collect();
+            allowed.sort_unstable();
+            return Err(FileServerError::PolicyViolation(format!(
+                "'{}' does not have an allowed extension ({})", name, allowed.join(", ")
+            )));
+        }
+        if self.denied.contains(&extension) {
+            return Err(FileServerError::PolicyViolation(format!(
+                "Files with extension '.{}' are not allowed", extension
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn check_size(&self, size: u64) -> Result<(), FileServerError> {
+        match self.max_file_size {
+            Some(max) if size > max => Err(FileServerError::PolicyViolation(format!(
+                "File would be larger than the maximum of {} bytes", max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Refuses content whose first bytes do not match the extension of
+    /// `path`. Files with an extension no signature is known for only have
+    /// to not be programs.
+    pub fn check_content(&self, path: &Path, header: &[u8]) -> Result<(), FileServerError> {
+        if !self.sniff_content {
+            return Ok(());
+        }
+
+        let extension = extension(path);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let detected = SIGNATURES.iter().find(|signature| signature.matches(header));
+        let expected = SIGNATURES.iter().find(|signature| signature.extensions.contains(&extension.as_str()));
+
+        match (expected, detected) {
+            (Some(_), Some(detected)) if detected.extensions.contains(&extension.as_str()) => Ok(()),
+            (Some(expected), _) => Err(FileServerError::PolicyViolation(format!(
+                "'{}' does not look like {}", name, expected.description
+            ))),
+            (None, Some(detected)) if detected.executable => Err(FileServerError::PolicyViolation(format!(
+                "'{}' looks like {}", name, detected.description
+            ))),
+            (None, _) => Ok(()),
+        }
+    }
+
+    /// Checks what is at `source` as if it were written to `destination`:
+    /// every file and link by name, and files by size and content. Links are
+    /// not followed. The check is blocking and meant to run on the blocking
+    /// thread pool.
+    pub fn check_tree(&self, source: &Path, destination: &Path) -> Result<(), FileServerError> {
+        let join = |base: &Path, relative: &Path| match relative.as_os_str().is_empty() {
+            true => base.to_path_buf(),
+            false => base.join(relative),
+        };
+
+        let mut pending = vec![PathBuf::new()];
+        while let Some(relative) = pending.pop() {
+            let path = join(source, &relative);
+            let metadata = fs::symlink_metadata(&path)
+                .map_err(|_| FileServerError::FileNotFound(path.to_string_lossy().to_string()))?;
+            if metadata.is_dir() {
+                for entry in fs::read_dir(&path)? {
+                    pending.push(relative.join(entry?.file_name()));
+                }
+                continue;
+            }
+
+            let target = join(destination, &relative);
+            self.check_name(&target)?;
+            if metadata.is_file() {
+                self.check_size(metadata.len())?;
+                let mut header = Vec::with_capacity(SNIFF_LEN);
+                File::open(&path)?.take(SNIFF_LEN as u64).read_to_end(&mut header)?;
+                self.check_content(&target, &header)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies a directory's file policy to one file as its chunks arrive
+pub struct PolicyGuard {
+    policy: Arc<FilePolicy>,
+    path: std::path::PathBuf,
+    /// The start of the file, and which of its bytes have been written
+    header: [u8; SNIFF_LEN],
+    filled: [bool; SNIFF_LEN],
+    size: u64,
+}
+
+impl PolicyGuard {
+    /// Checks the name of the file about to be written to `path`
+    pub fn new(policy: Arc<FilePolicy>, path: &Path) -> Result<Self, FileServerError> {
+        policy.check_name(path)?;
+        Ok(Self {
+            policy,
+            path: path.to_path_buf(),
+            header: [0; SNIFF_LEN],
+            filled: [false; SNIFF_LEN],
+            size: 0,
+        })
+    }
+
+    /// Checks a chunk about to be written at `offset`. The content is
+    /// sniffed as soon as enough of the start of the file has arrived, and
+    /// again whenever a chunk rewrites it.
+    pub fn check_chunk(&mut self, offset: u64, data: &[u8]) -> Result<(), FileServerError> {
+        let end = offset.saturating_add(data.len() as u64);
+        self.policy.check_size(end)?;
+
+        if offset < SNIFF_LEN as u64 {
+            self.sniff(offset as usize, data)?;
+        }
+        self.size = self.size.max(end);
+        Ok(())
+    }
+
+    /// Adds `data`, written at `start`, to the header and checks it once it
+    /// is complete. A rejected chunk is not written, so it is not kept.
+    fn sniff(&mut self, start: usize, data: &[u8]) -> Result<(), FileServerError> {
+        let end = (start + data.len()).min(SNIFF_LEN);
+        let (mut header, mut filled) = (self.header, self.filled);
+        header[start..end].copy_from_slice(&data[..end - start]);
+        filled[start..end].fill(true);
+
+        if filled.iter().all(|filled| *filled) {
+            self.policy.check_content(&self.path, &header)?;
+        }
+        (self.header, self.filled) = (header, filled);
+        Ok(())
+    }
+
+    /// Sniffs files too short to have been checked as they arrived. Bytes
+    /// never written read as zeros, as they do in the file.
+    pub fn finish(&self) -> Result<(), FileServerError> {
+        let len = self.size.min(SNIFF_LEN as u64) as usize;
+        self.policy.check_content(&self.path, &self.header[..len])
+    }
+}
+
+/// Lower-cased extension of the file name, empty if there is none
+fn extension(path: &Path) -> String {
+    path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(allowed: &[&str], denied: &[&str], sniff_content: bool) -> FilePolicy {
+        FilePolicy::new(&FilePolicyConfig {
+            max_file_size: Some(100),
+            allowed_extensions: allowed.iter().map(|e| e.to_string()).collect(),
+            denied_extensions: denied.iter().map(|e| e.to_string()).collect(),
+            sniff_content,
+        })
+    }
+
+    fn rejected(result: Result<(), FileServerError>) -> bool {
+        matches!(result, Err(FileServerError::PolicyViolation(_)))
+    }
+
+    #[test]
+    fn test_extensions() {
+        let images = policy(&["png", ".JPG", "pdf"], &[], false);
+        assert!(images.check_name(Path::new("photos/cat.png")).is_ok());
+        assert!(images.check_name(Path::new("photos/CAT.JPG")).is_ok());
+        assert!(rejected(images.check_name(Path::new("photos/cat.gif"))));
+        assert!(rejected(images.check_name(Path::new("photos/README"))));
+        assert!(rejected(images.check_name(Path::new("photos/cat.png.exe"))));
+
+        let no_programs = policy(&[], &["exe", "sh"], false);
+        assert!(no_programs.check_name(Path::new("notes.txt")).is_ok());
+        assert!(no_programs.check_name(Path::new("Makefile")).is_ok());
+        assert!(rejected(no_programs.check_name(Path::new("setup.EXE"))));
+        assert!(rejected(no_programs.check_name(Path::new("deploy.sh"))));
+
+        assert!(no_programs.check_size(100).is_ok());
+        assert!(rejected(no_programs.check_size(101)));
+    }
+
+    #[test]
+    fn test_content_sniffing() {
+        let sniffing = policy(&[], &[], true);
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+        let elf = b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+
+        assert!(sniffing.check_content(Path::new("cat.png"), png).is_ok());
+        assert!(sniffing.check_content(Path::new("cat.webp"), b"RIFF\x10\x00\x00\x00WEBPVP8 ").is_ok());
+        assert!(sniffing.check_content(Path::new("report.pdf"), b"%PDF-1.7\n").is_ok());
+        assert!(sniffing.check_content(Path::new("notes.txt"), b"Just some text").is_ok());
+        assert!(sniffing.check_content(Path::new("libssl.so"), elf).is_ok());
+
+        assert!(rejected(sniffing.check_content(Path::new("cat.png"), b"GIF89a\x01\x00")));
+        assert!(rejected(sniffing.check_content(Path::new("cat.png"), b"")));
+        assert!(rejected(sniffing.check_content(Path::new("cat.png"), elf)));
+        assert!(rejected(sniffing.check_content(Path::new("notes.txt"), elf)));
+        assert!(rejected(sniffing.check_content(Path::new("tool"), elf)));
+
+        assert!(policy(&[], &[], false).check_content(Path::new("cat.png"), elf).is_ok());
+    }
+
+    #[test]
+    fn test_check_tree() {
+        let dir = std::env::temp_dir().join(format!("policy_test_{}", uuid::Uuid::now_v7()));
+        fs::create_dir_all(dir.join("album/raw")).unwrap();
+        fs::write(dir.join("album/cat.png"), b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR").unwrap();
+        fs::write(dir.join("album/raw/notes.txt"), "Just some text").unwrap();
+        let sniffing = policy(&["png", "txt"], &[], true);
+
+        assert!(sniffing.check_tree(&dir.join("album"), &dir.join("copy")).is_ok());
+        assert!(sniffing.check_tree(&dir.join("album/cat.png"), &dir.join("dog.png")).is_ok());
+
+        // Renamed, disguised, too large or anywhere in the tree
+        assert!(rejected(sniffing.check_tree(&dir.join("album/cat.png"), &dir.join("cat.exe"))));
+        assert!(rejected(sniffing.check_tree(&dir.join("album/raw/notes.txt"), &dir.join("notes.png"))));
+        std::os::unix::fs::symlink("cat.png", dir.join("album/raw/run.sh")).unwrap();
+        assert!(rejected(sniffing.check_tree(&dir.join("album"), &dir.join("copy"))));
+        fs::remove_file(dir.join("album/raw/run.sh")).unwrap();
+        fs::write(dir.join("album/raw/notes.txt"), [b'a'; 101]).unwrap();
+        assert!(rejected(sniffing.check_tree(&dir.join("album"), &dir.join("copy"))));
+
+        assert!(matches!(
+            sniffing.check_tree(&dir.join("missing"), &dir.join("copy")),
+            Err(FileServerError::FileNotFound(_))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_guard_sniffs_as_chunks_arrive() {
+        let sniffing = Arc::new(policy(&["png", "txt"], &[], true));
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+
+        assert!(rejected(PolicyGuard::new(Arc::clone(&sniffing), Path::new("tool.exe")).map(drop)));
+
+        // Rejected as soon as the start of the file is known
+        let mut guard = PolicyGuard::new(Arc::clone(&sniffing), Path::new("cat.png")).unwrap();
+        guard.check_chunk(0, b"\x7fELF\x02\x01\x01\x00").unwrap();
+        assert!(rejected(guard.check_chunk(8, &[0; 64])));
+
+        // Chunks out of order are sniffed once the gap is filled
+        let mut guard = PolicyGuard::new(Arc::clone(&sniffing), Path::new("cat.png")).unwrap();
+        guard.check_chunk(8, &png[8..]).unwrap();
+        guard.check_chunk(0, &png[..8]).unwrap();
+        guard.finish().unwrap();
+        assert!(rejected(guard.check_chunk(0, b"GIF89a")));
+        assert!(rejected(guard.check_chunk(90, &[0; 20])));
+
+        // A short text file is only sniffed at the end
+        let mut guard = PolicyGuard::new(sniffing, Path::new("notes.txt")).unwrap();
+        guard.check_chunk(0, b"MZ").unwrap();
+        assert!(rejected(guard.finish()));
+    }
+}
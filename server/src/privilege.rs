@@ -1,5 +1,5 @@
 use common::FileServerError;
-use nix::unistd::{setgid, setuid, getuid, getgid, User, Group, Uid, Gid};
+use nix::unistd::{setgid, setuid, getuid, getgid, geteuid, User, Group, Uid, Gid};
 use tracing::{info, warn, error};
 
 pub struct PrivilegeManager;
@@ -92,6 +92,30 @@ impl PrivilegeManager {
         Ok(())
     }
 
+    /// Whether files can be given to other users and groups, which takes
+    /// root and so is no longer possible once privileges have been dropped
+    pub fn can_change_ownership(&self) -> bool {
+        geteuid().is_root()
+    }
+
+    /// Uid and gid for an ownership change, each given as a name or numeric id
+    pub fn resolve_ownership(&self, username: Option<&str>, groupname: Option<&str>) -> Result<(Option<u32>, Option<u32>), FileServerError> {
+        if !self.can_change_ownership() {
+            return Err(FileServerError::PermissionDenied(
+                "Ownership can only be changed while the server runs as root".to_string()
+            ));
+        }
+
+        let invalid = |e: FileServerError| match e {
+            FileServerError::ConfigError(message) => FileServerError::InvalidPath(message),
+            e => e,
+        };
+        let uid = username.map(|user_str| self.parse_user(user_str)).transpose().map_err(invalid)?;
+        let gid = groupname.map(|group_str| self.parse_group(group_str)).transpose().map_err(invalid)?;
+
+        Ok((uid.map(|(user, _)| user.uid.as_raw()), gid.map(|(group, _)| group.gid.as_raw())))
+    }
+
     pub fn validate_user_group(&self, username: Option<&str>, groupname: Option<&str>) -> Result<(), FileServerError> {
         // If running as root, validate that specified users/groups exist
         if getuid().is_root() {
@@ -138,6 +162,11 @@ mod tests {
 
     #[test]
     fn test_drop_privileges_not_root() {
+        // Dropping privileges as root would change the uid of the whole test process
+        if getuid().is_root() {
+            return;
+        }
+
         let manager = PrivilegeManager::new();
         
         // This should not fail when not running as root
@@ -242,4 +271,19 @@ mod tests {
             assert!(result.is_ok());
         }
     }
+
+    #[test]
+    fn test_resolve_ownership() {
+        let manager = PrivilegeManager::new();
+        let result = manager.resolve_ownership(Some("root"), Some("0"));
+
+        if manager.can_change_ownership() {
+            assert_eq!(result.unwrap(), (Some(0), Some(0)));
+            assert_eq!(manager.resolve_ownership(None, Some("root")).unwrap(), (None, Some(0)));
+            let result = manager.resolve_ownership(Some("nonexistent_user_12345"), None);
+            assert!(matches!(result, Err(FileServerError::InvalidPath(_))));
+        } else {
+            assert!(matches!(result, Err(FileServerError::PermissionDenied(_))));
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,541 @@
+use crate::config::{QuotaConfig, QuotaLimits};
+use crate::file_handler;
+use common::FileServerError;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Extended attribute naming the client that wrote a file, so per-client
+/// usage can be worked out again after a restart
+const OWNER_XATTR: &str = "user.fileserver.client";
+
+/// Longest client name read back from a file
+const OWNER_XATTR_MAX_LEN: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+impl Usage {
+    fn add(&mut self, other: Usage) {
+        self.bytes = self.bytes.saturating_add(other.bytes);
+        self.files = self.files.saturating_add(other.files);
+    }
+
+    fn sub(&mut self, other: Usage) {
+        self.bytes = self.bytes.saturating_sub(other.bytes);
+        self.files = self.files.saturating_sub(other.files);
+    }
+}
+
+/// Usage of a directory as a whole, and of the regular files each client
+/// wrote to it. Every entry counts toward the whole, but only regular files
+/// count against a client, once however many links they have; directories
+/// and links a client makes are not its own.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tally {
+    pub total: Usage,
+    pub clients: HashMap<String, Usage>,
+}
+
+impl Tally {
+    /// Usage of a single entry, counted against `owner` as well if given
+    pub fn of(owner: Option<&str>, usage: Usage) -> Self {
+        let clients = owner.map(|owner| (owner.to_string(), usage)).into_iter().collect();
+        Self { total: usage, clients }
+    }
+
+    /// Usage of a tree of `entries` entries, among them regular files using
+    /// `files`, which count against `owner` as well if given
+    pub fn of_tree(owner: Option<&str>, entries: u64, files: Usage) -> Self {
+        let mut tally = Self::of(owner, files);
+        tally.total.files = entries;
+        tally
+    }
+
+    /// Counts `path` and everything below it without following symbolic
+    /// links, with regular files counted against the client recorded on
+    /// them. Every entry counts as a file; bytes are those of regular files.
+    /// A file with hard links counts its bytes once, and only if all its
+    /// links are in the tree, since removing the tree frees them only then.
+    /// Temporary files of uploads in progress are left out, since their
+    /// reservations cover them.
+    pub fn measure(path: &Path) -> Result<Self, FileServerError> {
+        Self::measure_with(path, owner)
+    }
+
+    /// Like `measure`, but with every regular file counted against `owner`,
+    /// as they will be once moved
+    pub fn measure_as(path: &Path, owner: Option<&str>) -> Result<Self, FileServerError> {
+        Self::measure_with(path, |_| owner.map(str::to_string))
+    }
+
+    fn measure_with(path: &Path, owner: impl Fn(&Path) -> Option<String>) -> Result<Self, FileServerError> {
+        let mut tally = Tally::default();
+        // Files with more than one link: one of the links, the file's size
+        // and links, and how many of them were found
+        let mut linked: HashMap<(u64, u64), (PathBuf, u64, u64, u64)> = HashMap::new();
+        let mut pending = vec![path.to_path_buf()];
+
+        while let Some(path) = pending.pop() {
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                // Removed while being counted
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            if metadata.is_dir() {
+                for entry in fs::read_dir(&path)? {
+                    let entry = entry?;
+                    if !file_handler::is_upload_temp(&entry.file_name()) {
+                        pending.push(entry.path());
+                    }
+                }
+            }
+
+            tally.total.add(Usage { bytes: 0, files: 1 });
+            if metadata.is_file() && metadata.nlink() == 1 {
+                tally.add_file(owner(&path).as_deref(), metadata.len());
+            } else if metadata.is_file() {
+                let (_, _, _, found) = linked
+                    .entry((metadata.dev(), metadata.ino()))
+                    .or_insert_with(|| (path.clone(), metadata.len(), metadata.nlink(), 0));
+                *found += 1;
+            }
+        }
+
+        for (path, bytes, links, found) in linked.into_values() {
+            if found >= links {
+                tally.add_file(owner(&path).as_deref(), bytes);
+            }
+        }
+
+        Ok(tally)
+    }
+
+    /// Counts the data of a regular file already counted as an entry
+    fn add_file(&mut self, owner: Option<&str>, bytes: u64) {
+        self.total.add(Usage { bytes, files: 0 });
+        if let Some(owner) = owner {
+            self.clients.entry(owner.to_string()).or_default().add(Usage { bytes, files: 1 });
+        }
+    }
+
+    /// Usage of the file at `path` that would be freed by replacing it
+    fn replaced(path: &Path) -> Self {
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_file() && metadata.nlink() == 1 => {
+                Self::of(owner(path).as_deref(), Usage { bytes: metadata.len(), files: 1 })
+            }
+            Ok(metadata) if !metadata.is_dir() => Self::of(None, Usage { bytes: 0, files: 1 }),
+            _ => Self::default(),
+        }
+    }
+
+    pub fn client(&self, client: &str) -> Usage {
+        self.clients.get(client).copied().unwrap_or_default()
+    }
+
+    fn add(&mut self, other: &Tally) {
+        self.total.add(other.total);
+        for (client, usage) in &other.clients {
+            self.clients.entry(client.clone()).or_default().add(*usage);
+        }
+    }
+
+    fn sub(&mut self, other: &Tally) {
+        self.total.sub(other.total);
+        for (client, usage) in &other.clients {
+            if let Some(used) = self.clients.get_mut(client) {
+                used.sub(*usage);
+            }
+        }
+        self.clients.retain(|_, usage| *usage != Usage::default());
+    }
+}
+
+#[derive(Default)]
+struct QuotaState {
+    used: Tally,
+    /// Claimed by writes still in progress
+    reserved: Tally,
+}
+
+/// Storage limits of one configured directory. Usage is measured once at
+/// startup and then tracked as the server changes the directory; `rescan`
+/// corrects whatever drift changes made outside the server cause.
+pub struct Quota {
+    directory: String,
+    root: PathBuf,
+    config: QuotaConfig,
+    state: Mutex<QuotaState>,
+}
+
+impl Quota {
+    pub fn new(directory: &str, root: &Path, config: &QuotaConfig) -> Result<Self, FileServerError> {
+        let quota = Self {
+            directory: directory.to_string(),
+            root: root.to_path_buf(),
+            config: config.clone(),
+            state: Mutex::new(QuotaState::default()),
+        };
+        quota.state.lock().unwrap().used = quota.scan()?;
+        Ok(quota)
+    }
+
+    /// Current usage, leaving out the directory itself
+    fn scan(&self) -> Result<Tally, FileServerError> {
+        let mut used = Tally::measure(&self.root)?;
+        used.total.files = used.total.files.saturating_sub(1);
+        Ok(used)
+    }
+
+    /// Measures the directory again, replacing the tracked usage
+    pub async fn rescan(self: &Arc<Self>) -> Result<(), FileServerError> {
+        let quota = Arc::clone(self);
+        let used = tokio::task::spawn_blocking(move || quota.scan())
+            .await
+            .map_err(|e| FileServerError::IoError(std::io::Error::other(e)))??;
+        self.state.lock().unwrap().used = used;
+        Ok(())
+    }
+
+    /// Total usage, and that of `client` if given, along with their limits
+    pub fn usage(&self, client: Option<&str>) -> ((Usage, QuotaLimits), Option<(Usage, QuotaLimits)>) {
+        let state = self.state.lock().unwrap();
+        let total = (state.used.total, self.config.limits);
+        let client = client.map(|client| {
+            let limits = self.config.client_limits(client).copied().unwrap_or_default();
+            (state.used.client(client), limits)
+        });
+        (total, client)
+    }
+
+    /// Sets aside room for `claim`, to be taken up once the change it stands
+    /// for is made. `credit` is what the change frees, such as a file it
+    /// replaces. New files are counted against `owner`.
+    pub fn reserve(self: &Arc<Self>, owner: Option<&str>, claim: Tally, credit: Tally) -> Result<Reservation, FileServerError> {
+        let mut state = self.state.lock().unwrap();
+        self.check(&state, &claim, &credit)?;
+        state.reserved.add(&claim);
+
+        Ok(Reservation {
+            quota: Arc::clone(self),
+            owner: owner.map(str::to_string),
+            claim,
+            credit,
+            committed: false,
+        })
+    }
+
+    /// Stops counting what a change removed
+    pub fn release(&self, tally: &Tally) {
+        self.state.lock().unwrap().used.sub(tally);
+    }
+
+    /// Fails if adding `extra` on top of what is used and reserved, less
+    /// `credit`, would go over a limit
+    fn check(&self, state: &QuotaState, extra: &Tally, credit: &Tally) -> Result<(), FileServerError> {
+        let prospective = |used: Usage, reserved: Usage, credit: Usage| {
+            let mut usage = used;
+            usage.add(reserved);
+            usage.sub(credit);
+            usage
+        };
+
+        let total = prospective(state.used.total, state.reserved.total, credit.total);
+        check_limits(&self.config.limits, total, extra.total, &format!("directory '{}'", self.directory))?;
+
+        for (client, usage) in &extra.clients {
+            if let Some(limits) = self.config.client_limits(client) {
+                let current = prospective(state.used.client(client), state.reserved.client(client), credit.client(client));
+                check_limits(limits, current, *usage, &format!("client '{}' in directory '{}'", client, self.directory))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn check_limits(limits: &QuotaLimits, current: Usage, extra: Usage, subject: &str) -> Result<(), FileServerError> {
+    if let Some(max) = limits.max_bytes {
+        if extra.bytes > 0 && current.bytes.saturating_add(extra.bytes) > max {
+            return Err(FileServerError::QuotaExceeded(format!(
+                "{} would use {} bytes, limit is {}", subject, current.bytes.saturating_add(extra.bytes), max
+            )));
+        }
+    }
+    if let Some(max) = limits.max_files {
+        if extra.files > 0 && current.files.saturating_add(extra.files) > max {
+            return Err(FileServerError::QuotaExceeded(format!(
+                "{} would hold {} files, limit is {}", subject, current.files.saturating_add(extra.files), max
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Room set aside in a quota. Dropping it without calling `commit` gives
+/// the room back.
+pub struct Reservation {
+    quota: Arc<Quota>,
+    owner: Option<String>,
+    claim: Tally,
+    credit: Tally,
+    committed: bool,
+}
+
+impl Reservation {
+    /// Reserves room for a file written to `path`, crediting the file it
+    /// replaces
+    pub fn for_file(quota: &Arc<Quota>, owner: Option<&str>, path: &Path) -> Result<Self, FileServerError> {
+        let mut credit = Tally::replaced(path);
+        let mut claim = Tally::of(owner, Usage { bytes: 0, files: 1 });
+
+        // Replacing a file leaves the directory with as many as before
+        if credit.total.files > 0 {
+            credit.total.files = 0;
+            claim.total.files = 0;
+        }
+
+        quota.reserve(owner, claim, credit)
+    }
+
+    /// The client new files are counted against
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+
+    /// Grows the room for a single file to `bytes`, failing if that would
+    /// exceed the quota
+    pub fn grow_to(&mut self, bytes: u64) -> Result<(), FileServerError> {
+        if bytes <= self.claim.total.bytes {
+            return Ok(());
+        }
+
+        let extra = Tally::of(self.owner.as_deref(), Usage { bytes: bytes - self.claim.total.bytes, files: 0 });
+        let mut state = self.quota.state.lock().unwrap();
+        self.quota.check(&state, &extra, &self.credit)?;
+        state.reserved.add(&extra);
+        self.claim.add(&extra);
+        Ok(())
+    }
+
+    /// Counts the change as made
+    pub fn commit(mut self) {
+        let mut state = self.quota.state.lock().unwrap();
+        state.reserved.sub(&self.claim);
+        state.used.add(&self.claim);
+        state.used.sub(&self.credit);
+        self.committed = true;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.quota.state.lock().unwrap().reserved.sub(&self.claim);
+        }
+    }
+}
+
+/// Records `owner` as the client that wrote the file at `path`. File systems
+/// without user extended attributes cannot keep the record, which only
+/// matters for per-client limits after a rescan.
+pub fn set_owner(path: &Path, owner: &str) {
+    let (Ok(c_path), Ok(name)) = (CString::new(path.as_os_str().as_bytes()), CString::new(OWNER_XATTR)) else {
+        return;
+    };
+
+    // SAFETY: both strings are NUL-terminated and outlive the call, and the
+    // value pointer is valid for the length passed
+    let result = unsafe {
+        libc::lsetxattr(c_path.as_ptr(), name.as_ptr(), owner.as_ptr().cast(), owner.len(), 0)
+    };
+    if result != 0 {
+        tracing::debug!("Could not record the owner of '{}': {}", path.display(), std::io::Error::last_os_error());
+    }
+}
+
+/// Records `owner` on every regular file at or below `path`
+pub fn set_owner_recursive(path: &Path, owner: &str) {
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let Ok(metadata) = fs::symlink_metadata(&path) else { continue };
+        if metadata.is_file() {
+            set_owner(&path, owner);
+        } else if metadata.is_dir() {
+            if let Ok(entries) = fs::read_dir(&path) {
+                pending.extend(entries.flatten().map(|entry| entry.path()));
+            }
+        }
+    }
+}
+
+/// The client recorded as having written the file at `path`
+fn owner(path: &Path) -> Option<String> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let name = CString::new(OWNER_XATTR).ok()?;
+    let mut value = vec![0u8; OWNER_XATTR_MAX_LEN];
+
+    // SAFETY: both strings are NUL-terminated and outlive the call, and the
+    // buffer is valid for the length passed
+    let len = unsafe {
+        libc::lgetxattr(c_path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len())
+    };
+    if len <= 0 {
+        return None;
+    }
+
+    value.truncate(len as usize);
+    String::from_utf8(value).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_root() -> PathBuf {
+        let root = std::env::temp_dir().join(format!("quota_test_{}", uuid::Uuid::now_v7()));
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs/readme.txt"), "Hello").unwrap();
+        fs::write(root.join("notes.txt"), "0123456789").unwrap();
+        root
+    }
+
+    fn quota(root: &Path, max_bytes: Option<u64>, max_files: Option<u64>, clients: &[(&str, u64)]) -> Arc<Quota> {
+        let config = QuotaConfig {
+            limits: QuotaLimits { max_bytes, max_files },
+            clients: clients
+                .iter()
+                .map(|(name, max_bytes)| (name.to_string(), QuotaLimits { max_bytes: Some(*max_bytes), max_files: None }))
+                .collect(),
+        };
+        Arc::new(Quota::new("workspace", root, &config).unwrap())
+    }
+
+    fn exceeded<T>(result: Result<T, FileServerError>) -> bool {
+        matches!(result, Err(FileServerError::QuotaExceeded(_)))
+    }
+
+    #[test]
+    fn test_measure() {
+        let root = test_root();
+        fs::hard_link(root.join("notes.txt"), root.join("docs/notes.txt")).unwrap();
+        std::os::unix::fs::symlink("notes.txt", root.join("link")).unwrap();
+        fs::write(root.join(".notes.txt.0123456789abcdef.upload"), "partial").unwrap();
+        // Clients can write files named like this, so they count
+        fs::write(root.join(".big.upload"), "written").unwrap();
+
+        let tally = Tally::measure(&root).unwrap();
+        assert_eq!(tally.total, Usage { bytes: 22, files: 7 });
+
+        let quota = quota(&root, None, None, &[]);
+        assert_eq!(quota.usage(None).0 .0, Usage { bytes: 22, files: 6 });
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_measure_leaves_out_data_linked_from_elsewhere() {
+        let root = test_root();
+        fs::hard_link(root.join("notes.txt"), root.join("docs/notes.txt")).unwrap();
+        set_owner(&root.join("notes.txt"), "alice");
+
+        // Removing either link alone frees no data, nor a file of its owner
+        let tally = Tally::measure(&root.join("notes.txt")).unwrap();
+        assert_eq!(tally, Tally::of(None, Usage { bytes: 0, files: 1 }));
+        assert_eq!(Tally::measure(&root.join("docs")).unwrap().total, Usage { bytes: 5, files: 3 });
+
+        fs::remove_file(root.join("notes.txt")).unwrap();
+        let tally = Tally::measure(&root.join("docs")).unwrap();
+        assert_eq!(tally.total, Usage { bytes: 15, files: 3 });
+        if owner(&root.join("docs/notes.txt")).is_some() {
+            assert_eq!(tally.client("alice"), Usage { bytes: 10, files: 1 });
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_reservations_stop_at_the_limit() {
+        let root = test_root();
+        let quota = quota(&root, Some(100), Some(5), &[]);
+
+        let mut first = Reservation::for_file(&quota, None, &root.join("a.txt")).unwrap();
+        first.grow_to(60).unwrap();
+        let mut second = Reservation::for_file(&quota, None, &root.join("b.txt")).unwrap();
+        assert!(exceeded(second.grow_to(30)));
+        second.grow_to(25).unwrap();
+
+        // Giving up a reservation frees its room
+        drop(first);
+        second.grow_to(85).unwrap();
+        second.commit();
+        assert_eq!(quota.usage(None).0 .0, Usage { bytes: 100, files: 4 });
+
+        // Replacing a file is credited with what it frees
+        let mut replace = Reservation::for_file(&quota, None, &root.join("notes.txt")).unwrap();
+        assert!(exceeded(replace.grow_to(11)));
+        replace.grow_to(10).unwrap();
+
+        Reservation::for_file(&quota, None, &root.join("c.txt")).unwrap();
+        quota.reserve(None, Tally::of(None, Usage { bytes: 0, files: 1 }), Tally::default()).unwrap().commit();
+        assert!(exceeded(Reservation::for_file(&quota, None, &root.join("d.txt"))));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_client_limits() {
+        let root = test_root();
+        let quota = quota(&root, None, None, &[("alice", 10), ("*", 20)]);
+
+        let mut upload = Reservation::for_file(&quota, Some("alice"), &root.join("a.txt")).unwrap();
+        upload.grow_to(10).unwrap();
+        assert!(exceeded(upload.grow_to(11)));
+        upload.commit();
+
+        let mut other = Reservation::for_file(&quota, Some("bob"), &root.join("b.txt")).unwrap();
+        other.grow_to(20).unwrap();
+        other.commit();
+
+        let (total, client) = quota.usage(Some("alice"));
+        assert_eq!(total.0, Usage { bytes: 45, files: 5 });
+        assert_eq!(client.unwrap(), (Usage { bytes: 10, files: 1 }, QuotaLimits { max_bytes: Some(10), max_files: None }));
+
+        quota.release(&Tally::of(Some("alice"), Usage { bytes: 10, files: 1 }));
+        assert_eq!(quota.usage(Some("alice")).1.unwrap().0, Usage::default());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rescan_attributes_files_to_their_owners() {
+        let root = test_root();
+        let quota = quota(&root, None, None, &[]);
+        let file = root.join("docs/readme.txt");
+        set_owner(&file, "alice");
+
+        // Some file systems keep no user extended attributes
+        if owner(&file).is_none() {
+            fs::remove_dir_all(&root).unwrap();
+            return;
+        }
+
+        fs::write(root.join("extra.txt"), "abc").unwrap();
+        quota.rescan().await.unwrap();
+        let (total, client) = quota.usage(Some("alice"));
+        assert_eq!(total.0, Usage { bytes: 18, files: 4 });
+        assert_eq!(client.unwrap().0, Usage { bytes: 5, files: 1 });
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
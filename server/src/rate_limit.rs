@@ -0,0 +1,272 @@
+use crate::auth::CallerIdentity;
+use crate::config::{LimitsConfig, RateLimits};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::metadata::MetadataValue;
+use tonic::Status;
+
+/// Metadata telling a throttled caller how many seconds to wait before
+/// trying again
+pub const RETRY_AFTER: &str = "retry-after";
+
+/// Callers tracked before idle ones are forgotten
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// Idle time after which a caller's buckets have long refilled and can be
+/// dropped
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    Read,
+    Write,
+}
+
+/// Tokens refilling at `rate` per second up to `capacity`. Taking more than
+/// is there leaves the bucket in debt, which refilling pays off first.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    rate: f64,
+    capacity: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64, capacity: f64, now: Instant) -> Self {
+        Self { tokens: capacity, rate, capacity, updated: now }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// How long until the bucket holds `amount` tokens
+    fn wait_for(&self, amount: f64) -> Duration {
+        Duration::from_secs_f64(((amount - self.tokens) / self.rate).max(0.0))
+    }
+}
+
+/// The buckets of one caller; limits that are not configured have none
+#[derive(Debug)]
+struct CallerBuckets {
+    requests: Option<Bucket>,
+    read: Option<Bucket>,
+    write: Option<Bucket>,
+    last_seen: Instant,
+}
+
+impl CallerBuckets {
+    fn new(rates: &RateLimits, now: Instant) -> Self {
+        let requests = rates.requests_per_second.map(|rate| {
+            Bucket::new(rate as f64, rates.burst.unwrap_or(rate) as f64, now)
+        });
+        // Up to a second's worth of data can go out in one go
+        let bytes = |rate: Option<u64>| rate.map(|rate| Bucket::new(rate as f64, rate as f64, now));
+
+        Self {
+            requests,
+            read: bytes(rates.read_bytes_per_second),
+            write: bytes(rates.write_bytes_per_second),
+            last_seen: now,
+        }
+    }
+
+    fn bytes(&mut self, transfer: Transfer) -> Option<&mut Bucket> {
+        match transfer {
+            Transfer::Read => self.read.as_mut(),
+            Transfer::Write => self.write.as_mut(),
+        }
+    }
+
+    /// Takes a request token, unless the caller has used up its calls or
+    /// is still paying off data it transferred; then returns how long it
+    /// has to wait
+    fn admit(&mut self, transfer: Transfer, now: Instant) -> Result<(), Duration> {
+        self.last_seen = now;
+
+        let mut wait = Duration::ZERO;
+        if let Some(requests) = self.requests.as_mut() {
+            requests.refill(now);
+            wait = wait.max(requests.wait_for(1.0));
+        }
+        if let Some(bytes) = self.bytes(transfer) {
+            bytes.refill(now);
+            wait = wait.max(bytes.wait_for(0.0));
+        }
+
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        if let Some(requests) = self.requests.as_mut() {
+            requests.tokens -= 1.0;
+        }
+        Ok(())
+    }
+
+    /// Takes `bytes` tokens, returning how long the transfer has to pause to
+    /// stay within the rate
+    fn consume(&mut self, transfer: Transfer, bytes: usize, now: Instant) -> Duration {
+        self.last_seen = now;
+        match self.bytes(transfer) {
+            Some(bucket) => {
+                bucket.refill(now);
+                bucket.tokens -= bytes as f64;
+                bucket.wait_for(0.0)
+            }
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// Rate limits on the calls and transfers of every caller, from the
+/// `[limits]` section of the configuration
+pub struct RateLimiter {
+    config: LimitsConfig,
+    callers: Mutex<HashMap<String, CallerBuckets>>,
+}
+
+#[allow(clippy::result_large_err)]
+impl RateLimiter {
+    pub fn new(config: LimitsConfig) -> Self {
+        Self { config, callers: Mutex::new(HashMap::new()) }
+    }
+
+    /// Lets a Read or Write call start, or refuses it with RESOURCE_EXHAUSTED
+    /// and the time to wait in the `retry-after` metadata. The returned
+    /// throttle paces the call's data.
+    pub fn admit(self: &Arc<Self>, identity: &CallerIdentity, transfer: Transfer) -> Result<Throttle, Status> {
+        let name = identity.names().first().copied();
+        let key = name.map_or_else(|| identity.ip.to_string(), str::to_string);
+        let now = Instant::now();
+
+        let mut callers = self.callers.lock().unwrap();
+        if callers.len() >= PRUNE_THRESHOLD {
+            callers.retain(|_, buckets| now.saturating_duration_since(buckets.last_seen) < IDLE_TIMEOUT);
+        }
+
+        let rates = self.config.rates_for(name);
+        let buckets = callers.entry(key.clone()).or_insert_with(|| CallerBuckets::new(rates, now));
+
+        if let Err(wait) = buckets.admit(transfer, now) {
+            let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+            tracing::warn!("Throttled {}: retry after {}s", identity, retry_after);
+
+            let mut status = Status::resource_exhausted(format!(
+                "Rate limit exceeded for {}; retry in {:.2}s", key, wait.as_secs_f64()
+            ));
+            status.metadata_mut().insert(RETRY_AFTER, MetadataValue::from(retry_after));
+            return Err(status);
+        }
+
+        Ok(Throttle { limiter: Arc::clone(self), key, rates: *rates, transfer })
+    }
+}
+
+/// Paces the data of one Read or Write call to its caller's rate
+pub struct Throttle {
+    limiter: Arc<RateLimiter>,
+    key: String,
+    rates: RateLimits,
+    transfer: Transfer,
+}
+
+impl Throttle {
+    /// Counts `bytes` against the caller's rate, pausing for as long as it
+    /// takes to stay within it. Concurrent calls of a caller share the rate.
+    pub async fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut callers = self.limiter.callers.lock().unwrap();
+            let now = Instant::now();
+            callers
+                .entry(self.key.clone())
+                .or_insert_with(|| CallerBuckets::new(&self.rates, now))
+                .consume(self.transfer, bytes, now)
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rates(requests_per_second: Option<u32>, burst: Option<u32>, read_bytes_per_second: Option<u64>) -> RateLimits {
+        RateLimits { requests_per_second, burst, read_bytes_per_second, write_bytes_per_second: None }
+    }
+
+    #[test]
+    fn test_requests_are_limited_to_the_burst_and_refill() {
+        let start = Instant::now();
+        let mut buckets = CallerBuckets::new(&rates(Some(2), Some(3), None), start);
+
+        for _ in 0..3 {
+            assert!(buckets.admit(Transfer::Read, start).is_ok());
+        }
+        let wait = buckets.admit(Transfer::Read, start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+
+        // Half a second brings back one call, no more
+        let later = start + Duration::from_millis(500);
+        assert!(buckets.admit(Transfer::Write, later).is_ok());
+        assert!(buckets.admit(Transfer::Write, later).is_err());
+
+        // A long pause refills only up to the burst
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(buckets.admit(Transfer::Read, much_later).is_ok());
+        }
+        assert!(buckets.admit(Transfer::Read, much_later).is_err());
+    }
+
+    #[test]
+    fn test_bytes_are_paced_and_debt_blocks_new_calls() {
+        let start = Instant::now();
+        let mut buckets = CallerBuckets::new(&rates(None, None, Some(1000)), start);
+
+        // A second's worth goes out at once, the rest has to wait
+        assert_eq!(buckets.consume(Transfer::Read, 1000, start), Duration::ZERO);
+        assert_eq!(buckets.consume(Transfer::Read, 500, start), Duration::from_millis(500));
+
+        // While in debt no new reads start, but writes are not limited
+        assert_eq!(buckets.admit(Transfer::Read, start).unwrap_err(), Duration::from_millis(500));
+        assert!(buckets.admit(Transfer::Write, start).is_ok());
+        assert_eq!(buckets.consume(Transfer::Write, 1 << 30, start), Duration::ZERO);
+
+        let later = start + Duration::from_millis(500);
+        assert!(buckets.admit(Transfer::Read, later).is_ok());
+        assert_eq!(buckets.consume(Transfer::Read, 250, later + Duration::from_millis(500)), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_limits_per_client() {
+        let mut config = LimitsConfig { rates: rates(Some(1), None, None), ..Default::default() };
+        config.clients.insert("backup".to_string(), rates(None, None, None));
+        let limiter = Arc::new(RateLimiter::new(config));
+
+        let caller = |ip: &str, name: Option<&str>| CallerIdentity {
+            ip: ip.parse().unwrap(),
+            cert_subject: None,
+            client_name: name.map(str::to_string),
+        };
+
+        assert!(limiter.admit(&caller("10.0.0.1", None), Transfer::Read).is_ok());
+        let status = limiter.admit(&caller("10.0.0.1", None), Transfer::Read).err().unwrap();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(status.metadata().get(RETRY_AFTER).unwrap(), "1");
+
+        // Other addresses and clients have buckets of their own
+        assert!(limiter.admit(&caller("10.0.0.2", None), Transfer::Read).is_ok());
+        assert!(limiter.admit(&caller("10.0.0.1", Some("ci")), Transfer::Read).is_ok());
+        assert!(limiter.admit(&caller("10.0.0.1", Some("ci")), Transfer::Read).is_err());
+        for _ in 0..10 {
+            assert!(limiter.admit(&caller("10.0.0.1", Some("backup")), Transfer::Read).is_ok());
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::config::FollowSymlinks;
+use common::FileServerError;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+/// Where paths of one configured directory may lead once symbolic links are
+/// taken into account
+pub struct Confinement<'a> {
+    pub root: &'a Path,
+    pub policy: FollowSymlinks,
+    /// Subtree, relative to the root, that no link may lead into
+    pub hidden: Option<&'a Path>,
+}
+
+impl Confinement<'_> {
+    /// Walks `relative` from the root component by component, canonicalizing
+    /// each symbolic link met on the way and checking where it leads against
+    /// the policy, and returns where the walk ends up. A link as the final
+    /// component is only followed with `follow_final`, so operations on the
+    /// link itself still work when its target is out of bounds.
+    ///
+    /// The walk stops following links at the first component that does not
+    /// exist, since nothing below it can be a link yet.
+    pub fn resolve(&self, relative: &Path, follow_final: bool) -> Result<PathBuf, FileServerError> {
+        let components = relative
+            .components()
+            .filter(|c| *c != Component::CurDir)
+            .map(|c| match c {
+                Component::Normal(name) => Ok(name),
+                _ => Err(FileServerError::InvalidPath(format!("'{}' must be relative", relative.display()))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let root = fs::canonicalize(self.root)?;
+        let mut current = root.clone();
+
+        let mut walk = components.iter().enumerate();
+        for (index, name) in walk.by_ref() {
+            current.push(name);
+            let metadata = match fs::symlink_metadata(&current) {
+                Ok(metadata) => metadata,
+                Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => break,
+                Err(e) => return Err(e.into()),
+            };
+
+            let is_final = index + 1 == components.len();
+            if !metadata.file_type().is_symlink() || (is_final && !follow_final) {
+                continue;
+            }
+
+            let link = components[..=index].iter().collect::<PathBuf>();
+            if self.policy == FollowSymlinks::Never {
+                return Err(FileServerError::PermissionDenied(format!(
+                    "'{}' is a symbolic link", link.display()
+                )));
+            }
+
+            current = match fs::canonicalize(&current) {
+                Ok(target) => target,
+                Err(_) if self.policy == FollowSymlinks::Always => break,
+                Err(_) => {
+                    return Err(FileServerError::PermissionDenied(format!(
+                        "'{}' is a dangling symbolic link", link.display()
+                    )));
+                }
+            };
+
+            match current.strip_prefix(&root) {
+                Ok(target) if self.hidden.is_some_and(|hidden| target.starts_with(hidden)) => {
+                    return Err(FileServerError::PermissionDenied(format!(
+                        "'{}' links into a hidden location", link.display()
+                    )));
+                }
+                Err(_) if self.policy == FollowSymlinks::WithinRoot => {
+                    return Err(FileServerError::PermissionDenied(format!(
+                        "'{}' links outside the directory", link.display()
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        for (_, name) in walk {
+            current.push(name);
+        }
+        Ok(current)
+    }
+
+    /// Where a path returned by `resolve` lies relative to the root, or
+    /// `None` if a link led out of it
+    pub fn within_root(&self, resolved: &Path) -> Option<PathBuf> {
+        let root = fs::canonicalize(self.root).ok()?;
+        resolved.strip_prefix(root).ok().map(Path::to_path_buf)
+    }
+}
+
+/// Where a symbolic link at `link` pointing to `target` leads, both relative
+/// to a directory root, worked out without consulting the file system.
+/// Absolute targets and targets that climb above the root are refused.
+pub fn link_target(link: &Path, target: &Path) -> Result<PathBuf, FileServerError> {
+    if target.as_os_str().is_empty() {
+        return Err(FileServerError::InvalidPath("Link target cannot be empty".to_string()));
+    }
+
+    let mut resolved = link.parent().map(Path::to_path_buf).unwrap_or_default();
+    for component in target.components() {
+        match component {
+            Component::Normal(name) => resolved.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return Err(FileServerError::PermissionDenied(format!(
+                        "Link target '{}' leads outside the directory", target.display()
+                    )));
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(FileServerError::InvalidPath(format!(
+                    "Link target '{}' must be relative", target.display()
+                )));
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn test_root() -> PathBuf {
+        let root = std::env::temp_dir().join(format!("resolve_test_{}", uuid::Uuid::now_v7()));
+        fs::create_dir_all(root.join("docs/private")).unwrap();
+        fs::create_dir_all(root.join(".trash")).unwrap();
+        fs::write(root.join("docs/readme.txt"), "Hello").unwrap();
+        root
+    }
+
+    fn confinement(root: &Path, policy: FollowSymlinks) -> Confinement<'_> {
+        Confinement { root, policy, hidden: Some(Path::new(".trash")) }
+    }
+
+    fn is_denied(result: Result<PathBuf, FileServerError>) -> bool {
+        matches!(result, Err(FileServerError::PermissionDenied(_)))
+    }
+
+    #[test]
+    fn test_plain_and_missing_paths() {
+        let root = test_root();
+        let within = confinement(&root, FollowSymlinks::WithinRoot);
+
+        assert_eq!(within.resolve(Path::new("docs/readme.txt"), true).unwrap(), root.join("docs/readme.txt"));
+        assert_eq!(within.resolve(Path::new("new/dir/file.txt"), true).unwrap(), root.join("new/dir/file.txt"));
+        assert_eq!(within.resolve(Path::new("docs/readme.txt/below"), true).unwrap(), root.join("docs/readme.txt/below"));
+        assert_eq!(within.resolve(Path::new(""), true).unwrap(), root.join(""));
+        assert!(matches!(within.resolve(Path::new("../etc"), true), Err(FileServerError::InvalidPath(_))));
+        assert!(matches!(within.resolve(Path::new("/etc"), true), Err(FileServerError::InvalidPath(_))));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_escaping_links_are_denied() {
+        let root = test_root();
+        symlink("/etc", root.join("etc")).unwrap();
+        symlink("../..", root.join("docs/up")).unwrap();
+        symlink("/etc/passwd", root.join("passwd")).unwrap();
+        // A chain that only leaves the root at its last hop
+        symlink("docs/up", root.join("chain")).unwrap();
+        let within = confinement(&root, FollowSymlinks::WithinRoot);
+
+        assert!(is_denied(within.resolve(Path::new("etc/passwd"), true)));
+        assert!(is_denied(within.resolve(Path::new("etc/new.txt"), true)));
+        assert!(is_denied(within.resolve(Path::new("docs/up/etc/passwd"), true)));
+        assert!(is_denied(within.resolve(Path::new("passwd"), true)));
+        assert!(is_denied(within.resolve(Path::new("chain/x"), true)));
+
+        // The links themselves can still be operated on
+        assert_eq!(within.resolve(Path::new("passwd"), false).unwrap(), root.join("passwd"));
+        assert_eq!(within.resolve(Path::new("docs/up"), false).unwrap(), root.join("docs/up"));
+        assert!(is_denied(within.resolve(Path::new("etc/passwd"), false)));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_links_within_root_follow_the_policy() {
+        let root = test_root();
+        symlink("docs", root.join("alias")).unwrap();
+        symlink("../../alias/readme.txt", root.join("docs/private/readme")).unwrap();
+
+        let within = confinement(&root, FollowSymlinks::WithinRoot);
+        let resolved = within.resolve(Path::new("alias/readme.txt"), true).unwrap();
+        assert_eq!(resolved, root.join("docs/readme.txt"));
+        assert_eq!(within.within_root(&resolved).unwrap(), Path::new("docs/readme.txt"));
+        assert_eq!(within.resolve(Path::new("docs/private/readme"), true).unwrap(), root.join("docs/readme.txt"));
+        assert_eq!(within.resolve(Path::new("alias/new/file.txt"), true).unwrap(), root.join("docs/new/file.txt"));
+        assert_eq!(within.resolve(Path::new("alias"), false).unwrap(), root.join("alias"));
+
+        let never = confinement(&root, FollowSymlinks::Never);
+        assert!(is_denied(never.resolve(Path::new("alias/readme.txt"), true)));
+        assert!(is_denied(never.resolve(Path::new("alias"), true)));
+        assert!(never.resolve(Path::new("alias"), false).is_ok());
+        assert!(never.resolve(Path::new("docs/readme.txt"), true).is_ok());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_always_follows_links_out_of_the_root() {
+        let root = test_root();
+        symlink("/etc", root.join("etc")).unwrap();
+        symlink(".trash", root.join("bin")).unwrap();
+
+        let always = confinement(&root, FollowSymlinks::Always);
+        let resolved = always.resolve(Path::new("etc/passwd"), true).unwrap();
+        assert_eq!(resolved, Path::new("/etc/passwd"));
+        assert_eq!(always.within_root(&resolved), None);
+
+        // Even then the hidden subtree stays out of reach
+        assert!(is_denied(always.resolve(Path::new("bin/item"), true)));
+        assert!(is_denied(confinement(&root, FollowSymlinks::WithinRoot).resolve(Path::new("bin"), true)));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_dangling_links() {
+        let root = test_root();
+        symlink("/nonexistent/target", root.join("outside")).unwrap();
+        symlink("missing.txt", root.join("inside")).unwrap();
+        let within = confinement(&root, FollowSymlinks::WithinRoot);
+
+        // Writing through either would create a file wherever the link points
+        assert!(is_denied(within.resolve(Path::new("outside"), true)));
+        assert!(is_denied(within.resolve(Path::new("inside"), true)));
+        assert!(within.resolve(Path::new("outside"), false).is_ok());
+        assert!(confinement(&root, FollowSymlinks::Always).resolve(Path::new("outside"), true).is_ok());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_link_target() {
+        let target = |link: &str, target: &str| link_target(Path::new(link), Path::new(target));
+
+        assert_eq!(target("current", "releases/42").unwrap(), Path::new("releases/42"));
+        assert_eq!(target("app/current", "./releases/42").unwrap(), Path::new("app/releases/42"));
+        assert_eq!(target("app/bin/run", "../../shared/run.sh").unwrap(), Path::new("shared/run.sh"));
+        assert_eq!(target("app/top", "..").unwrap(), Path::new(""));
+
+        assert!(matches!(target("current", "../outside"), Err(FileServerError::PermissionDenied(_))));
+        assert!(matches!(target("app/current", "releases/../../../etc"), Err(FileServerError::PermissionDenied(_))));
+        assert!(matches!(target("current", "/etc/passwd"), Err(FileServerError::InvalidPath(_))));
+        assert!(matches!(target("current", ""), Err(FileServerError::InvalidPath(_))));
+    }
+}
@@ -1,25 +1,156 @@
-use crate::auth::AuthService;
-use crate::file_handler::FileHandler;
+use crate::audit;
+use crate::auth::{AuthService, CallerIdentity};
+use crate::config::{Operation, QuotaLimits};
+use crate::file_handler::{self, AttributeChanges, FileHandler, LinkTarget, UploadFile, UploadLimits};
+use crate::policy::{FilePolicy, PolicyGuard};
+use crate::privilege::PrivilegeManager;
+use crate::quota::{self, Quota, Reservation, Tally};
+use crate::rate_limit::{RateLimiter, Throttle, Transfer};
+use crate::trash::{Trash, TRASH_DIR};
+use crate::grep::{self, GrepBudget, GrepPattern};
+use crate::resolve::{self, Confinement};
+use crate::walk::{self, FindCriteria, ListOptions};
+use crate::upload::{PendingUpload, UploadSessions};
 use common::*;
-use std::path::Path;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
-use std::time::SystemTime;
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
 use tokio_stream::{wrappers::ReceiverStream, StreamExt};
 use tonic::{Request, Response, Status, Streaming};
 
+/// How often trash directories are checked for expired items
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Most results sent in one message of a find or grep
+const SEARCH_BATCH_SIZE: usize = 100;
+
+/// Longest a find or grep holds on to results before sending them
+const SEARCH_FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Minimum time between progress messages of a copy
+const COPY_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often directories with a quota are measured again, to correct for
+/// changes made outside the server
+const QUOTA_RESCAN_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
 pub struct FileServiceImpl {
     auth: Arc<AuthService>,
     file_handler: Arc<FileHandler>,
+    uploads: Arc<UploadSessions>,
+    trash: HashMap<String, Arc<Trash>>,
+    quotas: HashMap<String, Arc<Quota>>,
+    policies: HashMap<String, Arc<FilePolicy>>,
+    limiter: Option<Arc<RateLimiter>>,
+    /// Slots for the Read, Write and upload streams served at once
+    transfers: Option<Arc<Semaphore>>,
+    transfer_queue_timeout: Duration,
+    privileges: PrivilegeManager,
     start_time: SystemTime,
 }
 
+// Helpers return the `Status` the handlers answer with, large as it is
+#[allow(clippy::result_large_err)]
 impl FileServiceImpl {
-    pub fn new(auth: AuthService) -> Self {
-        Self {
-            auth: Arc::new(auth),
+    /// Fails if the usage of a directory with a quota cannot be measured
+    pub fn new(auth: Arc<AuthService>) -> Result<Self, FileServerError> {
+        let upload_timeout = Duration::from_secs(auth.config.server.upload_session_timeout_seconds);
+        let uploads = UploadSessions::new(upload_timeout, auth.config.server.max_upload_sessions_per_client);
+        let trash = auth.config.directories
+            .iter()
+            .filter_map(|dir| {
+                let trash = Trash::new(Path::new(&dir.path), dir.trash.as_ref()?);
+                Some((dir.name.clone(), Arc::new(trash)))
+            })
+            .collect();
+
+        let policies = auth.config.directories
+            .iter()
+            .filter_map(|dir| Some((dir.name.clone(), Arc::new(FilePolicy::new(dir.file_policy.as_ref()?)))))
+            .collect();
+
+        // No upload survives a restart, so whatever temporary files remain
+        // would otherwise stay there for good
+        for dir in auth.config.directories.iter().filter(|dir| dir.allows(Operation::Write)) {
+            match file_handler::remove_stale_uploads(Path::new(&dir.path)) {
+                Ok(0) => {}
+                Ok(removed) => tracing::info!("Removed {} stale upload files from '{}'", removed, dir.name),
+                Err(e) => tracing::warn!("Failed to remove stale upload files from '{}': {}", dir.name, e),
+            }
+        }
+
+        let mut quotas = HashMap::new();
+        for dir in &auth.config.directories {
+            if let Some(config) = &dir.quota {
+                let quota = Quota::new(&dir.name, Path::new(&dir.path), config)?;
+                let ((used, _), _) = quota.usage(None);
+                tracing::info!("Directory '{}' uses {} bytes in {} files", dir.name, used.bytes, used.files);
+                quotas.insert(dir.name.clone(), Arc::new(quota));
+            }
+        }
+
+        let limiter = auth.config.limits.clone().map(|limits| Arc::new(RateLimiter::new(limits)));
+        let transfers = auth.config.server.max_concurrent_transfers.map(|max| Arc::new(Semaphore::new(max)));
+        let transfer_queue_timeout = Duration::from_secs(auth.config.server.transfer_queue_timeout_seconds);
+
+        Ok(Self {
+            auth,
             file_handler: Arc::new(FileHandler::new()),
+            uploads: Arc::new(uploads),
+            trash,
+            quotas,
+            policies,
+            limiter,
+            transfers,
+            transfer_queue_timeout,
+            privileges: PrivilegeManager::new(),
             start_time: SystemTime::now(),
+        })
+    }
+
+    /// Starts housekeeping tasks that run for the lifetime of the server
+    pub fn spawn_background_tasks(&self) {
+        self.uploads.spawn_reaper();
+
+        if !self.trash.is_empty() {
+            let trash = self.trash.clone();
+            let quotas = self.quotas.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(TRASH_PURGE_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    for (directory, trash) in &trash {
+                        match trash.purge_expired(SystemTime::now()).await {
+                            Ok(0) => {}
+                            Ok(purged) => {
+                                tracing::info!("Purged {} expired trash items from '{}'", purged, directory);
+                                if let Some(quota) = quotas.get(directory) {
+                                    rescan_quota(directory, quota).await;
+                                }
+                            }
+                            Err(e) => tracing::error!("Failed to purge trash of '{}': {}", directory, e),
+                        }
+                    }
+                }
+            });
+        }
+
+        if !self.quotas.is_empty() {
+            let quotas = self.quotas.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(QUOTA_RESCAN_INTERVAL);
+                // The first tick completes at once, and usage was just measured
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    for (directory, quota) in &quotas {
+                        rescan_quota(directory, quota).await;
+                    }
+                }
+            });
         }
     }
 
@@ -27,46 +158,368 @@ impl FileServiceImpl {
         if path.is_empty() {
             return Err(Status::invalid_argument("Path cannot be empty"));
         }
+        audit::path(path);
 
         let parts: Vec<&str> = path.splitn(2, '/').collect();
         let directory_name = parts[0].to_string();
-        let file_path = if parts.len() > 1 {
-            parts[1].to_string()
-        } else {
-            String::new()
+        // Empty and `.` components would slip past the ACL prefixes, which
+        // are matched component by component
+        let file_path = match parts.get(1) {
+            Some(file_path) => file_path.split('/').filter(|c| !c.is_empty() && *c != ".").collect::<Vec<_>>().join("/"),
+            None => String::new(),
         };
 
         Ok((directory_name, file_path))
     }
 
-    fn resolve_full_path(&self, directory_name: &str, file_path: &str, operation: &str) -> Result<std::path::PathBuf, Status> {
+    fn caller<T>(request: &Request<T>) -> Result<CallerIdentity, Status> {
+        request.extensions()
+            .get::<CallerIdentity>()
+            .cloned()
+            .ok_or_else(|| Status::unauthenticated("Caller identity not established"))
+    }
+
+    /// Admits a Read or Write call under the caller's rate limits, if any
+    fn throttle(&self, identity: &CallerIdentity, transfer: Transfer) -> Result<Option<Throttle>, Status> {
+        self.limiter.as_ref().map(|limiter| limiter.admit(identity, transfer)).transpose()
+    }
+
+    /// Waits for one of the `max_concurrent_transfers` slots, refusing the
+    /// call if none frees up within the queue timeout
+    async fn transfer_slot(&self) -> Result<Option<OwnedSemaphorePermit>, Status> {
+        let Some(transfers) = &self.transfers else {
+            return Ok(None);
+        };
+
+        match tokio::time::timeout(self.transfer_queue_timeout, Arc::clone(transfers).acquire_owned()).await {
+            Ok(slot) => Ok(Some(slot.expect("semaphore is never closed"))),
+            Err(_) => {
+                tracing::warn!("Refused transfer after waiting {:?} for a slot", self.transfer_queue_timeout);
+                Err(Status::resource_exhausted("Too many transfers in progress, try again later"))
+            }
+        }
+    }
+
+    fn resolve_full_path(
+        &self,
+        identity: &CallerIdentity,
+        directory_name: &str,
+        file_path: &str,
+        operation: Operation,
+    ) -> Result<std::path::PathBuf, Status> {
+        self.resolve(identity, directory_name, file_path, operation, true)
+    }
+
+    /// Like `resolve_full_path`, but a symbolic link at the end of the path is
+    /// not followed, for operations on the link itself
+    fn resolve_entry_path(
+        &self,
+        identity: &CallerIdentity,
+        directory_name: &str,
+        file_path: &str,
+        operation: Operation,
+    ) -> Result<std::path::PathBuf, Status> {
+        self.resolve(identity, directory_name, file_path, operation, false)
+    }
+
+    fn resolve(
+        &self,
+        identity: &CallerIdentity,
+        directory_name: &str,
+        file_path: &str,
+        operation: Operation,
+        follow_final: bool,
+    ) -> Result<std::path::PathBuf, Status> {
         self.auth.validate_path(file_path)
             .map_err(|e| Status::invalid_argument(e.to_string()))?;
 
-        let base_path = self.auth.check_directory_access(directory_name, operation)
+        if self.is_trash_path(directory_name, file_path) {
+            return Err(Status::permission_denied("The trash is only accessible through the trash operations"));
+        }
+
+        let base_path = self.auth.check_directory_access(identity, directory_name, file_path, operation)
             .map_err(|e| Status::permission_denied(e.to_string()))?;
+        let policy = self.auth.config.get_directory(directory_name)
+            .map(|directory| directory.follow_symlinks)
+            .unwrap_or_default();
+
+        let confinement = Confinement {
+            root: Path::new(&base_path),
+            policy,
+            hidden: self.trash.contains_key(directory_name).then_some(Path::new(TRASH_DIR)),
+        };
+        let resolved = confinement.resolve(Path::new(file_path), follow_final).map_err(status_from_error)?;
+        audit::resolved(&resolved);
+
+        // A link may lead somewhere else in the directory, which has to be
+        // open to the caller as well
+        if let Some(target) = confinement.within_root(&resolved) {
+            let target = target.to_string_lossy();
+            if target != file_path {
+                self.auth.validate_path(&target)
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?;
+                if self.is_trash_path(directory_name, &target) {
+                    return Err(Status::permission_denied("The trash is only accessible through the trash operations"));
+                }
+                self.auth.check_directory_access(identity, directory_name, &target, operation)
+                    .map_err(|e| Status::permission_denied(e.to_string()))?;
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Where `full_path`, as returned by `resolve`, lies in the directory,
+    /// which links may have made different from the `file_path` asked for
+    fn resolved_path(&self, directory_name: &str, file_path: &str, full_path: &Path) -> String {
+        self.auth.config.get_directory(directory_name)
+            .and_then(|directory| std::fs::canonicalize(&directory.path).ok())
+            .and_then(|root| full_path.strip_prefix(root).ok().map(|path| path.to_string_lossy().to_string()))
+            .unwrap_or_else(|| file_path.to_string())
+    }
+
+    fn is_trash_path(&self, directory_name: &str, file_path: &str) -> bool {
+        self.trash.contains_key(directory_name) && is_in_trash(Path::new(file_path))
+    }
+
+    /// Filter for walks below `full_path`, as returned by `resolve` for
+    /// `file_path`, hiding entries the caller's ACLs do not let them read,
+    /// the trash and the temporary files of uploads. Entries are checked
+    /// where they really lie, which links may have made different from
+    /// `file_path`.
+    fn visibility(
+        &self,
+        identity: CallerIdentity,
+        directory_name: &str,
+        file_path: &str,
+        full_path: &Path,
+    ) -> impl Fn(&Path, bool) -> bool + Send + 'static {
+        let auth = Arc::clone(&self.auth);
+        let has_trash = self.trash.contains_key(directory_name);
+        let directory_name = directory_name.to_string();
+        let file_path = self.resolved_path(&directory_name, file_path, full_path);
+
+        move |entry: &Path, _is_directory: bool| {
+            let entry_path = Path::new(&file_path).join(entry);
+            let hidden = (has_trash && is_in_trash(&entry_path))
+                || entry.file_name().is_some_and(file_handler::is_upload_temp);
+            !hidden
+                && auth
+                    .check_directory_access(&identity, &directory_name, &entry_path.to_string_lossy(), Operation::Read)
+                    .is_ok()
+        }
+    }
+
+    fn trash(&self, directory_name: &str) -> Result<&Arc<Trash>, Status> {
+        self.trash.get(directory_name).ok_or_else(|| {
+            Status::failed_precondition(format!("Directory '{}' has no trash", directory_name))
+        })
+    }
+
+    fn check_links_allowed(&self, directory_name: &str) -> Result<(), Status> {
+        match self.auth.config.get_directory(directory_name) {
+            Some(directory) if directory.allow_links => Ok(()),
+            _ => Err(Status::permission_denied(format!("Links are not enabled for directory '{}'", directory_name))),
+        }
+    }
+
+    /// Checks that a symbolic link at `link` to `target` leads somewhere in
+    /// the directory the caller could read directly
+    fn check_link_target(&self, identity: &CallerIdentity, directory_name: &str, link: &Path, target: &Path) -> Result<(), Status> {
+        let resolved = resolve::link_target(link, target).map_err(status_from_error)?;
+        self.resolve_full_path(identity, directory_name, &resolved.to_string_lossy(), Operation::Read)?;
+        Ok(())
+    }
+
+    /// Checks that symbolic links about to be placed at or below
+    /// `file_path`, given by their paths relative to it and their targets,
+    /// are ones the caller could make there with Symlink
+    fn check_links(
+        &self,
+        identity: &CallerIdentity,
+        directory_name: &str,
+        file_path: &str,
+        links: &[(PathBuf, PathBuf)],
+    ) -> Result<(), Status> {
+        if links.is_empty() {
+            return Ok(());
+        }
+
+        self.check_links_allowed(directory_name)?;
+        for (relative, target) in links {
+            let link = Path::new(file_path).components().chain(relative.components()).collect::<PathBuf>();
+            self.check_link_target(identity, directory_name, &link, target)?;
+        }
+        Ok(())
+    }
+
+    fn check_set_attributes_allowed(&self, directory_name: &str) -> Result<(), Status> {
+        match self.auth.config.get_directory(directory_name) {
+            Some(directory) if directory.allow_set_attributes => Ok(()),
+            _ => Err(Status::permission_denied(format!("Setting attributes is not enabled for directory '{}'", directory_name))),
+        }
+    }
+
+    /// Room in the directory's quota for a new file or link at `full_path`,
+    /// if the directory has a quota. Files are counted against `owner`;
+    /// links count toward the directory alone, like directories.
+    fn reserve_entry(
+        &self,
+        directory_name: &str,
+        owner: Option<&str>,
+        full_path: &Path,
+    ) -> Result<Option<Reservation>, Status> {
+        self.quotas
+            .get(directory_name)
+            .map(|quota| Reservation::for_file(quota, owner, full_path))
+            .transpose()
+            .map_err(status_from_error)
+    }
+
+    /// The quota and file policy a file written to `full_path` by the
+    /// caller has to keep to
+    fn upload_limits(
+        &self,
+        identity: &CallerIdentity,
+        directory_name: &str,
+        full_path: &Path,
+    ) -> Result<UploadLimits, Status> {
+        let policy = self.policies
+            .get(directory_name)
+            .map(|policy| PolicyGuard::new(Arc::clone(policy), full_path))
+            .transpose()
+            .map_err(status_from_error)?;
+        let quota = self.reserve_entry(directory_name, quota_owner(identity), full_path)?;
+
+        Ok(UploadLimits { quota, policy })
+    }
+
+    /// Applies the directory's file policy to what `source` would become at
+    /// `destination` once moved, copied or linked there
+    async fn check_policy(&self, directory_name: &str, source: &Path, destination: &Path) -> Result<(), Status> {
+        let Some(policy) = self.policies.get(directory_name).map(Arc::clone) else {
+            return Ok(());
+        };
+        let (source, destination) = (source.to_path_buf(), destination.to_path_buf());
+        tokio::task::spawn_blocking(move || policy.check_tree(&source, &destination))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(status_from_error)
+    }
+
+    /// What `full_path` and everything below it count for in the directory's
+    /// quota, or `None` if the directory has no quota or the path could not
+    /// be measured, in which case the next rescan catches up
+    async fn measure(&self, directory_name: &str, full_path: &Path) -> Option<(Arc<Quota>, Tally)> {
+        let quota = Arc::clone(self.quotas.get(directory_name)?);
+        let path = full_path.to_path_buf();
+        match tokio::task::spawn_blocking(move || Tally::measure(&path)).await {
+            Ok(Ok(tally)) => Some((quota, tally)),
+            _ => None,
+        }
+    }
+
+    /// Deletes a path, or moves it to the trash if the directory has one.
+    /// Returns the id of the trash item.
+    async fn remove_path(
+        &self,
+        identity: &CallerIdentity,
+        directory_name: &str,
+        file_path: &str,
+        full_path: &Path,
+        recursive: bool,
+        directory_only: bool,
+    ) -> Result<Option<String>, FileServerError> {
+        let Some(trash) = self.trash.get(directory_name) else {
+            let removed = self.measure(directory_name, full_path).await;
+            if directory_only {
+                self.file_handler.remove_directory(full_path, recursive).await?;
+            } else {
+                self.file_handler.delete_file(full_path, recursive).await?;
+            }
+            if let Some((quota, tally)) = removed {
+                quota.release(&tally);
+            }
+            return Ok(None);
+        };
+
+        let metadata = self.file_handler.check_removable(full_path, recursive).await?;
+        if directory_only && !metadata.is_dir() {
+            return Err(FileServerError::InvalidPath("Path is not a directory".to_string()));
+        }
+
+        let id = trash.put(full_path, file_path, &identity.to_string()).await?;
+        Ok(Some(id))
+    }
+
+    /// Recursive deletes can be turned off per directory
+    fn check_recursive_delete(&self, directory_name: &str) -> Result<(), Status> {
+        match self.auth.config.get_directory(directory_name) {
+            Some(dir) if !dir.allow_recursive_delete => Err(Status::permission_denied(format!(
+                "Recursive delete is disabled for directory '{}'", directory_name
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn upload_session(&self, upload_id: &str, pending: &PendingUpload) -> UploadSession {
+        UploadSession {
+            upload_id: upload_id.to_string(),
+            path: pending.path().to_string(),
+            committed_bytes: pending.committed(),
+            expires_in_seconds: self.uploads.expires_in(pending),
+        }
+    }
+
+    /// Writes chunks to an upload session until the stream ends. Every chunk
+    /// must continue exactly where the session left off.
+    async fn append_chunks(
+        pending: &mut PendingUpload,
+        first: UploadChunk,
+        stream: &mut Streaming<UploadChunk>,
+        throttle: Option<&Throttle>,
+    ) -> Result<(), Status> {
+        let upload_id = first.upload_id.clone();
+        let mut next = Some(first);
+
+        while let Some(chunk) = next {
+            if chunk.upload_id != upload_id {
+                return Err(Status::invalid_argument("All chunks must have the same upload id"));
+            }
 
-        let full_path = Path::new(&base_path).join(file_path);
-        
-        if !full_path.starts_with(&base_path) {
-            return Err(Status::permission_denied("Path traversal attempt detected"));
+            if chunk.offset != pending.committed() {
+                return Err(Status::failed_precondition(format!(
+                    "Chunk offset {} does not match committed size {}",
+                    chunk.offset,
+                    pending.committed()
+                )));
+            }
+
+            verify_crc32c(&chunk.data, chunk.crc32c, chunk.offset)?;
+            pending.append(&chunk.data).await
+                .map_err(status_from_error)?;
+            audit::add_bytes(chunk.data.len());
+            if let Some(throttle) = throttle {
+                throttle.consume(chunk.data.len()).await;
+            }
+
+            next = stream.next().await.transpose()?;
         }
 
-        Ok(full_path)
+        Ok(())
     }
 }
 
 #[tonic::async_trait]
 impl file_service_server::FileService for FileServiceImpl {
     async fn authenticate(&self, request: Request<ConnectRequest>) -> Result<Response<ConnectResponse>, Status> {
-        self.auth.authorize_connection(&Request::new(()))?;
-
+        let identity = Self::caller(&request)?;
         let req = request.into_inner();
-        tracing::info!("Client {} connected", req.client_id);
+        tracing::info!("Client {} connected ({})", req.client_id, identity);
 
         let auth = Arc::clone(&self.auth);
         let available_directories: Vec<String> = auth.config.directories
             .iter()
+            .filter(|d| auth.can_see_directory(&identity, d))
             .map(|d| d.name.clone())
             .collect();
 
@@ -96,9 +549,10 @@ impl file_service_server::FileService for FileServiceImpl {
     }
 
     async fn stat(&self, request: Request<StatRequest>) -> Result<Response<FileMetadata>, Status> {
+        let identity = Self::caller(&request)?;
         let req = request.into_inner();
         let (directory_name, file_path) = self.parse_path(&req.path)?;
-        let full_path = self.resolve_full_path(&directory_name, &file_path, "read")?;
+        let full_path = self.resolve_entry_path(&identity, &directory_name, &file_path, Operation::Read)?;
 
         let metadata = self.file_handler.stat(&full_path).await
             .map_err(|e| Status::not_found(e.to_string()))?;
@@ -107,57 +561,96 @@ impl file_service_server::FileService for FileServiceImpl {
     }
 
     async fn list(&self, request: Request<ListRequest>) -> Result<Response<ListResponse>, Status> {
+        let identity = Self::caller(&request)?;
         let req = request.into_inner();
         let (directory_name, file_path) = self.parse_path(&req.path)?;
-        let full_path = self.resolve_full_path(&directory_name, &file_path, "read")?;
+        let full_path = self.resolve_full_path(&identity, &directory_name, &file_path, Operation::Read)?;
 
-        let entries = self.file_handler.list_directory(&full_path).await
-            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let options = ListOptions::from_request(&req).map_err(status_from_error)?;
 
-        let response = ListResponse { entries };
+        let visible = self.visibility(identity, &directory_name, &file_path, &full_path);
+        let page = self.file_handler.list_directory(&full_path, options, visible).await
+            .map_err(status_from_error)?;
+
+        let response = ListResponse {
+            entries: page.entries,
+            next_page_token: page.next_page_token.unwrap_or_default(),
+        };
         Ok(Response::new(response))
     }
 
     type ReadStream = ReceiverStream<Result<DataChunk, Status>>;
 
     async fn read(&self, request: Request<ReadRequest>) -> Result<Response<Self::ReadStream>, Status> {
+        let identity = Self::caller(&request)?;
+        let throttle = self.throttle(&identity, Transfer::Read)?;
+        let slot = self.transfer_slot().await?;
         let req = request.into_inner();
         let (directory_name, file_path) = self.parse_path(&req.path)?;
-        let full_path = self.resolve_full_path(&directory_name, &file_path, "read")?;
+        let full_path = self.resolve_full_path(&identity, &directory_name, &file_path, Operation::Read)?;
 
+        let mut reader = self.file_handler.open_reader(&full_path, req.offset, req.length).await
+            .map_err(|e| Status::not_found(e.to_string()))?;
+
+        // The bounded channel provides backpressure: the next chunk is only
+        // read from disk once the client has made room for it.
         let (tx, rx) = mpsc::channel(4);
-        let file_handler = Arc::clone(&self.file_handler);
         let path_clone = req.path.clone();
+        let audit = audit::current();
 
         tokio::spawn(async move {
-            const CHUNK_SIZE: usize = 64 * 1024; // 64KB chunks
-            
-            match file_handler.read_file(&full_path, req.offset, req.length).await {
-                Ok(data) => {
-                    let mut offset = req.offset.unwrap_or(0);
-                    
-                    for chunk in data.chunks(CHUNK_SIZE) {
-                        let is_last = chunk.len() < CHUNK_SIZE;
+            // The slot is given back once the whole file has been sent
+            let _slot = slot;
+            let mut hasher = Sha256::new();
+
+            if reader.remaining() == 0 {
+                let data_chunk = DataChunk {
+                    path: path_clone,
+                    data: Vec::new(),
+                    offset: reader.offset(),
+                    is_last: true,
+                    crc32c: Some(crc32c::crc32c(&[])),
+                    sha256: Some(hex::encode(hasher.finalize())),
+                };
+                let _ = tx.send(Ok(data_chunk)).await;
+                return;
+            }
+
+            loop {
+                let offset = reader.offset();
+
+                match reader.next_chunk().await {
+                    Ok(Some(data)) => {
+                        let is_last = reader.remaining() == 0;
+                        hasher.update(&data);
+                        if let Some(throttle) = &throttle {
+                            throttle.consume(data.len()).await;
+                        }
+                        if let Some(audit) = &audit {
+                            audit.add_bytes(data.len());
+                        }
                         let data_chunk = DataChunk {
                             path: path_clone.clone(),
-                            data: chunk.to_vec(),
+                            crc32c: Some(crc32c::crc32c(&data)),
+                            sha256: is_last.then(|| hex::encode(hasher.clone().finalize())),
+                            data,
                             offset,
                             is_last,
                         };
                         
-                        if tx.send(Ok(data_chunk)).await.is_err() {
-                            break;
-                        }
-                        
-                        offset += chunk.len() as u64;
-                        
-                        if is_last {
+                        if tx.send(Ok(data_chunk)).await.is_err() || is_last {
                             break;
                         }
                     }
-                }
-                Err(e) => {
-                    let _ = tx.send(Err(Status::internal(e.to_string()))).await;
+                    // The file shrank while it was being read
+                    Ok(None) => {
+                        let _ = tx.send(Err(Status::data_loss("File changed during read"))).await;
+                        break;
+                    }
+                    Err(e) => {
+                        let _ = tx.send(Err(Status::internal(e.to_string()))).await;
+                        break;
+                    }
                 }
             }
         });
@@ -166,86 +659,136 @@ impl file_service_server::FileService for FileServiceImpl {
     }
 
     async fn write(&self, request: Request<Streaming<DataChunk>>) -> Result<Response<WriteResponse>, Status> {
+        let identity = Self::caller(&request)?;
+        let throttle = self.throttle(&identity, Transfer::Write)?;
+        let _slot = self.transfer_slot().await?;
         let mut stream = request.into_inner();
-        let total_bytes;
         let mut current_path = String::new();
-        let mut buffer = Vec::new();
+        let mut upload: Option<UploadFile> = None;
+        let mut complete = false;
+
+        let write_failed = |path: &str, e: FileServerError| {
+            tracing::error!(
+                "File write failed: path='{}', error='{}'", 
+                path, 
+                e.to_string()
+            );
+            status_from_error(e)
+        };
 
+        // Chunks go straight to a temporary file; returning early at any point
+        // drops `upload`, which removes it, along with the room it took up in
+        // the directory's quota.
         while let Some(chunk_result) = stream.next().await {
             let chunk = chunk_result?;
-            
-            if current_path.is_empty() {
-                current_path = chunk.path.clone();
-            } else if current_path != chunk.path {
-                return Err(Status::invalid_argument("All chunks must have the same path"));
-            }
 
-            buffer.extend_from_slice(&chunk.data);
+            let file = match upload.as_mut() {
+                Some(file) => {
+                    if current_path != chunk.path {
+                        return Err(Status::invalid_argument("All chunks must have the same path"));
+                    }
+                    file
+                }
+                None => {
+                    current_path = chunk.path.clone();
+                    let (directory_name, file_path) = self.parse_path(&current_path)?;
+                    let full_path = self.resolve_full_path(&identity, &directory_name, &file_path, Operation::Write)?;
+
+                    tracing::info!(
+                        "Starting file write: path='{}', directory='{}'", 
+                        current_path, 
+                        directory_name
+                    );
+
+                    let limits = self.upload_limits(&identity, &directory_name, &full_path)?;
+                    let file = self.file_handler.begin_upload(&full_path, limits).await
+                        .map_err(|e| write_failed(&current_path, e))?;
+                    upload.insert(file)
+                }
+            };
+
+            verify_crc32c(&chunk.data, chunk.crc32c, chunk.offset)?;
+            file.write_chunk(chunk.offset, &chunk.data).await
+                .map_err(|e| write_failed(&current_path, e))?;
+            audit::add_bytes(chunk.data.len());
+            if let Some(throttle) = &throttle {
+                throttle.consume(chunk.data.len()).await;
+            }
             
             if chunk.is_last {
+                complete = true;
                 break;
             }
         }
 
-        if current_path.is_empty() {
-            return Err(Status::invalid_argument("No data received"));
-        }
-
-        let (directory_name, file_path) = self.parse_path(&current_path)?;
-        let full_path = self.resolve_full_path(&directory_name, &file_path, "write")?;
+        let mut upload = upload.ok_or_else(|| Status::invalid_argument("No data received"))?;
 
-        tracing::info!(
-            "Starting file write: path='{}', directory='{}', size={} bytes", 
-            current_path, 
-            directory_name, 
-            buffer.len()
-        );
+        if !complete {
+            tracing::warn!("File write aborted before the last chunk: path='{}'", current_path);
+            return Err(Status::aborted("Upload ended before the last chunk"));
+        }
 
-        total_bytes = self.file_handler.write_file(&full_path, &buffer, None).await
-            .map_err(|e| {
-                tracing::error!(
-                    "File write failed: path='{}', error='{}'", 
-                    current_path, 
-                    e.to_string()
-                );
-                Status::internal(e.to_string())
-            })?;
+        let sha256 = upload.sha256().await
+            .map_err(|e| write_failed(&current_path, e))?;
+        let total_bytes = upload.commit().await
+            .map_err(|e| write_failed(&current_path, e))?;
 
         tracing::info!(
-            "File write completed: path='{}', bytes_written={}", 
+            "File write completed: path='{}', bytes_written={}, sha256={}", 
             current_path, 
-            total_bytes
+            total_bytes,
+            sha256
         );
 
         let response = WriteResponse {
             success: true,
             message: "File written successfully".to_string(),
             bytes_written: total_bytes,
+            sha256,
         };
 
         Ok(Response::new(response))
     }
 
     async fn delete(&self, request: Request<DeleteRequest>) -> Result<Response<DeleteResponse>, Status> {
+        let identity = Self::caller(&request)?;
         let req = request.into_inner();
         let (directory_name, file_path) = self.parse_path(&req.path)?;
-        let full_path = self.resolve_full_path(&directory_name, &file_path, "write")?;
+        let full_path = self.resolve_entry_path(&identity, &directory_name, &file_path, Operation::Delete)?;
+
+        if file_path.is_empty() {
+            return Err(Status::invalid_argument("Cannot delete a configured directory"));
+        }
+        if req.recursive {
+            self.check_recursive_delete(&directory_name)?;
+            // Everything below goes too, including subtrees under stricter rules
+            let tree_path = self.resolved_path(&directory_name, &file_path, &full_path);
+            self.auth.check_tree_access(&identity, &directory_name, &tree_path, Operation::Delete)
+                .map_err(status_from_error)?;
+        }
 
         tracing::info!(
-            "Starting file deletion: path='{}', directory='{}'", 
+            "Starting file deletion: path='{}', directory='{}', recursive={}", 
             req.path, 
-            directory_name
+            directory_name,
+            req.recursive
         );
 
-        match self.file_handler.delete_file(&full_path).await {
-            Ok(()) => {
+        match self.remove_path(&identity, &directory_name, &file_path, &full_path, req.recursive, false).await {
+            Ok(trash_id) => {
                 tracing::info!(
-                    "File deletion completed: path='{}'", 
-                    req.path
+                    "File deletion completed: path='{}', trash_id='{}'", 
+                    req.path,
+                    trash_id.as_deref().unwrap_or("")
                 );
+                let message = match trash_id {
+                    Some(_) => "File moved to trash",
+                    None => "File deleted successfully",
+                };
                 let response = DeleteResponse {
                     success: true,
-                    message: "File deleted successfully".to_string(),
+                    message: message.to_string(),
+                    trash_id: trash_id.unwrap_or_default(),
                 };
                 Ok(Response::new(response))
             }
@@ -255,12 +798,2700 @@ impl file_service_server::FileService for FileServiceImpl {
                     req.path, 
                     e.to_string()
                 );
+                audit::failed(&e.to_string());
                 let response = DeleteResponse {
                     success: false,
                     message: e.to_string(),
+                    trash_id: String::new(),
                 };
                 Ok(Response::new(response))
             }
         }
     }
-}
\ No newline at end of file
+
+    async fn make_directory(&self, request: Request<MakeDirectoryRequest>) -> Result<Response<DirectoryResponse>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let (directory_name, file_path) = self.parse_path(&req.path)?;
+        let full_path = self.resolve_full_path(&identity, &directory_name, &file_path, Operation::Write)?;
+
+        // Every missing parent created along the way counts as well
+        let reservation = match self.quotas.get(&directory_name) {
+            Some(quota) => {
+                let created = full_path.ancestors().take_while(|path| path.symlink_metadata().is_err()).count();
+                let claim = Tally::of(None, quota::Usage { bytes: 0, files: created as u64 });
+                Some(quota.reserve(None, claim, Tally::default()).map_err(status_from_error)?)
+            }
+            None => None,
+        };
+
+        self.file_handler.make_directory(&full_path, req.parents).await
+            .map_err(status_from_error)?;
+        if let Some(reservation) = reservation {
+            reservation.commit();
+        }
+
+        tracing::info!("Directory created: path='{}'", req.path);
+
+        let response = DirectoryResponse {
+            success: true,
+            message: "Directory created successfully".to_string(),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn remove_directory(&self, request: Request<RemoveDirectoryRequest>) -> Result<Response<DirectoryResponse>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let (directory_name, file_path) = self.parse_path(&req.path)?;
+        let full_path = self.resolve_entry_path(&identity, &directory_name, &file_path, Operation::Delete)?;
+
+        if file_path.is_empty() {
+            return Err(Status::invalid_argument("Cannot remove a configured directory"));
+        }
+        if req.recursive {
+            self.check_recursive_delete(&directory_name)?;
+            // Everything below goes too, including subtrees under stricter rules
+            let tree_path = self.resolved_path(&directory_name, &file_path, &full_path);
+            self.auth.check_tree_access(&identity, &directory_name, &tree_path, Operation::Delete)
+                .map_err(status_from_error)?;
+        }
+
+        let trash_id = self.remove_path(&identity, &directory_name, &file_path, &full_path, req.recursive, true).await
+            .map_err(status_from_error)?;
+
+        tracing::info!(
+            "Directory removed: path='{}', recursive={}, trash_id='{}'", 
+            req.path, 
+            req.recursive,
+            trash_id.as_deref().unwrap_or("")
+        );
+
+        let message = match trash_id {
+            Some(id) => format!("Directory moved to trash as '{}'", id),
+            None => "Directory removed successfully".to_string(),
+        };
+        let response = DirectoryResponse {
+            success: true,
+            message,
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn list_trash(&self, request: Request<ListTrashRequest>) -> Result<Response<ListTrashResponse>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        audit::path(&req.directory);
+        let trash = self.trash(&req.directory)?;
+
+        // Only show items the caller could read where they came from
+        let mut items = trash.list().await.map_err(status_from_error)?;
+        items.retain(|item| {
+            self.auth
+                .check_directory_access(&identity, &req.directory, &item.original_path, Operation::Read)
+                .is_ok()
+        });
+
+        Ok(Response::new(ListTrashResponse { items }))
+    }
+
+    async fn restore_trash(&self, request: Request<RestoreTrashRequest>) -> Result<Response<TrashResponse>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let trash = self.trash(&req.directory)?;
+        let item = trash.get(&req.id).await.map_err(|e| {
+            audit::path(&req.directory);
+            status_from_error(e)
+        })?;
+        audit::path(&format!("{}/{}", req.directory, item.original_path));
+        let destination = self.resolve_entry_path(&identity, &req.directory, &item.original_path, Operation::Write)?;
+        let source = trash.item_path(&req.id).map_err(status_from_error)?;
+        self.check_policy(&req.directory, &source, &destination).await?;
+
+        // Restoring a file over another deletes that one, which the caller
+        // has to be allowed to do, and sends it to the trash in turn
+        let mut replaced = None;
+        let restores_file = tokio::fs::symlink_metadata(&source).await.is_ok_and(|metadata| metadata.is_file());
+        let existing = tokio::fs::symlink_metadata(&destination).await;
+        if req.overwrite && restores_file && existing.is_ok_and(|metadata| !metadata.is_dir()) {
+            self.resolve_entry_path(&identity, &req.directory, &item.original_path, Operation::Delete)?;
+            let id = trash.put(&destination, &item.original_path, &identity.to_string()).await
+                .map_err(status_from_error)?;
+            replaced = Some(id);
+        }
+
+        // Trash items are the server's own, so they may always be moved back
+        self.file_handler.move_path(&source, &destination, req.overwrite, true).await
+            .map_err(status_from_error)?;
+        trash.remove(&req.id).await.map_err(status_from_error)?;
+
+        tracing::info!(
+            "Trash item restored: directory='{}', id='{}', path='{}', replaced='{}', caller={}", 
+            req.directory, 
+            req.id,
+            item.original_path,
+            replaced.as_deref().unwrap_or(""),
+            identity
+        );
+
+        let message = match replaced {
+            Some(id) => format!("Restored '{}/{}', moving the file it replaced to the trash as '{}'", req.directory, item.original_path, id),
+            None => format!("Restored '{}/{}'", req.directory, item.original_path),
+        };
+        let response = TrashResponse {
+            success: true,
+            message,
+            items: 1,
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn purge_trash(&self, request: Request<PurgeTrashRequest>) -> Result<Response<TrashResponse>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let trash = self.trash(&req.directory)?;
+
+        let items = if req.id.is_empty() {
+            audit::path(&req.directory);
+            trash.list().await.map_err(status_from_error)?
+        } else {
+            let item = trash.get(&req.id).await.map_err(|e| {
+                audit::path(&req.directory);
+                status_from_error(e)
+            })?;
+            audit::path(&format!("{}/{}", req.directory, item.original_path));
+            vec![item]
+        };
+
+        let mut purged = 0;
+        for item in items {
+            let allowed = self.auth
+                .check_directory_access(&identity, &req.directory, &item.original_path, Operation::Delete);
+
+            match allowed {
+                Ok(_) => {
+                    let item_path = trash.item_path(&item.id).map_err(status_from_error)?;
+                    let removed = self.measure(&req.directory, &item_path).await;
+                    trash.remove(&item.id).await.map_err(status_from_error)?;
+                    if let Some((quota, tally)) = removed {
+                        quota.release(&tally);
+                    }
+                    purged += 1;
+                }
+                // Purging everything skips what the caller may not delete
+                Err(e) if !req.id.is_empty() => return Err(Status::permission_denied(e.to_string())),
+                Err(_) => {}
+            }
+        }
+
+        tracing::info!(
+            "Trash purged: directory='{}', items={}, caller={}", 
+            req.directory, 
+            purged,
+            identity
+        );
+
+        let response = TrashResponse {
+            success: true,
+            message: format!("Purged {} item(s)", purged),
+            items: purged,
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn begin_upload(&self, request: Request<BeginUploadRequest>) -> Result<Response<UploadSession>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let (directory_name, file_path) = self.parse_path(&req.path)?;
+        let full_path = self.resolve_full_path(&identity, &directory_name, &file_path, Operation::Write)?;
+
+        let limits = self.upload_limits(&identity, &directory_name, &full_path)?;
+        let upload = self.file_handler.begin_upload(&full_path, limits).await
+            .map_err(status_from_error)?;
+        let upload_id = self.uploads.begin(&identity, req.path.clone(), upload)?;
+
+        tracing::info!(
+            "Upload session started: upload_id='{}', path='{}', caller={}", 
+            upload_id, 
+            req.path,
+            identity
+        );
+
+        let response = UploadSession {
+            upload_id,
+            path: req.path,
+            committed_bytes: 0,
+            expires_in_seconds: self.uploads.timeout().as_secs(),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn get_upload_status(&self, request: Request<UploadStatusRequest>) -> Result<Response<UploadSession>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let session = self.uploads.get(&identity, &req.upload_id)?;
+        let pending = session.try_lock().map_err(|_| UploadSessions::busy())?;
+
+        Ok(Response::new(self.upload_session(&req.upload_id, &pending)))
+    }
+
+    async fn append_upload(&self, request: Request<Streaming<UploadChunk>>) -> Result<Response<UploadSession>, Status> {
+        let identity = Self::caller(&request)?;
+        let throttle = self.throttle(&identity, Transfer::Write)?;
+        let _slot = self.transfer_slot().await?;
+        let mut stream = request.into_inner();
+        let first = stream.next().await
+            .ok_or_else(|| Status::invalid_argument("No data received"))??;
+        let upload_id = first.upload_id.clone();
+
+        let session = self.uploads.get(&identity, &upload_id)?;
+        let mut pending = session.try_lock().map_err(|_| UploadSessions::busy())?;
+
+        let result = Self::append_chunks(&mut pending, first, &mut stream, throttle.as_ref()).await;
+
+        // Keep whatever arrived before a failure so the client can resume after it
+        pending.sync().await
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        if let Err(status) = result {
+            tracing::warn!(
+                "Upload append interrupted: upload_id='{}', committed_bytes={}, error='{}'", 
+                upload_id, 
+                pending.committed(),
+                status.message()
+            );
+            return Err(status);
+        }
+
+        Ok(Response::new(self.upload_session(&upload_id, &pending)))
+    }
+
+    async fn finish_upload(&self, request: Request<FinishUploadRequest>) -> Result<Response<WriteResponse>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+
+        if req.sha256.is_empty() {
+            return Err(Status::invalid_argument("Upload checksum is required"));
+        }
+
+        let pending = self.uploads.take(&identity, &req.upload_id)?;
+        let path = pending.path().to_string();
+        let checksum = pending.sha256();
+
+        // A mismatch means the data cannot be trusted; dropping the session discards it
+        if !checksum.eq_ignore_ascii_case(&req.sha256) {
+            tracing::error!(
+                "Upload checksum mismatch: upload_id='{}', path='{}', expected='{}', actual='{}'", 
+                req.upload_id, 
+                path,
+                req.sha256,
+                checksum
+            );
+            return Err(Status::data_loss(format!(
+                "Checksum mismatch: expected {}, got {}; upload discarded",
+                req.sha256, checksum
+            )));
+        }
+
+        let total_bytes = pending.into_upload().commit().await
+            .map_err(|e| {
+                tracing::error!(
+                    "Upload finish failed: upload_id='{}', path='{}', error='{}'", 
+                    req.upload_id, 
+                    path, 
+                    e.to_string()
+                );
+                status_from_error(e)
+            })?;
+
+        tracing::info!(
+            "Upload session completed: upload_id='{}', path='{}', bytes_written={}", 
+            req.upload_id, 
+            path, 
+            total_bytes
+        );
+
+        let response = WriteResponse {
+            success: true,
+            message: "File written successfully".to_string(),
+            bytes_written: total_bytes,
+            sha256: checksum,
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn checksum(&self, request: Request<ChecksumRequest>) -> Result<Response<ChecksumResponse>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let (directory_name, file_path) = self.parse_path(&req.path)?;
+        let full_path = self.resolve_full_path(&identity, &directory_name, &file_path, Operation::Read)?;
+
+        let (size, sha256) = self.file_handler.checksum(&full_path).await
+            .map_err(|e| Status::not_found(e.to_string()))?;
+
+        let response = ChecksumResponse {
+            path: req.path,
+            size,
+            sha256,
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn r#move(&self, request: Request<MoveRequest>) -> Result<Response<MoveResponse>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let (source_directory, source_path) = self.parse_path(&req.source)?;
+        let (destination_directory, destination_path) = self.parse_path(&req.destination)?;
+
+        if source_path.is_empty() || destination_path.is_empty() {
+            return Err(Status::invalid_argument("Cannot move a configured directory itself"));
+        }
+
+        let source = self.resolve_entry_path(&identity, &source_directory, &source_path, Operation::Write)?;
+        let destination = self.resolve_entry_path(&identity, &destination_directory, &destination_path, Operation::Write)?;
+
+        // The source goes away from where it was, along with everything
+        // below it, some of which may be under stricter rules of its own
+        let source_tree = self.resolved_path(&source_directory, &source_path, &source);
+        for operation in [Operation::Write, Operation::Delete] {
+            self.auth.check_tree_access(&identity, &source_directory, &source_tree, operation)
+                .map_err(status_from_error)?;
+        }
+        let destination_tree = self.resolved_path(&destination_directory, &destination_path, &destination);
+        self.auth.check_tree_access(&identity, &destination_directory, &destination_tree, Operation::Write)
+            .map_err(status_from_error)?;
+        let recursive_delete = self.check_recursive_delete(&source_directory).is_ok();
+        self.check_policy(&destination_directory, &source, &destination).await?;
+
+        // Links moved to another directory have to be ones the caller could
+        // make there, and relative targets have to hold up from their new
+        // place
+        if source_directory != destination_directory {
+            let path = source.clone();
+            let links = tokio::task::spawn_blocking(move || walk::symlinks(&path))
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?
+                .map_err(status_from_error)?;
+            self.check_links(&identity, &destination_directory, &destination_tree, &links)?;
+        }
+
+        if source == destination {
+            return Err(Status::invalid_argument("Source and destination are the same"));
+        }
+
+        // Moving between directories takes what was moved out of one quota
+        // and into the other, where its files count against the caller like
+        // a copy
+        let mut moved = None;
+        let mut reservation = None;
+        if source_directory != destination_directory {
+            moved = self.measure(&source_directory, &source).await;
+            if let Some(quota) = self.quotas.get(&destination_directory) {
+                let path = source.clone();
+                let owner = quota_owner(&identity).map(str::to_string);
+                let claim = tokio::task::spawn_blocking(move || Tally::measure_as(&path, owner.as_deref()))
+                    .await
+                    .map_err(|e| Status::internal(e.to_string()))?
+                    .map_err(status_from_error)?;
+                let credit = match self.measure(&destination_directory, &destination).await {
+                    Some((_, replaced)) if req.overwrite => replaced,
+                    _ => Tally::default(),
+                };
+                reservation = Some(quota.reserve(quota_owner(&identity), claim, credit).map_err(status_from_error)?);
+            }
+        }
+
+        tracing::info!(
+            "Starting move: source='{}', destination='{}', overwrite={}", 
+            req.source, 
+            req.destination,
+            req.overwrite
+        );
+
+        self.file_handler.move_path(&source, &destination, req.overwrite, recursive_delete).await
+            .map_err(|e| {
+                tracing::error!(
+                    "Move failed: source='{}', destination='{}', error='{}'", 
+                    req.source, 
+                    req.destination, 
+                    e.to_string()
+                );
+                status_from_error(e)
+            })?;
+
+        if let Some(reservation) = reservation {
+            if let Some(owner) = reservation.owner().map(str::to_string) {
+                let destination = destination.clone();
+                let _ = tokio::task::spawn_blocking(move || quota::set_owner_recursive(&destination, &owner)).await;
+            }
+            reservation.commit();
+        }
+        if let Some((quota, tally)) = moved {
+            quota.release(&tally);
+        }
+
+        tracing::info!(
+            "Move completed: source='{}', destination='{}'", 
+            req.source, 
+            req.destination
+        );
+
+        let response = MoveResponse {
+            success: true,
+            message: "Moved successfully".to_string(),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    type CopyStream = ReceiverStream<Result<CopyProgress, Status>>;
+
+    async fn copy(&self, request: Request<CopyRequest>) -> Result<Response<Self::CopyStream>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let (source_directory, source_path) = self.parse_path(&req.source)?;
+        let (destination_directory, destination_path) = self.parse_path(&req.destination)?;
+
+        if source_path.is_empty() {
+            return Err(Status::invalid_argument("Cannot copy a configured directory itself"));
+        }
+        if destination_path.is_empty() {
+            return Err(Status::invalid_argument("Cannot copy over a configured directory"));
+        }
+
+        let source = self.resolve_entry_path(&identity, &source_directory, &source_path, Operation::Read)?;
+        let destination = self.resolve_entry_path(&identity, &destination_directory, &destination_path, Operation::Write)?;
+
+        self.check_policy(&destination_directory, &source, &destination).await?;
+
+        // Only what the caller could read in the source comes along
+        let visible = self.visibility(identity.clone(), &source_directory, &source_path, &source);
+        let job = self.file_handler.plan_copy(&source, &destination, req.overwrite, visible).await
+            .map_err(status_from_error)?;
+
+        // Links copied are made anew, so they have to be ones the caller
+        // could make at the destination
+        let destination_tree = self.resolved_path(&destination_directory, &destination_path, &destination);
+        self.check_links(&identity, &destination_directory, &destination_tree, &job.links())?;
+
+        // Overwriting a file gives back the room the old one took
+        let replaced = match self.measure(&destination_directory, &destination).await {
+            Some((_, replaced)) if req.overwrite => replaced,
+            _ => Tally::default(),
+        };
+        let owner = quota_owner(&identity).map(str::to_string);
+        let reservation = self.quotas.get(&destination_directory)
+            .map(|quota| {
+                let files = quota::Usage { bytes: job.bytes_total(), files: job.files_total() };
+                let claim = Tally::of_tree(owner.as_deref(), job.entries_total(), files);
+                quota.reserve(owner.as_deref(), claim, replaced)
+            })
+            .transpose()
+            .map_err(status_from_error)?;
+
+        tracing::info!(
+            "Starting copy: source='{}', destination='{}', files={}, bytes={}", 
+            req.source, 
+            req.destination,
+            job.files_total(),
+            job.bytes_total()
+        );
+
+        let (tx, rx) = mpsc::channel(4);
+        let audit = audit::current();
+
+        tokio::task::spawn_blocking(move || {
+            let mut last_report: Option<Instant> = None;
+
+            // Progress is sent at most a few times a second; a closed channel
+            // means the client went away, which cancels the copy
+            let result = job.run(|progress| {
+                if last_report.is_some_and(|t| t.elapsed() < COPY_PROGRESS_INTERVAL) {
+                    return !tx.is_closed();
+                }
+                last_report = Some(Instant::now());
+                tx.blocking_send(Ok(progress.clone())).is_ok()
+            });
+
+            match result {
+                Ok(progress) => {
+                    tracing::info!(
+                        "Copy completed: source='{}', destination='{}', files={}, bytes={}", 
+                        req.source, 
+                        req.destination,
+                        progress.files_copied,
+                        progress.bytes_copied
+                    );
+                    if let Some(audit) = &audit {
+                        audit.add_bytes(progress.bytes_copied as usize);
+                    }
+                    if let Some(reservation) = reservation {
+                        if let Some(owner) = reservation.owner() {
+                            quota::set_owner_recursive(&destination, owner);
+                        }
+                        reservation.commit();
+                    }
+                    let _ = tx.blocking_send(Ok(CopyProgress { done: true, ..progress }));
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "Copy failed: source='{}', destination='{}', error='{}'", 
+                        req.source, 
+                        req.destination, 
+                        e.to_string()
+                    );
+                    let _ = tx.blocking_send(Err(status_from_error(e)));
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type FindStream = ReceiverStream<Result<FindResponse, Status>>;
+
+    async fn find(&self, request: Request<FindRequest>) -> Result<Response<Self::FindStream>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let (directory_name, file_path) = self.parse_path(&req.path)?;
+        let full_path = self.resolve_full_path(&identity, &directory_name, &file_path, Operation::Read)?;
+
+        if !full_path.is_dir() {
+            return Err(Status::invalid_argument("Path is not a directory"));
+        }
+
+        let limit = self.auth.config.server.max_find_results as usize;
+        let criteria = FindCriteria::from_request(&req, limit).map_err(status_from_error)?;
+        let visible = self.visibility(identity, &directory_name, &file_path, &full_path);
+
+        tracing::info!("Starting find: path='{}'", req.path);
+
+        let (tx, rx) = mpsc::channel(4);
+        let audit = audit::current();
+
+        tokio::task::spawn_blocking(move || {
+            let mut batch = Vec::new();
+            let mut last_sent = Instant::now();
+
+            // Find reads no file contents, so what it sends back is what it moved
+            let send = |response: FindResponse| {
+                if let Some(audit) = &audit {
+                    audit.add_bytes(prost::Message::encoded_len(&response));
+                }
+                tx.blocking_send(Ok(response)).is_ok()
+            };
+
+            // Matches go out in batches, or as soon as a few hundred
+            // milliseconds have passed; a closed channel means the client
+            // went away, which stops the search
+            let result = walk::find(&full_path, &criteria, visible, |entry| {
+                batch.push(entry);
+                if batch.len() < SEARCH_BATCH_SIZE && last_sent.elapsed() < SEARCH_FLUSH_INTERVAL {
+                    return !tx.is_closed();
+                }
+                last_sent = Instant::now();
+                send(FindResponse { entries: std::mem::take(&mut batch), truncated: false })
+            });
+
+            match result {
+                Ok(truncated) => {
+                    tracing::info!("Find completed: path='{}', truncated={}", req.path, truncated);
+                    send(FindResponse { entries: batch, truncated });
+                }
+                Err(e) => {
+                    tracing::error!("Find failed: path='{}', error='{}'", req.path, e.to_string());
+                    let _ = tx.blocking_send(Err(status_from_error(e)));
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type GrepStream = ReceiverStream<Result<GrepResponse, Status>>;
+
+    async fn grep(&self, request: Request<GrepRequest>) -> Result<Response<Self::GrepStream>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let (directory_name, file_path) = self.parse_path(&req.path)?;
+        let full_path = self.resolve_full_path(&identity, &directory_name, &file_path, Operation::Read)?;
+
+        let pattern = GrepPattern::new(&req.pattern, req.regex, req.ignore_case).map_err(status_from_error)?;
+        let settings = &self.auth.config.server;
+        let max_entries = usize::try_from(settings.max_grep_entries).unwrap_or(usize::MAX);
+        let criteria = FindCriteria::files(&req.name_glob, req.max_depth, max_entries).map_err(status_from_error)?;
+        let visible = self.visibility(identity, &directory_name, &file_path, &full_path);
+
+        let max_matches = match req.max_matches {
+            0 => settings.max_grep_matches,
+            max => max.min(settings.max_grep_matches),
+        };
+        let mut budget = GrepBudget::new(settings.max_grep_bytes, max_matches as usize);
+
+        let metadata = std::fs::symlink_metadata(&full_path)
+            .map_err(|_| Status::not_found(format!("File not found: {}", req.path)))?;
+        if !metadata.is_file() && !metadata.is_dir() {
+            return Err(Status::invalid_argument("Path is not a file or directory"));
+        }
+
+        tracing::info!("Starting grep: path='{}'", req.path);
+
+        let (tx, rx) = mpsc::channel(4);
+        let audit = audit::current();
+
+        tokio::task::spawn_blocking(move || {
+            let mut batch = Vec::new();
+            let mut last_sent = Instant::now();
+            let mut files_scanned = 0;
+
+            // Batched like the results of a find
+            let mut send = |grep_match| {
+                batch.push(grep_match);
+                if batch.len() < SEARCH_BATCH_SIZE && last_sent.elapsed() < SEARCH_FLUSH_INTERVAL {
+                    return !tx.is_closed();
+                }
+                last_sent = Instant::now();
+                let response = GrepResponse { matches: std::mem::take(&mut batch), ..Default::default() };
+                tx.blocking_send(Ok(response)).is_ok()
+            };
+
+            let result = if metadata.is_file() {
+                files_scanned += 1;
+                let name = full_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                grep::grep_file(&full_path, &name, &pattern, &mut budget, &mut send).map(|_| false)
+            } else {
+                walk::find(&full_path, &criteria, visible, |entry| {
+                    files_scanned += 1;
+                    grep::grep_file(&full_path.join(&entry.name), &entry.name, &pattern, &mut budget, &mut send)
+                        .unwrap_or_else(|e| {
+                            tracing::debug!("Skipping unreadable file '{}': {}", entry.name, e);
+                            true
+                        })
+                })
+            };
+
+            match result {
+                Ok(too_many_entries) => {
+                    let truncated = too_many_entries || budget.is_exhausted();
+                    tracing::info!(
+                        "Grep completed: path='{}', files={}, bytes={}, truncated={}", 
+                        req.path, 
+                        files_scanned,
+                        budget.bytes_scanned(),
+                        truncated
+                    );
+                    if let Some(audit) = &audit {
+                        audit.add_bytes(budget.bytes_scanned() as usize);
+                    }
+                    let response = GrepResponse {
+                        matches: batch,
+                        truncated,
+                        files_scanned,
+                        bytes_scanned: budget.bytes_scanned(),
+                    };
+                    let _ = tx.blocking_send(Ok(response));
+                }
+                Err(e) => {
+                    tracing::error!("Grep failed: path='{}', error='{}'", req.path, e.to_string());
+                    let _ = tx.blocking_send(Err(status_from_error(e)));
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn symlink(&self, request: Request<SymlinkRequest>) -> Result<Response<LinkResponse>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let (directory_name, file_path) = self.parse_path(&req.path)?;
+        // The target is recorded as given, before it is resolved
+        audit::path(&req.target);
+
+        if file_path.is_empty() {
+            return Err(Status::invalid_argument("Cannot replace a configured directory with a link"));
+        }
+
+        let full_path = self.resolve_entry_path(&identity, &directory_name, &file_path, Operation::Write)?;
+        self.check_links_allowed(&directory_name)?;
+
+        // The target must be somewhere the caller could read directly
+        let target = Path::new(&req.target);
+        self.check_link_target(&identity, &directory_name, Path::new(&file_path), target)?;
+        if let Some(policy) = self.policies.get(&directory_name) {
+            policy.check_name(&full_path).map_err(status_from_error)?;
+        }
+
+        let reservation = self.reserve_entry(&directory_name, None, &full_path)?;
+        self.file_handler.create_link(LinkTarget::Symbolic(target), &full_path, req.overwrite).await
+            .map_err(status_from_error)?;
+        if let Some(reservation) = reservation {
+            reservation.commit();
+        }
+
+        tracing::info!("Symbolic link created: path='{}', target='{}'", req.path, req.target);
+
+        let response = LinkResponse {
+            success: true,
+            message: "Symbolic link created successfully".to_string(),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn link(&self, request: Request<LinkRequest>) -> Result<Response<LinkResponse>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let (directory_name, file_path) = self.parse_path(&req.path)?;
+        let (target_directory, target_path) = self.parse_path(&req.target)?;
+
+        if file_path.is_empty() || target_path.is_empty() {
+            return Err(Status::invalid_argument("Cannot link a configured directory itself"));
+        }
+
+        let full_path = self.resolve_entry_path(&identity, &directory_name, &file_path, Operation::Write)?;
+        self.check_links_allowed(&directory_name)?;
+        // A hard link shares the file, so whoever holds it can read and
+        // change it: the caller must be able to do both already
+        self.resolve_entry_path(&identity, &target_directory, &target_path, Operation::Read)?;
+        let source = self.resolve_entry_path(&identity, &target_directory, &target_path, Operation::Write)?;
+        self.check_policy(&directory_name, &source, &full_path).await?;
+
+        // The file stays counted against whoever wrote it
+        let reservation = self.reserve_entry(&directory_name, None, &full_path)?;
+        self.file_handler.create_link(LinkTarget::Hard(&source), &full_path, req.overwrite).await
+            .map_err(status_from_error)?;
+        if let Some(reservation) = reservation {
+            reservation.commit();
+        }
+
+        tracing::info!("Hard link created: path='{}', target='{}'", req.path, req.target);
+
+        let response = LinkResponse {
+            success: true,
+            message: "Hard link created successfully".to_string(),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn read_link(&self, request: Request<ReadLinkRequest>) -> Result<Response<ReadLinkResponse>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let (directory_name, file_path) = self.parse_path(&req.path)?;
+        let full_path = self.resolve_entry_path(&identity, &directory_name, &file_path, Operation::Read)?;
+
+        let target = self.file_handler.read_link(&full_path).await
+            .map_err(status_from_error)?;
+        let resolved = resolve::link_target(Path::new(&file_path), &target)
+            .map(|resolved| Path::new(&directory_name).join(resolved).to_string_lossy().trim_end_matches('/').to_string())
+            .unwrap_or_default();
+
+        let response = ReadLinkResponse {
+            target: target.to_string_lossy().to_string(),
+            resolved,
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn set_attributes(&self, request: Request<SetAttributesRequest>) -> Result<Response<FileMetadata>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        let (directory_name, file_path) = self.parse_path(&req.path)?;
+        let full_path = self.resolve_full_path(&identity, &directory_name, &file_path, Operation::Write)?;
+        self.check_set_attributes_allowed(&directory_name)?;
+
+        if let Some(mode) = req.mode {
+            if mode & !0o7777 != 0 {
+                return Err(Status::invalid_argument(format!("Invalid mode {:o}", mode)));
+            }
+            // They would let clients plant programs that run as the file's owner
+            if mode & 0o6000 != 0 {
+                return Err(Status::permission_denied("Setuid and setgid bits cannot be set"));
+            }
+        }
+
+        let (uid, gid) = if req.owner.is_some() || req.group.is_some() {
+            self.privileges.resolve_ownership(req.owner.as_deref(), req.group.as_deref())
+                .map_err(status_from_error)?
+        } else {
+            (None, None)
+        };
+
+        let changes = AttributeChanges {
+            mode: req.mode,
+            atime_ns: req.atime_ns,
+            mtime_ns: req.mtime_ns,
+            uid,
+            gid,
+        };
+        self.file_handler.set_attributes(&full_path, changes).await
+            .map_err(status_from_error)?;
+
+        tracing::info!("Attributes changed: path='{}', changes={:?}", req.path, changes);
+
+        let metadata = self.file_handler.stat(&full_path).await
+            .map_err(status_from_error)?;
+
+        Ok(Response::new(metadata))
+    }
+
+    async fn get_usage(&self, request: Request<GetUsageRequest>) -> Result<Response<UsageResponse>, Status> {
+        let identity = Self::caller(&request)?;
+        let req = request.into_inner();
+        audit::path(&req.directory);
+        self.auth.check_directory_access(&identity, &req.directory, "", Operation::Read)
+            .map_err(|e| Status::permission_denied(e.to_string()))?;
+
+        let quota = self.quotas.get(&req.directory).ok_or_else(|| {
+            Status::failed_precondition(format!("Directory '{}' has no quota", req.directory))
+        })?;
+
+        let quota_usage = |(used, limits): (quota::Usage, QuotaLimits)| QuotaUsage {
+            used_bytes: used.bytes,
+            used_files: used.files,
+            max_bytes: limits.max_bytes,
+            max_files: limits.max_files,
+        };
+        let (total, client) = quota.usage(quota_owner(&identity));
+
+        let response = UsageResponse {
+            total: Some(quota_usage(total)),
+            client: client.map(quota_usage),
+        };
+
+        Ok(Response::new(response))
+    }
+}
+
+/// Maps file handler errors to the matching gRPC status
+fn status_from_error(e: FileServerError) -> Status {
+    match &e {
+        FileServerError::FileNotFound(_) => Status::not_found(e.to_string()),
+        FileServerError::AlreadyExists(_) => Status::already_exists(e.to_string()),
+        FileServerError::DirectoryNotEmpty(_) => Status::failed_precondition(e.to_string()),
+        FileServerError::InvalidPath(_) => Status::invalid_argument(e.to_string()),
+        FileServerError::PermissionDenied(_) => Status::permission_denied(e.to_string()),
+        FileServerError::ChecksumMismatch(_) => Status::data_loss(e.to_string()),
+        FileServerError::QuotaExceeded(_) => Status::resource_exhausted(e.to_string()),
+        FileServerError::PolicyViolation(_) => Status::invalid_argument(e.to_string()),
+        FileServerError::IoError(io) => match io.kind() {
+            std::io::ErrorKind::NotFound => Status::not_found(e.to_string()),
+            std::io::ErrorKind::AlreadyExists => Status::already_exists(e.to_string()),
+            std::io::ErrorKind::PermissionDenied => Status::permission_denied(e.to_string()),
+            _ => Status::internal(e.to_string()),
+        },
+        _ => Status::internal(e.to_string()),
+    }
+}
+
+/// The name files the caller writes are counted against in per-client quotas
+fn quota_owner(identity: &CallerIdentity) -> Option<&str> {
+    identity.names().first().copied()
+}
+
+async fn rescan_quota(directory: &str, quota: &Arc<Quota>) {
+    if let Err(e) = quota.rescan().await {
+        tracing::error!("Failed to measure the usage of '{}': {}", directory, e);
+    }
+}
+
+/// Whether `file_path`, relative to a configured directory, lies in its trash
+fn is_in_trash(file_path: &Path) -> bool {
+    file_path
+        .components()
+        .find(|c| matches!(c, Component::Normal(_)))
+        == Some(Component::Normal(TRASH_DIR.as_ref()))
+}
+
+/// Rejects a chunk whose data does not match the CRC32C the client sent with it
+#[allow(clippy::result_large_err)]
+fn verify_crc32c(data: &[u8], expected: Option<u32>, offset: u64) -> Result<(), Status> {
+    match expected {
+        Some(expected) if crc32c::crc32c(data) != expected => Err(Status::data_loss(format!(
+            "CRC32C mismatch in chunk at offset {}", offset
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditLog;
+    use crate::config::{AclRule, ApiClientConfig, DirectoryConfig, ServerConfig, ServerSettings, TlsConfig, TrashConfig, FollowSymlinks, QuotaConfig, FilePolicyConfig, LimitsConfig, RateLimits, AuditConfig};
+    use common::file_service_client::FileServiceClient;
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+    use tokio::net::TcpListener;
+    use std::net::SocketAddr;
+    use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity, Server};
+
+    fn create_test_config(allowed_ips: &[&str]) -> ServerConfig {
+        let temp_dir = std::env::temp_dir().join(format!("fileserver_service_test_{}", uuid::Uuid::now_v7()));
+        let workspace_dir = temp_dir.join("workspace");
+        fs::create_dir_all(&workspace_dir).unwrap();
+        fs::write(workspace_dir.join("hello.txt"), "Hello, World!").unwrap();
+
+        ServerConfig {
+            server: ServerSettings {
+                port: 8080,
+                allowed_ips: allowed_ips.iter().map(|ip| ip.to_string()).collect(),
+                user: None,
+                group: None,
+                upload_session_timeout_seconds: 3600,
+                max_upload_sessions_per_client: 16,
+                max_find_results: 10_000,
+                max_grep_matches: 1000,
+                max_grep_bytes: 1024 * 1024 * 1024,
+                max_grep_entries: 1_000_000,
+                max_connections: None,
+                max_concurrent_streams: None,
+                concurrency_limit_per_connection: None,
+                keepalive_interval_seconds: None,
+                keepalive_timeout_seconds: None,
+                max_receive_message_size: None,
+                max_send_message_size: None,
+                max_concurrent_transfers: None,
+                transfer_queue_timeout_seconds: 30,
+            },
+            directories: vec![DirectoryConfig {
+                name: "workspace".to_string(),
+                path: workspace_dir.to_string_lossy().to_string(),
+                permissions: "read-write".to_string(),
+                acl: vec![],
+                allow_recursive_delete: true,
+                trash: None,
+                follow_symlinks: FollowSymlinks::WithinRoot,
+                allow_links: false,
+                allow_set_attributes: false,
+                quota: None,
+                file_policy: None,
+            }],
+            limits: None,
+            audit: None,
+            tls: None,
+            clients: vec![],
+        }
+    }
+
+    fn cleanup_test_dirs(config: &ServerConfig) {
+        if let Some(dir_config) = config.directories.first() {
+            if let Some(parent) = Path::new(&dir_config.path).parent() {
+                fs::remove_dir_all(parent).ok();
+            }
+        }
+    }
+
+    /// Starts the service on a real loopback socket, with TLS if configured.
+    async fn spawn_server(config: ServerConfig) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut server = crate::transport::configure(Server::builder(), &config.server);
+        if let Some(tls) = &config.tls {
+            server = server.tls_config(crate::tls::load_server_tls_config(tls).unwrap()).unwrap();
+        }
+
+        let settings = config.server.clone();
+        let audit = config.audit.as_ref().map(|audit| Arc::new(AuditLog::open(audit).unwrap()));
+        let auth = Arc::new(AuthService::new(config));
+        let service = FileServiceImpl::new(Arc::clone(&auth)).unwrap();
+
+        tokio::spawn(
+            server
+                .add_service(crate::transport::file_service(service, auth, audit, &settings))
+                .serve_with_incoming(crate::transport::incoming(listener, settings.max_connections)),
+        );
+
+        addr
+    }
+
+    async fn start_server(config: ServerConfig) -> FileServiceClient<Channel> {
+        let addr = spawn_server(config).await;
+        FileServiceClient::connect(format!("http://{}", addr)).await.unwrap()
+    }
+
+    fn write_chunk(path: &str, data: &[u8]) -> DataChunk {
+        DataChunk {
+            path: path.to_string(),
+            data: data.to_vec(),
+            offset: 0,
+            is_last: true,
+            crc32c: Some(crc32c::crc32c(data)),
+            sha256: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_allowed_peer_can_use_every_rpc() {
+        let config = create_test_config(&["127.0.0.1"]);
+        let mut client = start_server(config.clone()).await;
+
+        let response = client.authenticate(ConnectRequest { client_id: "test".to_string() }).await;
+        assert!(response.is_ok());
+
+        assert!(client.health_check(Empty {}).await.is_ok());
+        assert!(client.stat(StatRequest { path: "workspace/hello.txt".to_string() }).await.is_ok());
+        assert!(client.list(ListRequest { path: "workspace".to_string(), ..Default::default() }).await.is_ok());
+
+        let mut stream = client.read(ReadRequest {
+            path: "workspace/hello.txt".to_string(),
+            offset: None,
+            length: None,
+        }).await.unwrap().into_inner();
+        let chunk = stream.next().await.unwrap().unwrap();
+        assert_eq!(chunk.data, b"Hello, World!");
+
+        let chunks = tokio_stream::iter(vec![write_chunk("workspace/new.txt", b"data")]);
+        assert!(client.write(chunks).await.is_ok());
+
+        let response = client.delete(DeleteRequest { path: "workspace/new.txt".to_string(), recursive: false }).await.unwrap();
+        assert!(response.into_inner().success);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_peer_is_rejected_on_every_rpc() {
+        let config = create_test_config(&["10.0.0.0/8"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let mut client = start_server(config.clone()).await;
+
+        let denied = |status: Status| {
+            assert_eq!(status.code(), tonic::Code::PermissionDenied);
+            assert!(status.message().contains("127.0.0.1"));
+        };
+
+        denied(client.authenticate(ConnectRequest { client_id: "test".to_string() }).await.unwrap_err());
+        denied(client.health_check(Empty {}).await.unwrap_err());
+        denied(client.stat(StatRequest { path: "workspace/hello.txt".to_string() }).await.unwrap_err());
+        denied(client.list(ListRequest { path: "workspace".to_string(), ..Default::default() }).await.unwrap_err());
+        denied(client.read(ReadRequest {
+            path: "workspace/hello.txt".to_string(),
+            offset: None,
+            length: None,
+        }).await.unwrap_err());
+
+        let chunks = tokio_stream::iter(vec![write_chunk("workspace/new.txt", b"data")]);
+        denied(client.write(chunks).await.unwrap_err());
+        assert!(!workspace.join("new.txt").exists());
+
+        denied(client.delete(DeleteRequest { path: "workspace/hello.txt".to_string(), recursive: false }).await.unwrap_err());
+        assert!(workspace.join("hello.txt").exists());
+
+        cleanup_test_dirs(&config);
+    }
+
+    async fn read_chunks(client: &mut FileServiceClient<Channel>, request: ReadRequest) -> Vec<DataChunk> {
+        let mut stream = client.read(request).await.unwrap().into_inner();
+        let mut chunks = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            chunks.push(chunk.unwrap());
+        }
+        chunks
+    }
+
+    #[tokio::test]
+    async fn test_read_streams_file_in_chunks() {
+        let config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let chunk_size = crate::file_handler::READ_CHUNK_SIZE;
+        let data: Vec<u8> = (0..chunk_size * 3).map(|i| (i % 251) as u8).collect();
+        fs::write(workspace.join("large.bin"), &data).unwrap();
+        fs::write(workspace.join("empty.bin"), b"").unwrap();
+        let mut client = start_server(config.clone()).await;
+
+        let read_request = |path: &str, offset: Option<u64>, length: Option<u64>| ReadRequest {
+            path: path.to_string(),
+            offset,
+            length,
+        };
+
+        // A size that is an exact multiple of the chunk size still ends with is_last
+        let chunks = read_chunks(&mut client, read_request("workspace/large.bin", None, None)).await;
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|c| c.data.len() == chunk_size));
+        assert_eq!(chunks.iter().map(|c| c.offset).collect::<Vec<_>>(), vec![0, chunk_size as u64, 2 * chunk_size as u64]);
+        assert!(chunks[2].is_last && !chunks[1].is_last);
+        assert_eq!(chunks.iter().flat_map(|c| c.data.clone()).collect::<Vec<_>>(), data);
+        assert!(chunks.iter().all(|c| c.crc32c == Some(crc32c::crc32c(&c.data))));
+        assert_eq!(chunks[2].sha256, Some(hex::encode(Sha256::digest(&data))));
+        assert!(chunks[0].sha256.is_none());
+
+        let chunks = read_chunks(&mut client, read_request("workspace/large.bin", Some(10), Some(chunk_size as u64 + 5))).await;
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].offset, 10);
+        assert_eq!(chunks[1].offset, chunk_size as u64 + 10);
+        assert_eq!(chunks[1].data.len(), 5);
+        assert!(chunks[1].is_last);
+
+        let chunks = read_chunks(&mut client, read_request("workspace/empty.bin", None, None)).await;
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].data.is_empty() && chunks[0].is_last);
+        assert_eq!(chunks[0].sha256, Some(hex::encode(Sha256::digest(b""))));
+
+        let status = client.read(read_request("workspace/missing.bin", None, None)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_write_streams_chunks_to_disk() {
+        let config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let mut client = start_server(config.clone()).await;
+
+        let chunk = |data: &[u8], offset: u64, is_last: bool| DataChunk {
+            path: "workspace/upload.txt".to_string(),
+            data: data.to_vec(),
+            offset,
+            is_last,
+            crc32c: None,
+            sha256: None,
+        };
+
+        let chunks = vec![chunk(b"Hello, ", 0, false), chunk(b"World!", 7, true)];
+        let response = client.write(tokio_stream::iter(chunks)).await.unwrap().into_inner();
+        assert_eq!(response.bytes_written, 13);
+        assert_eq!(response.sha256, hex::encode(Sha256::digest(b"Hello, World!")));
+        assert_eq!(fs::read(workspace.join("upload.txt")).unwrap(), b"Hello, World!");
+
+        // A stream that ends without is_last is discarded, leaving the old file
+        let chunks = vec![chunk(b"truncated", 0, false)];
+        let status = client.write(tokio_stream::iter(chunks)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Aborted);
+        assert_eq!(fs::read(workspace.join("upload.txt")).unwrap(), b"Hello, World!");
+
+        // So is one that fails part way through
+        let mut bad = chunk(b"other", 9, true);
+        bad.path = "workspace/other.txt".to_string();
+        let chunks = vec![chunk(b"replacement", 0, false), bad];
+        let status = client.write(tokio_stream::iter(chunks)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(fs::read(workspace.join("upload.txt")).unwrap(), b"Hello, World!");
+
+        let leftovers: Vec<_> = fs::read_dir(&workspace).unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name.ends_with(".upload"))
+            .collect();
+        assert!(leftovers.is_empty(), "temporary files left behind: {:?}", leftovers);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_upload_temporary_files_are_out_of_reach() {
+        let config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        fs::write(workspace.join(".stale.txt.0123456789abcdef.upload"), "partial").unwrap();
+        let mut client = start_server(config.clone()).await;
+
+        // Those of uploads that did not survive a restart are swept up
+        assert!(!workspace.join(".stale.txt.0123456789abcdef.upload").exists());
+
+        let (tx, rx) = mpsc::channel(1);
+        tx.send(DataChunk { is_last: false, ..write_chunk("workspace/new.txt", b"Hello") }).await.unwrap();
+        let mut writer = client.clone();
+        let write = tokio::spawn(async move { writer.write(ReceiverStream::new(rx)).await });
+
+        let temp_name = || {
+            fs::read_dir(&workspace).unwrap()
+                .map(|e| e.unwrap().file_name())
+                .find(|name| file_handler::is_upload_temp(name))
+        };
+        for _ in 0..250 {
+            if temp_name().is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let temp_path = format!("workspace/{}", temp_name().unwrap().to_string_lossy());
+
+        let entries = client.list(ListRequest { path: "workspace".to_string(), ..Default::default() }).await.unwrap().into_inner().entries;
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["hello.txt"]);
+
+        let read = ReadRequest { path: temp_path.clone(), offset: None, length: None };
+        assert_eq!(client.read(read).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+        let delete = DeleteRequest { path: temp_path, recursive: false };
+        assert_eq!(client.delete(delete).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+        drop(tx);
+        write.await.unwrap().unwrap_err();
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_write_rejects_corrupted_chunk() {
+        let config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let mut client = start_server(config.clone()).await;
+
+        let mut chunk = write_chunk("workspace/hello.txt", b"Goodbye");
+        chunk.crc32c = Some(crc32c::crc32c(b"Hello"));
+        let status = client.write(tokio_stream::iter(vec![chunk])).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DataLoss);
+        assert_eq!(fs::read(workspace.join("hello.txt")).unwrap(), b"Hello, World!");
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_checksum_hashes_remote_file() {
+        let config = create_test_config(&["127.0.0.1"]);
+        let mut client = start_server(config.clone()).await;
+
+        let response = client.checksum(ChecksumRequest {
+            path: "workspace/hello.txt".to_string(),
+        }).await.unwrap().into_inner();
+        assert_eq!(response.size, 13);
+        assert_eq!(response.sha256, hex::encode(Sha256::digest(b"Hello, World!")));
+
+        let status = client.checksum(ChecksumRequest {
+            path: "workspace".to_string(),
+        }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_move_within_and_across_directories() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let root = workspace.parent().unwrap().to_path_buf();
+        for (name, permissions) in [("published", "read-write"), ("archive", "read-only")] {
+            fs::create_dir_all(root.join(name)).unwrap();
+            config.directories.push(DirectoryConfig {
+                name: name.to_string(),
+                path: root.join(name).to_string_lossy().to_string(),
+                permissions: permissions.to_string(),
+                acl: vec![],
+                allow_recursive_delete: true,
+                trash: None,
+                follow_symlinks: FollowSymlinks::WithinRoot,
+                allow_links: name == "published",
+                allow_set_attributes: false,
+                quota: None,
+                file_policy: None,
+            });
+        }
+        let mut client = start_server(config.clone()).await;
+
+        let move_request = |source: &str, destination: &str, overwrite: bool| MoveRequest {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            overwrite,
+        };
+
+        client.r#move(move_request("workspace/hello.txt", "workspace/tmp/hello.txt", false)).await.unwrap();
+        assert!(!workspace.join("hello.txt").exists());
+        assert!(workspace.join("tmp/hello.txt").exists());
+
+        client.r#move(move_request("workspace/tmp/hello.txt", "published/hello.txt", false)).await.unwrap();
+        assert_eq!(fs::read(root.join("published/hello.txt")).unwrap(), b"Hello, World!");
+
+        fs::write(workspace.join("hello.txt"), "Second").unwrap();
+        let status = client.r#move(move_request("workspace/hello.txt", "published/hello.txt", false)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::AlreadyExists);
+        client.r#move(move_request("workspace/hello.txt", "published/hello.txt", true)).await.unwrap();
+        assert_eq!(fs::read(root.join("published/hello.txt")).unwrap(), b"Second");
+
+        // Links only go to directories with links enabled, and have to lead
+        // somewhere within the directory from where they end up
+        fs::create_dir_all(workspace.join("deep/bundle")).unwrap();
+        std::os::unix::fs::symlink("../../hello.txt", workspace.join("deep/bundle/up")).unwrap();
+        let status = client.r#move(move_request("workspace/deep/bundle", "published/bundle", false)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        client.r#move(move_request("workspace/deep/bundle", "published/deep/bundle", false)).await.unwrap();
+        assert_eq!(fs::read(root.join("published/deep/bundle/up")).unwrap(), b"Second");
+        for source in ["published/deep/bundle", "published/deep/bundle/up"] {
+            let status = client.r#move(move_request(source, "workspace/deep/bundle", false)).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::PermissionDenied, "{}", source);
+        }
+
+        // Both sides need write access
+        let status = client.r#move(move_request("published/hello.txt", "archive/hello.txt", false)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        fs::write(root.join("archive/old.txt"), "Old").unwrap();
+        let status = client.r#move(move_request("archive/old.txt", "published/old.txt", false)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert!(root.join("archive/old.txt").exists());
+
+        let status = client.r#move(move_request("workspace/missing.txt", "published/missing.txt", false)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_move_needs_delete_on_the_whole_source_tree() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        fs::create_dir_all(workspace.join("projects/team/locked")).unwrap();
+        fs::create_dir_all(workspace.join("projects/other")).unwrap();
+        fs::write(workspace.join("projects/team/locked/plan.txt"), "plan").unwrap();
+        std::os::unix::fs::symlink("projects", workspace.join("teams")).unwrap();
+
+        let rule = |path: &str, allow: Vec<Operation>| AclRule {
+            path: path.to_string(),
+            clients: vec!["*".to_string()],
+            ips: vec![],
+            allow,
+        };
+        config.directories[0].acl = vec![
+            rule("", vec![Operation::Read, Operation::Write]),
+            rule("projects", vec![Operation::Read, Operation::Write, Operation::Delete]),
+            rule("projects/team/locked", vec![Operation::Read]),
+        ];
+        let mut client = start_server(config.clone()).await;
+
+        let move_request = |source: &str, destination: &str| MoveRequest {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            overwrite: false,
+        };
+
+        // Moving takes the source away, which writing alone does not allow
+        let status = client.r#move(move_request("workspace/hello.txt", "workspace/projects/hello.txt")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert!(workspace.join("hello.txt").exists());
+
+        // Nor can a directory be moved with a locked subtree inside it
+        for source in ["workspace/projects/team", "workspace/teams/team"] {
+            let status = client.r#move(move_request(source, "workspace/projects/moved")).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::PermissionDenied, "{}", source);
+        }
+        assert!(workspace.join("projects/team/locked/plan.txt").exists());
+
+        client.r#move(move_request("workspace/projects/other", "workspace/projects/moved")).await.unwrap();
+        assert!(workspace.join("projects/moved").is_dir());
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_recursive_delete_needs_delete_on_the_whole_tree() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        fs::create_dir_all(workspace.join("project/records")).unwrap();
+        fs::write(workspace.join("project/records/2024.csv"), "kept").unwrap();
+        fs::create_dir_all(workspace.join("scratch/tmp")).unwrap();
+        config.directories[0].acl = vec![
+            AclRule { path: "".to_string(), clients: vec!["*".to_string()], ips: vec![], allow: vec![Operation::Read, Operation::Write, Operation::Delete] },
+            AclRule { path: "project/records".to_string(), clients: vec!["*".to_string()], ips: vec![], allow: vec![Operation::Read, Operation::Write] },
+        ];
+        let mut client = start_server(config.clone()).await;
+
+        let response = client.delete(DeleteRequest { path: "workspace/project".to_string(), recursive: true }).await;
+        assert_eq!(response.unwrap_err().code(), tonic::Code::PermissionDenied);
+        let request = RemoveDirectoryRequest { path: "workspace/project".to_string(), recursive: true };
+        assert_eq!(client.remove_directory(request).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+        assert!(workspace.join("project/records/2024.csv").exists());
+
+        let request = RemoveDirectoryRequest { path: "workspace/scratch".to_string(), recursive: true };
+        client.remove_directory(request).await.unwrap();
+        assert!(!workspace.join("scratch").exists());
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_copy_streams_progress() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let root = workspace.parent().unwrap().to_path_buf();
+        fs::create_dir_all(root.join("shared/dataset/part")).unwrap();
+        fs::write(root.join("shared/dataset/a.csv"), "1,2,3").unwrap();
+        fs::write(root.join("shared/dataset/part/b.csv"), "4,5").unwrap();
+        fs::create_dir_all(root.join("shared/linked")).unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", root.join("shared/linked/passwd")).unwrap();
+        fs::create_dir_all(root.join("shared/notes")).unwrap();
+        std::os::unix::fs::symlink("../dataset/a.csv", root.join("shared/notes/today")).unwrap();
+        fs::create_dir_all(root.join("plain")).unwrap();
+        config.directories[0].allow_links = true;
+        let shared = DirectoryConfig {
+            name: "shared".to_string(),
+            path: root.join("shared").to_string_lossy().to_string(),
+            permissions: "read-only".to_string(),
+            acl: vec![],
+            allow_recursive_delete: true,
+            trash: None,
+            follow_symlinks: FollowSymlinks::Never,
+            allow_links: false,
+            allow_set_attributes: false,
+            quota: None,
+            file_policy: None,
+        };
+        config.directories.push(DirectoryConfig {
+            name: "plain".to_string(),
+            path: root.join("plain").to_string_lossy().to_string(),
+            permissions: "read-write".to_string(),
+            ..shared.clone()
+        });
+        config.directories.push(shared);
+        let mut client = start_server(config.clone()).await;
+
+        let copy_request = |source: &str, destination: &str| CopyRequest {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            overwrite: false,
+        };
+
+        let mut stream = client.copy(copy_request("shared/dataset", "workspace/dataset")).await.unwrap().into_inner();
+        let mut updates = Vec::new();
+        while let Some(progress) = stream.next().await {
+            updates.push(progress.unwrap());
+        }
+        let last = updates.last().unwrap();
+        assert!(last.done);
+        assert_eq!((last.files_copied, last.files_total), (2, 2));
+        assert_eq!((last.bytes_copied, last.bytes_total), (8, 8));
+        assert_eq!(fs::read(workspace.join("dataset/part/b.csv")).unwrap(), b"4,5");
+        assert!(root.join("shared/dataset/a.csv").exists());
+
+        // Copying into an existing path needs overwrite, and only for files
+        let status = client.copy(copy_request("shared/dataset", "workspace/dataset")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::AlreadyExists);
+
+        // Read-only directories can be copied from but not into
+        let status = client.copy(copy_request("workspace/hello.txt", "shared/hello.txt")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        // Links are only made where links are enabled, and only if they lead
+        // somewhere within the directory from their new place
+        let status = client.copy(copy_request("shared/notes", "plain/notes")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        let status = client.copy(copy_request("shared/linked", "workspace/linked")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(!workspace.join("linked").exists());
+        let mut stream = client.copy(copy_request("shared/notes", "workspace/notes")).await.unwrap().into_inner();
+        while let Some(progress) = stream.next().await {
+            progress.unwrap();
+        }
+        assert_eq!(fs::read(workspace.join("notes/today")).unwrap(), b"1,2,3");
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_copy_leaves_out_what_the_caller_cannot_read() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        fs::create_dir_all(workspace.join("project/secret")).unwrap();
+        fs::write(workspace.join("project/readme.txt"), "readme").unwrap();
+        fs::write(workspace.join("project/secret/keys.txt"), "keys").unwrap();
+        config.directories[0].trash = Some(TrashConfig { retention_days: 30 });
+        config.directories[0].acl = vec![
+            AclRule { path: "".to_string(), clients: vec!["*".to_string()], ips: vec![], allow: vec![Operation::Read, Operation::Write, Operation::Delete] },
+            AclRule { path: "project/secret".to_string(), clients: vec!["admin".to_string()], ips: vec![], allow: vec![Operation::Read] },
+        ];
+        let mut client = start_server(config.clone()).await;
+
+        client.delete(DeleteRequest { path: "workspace/hello.txt".to_string(), recursive: false }).await.unwrap();
+
+        let copy_request = |source: &str, destination: &str| CopyRequest {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            overwrite: false,
+        };
+
+        let mut stream = client.copy(copy_request("workspace/project", "workspace/backup")).await.unwrap().into_inner();
+        while let Some(progress) = stream.next().await {
+            progress.unwrap();
+        }
+        assert!(workspace.join("backup/readme.txt").exists());
+        assert!(!workspace.join("backup/secret").exists());
+
+        // Copying the whole directory would take the trash along
+        let status = client.copy(copy_request("workspace", "workspace/everything")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(!workspace.join("everything").exists());
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_directory_operations() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let mut protected = config.directories[0].clone();
+        protected.name = "protected".to_string();
+        protected.allow_recursive_delete = false;
+        config.directories.push(protected);
+        let mut client = start_server(config.clone()).await;
+
+        let mkdir = |path: &str, parents: bool| MakeDirectoryRequest { path: path.to_string(), parents };
+        let rmdir = |path: &str, recursive: bool| RemoveDirectoryRequest { path: path.to_string(), recursive };
+        let delete = |path: &str, recursive: bool| DeleteRequest { path: path.to_string(), recursive };
+
+        let status = client.make_directory(mkdir("workspace/a/b", false)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        client.make_directory(mkdir("workspace/a/b", true)).await.unwrap();
+        assert!(workspace.join("a/b").is_dir());
+        let status = client.make_directory(mkdir("workspace/a", false)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::AlreadyExists);
+
+        let status = client.remove_directory(rmdir("workspace/a", false)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        client.remove_directory(rmdir("workspace/a/b", false)).await.unwrap();
+        assert!(!workspace.join("a/b").exists());
+
+        // Delete refuses non-empty directories unless asked to recurse
+        fs::create_dir_all(workspace.join("project/src")).unwrap();
+        let response = client.delete(delete("workspace/project", false)).await.unwrap().into_inner();
+        assert!(!response.success);
+        assert!(workspace.join("project/src").exists());
+
+        // Recursion can be disabled for a directory, here the same one under another name
+        let status = client.delete(delete("protected/project", true)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        let status = client.remove_directory(rmdir("protected/project", true)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert!(workspace.join("project/src").exists());
+
+        let response = client.delete(delete("workspace/project", true)).await.unwrap().into_inner();
+        assert!(response.success);
+        assert!(!workspace.join("project").exists());
+
+        let status = client.delete(delete("workspace", true)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(workspace.exists());
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_symlinks_cannot_escape_the_directory() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let outside = workspace.parent().unwrap().join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(&outside, workspace.join("escape")).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), workspace.join("secret.txt")).unwrap();
+        std::os::unix::fs::symlink("hello.txt", workspace.join("alias.txt")).unwrap();
+        let mut strict = config.directories[0].clone();
+        strict.name = "strict".to_string();
+        strict.follow_symlinks = FollowSymlinks::Never;
+        config.directories.push(strict);
+        let mut client = start_server(config.clone()).await;
+
+        let read_request = |path: &str| ReadRequest { path: path.to_string(), offset: None, length: None };
+        let stat_request = |path: &str| StatRequest { path: path.to_string() };
+
+        for path in ["workspace/escape/secret.txt", "workspace/secret.txt"] {
+            let status = client.read(read_request(path)).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::PermissionDenied, "{}", path);
+        }
+        let status = client.stat(stat_request("workspace/escape/secret.txt")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        // The link itself is described, not followed
+        let metadata = client.stat(stat_request("workspace/escape")).await.unwrap().into_inner();
+        assert!(!metadata.is_directory);
+        assert_eq!(metadata.posix.unwrap().file_type(), FileType::Symlink);
+
+        let chunks = vec![write_chunk("workspace/escape/planted.txt", b"planted")];
+        let status = client.write(tokio_stream::iter(chunks)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert!(!outside.join("planted.txt").exists());
+
+        let delete = DeleteRequest { path: "workspace/escape/secret.txt".to_string(), recursive: false };
+        let status = client.delete(delete).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        // Links inside the directory are followed unless the policy forbids it
+        let chunks = read_chunks(&mut client, read_request("workspace/alias.txt")).await;
+        assert_eq!(chunks[0].data, b"Hello, World!");
+        let status = client.read(read_request("strict/alias.txt")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        // The escaping link itself can still be removed, leaving its target alone
+        let delete = DeleteRequest { path: "workspace/escape".to_string(), recursive: false };
+        assert!(client.delete(delete).await.unwrap().into_inner().success);
+        assert!(fs::symlink_metadata(workspace.join("escape")).is_err());
+        assert!(outside.join("secret.txt").exists());
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_create_and_read_links() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        fs::create_dir_all(workspace.join("releases/42")).unwrap();
+        fs::write(workspace.join("releases/42/VERSION"), "42").unwrap();
+        fs::create_dir_all(workspace.join("readonly")).unwrap();
+        fs::write(workspace.join("readonly/config.toml"), "shared").unwrap();
+        fs::create_dir_all(workspace.join("uploads")).unwrap();
+        fs::write(workspace.join("uploads/report.pdf"), "private").unwrap();
+        let plain = config.directories[0].clone();
+        config.directories[0].allow_links = true;
+        config.directories[0].acl = vec![
+            AclRule { path: "".to_string(), clients: vec!["*".to_string()], ips: vec![], allow: vec![Operation::Read, Operation::Write, Operation::Delete] },
+            AclRule { path: "readonly".to_string(), clients: vec!["*".to_string()], ips: vec![], allow: vec![Operation::Read] },
+            AclRule { path: "uploads".to_string(), clients: vec!["*".to_string()], ips: vec![], allow: vec![Operation::Write] },
+        ];
+        config.directories.push(DirectoryConfig { name: "plain".to_string(), ..plain });
+        let mut client = start_server(config.clone()).await;
+
+        let symlink = |path: &str, target: &str, overwrite: bool| SymlinkRequest {
+            path: path.to_string(),
+            target: target.to_string(),
+            overwrite,
+        };
+        let link = |path: &str, target: &str| LinkRequest {
+            path: path.to_string(),
+            target: target.to_string(),
+            overwrite: false,
+        };
+        let read_link = |path: &str| ReadLinkRequest { path: path.to_string() };
+
+        let response = client.symlink(symlink("workspace/current", "releases/42", false)).await.unwrap();
+        assert!(response.into_inner().success);
+        let chunks = read_chunks(&mut client, ReadRequest {
+            path: "workspace/current/VERSION".to_string(),
+            offset: None,
+            length: None,
+        }).await;
+        assert_eq!(chunks[0].data, b"42");
+
+        let response = client.read_link(read_link("workspace/current")).await.unwrap().into_inner();
+        assert_eq!(response.target, "releases/42");
+        assert_eq!(response.resolved, "workspace/releases/42");
+
+        // Links are replaced only when asked to
+        let status = client.symlink(symlink("workspace/current", "releases/43", false)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::AlreadyExists);
+        client.symlink(symlink("workspace/current", "releases/43", true)).await.unwrap();
+        assert_eq!(fs::read_link(workspace.join("current")).unwrap(), Path::new("releases/43"));
+
+        for target in ["../outside", "/etc/passwd", "releases/../../.."] {
+            let status = client.symlink(symlink("workspace/escape", target, false)).await.unwrap_err();
+            assert_ne!(status.code(), tonic::Code::Ok, "{}", target);
+            assert!(fs::symlink_metadata(workspace.join("escape")).is_err(), "{}", target);
+        }
+
+        let status = client.symlink(symlink("plain/current2", "releases/42", false)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        client.link(link("workspace/hello-again.txt", "workspace/hello.txt")).await.unwrap();
+        assert_eq!(fs::metadata(workspace.join("hello.txt")).unwrap().nlink(), 2);
+        let status = client.link(link("workspace/releases-again", "workspace/releases")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let status = client.link(link("plain/hello-again.txt", "workspace/hello.txt")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        // A hard link to a file the caller can only read would let them write it
+        let status = client.link(link("workspace/config.toml", "workspace/readonly/config.toml")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert!(fs::symlink_metadata(workspace.join("config.toml")).is_err());
+        // And one to a file in a drop box would let them read it
+        let status = client.link(link("workspace/report.pdf", "workspace/uploads/report.pdf")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert!(fs::symlink_metadata(workspace.join("report.pdf")).is_err());
+
+        let status = client.read_link(read_link("workspace/hello.txt")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_set_attributes() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let plain = config.directories[0].clone();
+        config.directories[0].allow_set_attributes = true;
+        config.directories.push(DirectoryConfig { name: "plain".to_string(), ..plain });
+        let mut client = start_server(config.clone()).await;
+
+        let request = |path: &str| SetAttributesRequest { path: path.to_string(), ..Default::default() };
+
+        let metadata = client.set_attributes(SetAttributesRequest {
+            mode: Some(0o755),
+            mtime_ns: Some(1_600_000_000_000_000_000),
+            ..request("workspace/hello.txt")
+        }).await.unwrap().into_inner();
+        assert_eq!(metadata.posix.unwrap().mode, 0o755);
+        assert_eq!(metadata.modified_time, 1_600_000_000);
+        assert_eq!(fs::metadata(workspace.join("hello.txt")).unwrap().mode() & 0o7777, 0o755);
+
+        let status = client.set_attributes(SetAttributesRequest { mode: Some(0o644), ..request("plain/hello.txt") }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        let status = client.set_attributes(SetAttributesRequest { mode: Some(0o4755), ..request("workspace/hello.txt") }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        let status = client.set_attributes(SetAttributesRequest { mode: Some(0o10644), ..request("workspace/hello.txt") }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let status = client.set_attributes(SetAttributesRequest { mode: Some(0o644), ..request("workspace/missing.txt") }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        // Ownership only changes while the server still runs as root
+        let chown = client.set_attributes(SetAttributesRequest { owner: Some("nobody".to_string()), ..request("workspace/hello.txt") }).await;
+        if nix::unistd::geteuid().is_root() {
+            let nobody = nix::unistd::User::from_name("nobody").unwrap().unwrap();
+            assert_eq!(chown.unwrap().into_inner().posix.unwrap().uid, nobody.uid.as_raw());
+        } else {
+            assert_eq!(chown.unwrap_err().code(), tonic::Code::PermissionDenied);
+        }
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_quota_rejects_writes_and_reports_usage() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let plain = config.directories[0].clone();
+        config.directories[0].quota = Some(QuotaConfig {
+            limits: QuotaLimits { max_bytes: Some(100), max_files: Some(5) },
+            clients: HashMap::new(),
+        });
+        config.directories[0].allow_links = true;
+        config.directories.push(DirectoryConfig { name: "plain".to_string(), ..plain });
+        let mut client = start_server(config.clone()).await;
+
+        let usage = |response: UsageResponse| {
+            let total = response.total.unwrap();
+            (total.used_bytes, total.used_files)
+        };
+
+        let chunks = tokio_stream::iter(vec![write_chunk("workspace/a.txt", &[b'a'; 50])]);
+        client.write(chunks).await.unwrap();
+
+        // The write stops at the chunk that would go over the limit
+        let chunks = tokio_stream::iter(vec![
+            DataChunk { is_last: false, ..write_chunk("workspace/b.txt", &[b'b'; 30]) },
+            DataChunk { offset: 30, ..write_chunk("workspace/b.txt", &[b'b'; 30]) },
+        ]);
+        let status = client.write(chunks).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert!(!workspace.join("b.txt").exists());
+
+        // Replacing a file only needs room for the difference
+        let chunks = tokio_stream::iter(vec![write_chunk("workspace/a.txt", &[b'a'; 80])]);
+        client.write(chunks).await.unwrap();
+
+        let response = client.get_usage(GetUsageRequest { directory: "workspace".to_string() }).await.unwrap().into_inner();
+        assert_eq!(response.total.unwrap(), QuotaUsage { used_bytes: 93, used_files: 2, max_bytes: Some(100), max_files: Some(5) });
+        assert!(response.client.is_none());
+
+        // Resumable uploads count too
+        let session = client.begin_upload(BeginUploadRequest { path: "workspace/c.txt".to_string() }).await.unwrap().into_inner();
+        let chunk = UploadChunk { upload_id: session.upload_id, offset: 0, data: vec![b'c'; 8], crc32c: None };
+        let status = client.append_upload(tokio_stream::iter(vec![chunk])).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+        client.make_directory(MakeDirectoryRequest { path: "workspace/x/y".to_string(), parents: true }).await.unwrap();
+        let status = client.make_directory(MakeDirectoryRequest { path: "workspace/z".to_string(), parents: false }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+        client.delete(DeleteRequest { path: "workspace/a.txt".to_string(), recursive: false }).await.unwrap();
+        let response = client.get_usage(GetUsageRequest { directory: "workspace".to_string() }).await.unwrap().into_inner();
+        assert_eq!(usage(response), (13, 3));
+
+        // Copying over a file only counts the difference as well
+        for overwrite in [false, true] {
+            let request = CopyRequest { source: "workspace/hello.txt".to_string(), destination: "workspace/copy.txt".to_string(), overwrite };
+            let mut stream = client.copy(request).await.unwrap().into_inner();
+            while let Some(progress) = stream.next().await {
+                progress.unwrap();
+            }
+            let response = client.get_usage(GetUsageRequest { directory: "workspace".to_string() }).await.unwrap().into_inner();
+            assert_eq!(usage(response), (26, 4));
+        }
+
+        // A file with another link keeps its data when one of them goes
+        client.delete(DeleteRequest { path: "workspace/x".to_string(), recursive: true }).await.unwrap();
+        let link = LinkRequest { path: "workspace/linked.txt".to_string(), target: "workspace/copy.txt".to_string(), overwrite: false };
+        client.link(link).await.unwrap();
+        client.delete(DeleteRequest { path: "workspace/copy.txt".to_string(), recursive: false }).await.unwrap();
+        let response = client.get_usage(GetUsageRequest { directory: "workspace".to_string() }).await.unwrap().into_inner();
+        assert_eq!(usage(response), (26, 2));
+
+        let status = client.get_usage(GetUsageRequest { directory: "plain".to_string() }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_file_policy_aborts_disallowed_writes() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        config.directories[0].file_policy = Some(FilePolicyConfig {
+            max_file_size: Some(64),
+            allowed_extensions: vec!["png".to_string(), "txt".to_string()],
+            denied_extensions: vec![],
+            sniff_content: true,
+        });
+        config.directories[0].allow_links = true;
+        let outside = workspace.parent().unwrap().join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("large.txt"), [b'a'; 100]).unwrap();
+        config.directories.push(DirectoryConfig {
+            name: "outside".to_string(),
+            path: outside.to_string_lossy().to_string(),
+            file_policy: None,
+            ..config.directories[0].clone()
+        });
+        let mut client = start_server(config.clone()).await;
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR";
+
+        let rejected = |status: Status, reason: &str| {
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+            assert!(status.message().contains(reason), "{}", status.message());
+        };
+
+        let chunks = tokio_stream::iter(vec![write_chunk("workspace/cat.gif", b"GIF89a")]);
+        rejected(client.write(chunks).await.unwrap_err(), "allowed extension");
+
+        let chunks = tokio_stream::iter(vec![write_chunk("workspace/cat.png", b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00")]);
+        rejected(client.write(chunks).await.unwrap_err(), "does not look like a PNG image");
+
+        // The first chunk is fine, the second would make the file too large
+        let chunks = tokio_stream::iter(vec![
+            DataChunk { is_last: false, ..write_chunk("workspace/cat.png", png) },
+            DataChunk { offset: 16, ..write_chunk("workspace/cat.png", &[0; 64]) },
+        ]);
+        rejected(client.write(chunks).await.unwrap_err(), "maximum of 64 bytes");
+        assert!(!workspace.join("cat.png").exists());
+
+        let chunks = tokio_stream::iter(vec![write_chunk("workspace/cat.png", png)]);
+        client.write(chunks).await.unwrap();
+        let chunks = tokio_stream::iter(vec![write_chunk("workspace/notes.txt", b"Hello")]);
+        client.write(chunks).await.unwrap();
+
+        let status = client.begin_upload(BeginUploadRequest { path: "workspace/tool.exe".to_string() }).await.unwrap_err();
+        rejected(status, "allowed extension");
+
+        // Files cannot get in other than by writing them either
+        let move_request = |source: &str, destination: &str| MoveRequest {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            overwrite: false,
+        };
+        rejected(client.r#move(move_request("workspace/cat.png", "workspace/cat.exe")).await.unwrap_err(), "allowed extension");
+        rejected(client.r#move(move_request("workspace/notes.txt", "workspace/notes.png")).await.unwrap_err(), "does not look like");
+        rejected(client.r#move(move_request("outside/large.txt", "workspace/large.txt")).await.unwrap_err(), "maximum of 64 bytes");
+        assert!(workspace.join("cat.png").exists());
+
+        let copy = CopyRequest { source: "outside/large.txt".to_string(), destination: "workspace/large.txt".to_string(), overwrite: false };
+        rejected(client.copy(copy).await.unwrap_err(), "maximum of 64 bytes");
+        assert!(!workspace.join("large.txt").exists());
+
+        let symlink = SymlinkRequest { path: "workspace/cat.exe".to_string(), target: "cat.png".to_string(), overwrite: false };
+        rejected(client.symlink(symlink).await.unwrap_err(), "allowed extension");
+        let link = LinkRequest { path: "workspace/cat.exe".to_string(), target: "workspace/cat.png".to_string(), overwrite: false };
+        rejected(client.link(link).await.unwrap_err(), "allowed extension");
+        assert!(fs::symlink_metadata(workspace.join("cat.exe")).is_err());
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_rejects_reads_and_writes() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        config.limits = Some(LimitsConfig {
+            rates: RateLimits { requests_per_second: Some(1), burst: Some(1), ..Default::default() },
+            ..Default::default()
+        });
+        let mut client = start_server(config.clone()).await;
+        let request = ReadRequest { path: "workspace/hello.txt".to_string(), ..Default::default() };
+
+        let chunks = read_chunks(&mut client, request.clone()).await;
+        assert_eq!(chunks[0].data, b"Hello, World!");
+
+        let status = client.read(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(status.metadata().get(crate::rate_limit::RETRY_AFTER).unwrap(), "1");
+
+        // Reads and writes share the caller's calls
+        let chunks = tokio_stream::iter(vec![write_chunk("workspace/new.txt", b"data")]);
+        let status = client.write(chunks).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_transfers_are_limited() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        config.server.max_concurrent_transfers = Some(1);
+        config.server.transfer_queue_timeout_seconds = 0;
+        let mut client = start_server(config.clone()).await;
+        let request = ReadRequest { path: "workspace/hello.txt".to_string(), ..Default::default() };
+
+        // A write that has not sent its last chunk holds the only slot
+        let (tx, rx) = mpsc::channel(1);
+        tx.send(DataChunk { is_last: false, ..write_chunk("workspace/new.txt", b"Hello") }).await.unwrap();
+        let mut writer = client.clone();
+        let write = tokio::spawn(async move { writer.write(ReceiverStream::new(rx)).await });
+
+        // The write has its slot once it has started its temporary file
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let uploading = || fs::read_dir(&workspace).unwrap().any(|e| e.unwrap().file_name().to_string_lossy().ends_with(".upload"));
+        for _ in 0..250 {
+            if uploading() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(uploading());
+
+        let status = client.read(request.clone()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+
+        tx.send(DataChunk { offset: 5, ..write_chunk("workspace/new.txt", b", World!") }).await.unwrap();
+        assert_eq!(write.await.unwrap().unwrap().into_inner().bytes_written, 13);
+
+        let chunks = read_chunks(&mut client, request).await;
+        assert_eq!(chunks[0].data, b"Hello, World!");
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_connections_and_message_sizes_are_limited() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        config.server.max_connections = Some(1);
+        config.server.max_receive_message_size = Some(1024 * 1024);
+        let addr = spawn_server(config.clone()).await;
+        let request = ReadRequest { path: "workspace/hello.txt".to_string(), ..Default::default() };
+
+        let mut first = FileServiceClient::connect(format!("http://{}", addr)).await.unwrap();
+        read_chunks(&mut first, request.clone()).await;
+
+        let chunks = tokio_stream::iter(vec![write_chunk("workspace/big.bin", &vec![0; 2 * 1024 * 1024])]);
+        let status = first.write(chunks).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+
+        // The second connection is only served once the first one closes
+        let second = tokio::spawn(async move {
+            let mut client = FileServiceClient::connect(format!("http://{}", addr)).await.unwrap();
+            read_chunks(&mut client, request).await
+        });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!second.is_finished());
+
+        drop(first);
+        let chunks = tokio::time::timeout(Duration::from_secs(5), second).await.unwrap().unwrap();
+        assert_eq!(chunks[0].data, b"Hello, World!");
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_audit_log_records_every_call() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let audit_path = workspace.parent().unwrap().join("audit.log");
+        config.audit = Some(AuditConfig {
+            path: Some(audit_path.to_string_lossy().into_owned()),
+            max_file_size: 1024 * 1024,
+            max_files: 1,
+            syslog: false,
+        });
+        config.directories[0].trash = Some(TrashConfig { retention_days: 30 });
+        config.directories[0].allow_links = true;
+        let mut client = start_server(config.clone()).await;
+
+        read_chunks(&mut client, ReadRequest { path: "workspace/hello.txt".to_string(), ..Default::default() }).await;
+        let chunks = tokio_stream::iter(vec![write_chunk("workspace/notes.txt", b"Some notes")]);
+        client.write(chunks).await.unwrap();
+        client.r#move(MoveRequest {
+            source: "workspace/notes.txt".to_string(),
+            destination: "workspace/moved.txt".to_string(),
+            overwrite: false,
+        }).await.unwrap();
+        client.stat(StatRequest { path: "workspace/missing.txt".to_string() }).await.unwrap_err();
+        client.stat(StatRequest { path: "secret/file.txt".to_string() }).await.unwrap_err();
+        let deleted = client.delete(DeleteRequest { path: "workspace/missing.txt".to_string(), recursive: false }).await.unwrap();
+        assert!(!deleted.into_inner().success);
+        let deleted = client.delete(DeleteRequest { path: "workspace/moved.txt".to_string(), recursive: false }).await.unwrap();
+        let restore = RestoreTrashRequest { directory: "workspace".to_string(), id: deleted.into_inner().trash_id, overwrite: false };
+        client.restore_trash(restore).await.unwrap();
+        client.list_trash(ListTrashRequest { directory: "workspace".to_string() }).await.unwrap();
+        client.get_usage(GetUsageRequest { directory: "workspace".to_string() }).await.unwrap_err();
+        client.symlink(SymlinkRequest {
+            path: "workspace/greeting".to_string(),
+            target: "hello.txt".to_string(),
+            overwrite: false,
+        }).await.unwrap();
+        let copy = CopyRequest {
+            source: "workspace/hello.txt".to_string(),
+            destination: "workspace/copy.txt".to_string(),
+            overwrite: false,
+        };
+        let mut stream = client.copy(copy).await.unwrap().into_inner();
+        while stream.next().await.is_some() {}
+        let (found, _) = find_all(&mut client, FindRequest { path: "workspace".to_string(), ..Default::default() }).await;
+        assert!(!found.is_empty());
+        let grep = GrepRequest { path: "workspace/hello.txt".to_string(), pattern: "World".to_string(), ..Default::default() };
+        grep_all(&mut client, grep).await;
+
+        // Records are written in the background
+        let mut lines = Vec::new();
+        for _ in 0..100 {
+            lines = fs::read_to_string(&audit_path).unwrap_or_default().lines().map(str::to_string).collect();
+            if lines.len() >= 14 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(lines.len(), 14, "{:?}", lines);
+        let resolved = |name: &str| workspace.join(name).to_string_lossy().into_owned();
+
+        assert!(lines.iter().all(|line| line.starts_with(r#"{"timestamp":""#) && line.ends_with('}')));
+        assert!(lines.iter().all(|line| line.contains(r#""client":null,"cert_subject":null,"peer_ip":"127.0.0.1""#)));
+
+        assert!(lines[0].contains(r#""operation":"Read","path":"workspace/hello.txt""#));
+        assert!(lines[0].contains(&format!(r#""resolved_path":"{}""#, resolved("hello.txt"))));
+        assert!(lines[0].contains(r#""bytes":13,"outcome":"OK","error":null"#));
+
+        assert!(lines[1].contains(r#""operation":"Write","path":"workspace/notes.txt""#));
+        assert!(lines[1].contains(r#""bytes":10,"outcome":"OK""#));
+
+        assert!(lines[2].contains(r#""operation":"Move","path":"workspace/notes.txt""#));
+        assert!(lines[2].contains(r#""target_path":"workspace/moved.txt""#));
+        assert!(lines[2].contains(&format!(r#""resolved_target_path":"{}""#, resolved("moved.txt"))));
+
+        assert!(lines[3].contains(r#""operation":"Stat","path":"workspace/missing.txt""#));
+        assert!(lines[3].contains(r#""outcome":"NOT_FOUND""#));
+
+        assert!(lines[4].contains(r#""path":"secret/file.txt","resolved_path":null"#));
+        assert!(lines[4].contains(r#""outcome":"PERMISSION_DENIED""#));
+
+        // Deletes report failures in their response rather than their status
+        assert!(lines[5].contains(r#""operation":"Delete","path":"workspace/missing.txt""#));
+        assert!(lines[5].contains(r#""outcome":"FAILED","error":""#));
+
+        // Trash and usage calls name a directory, or the item they act on
+        assert!(lines[7].contains(r#""operation":"RestoreTrash","path":"workspace/moved.txt""#));
+        assert!(lines[7].contains(&format!(r#""resolved_path":"{}""#, resolved("moved.txt"))));
+        assert!(lines[8].contains(r#""operation":"ListTrash","path":"workspace""#));
+        assert!(lines[9].contains(r#""operation":"GetUsage","path":"workspace""#));
+
+        // A link's target is recorded as given as well as where it leads
+        assert!(lines[10].contains(r#""operation":"Symlink","path":"workspace/greeting""#));
+        assert!(lines[10].contains(r#""target_path":"hello.txt""#));
+        assert!(lines[10].contains(&format!(r#""resolved_target_path":"{}""#, resolved("hello.txt"))));
+
+        // Copies count the bytes written, greps the bytes read and finds
+        // the listings sent back
+        assert!(lines[11].contains(r#""operation":"Copy","path":"workspace/hello.txt""#));
+        assert!(lines[11].contains(r#""bytes":13,"outcome":"OK""#));
+        assert!(lines[12].contains(r#""operation":"Find","path":"workspace""#));
+        assert!(!lines[12].contains(r#""bytes":0,"#));
+        assert!(lines[13].contains(r#""operation":"Grep","path":"workspace/hello.txt""#));
+        assert!(lines[13].contains(r#""bytes":13,"outcome":"OK""#));
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_trash_restore_and_purge() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        config.directories[0].trash = Some(TrashConfig { retention_days: 30 });
+        let mut client = start_server(config.clone()).await;
+
+        fs::create_dir_all(workspace.join("project/src")).unwrap();
+        fs::write(workspace.join("project/src/main.rs"), "fn main() {}").unwrap();
+
+        let delete = |path: &str, recursive: bool| DeleteRequest { path: path.to_string(), recursive };
+        let restore = |id: &str, overwrite: bool| RestoreTrashRequest {
+            directory: "workspace".to_string(),
+            id: id.to_string(),
+            overwrite,
+        };
+        let list_trash = || ListTrashRequest { directory: "workspace".to_string() };
+
+        let response = client.delete(delete("workspace/hello.txt", false)).await.unwrap().into_inner();
+        assert!(response.success);
+        let file_id = response.trash_id;
+        assert!(!file_id.is_empty());
+        assert!(!workspace.join("hello.txt").exists());
+
+        // Non-empty directories still need a recursive delete
+        let response = client.delete(delete("workspace/project", false)).await.unwrap().into_inner();
+        assert!(!response.success);
+        let response = client.remove_directory(RemoveDirectoryRequest {
+            path: "workspace/project".to_string(),
+            recursive: true,
+        }).await.unwrap().into_inner();
+        assert!(response.success);
+        assert!(!workspace.join("project").exists());
+
+        // The trash is hidden from listings and file operations
+        let entries = client.list(ListRequest { path: "workspace".to_string(), ..Default::default() }).await.unwrap().into_inner().entries;
+        assert!(entries.is_empty());
+        let status = client.stat(StatRequest { path: format!("workspace/{}", TRASH_DIR) }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        let status = client.delete(delete(&format!("workspace/{}", TRASH_DIR), true)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        let items = client.list_trash(list_trash()).await.unwrap().into_inner().items;
+        assert_eq!(items.len(), 2);
+        let file = items.iter().find(|i| i.id == file_id).unwrap();
+        assert_eq!(file.original_path, "hello.txt");
+        assert_eq!(file.size, 13);
+        let project = items.iter().find(|i| i.is_directory).unwrap();
+        assert_eq!(project.original_path, "project");
+
+        // Restoring refuses to replace a file unless asked to
+        fs::write(workspace.join("hello.txt"), "Replacement").unwrap();
+        let status = client.restore_trash(restore(&file_id, false)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::AlreadyExists);
+        client.restore_trash(restore(&file_id, true)).await.unwrap();
+        assert_eq!(fs::read_to_string(workspace.join("hello.txt")).unwrap(), "Hello, World!");
+
+        // The file replaced goes to the trash in turn
+        let items = client.list_trash(list_trash()).await.unwrap().into_inner().items;
+        let replaced = items.iter().find(|i| i.original_path == "hello.txt").unwrap();
+        assert_eq!(replaced.size, 11);
+
+        let status = client.restore_trash(restore(&file_id, false)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        let response = client.purge_trash(PurgeTrashRequest {
+            directory: "workspace".to_string(),
+            id: String::new(),
+        }).await.unwrap().into_inner();
+        assert_eq!(response.items, 2);
+        assert!(client.list_trash(list_trash()).await.unwrap().into_inner().items.is_empty());
+        assert!(!workspace.join("project").exists());
+
+        let status = client.list_trash(ListTrashRequest { directory: "other".to_string() }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_restoring_over_a_file_needs_delete() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let trash_config = TrashConfig { retention_days: 30 };
+        config.directories[0].trash = Some(trash_config.clone());
+        config.directories[0].acl = vec![
+            AclRule { path: "".to_string(), clients: vec!["*".to_string()], ips: vec![], allow: vec![Operation::Read, Operation::Write, Operation::Delete] },
+            AclRule { path: "locked".to_string(), clients: vec!["*".to_string()], ips: vec![], allow: vec![Operation::Read, Operation::Write] },
+        ];
+        fs::create_dir_all(workspace.join("locked")).unwrap();
+        fs::write(workspace.join("locked/notes.txt"), "Current").unwrap();
+
+        // Deleted by someone allowed to, before the file was written again
+        fs::write(workspace.join("notes.txt"), "Deleted").unwrap();
+        let id = Trash::new(&workspace, &trash_config).put(&workspace.join("notes.txt"), "locked/notes.txt", "admin").await.unwrap();
+        let mut client = start_server(config.clone()).await;
+
+        let restore = RestoreTrashRequest { directory: "workspace".to_string(), id, overwrite: true };
+        let status = client.restore_trash(restore).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert_eq!(fs::read_to_string(workspace.join("locked/notes.txt")).unwrap(), "Current");
+        let items = client.list_trash(ListTrashRequest { directory: "workspace".to_string() }).await.unwrap().into_inner().items;
+        assert_eq!(items.len(), 1);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_upload_session_resumes_after_interruption() {
+        let config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let mut client = start_server(config.clone()).await;
+
+        let session = client.begin_upload(BeginUploadRequest {
+            path: "workspace/resumed.txt".to_string(),
+        }).await.unwrap().into_inner();
+        assert_eq!(session.committed_bytes, 0);
+        assert!(session.expires_in_seconds > 0);
+
+        let chunk = |data: &[u8], offset: u64| UploadChunk {
+            upload_id: session.upload_id.clone(),
+            data: data.to_vec(),
+            offset,
+            crc32c: Some(crc32c::crc32c(data)),
+        };
+
+        // The second chunk is rejected, but the first one is kept
+        let chunks = vec![chunk(b"Hello, ", 0), chunk(b"World!", 3)];
+        let status = client.append_upload(tokio_stream::iter(chunks)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        let status = client.get_upload_status(UploadStatusRequest {
+            upload_id: session.upload_id.clone(),
+        }).await.unwrap().into_inner();
+        assert_eq!(status.committed_bytes, 7);
+        assert_eq!(status.path, "workspace/resumed.txt");
+        assert!(!workspace.join("resumed.txt").exists());
+
+        let chunks = vec![chunk(b"World!", status.committed_bytes)];
+        let status = client.append_upload(tokio_stream::iter(chunks)).await.unwrap().into_inner();
+        assert_eq!(status.committed_bytes, 13);
+
+        let response = client.finish_upload(FinishUploadRequest {
+            upload_id: session.upload_id.clone(),
+            sha256: hex::encode(Sha256::digest(b"Hello, World!")),
+        }).await.unwrap().into_inner();
+        assert_eq!(response.bytes_written, 13);
+        assert_eq!(fs::read(workspace.join("resumed.txt")).unwrap(), b"Hello, World!");
+
+        // The session is gone once finished
+        let status = client.get_upload_status(UploadStatusRequest {
+            upload_id: session.upload_id.clone(),
+        }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_upload_session_checksum_mismatch_discards_upload() {
+        let config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let mut client = start_server(config.clone()).await;
+
+        let session = client.begin_upload(BeginUploadRequest {
+            path: "workspace/hello.txt".to_string(),
+        }).await.unwrap().into_inner();
+
+        let chunks = vec![UploadChunk {
+            upload_id: session.upload_id.clone(),
+            data: b"corrupted".to_vec(),
+            offset: 0,
+            crc32c: None,
+        }];
+        client.append_upload(tokio_stream::iter(chunks)).await.unwrap();
+
+        let status = client.finish_upload(FinishUploadRequest {
+            upload_id: session.upload_id.clone(),
+            sha256: "00".repeat(32),
+        }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DataLoss);
+
+        assert_eq!(fs::read(workspace.join("hello.txt")).unwrap(), b"Hello, World!");
+        assert_eq!(fs::read_dir(&workspace).unwrap().count(), 1);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_required_on_every_rpc() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        config.clients = vec![ApiClientConfig {
+            name: "backup-agent".to_string(),
+            api_key_hash: crate::auth::hash_api_key("backup-key"),
+        }];
+        let mut client = start_server(config.clone()).await;
+
+        let stat_request = |key: Option<&str>| {
+            let mut request = Request::new(StatRequest { path: "workspace/hello.txt".to_string() });
+            if let Some(key) = key {
+                request.metadata_mut().insert("authorization", format!("Bearer {}", key).parse().unwrap());
+            }
+            request
+        };
+
+        let status = client.stat(stat_request(None)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        let status = client.stat(stat_request(Some("wrong-key"))).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        let status = client.health_check(Empty {}).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+        let metadata = client.stat(stat_request(Some("backup-key"))).await.unwrap().into_inner();
+        assert_eq!(metadata.size, 13);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_acls_limit_visible_directories_and_entries() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        let private = workspace.parent().unwrap().join("private");
+        fs::create_dir_all(workspace.join("finance")).unwrap();
+        fs::write(workspace.join("finance/q3.csv"), "revenue").unwrap();
+        fs::create_dir_all(&private).unwrap();
+
+        let rule = |path: &str, clients: &[&str], allow: Vec<Operation>| AclRule {
+            path: path.to_string(),
+            clients: clients.iter().map(|c| c.to_string()).collect(),
+            ips: vec![],
+            allow,
+        };
+        config.directories[0].acl = vec![
+            rule("", &["*"], vec![Operation::Read]),
+            rule("finance", &["finance"], vec![Operation::Read]),
+        ];
+        config.directories.push(DirectoryConfig {
+            name: "private".to_string(),
+            path: private.to_string_lossy().to_string(),
+            permissions: "read-write".to_string(),
+            acl: vec![rule("", &["finance"], vec![Operation::Read, Operation::Write])],
+            allow_recursive_delete: true,
+            trash: None,
+            follow_symlinks: FollowSymlinks::WithinRoot,
+            allow_links: false,
+            allow_set_attributes: false,
+            quota: None,
+            file_policy: None,
+        });
+        let mut client = start_server(config.clone()).await;
+
+        let response = client.authenticate(ConnectRequest { client_id: "test".to_string() }).await.unwrap();
+        assert_eq!(response.into_inner().available_directories, vec!["workspace"]);
+
+        let entries = client.list(ListRequest { path: "workspace".to_string(), ..Default::default() }).await.unwrap().into_inner().entries;
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["hello.txt"]);
+
+        // Nor does a recursive listing descend into hidden directories
+        let request = ListRequest { path: "workspace".to_string(), recursive: true, ..Default::default() };
+        let entries = client.list(request).await.unwrap().into_inner().entries;
+        assert_eq!(entries.len(), 1);
+
+        let status = client.stat(StatRequest { path: "workspace/finance/q3.csv".to_string() }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        let chunks = tokio_stream::iter(vec![write_chunk("workspace/new.txt", b"data")]);
+        let status = client.write(chunks).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+
+        // Spelling the path differently or going through links does not get
+        // around the rules
+        std::os::unix::fs::symlink(".", workspace.join("s")).unwrap();
+        std::os::unix::fs::symlink("finance", workspace.join("books")).unwrap();
+        for path in [
+            "workspace/./finance/q3.csv",
+            "workspace//finance/q3.csv",
+            "workspace/finance/./q3.csv",
+            "workspace/s/finance/q3.csv",
+            "workspace/books/q3.csv",
+        ] {
+            let status = client.stat(StatRequest { path: path.to_string() }).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::PermissionDenied, "{}", path);
+        }
+        let read = ReadRequest { path: "workspace/books/q3.csv".to_string(), offset: None, length: None };
+        assert_eq!(client.read(read).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+        let list = ListRequest { path: "workspace/books".to_string(), ..Default::default() };
+        assert_eq!(client.list(list).await.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_recursive_list_in_pages() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        config.directories[0].trash = Some(TrashConfig { retention_days: 30 });
+        for i in 0..5 {
+            fs::create_dir_all(workspace.join(format!("logs/{}", i))).unwrap();
+            fs::write(workspace.join(format!("logs/{}/app.log", i)), "log").unwrap();
+        }
+        let mut client = start_server(config.clone()).await;
+
+        client.delete(DeleteRequest { path: "workspace/hello.txt".to_string(), recursive: false }).await.unwrap();
+
+        let mut request = ListRequest {
+            path: "workspace".to_string(),
+            recursive: true,
+            page_size: 4,
+            ..Default::default()
+        };
+        let mut names = Vec::new();
+        let mut pages = 0;
+        loop {
+            let page = client.list(request.clone()).await.unwrap().into_inner();
+            assert!(page.entries.len() <= 4);
+            names.extend(page.entries.into_iter().map(|e| e.name));
+            pages += 1;
+            if page.next_page_token.is_empty() {
+                break;
+            }
+            request.page_token = page.next_page_token;
+        }
+
+        // logs, five directories and five files; the trash stays hidden
+        assert_eq!(names.len(), 11);
+        assert_eq!(pages, 3);
+        assert_eq!(names[..3], ["logs", "logs/0", "logs/0/app.log"]);
+        assert!(names.iter().all(|name| !name.starts_with(TRASH_DIR)));
+
+        let request = ListRequest {
+            path: "workspace/logs".to_string(),
+            recursive: true,
+            include: vec!["*.log".to_string()],
+            exclude: vec!["[0-2]".to_string()],
+            ..Default::default()
+        };
+        let entries = client.list(request).await.unwrap().into_inner().entries;
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["3/app.log", "4/app.log"]);
+
+        let request = ListRequest {
+            path: "workspace".to_string(),
+            page_token: "../outside".to_string(),
+            ..Default::default()
+        };
+        let status = client.list(request).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        cleanup_test_dirs(&config);
+    }
+
+    async fn find_all(client: &mut FileServiceClient<Channel>, request: FindRequest) -> (Vec<String>, bool) {
+        let mut stream = client.find(request).await.unwrap().into_inner();
+        let mut names = Vec::new();
+        let mut truncated = false;
+        while let Some(response) = stream.next().await {
+            let response = response.unwrap();
+            names.extend(response.entries.into_iter().map(|e| e.name));
+            truncated = response.truncated;
+        }
+        (names, truncated)
+    }
+
+    #[tokio::test]
+    async fn test_find_respects_acls_and_result_limit() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        config.server.max_find_results = 3;
+        config.directories[0].acl = vec![
+            AclRule { path: "".to_string(), clients: vec!["*".to_string()], ips: vec![], allow: vec![Operation::Read] },
+            AclRule { path: "finance".to_string(), clients: vec!["finance".to_string()], ips: vec![], allow: vec![Operation::Read] },
+        ];
+        fs::create_dir_all(workspace.join("finance")).unwrap();
+        fs::write(workspace.join("finance/q3.parquet"), vec![0u8; 2048]).unwrap();
+        for i in 0..4 {
+            fs::create_dir_all(workspace.join(format!("data/{}", i))).unwrap();
+            fs::write(workspace.join(format!("data/{}/part.parquet", i)), vec![0u8; 1024 * (i + 1)]).unwrap();
+        }
+        let mut client = start_server(config.clone()).await;
+
+        let large = FindRequest {
+            path: "workspace".to_string(),
+            name_glob: "*.parquet".to_string(),
+            min_size: Some(2048),
+            ..Default::default()
+        };
+        let (names, truncated) = find_all(&mut client, large).await;
+        assert_eq!(names, ["data/1/part.parquet", "data/2/part.parquet", "data/3/part.parquet"]);
+        assert!(!truncated);
+
+        let all = FindRequest {
+            path: "workspace".to_string(),
+            entry_type: EntryType::File as i32,
+            ..Default::default()
+        };
+        let (names, truncated) = find_all(&mut client, all).await;
+        assert_eq!(names.len(), 3);
+        assert!(truncated);
+
+        let status = client.find(FindRequest {
+            path: "workspace/hello.txt".to_string(),
+            ..Default::default()
+        }).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        cleanup_test_dirs(&config);
+    }
+
+    async fn grep_all(client: &mut FileServiceClient<Channel>, request: GrepRequest) -> (Vec<GrepMatch>, GrepResponse) {
+        let mut stream = client.grep(request).await.unwrap().into_inner();
+        let mut matches = Vec::new();
+        let mut last = GrepResponse::default();
+        while let Some(response) = stream.next().await {
+            let mut response = response.unwrap();
+            matches.append(&mut response.matches);
+            last = response;
+        }
+        (matches, last)
+    }
+
+    #[tokio::test]
+    async fn test_grep_searches_readable_text_files() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        config.server.max_grep_matches = 2;
+        config.directories[0].acl = vec![
+            AclRule { path: "".to_string(), clients: vec!["*".to_string()], ips: vec![], allow: vec![Operation::Read] },
+            AclRule { path: "private".to_string(), clients: vec!["admin".to_string()], ips: vec![], allow: vec![Operation::Read] },
+        ];
+        fs::create_dir_all(workspace.join("logs")).unwrap();
+        fs::create_dir_all(workspace.join("private")).unwrap();
+        fs::write(workspace.join("logs/app.log"), "started\nrequest_id=r-42 ok\nended\n").unwrap();
+        fs::write(workspace.join("logs/app.log.1"), "request_id=r-42 retry\n").unwrap();
+        fs::write(workspace.join("logs/core.bin"), b"\0request_id=r-42").unwrap();
+        fs::write(workspace.join("private/audit.log"), "request_id=r-42\n").unwrap();
+        let mut client = start_server(config.clone()).await;
+
+        let grep = |path: &str, pattern: &str| GrepRequest {
+            path: path.to_string(),
+            pattern: pattern.to_string(),
+            ..Default::default()
+        };
+
+        let (matches, last) = grep_all(&mut client, grep("workspace", "r-42")).await;
+        let found: Vec<(&str, u64)> = matches.iter().map(|m| (m.path.as_str(), m.line_number)).collect();
+        assert_eq!(found, [("logs/app.log", 2), ("logs/app.log.1", 1)]);
+        assert_eq!(matches[0].line, "request_id=r-42 ok");
+        assert!(!last.truncated);
+        assert_eq!(last.files_scanned, 4);
+
+        let by_name = GrepRequest { name_glob: "*.log".to_string(), ..grep("workspace/logs", "r-42") };
+        let (matches, _) = grep_all(&mut client, by_name).await;
+        assert_eq!(matches.len(), 1);
+
+        // A single file can be searched, and the server caps the matches
+        let (matches, last) = grep_all(&mut client, grep("workspace/logs/app.log", "e")).await;
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].path, "app.log");
+        assert!(last.truncated);
+
+        let regex = GrepRequest { regex: true, ..grep("workspace", "(") };
+        let status = client.grep(regex).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        cleanup_test_dirs(&config);
+    }
+
+    #[tokio::test]
+    async fn test_walks_through_links_hide_what_the_target_hides() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let workspace = Path::new(&config.directories[0].path).to_path_buf();
+        config.directories[0].acl = vec![
+            AclRule { path: "".to_string(), clients: vec!["*".to_string()], ips: vec![], allow: vec![Operation::Read] },
+            AclRule { path: "finance/secret".to_string(), clients: vec!["finance".to_string()], ips: vec![], allow: vec![Operation::Read] },
+        ];
+        fs::create_dir_all(workspace.join("finance/secret")).unwrap();
+        fs::write(workspace.join("finance/q3.csv"), "revenue").unwrap();
+        fs::write(workspace.join("finance/secret/salaries.csv"), "revenue").unwrap();
+        std::os::unix::fs::symlink("finance", workspace.join("books")).unwrap();
+        let mut client = start_server(config.clone()).await;
+
+        let request = ListRequest { path: "workspace/books".to_string(), recursive: true, ..Default::default() };
+        let entries = client.list(request).await.unwrap().into_inner().entries;
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["q3.csv"]);
+
+        let find = FindRequest { path: "workspace/books".to_string(), ..Default::default() };
+        let (names, _) = find_all(&mut client, find).await;
+        assert_eq!(names, ["q3.csv"]);
+
+        let grep = GrepRequest { path: "workspace/books".to_string(), pattern: "revenue".to_string(), ..Default::default() };
+        let (matches, _) = grep_all(&mut client, grep).await;
+        let found: Vec<&str> = matches.iter().map(|m| m.path.as_str()).collect();
+        assert_eq!(found, ["q3.csv"]);
+
+        cleanup_test_dirs(&config);
+    }
+
+    struct TestPki {
+        ca_cert: rcgen::Certificate,
+        ca_key: rcgen::KeyPair,
+    }
+
+    impl TestPki {
+        fn new() -> Self {
+            let ca_key = rcgen::KeyPair::generate().unwrap();
+            let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+            params.distinguished_name.push(rcgen::DnType::CommonName, "Test CA");
+            let ca_cert = params.self_signed(&ca_key).unwrap();
+            Self { ca_cert, ca_key }
+        }
+
+        /// Issues a certificate and returns it as (cert PEM, key PEM).
+        fn issue(&self, common_name: &str, names: Vec<String>) -> (String, String) {
+            let key = rcgen::KeyPair::generate().unwrap();
+            let mut params = rcgen::CertificateParams::new(names).unwrap();
+            params.distinguished_name.push(rcgen::DnType::CommonName, common_name);
+            let cert = params.signed_by(&key, &self.ca_cert, &self.ca_key).unwrap();
+            (cert.pem(), key.serialize_pem())
+        }
+    }
+
+    async fn connect_tls(
+        addr: SocketAddr,
+        ca_pem: &str,
+        identity: Option<&(String, String)>,
+    ) -> Result<FileServiceClient<Channel>, tonic::transport::Error> {
+        let mut tls = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(ca_pem))
+            .domain_name("localhost");
+        if let Some((cert, key)) = identity {
+            tls = tls.identity(Identity::from_pem(cert, key));
+        }
+
+        let channel = Channel::from_shared(format!("https://{}", addr))
+            .unwrap()
+            .tls_config(tls)?
+            .connect()
+            .await?;
+        Ok(FileServiceClient::new(channel))
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls_authorizes_certificate_subject() {
+        let mut config = create_test_config(&["127.0.0.1"]);
+        let test_dir = Path::new(&config.directories[0].path).parent().unwrap().to_path_buf();
+
+        let pki = TestPki::new();
+        let ca_pem = pki.ca_cert.pem();
+        let (server_cert, server_key) = pki.issue("localhost", vec!["localhost".to_string()]);
+        let allowed_client = pki.issue("client-a", vec![]);
+        let other_client = pki.issue("client-b", vec![]);
+
+        fs::write(test_dir.join("ca.crt"), &ca_pem).unwrap();
+        fs::write(test_dir.join("server.crt"), server_cert).unwrap();
+        fs::write(test_dir.join("server.key"), server_key).unwrap();
+
+        config.tls = Some(TlsConfig {
+            cert: test_dir.join("server.crt").to_string_lossy().to_string(),
+            key: test_dir.join("server.key").to_string_lossy().to_string(),
+            client_ca: Some(test_dir.join("ca.crt").to_string_lossy().to_string()),
+            allowed_subjects: vec!["CN=client-a".to_string()],
+        });
+        assert!(config.validate().is_ok());
+
+        let addr = spawn_server(config.clone()).await;
+        let stat_request = || StatRequest { path: "workspace/hello.txt".to_string() };
+
+        // Client certificate with an allowed subject
+        let mut client = connect_tls(addr, &ca_pem, Some(&allowed_client)).await.unwrap();
+        let metadata = client.stat(stat_request()).await.unwrap().into_inner();
+        assert_eq!(metadata.size, 13);
+
+        // Valid certificate, but the subject is not on the allowlist
+        let mut client = connect_tls(addr, &ca_pem, Some(&other_client)).await.unwrap();
+        let status = client.stat(stat_request()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        assert!(status.message().contains("CN=client-b"));
+
+        // No client certificate: rejected during or right after the handshake
+        if let Ok(mut client) = connect_tls(addr, &ca_pem, None).await {
+            assert!(client.stat(stat_request()).await.is_err());
+        }
+
+        // Plaintext clients cannot talk to a TLS server
+        if let Ok(mut client) = FileServiceClient::connect(format!("http://{}", addr)).await {
+            assert!(client.stat(stat_request()).await.is_err());
+        }
+
+        cleanup_test_dirs(&config);
+    }
+}
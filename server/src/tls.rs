@@ -0,0 +1,34 @@
+use crate::config::TlsConfig;
+use common::FileServerError;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+use tonic::Request;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Builds the transport TLS settings. When a client CA is configured the
+/// server requires every client to present a certificate signed by it.
+pub fn load_server_tls_config(tls: &TlsConfig) -> Result<ServerTlsConfig, FileServerError> {
+    let cert = read_pem(&tls.cert)?;
+    let key = read_pem(&tls.key)?;
+
+    let mut tls_config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+
+    if let Some(client_ca) = &tls.client_ca {
+        let ca = read_pem(client_ca)?;
+        tls_config = tls_config.client_ca_root(Certificate::from_pem(ca));
+    }
+
+    Ok(tls_config)
+}
+
+/// Returns the subject of the client certificate verified during the handshake.
+pub fn peer_certificate_subject<T>(request: &Request<T>) -> Option<String> {
+    let certs = request.peer_certs()?;
+    let leaf = certs.first()?;
+    let (_, cert) = X509Certificate::from_der(leaf.get_ref()).ok()?;
+    Some(cert.subject().to_string())
+}
+
+fn read_pem(path: &str) -> Result<Vec<u8>, FileServerError> {
+    std::fs::read(path)
+        .map_err(|e| FileServerError::ConfigError(format!("Failed to read TLS file '{}': {}", path, e)))
+}
@@ -0,0 +1,134 @@
+use crate::audit::{AuditLog, Audited};
+use crate::auth::{AuthInterceptor, AuthService};
+use crate::config::ServerSettings;
+use crate::service::FileServiceImpl;
+use common::file_service_server::FileServiceServer;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::server::{Connected, TcpConnectInfo};
+use tonic::transport::Server;
+
+/// Pause after a failed accept, which is usually the process running out of
+/// file descriptors
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Applies the HTTP/2 settings of `[server]` to a server builder
+pub fn configure(server: Server, settings: &ServerSettings) -> Server {
+    let mut server = server
+        .max_concurrent_streams(settings.max_concurrent_streams)
+        .http2_keepalive_interval(settings.keepalive_interval_seconds.map(Duration::from_secs))
+        .http2_keepalive_timeout(settings.keepalive_timeout_seconds.map(Duration::from_secs));
+
+    if let Some(limit) = settings.concurrency_limit_per_connection {
+        server = server.concurrency_limit_per_connection(limit);
+    }
+    server
+}
+
+/// The file service behind the authentication interceptor, with the message
+/// size limits of `[server]` and every call audited
+pub fn file_service(
+    service: FileServiceImpl,
+    auth: Arc<AuthService>,
+    audit: Option<Arc<AuditLog>>,
+    settings: &ServerSettings,
+) -> Audited<InterceptedService<FileServiceServer<FileServiceImpl>, AuthInterceptor>> {
+    let mut server = FileServiceServer::new(service);
+    if let Some(size) = settings.max_receive_message_size {
+        server = server.max_decoding_message_size(size);
+    }
+    if let Some(size) = settings.max_send_message_size {
+        server = server.max_encoding_message_size(size);
+    }
+    Audited::new(InterceptedService::new(server, AuthInterceptor::new(auth)), audit)
+}
+
+/// Connections accepted on `listener`. With `max_connections` set, no more
+/// are accepted while that many are open, so further clients wait in the
+/// listen backlog until one closes.
+pub fn incoming(listener: TcpListener, max_connections: Option<usize>) -> ReceiverStream<io::Result<Connection>> {
+    let (tx, rx) = mpsc::channel(1);
+    let slots = max_connections.map(|max| Arc::new(Semaphore::new(max)));
+
+    tokio::spawn(async move {
+        loop {
+            let slot = match &slots {
+                Some(slots) => Some(Arc::clone(slots).acquire_owned().await.expect("semaphore is never closed")),
+                None => None,
+            };
+
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!("Failed to accept connection: {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+            if let Err(e) = stream.set_nodelay(true) {
+                tracing::debug!("Failed to set TCP_NODELAY: {}", e);
+            }
+
+            if tx.send(Ok(Connection { stream, _slot: slot })).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+/// An accepted connection, holding its slot among `max_connections` until
+/// it is closed
+pub struct Connection {
+    stream: TcpStream,
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+impl Connected for Connection {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.stream.connect_info()
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,209 @@
+use crate::config::TrashConfig;
+use common::{FileServerError, TrashItem};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs as async_fs;
+
+/// Hidden directory at the root of a configured directory holding its trash
+pub const TRASH_DIR: &str = ".trash";
+
+const ITEM_NAME: &str = "item";
+const INFO_NAME: &str = "info.toml";
+
+#[derive(Serialize, Deserialize)]
+struct TrashInfo {
+    original_path: String,
+    deleted_at: i64,
+    deleted_by: String,
+}
+
+/// Recycle bin of one configured directory. Every deleted item is kept in
+/// its own `.trash/<id>/` directory together with a record of its origin.
+pub struct Trash {
+    root: PathBuf,
+    retention: Duration,
+}
+
+impl Trash {
+    pub fn new(directory_path: &Path, config: &TrashConfig) -> Self {
+        Self {
+            root: directory_path.join(TRASH_DIR),
+            retention: Duration::from_secs(config.retention_days * 24 * 60 * 60),
+        }
+    }
+
+    /// Moves `full_path` into the trash and returns the id of the new item.
+    /// `original_path` is relative to the configured directory.
+    pub async fn put(&self, full_path: &Path, original_path: &str, deleted_by: &str) -> Result<String, FileServerError> {
+        let deleted_at = unix_time(SystemTime::now());
+        let id = format!("{}-{:08x}", deleted_at, rand::random::<u32>());
+        let entry = self.root.join(&id);
+        async_fs::create_dir_all(&entry).await?;
+
+        let info = TrashInfo {
+            original_path: original_path.to_string(),
+            deleted_at,
+            deleted_by: deleted_by.to_string(),
+        };
+        let info = toml::to_string(&info).map_err(std::io::Error::other)?;
+
+        let moved = async {
+            async_fs::write(entry.join(INFO_NAME), info).await?;
+            async_fs::rename(full_path, entry.join(ITEM_NAME)).await
+        };
+        if let Err(e) = moved.await {
+            let _ = async_fs::remove_dir_all(&entry).await;
+            return Err(e.into());
+        }
+
+        Ok(id)
+    }
+
+    /// All items in the trash, most recently deleted first
+    pub async fn list(&self) -> Result<Vec<TrashItem>, FileServerError> {
+        let mut items = Vec::new();
+        let mut dir = match async_fs::read_dir(&self.root).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(items),
+            Err(e) => return Err(e.into()),
+        };
+
+        while let Some(entry) = dir.next_entry().await? {
+            // Entries without a readable record are skipped rather than
+            // failing the whole listing
+            if let Ok(item) = self.get(&entry.file_name().to_string_lossy()).await {
+                items.push(item);
+            }
+        }
+
+        items.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then_with(|| a.id.cmp(&b.id)));
+        Ok(items)
+    }
+
+    pub async fn get(&self, id: &str) -> Result<TrashItem, FileServerError> {
+        let entry = self.entry(id)?;
+        let not_found = || FileServerError::FileNotFound(format!("Trash item '{}'", id));
+
+        let info = async_fs::read_to_string(entry.join(INFO_NAME)).await.map_err(|_| not_found())?;
+        let info: TrashInfo = toml::from_str(&info)?;
+        let metadata = async_fs::symlink_metadata(entry.join(ITEM_NAME)).await.map_err(|_| not_found())?;
+
+        Ok(TrashItem {
+            id: id.to_string(),
+            original_path: info.original_path,
+            deleted_at: info.deleted_at,
+            deleted_by: info.deleted_by,
+            is_directory: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+        })
+    }
+
+    /// Location of an item's data, for restoring it
+    pub fn item_path(&self, id: &str) -> Result<PathBuf, FileServerError> {
+        Ok(self.entry(id)?.join(ITEM_NAME))
+    }
+
+    /// Permanently removes an item, or what is left of it after a restore
+    pub async fn remove(&self, id: &str) -> Result<(), FileServerError> {
+        async_fs::remove_dir_all(self.entry(id)?).await?;
+        Ok(())
+    }
+
+    /// Purges items deleted longer than the retention period before `now`
+    pub async fn purge_expired(&self, now: SystemTime) -> Result<usize, FileServerError> {
+        let cutoff = unix_time(now) - self.retention.as_secs() as i64;
+        let mut purged = 0;
+
+        for item in self.list().await? {
+            if item.deleted_at <= cutoff {
+                self.remove(&item.id).await?;
+                purged += 1;
+            }
+        }
+
+        Ok(purged)
+    }
+
+    fn entry(&self, id: &str) -> Result<PathBuf, FileServerError> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(FileServerError::InvalidPath(format!("Invalid trash item id '{}'", id)));
+        }
+        Ok(self.root.join(id))
+    }
+}
+
+fn unix_time(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn test_trash() -> (PathBuf, Trash) {
+        let dir = std::env::temp_dir().join(format!("trash_test_{}", uuid::Uuid::now_v7()));
+        fs::create_dir_all(dir.join("project/src")).unwrap();
+        fs::write(dir.join("notes.txt"), "Notes").unwrap();
+        fs::write(dir.join("project/src/main.rs"), "fn main() {}").unwrap();
+        let trash = Trash::new(&dir, &TrashConfig { retention_days: 30 });
+        (dir, trash)
+    }
+
+    #[tokio::test]
+    async fn test_put_and_list() {
+        let (dir, trash) = test_trash();
+        assert!(trash.list().await.unwrap().is_empty());
+
+        let file_id = trash.put(&dir.join("notes.txt"), "notes.txt", "alice").await.unwrap();
+        let dir_id = trash.put(&dir.join("project"), "project", "bob").await.unwrap();
+        assert!(!dir.join("notes.txt").exists());
+        assert!(!dir.join("project").exists());
+
+        let items = trash.list().await.unwrap();
+        assert_eq!(items.len(), 2);
+
+        let file = items.iter().find(|i| i.id == file_id).unwrap();
+        assert_eq!(file.original_path, "notes.txt");
+        assert_eq!(file.deleted_by, "alice");
+        assert_eq!(file.size, 5);
+        assert!(!file.is_directory);
+
+        let project = items.iter().find(|i| i.id == dir_id).unwrap();
+        assert!(project.is_directory);
+        let data = trash.item_path(&dir_id).unwrap();
+        assert_eq!(fs::read_to_string(data.join("src/main.rs")).unwrap(), "fn main() {}");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired() {
+        let (dir, trash) = test_trash();
+        let id = trash.put(&dir.join("notes.txt"), "notes.txt", "alice").await.unwrap();
+
+        assert_eq!(trash.purge_expired(SystemTime::now()).await.unwrap(), 0);
+        assert!(trash.get(&id).await.is_ok());
+
+        let later = SystemTime::now() + Duration::from_secs(31 * 24 * 60 * 60);
+        assert_eq!(trash.purge_expired(later).await.unwrap(), 1);
+        assert!(matches!(trash.get(&id).await, Err(FileServerError::FileNotFound(_))));
+        assert_eq!(fs::read_dir(dir.join(TRASH_DIR)).unwrap().count(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_ids_are_rejected() {
+        let (dir, trash) = test_trash();
+
+        for id in ["", "../notes.txt", "a/b", "."] {
+            assert!(matches!(trash.get(id).await, Err(FileServerError::InvalidPath(_))), "id {:?}", id);
+            assert!(trash.remove(id).await.is_err());
+        }
+        assert!(dir.join("notes.txt").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,341 @@
+use crate::auth::CallerIdentity;
+use crate::file_handler::UploadFile;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex as AsyncMutex;
+use tonic::Status;
+
+/// An upload session's data on disk. Chunks are only accepted in order, so
+/// everything before `committed` has been written and hashed.
+pub struct PendingUpload {
+    path: String,
+    upload: UploadFile,
+    committed: u64,
+    hasher: Sha256,
+    last_activity: Instant,
+}
+
+impl PendingUpload {
+    /// The `directory/file` path the upload will be stored at
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Bytes received so far; the next chunk must start here
+    pub fn committed(&self) -> u64 {
+        self.committed
+    }
+
+    pub async fn append(&mut self, data: &[u8]) -> Result<(), common::FileServerError> {
+        self.upload.write_chunk(self.committed, data).await?;
+        self.hasher.update(data);
+        self.committed += data.len() as u64;
+        self.last_activity = Instant::now();
+        Ok(())
+    }
+
+    pub async fn sync(&mut self) -> Result<(), common::FileServerError> {
+        self.upload.sync().await
+    }
+
+    /// Hex-encoded SHA-256 of the data received so far
+    pub fn sha256(&self) -> String {
+        hex::encode(self.hasher.clone().finalize())
+    }
+
+    pub fn into_upload(self) -> UploadFile {
+        self.upload
+    }
+}
+
+struct SessionEntry {
+    owner: Option<String>,
+    /// Who the session counts against in the per-client limit
+    client: String,
+    pending: Arc<AsyncMutex<PendingUpload>>,
+}
+
+/// Upload sessions that survive dropped connections, so a client can query
+/// how much was received and carry on from there. Sessions idle for longer
+/// than the timeout are discarded along with their temporary files.
+pub struct UploadSessions {
+    sessions: Mutex<HashMap<String, SessionEntry>>,
+    timeout: Duration,
+    /// Sessions one client may have open, each holding a file descriptor
+    /// and a temporary file
+    max_per_client: usize,
+}
+
+#[allow(clippy::result_large_err)]
+impl UploadSessions {
+    pub fn new(timeout: Duration, max_per_client: usize) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            timeout,
+            max_per_client,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Registers a new session and returns its id, unless the caller already
+    /// has as many open as it may
+    pub fn begin(&self, identity: &CallerIdentity, path: String, upload: UploadFile) -> Result<String, Status> {
+        let client = Self::owner(identity).unwrap_or_else(|| identity.ip.to_string());
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.values().filter(|entry| entry.client == client).count() >= self.max_per_client {
+            return Err(Status::resource_exhausted(format!(
+                "{} already has {} upload sessions open; finish or abandon one first",
+                client, self.max_per_client
+            )));
+        }
+
+        let upload_id = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+        let pending = PendingUpload {
+            path,
+            upload,
+            committed: 0,
+            hasher: Sha256::new(),
+            last_activity: Instant::now(),
+        };
+
+        sessions.insert(upload_id.clone(), SessionEntry {
+            owner: Self::owner(identity),
+            client,
+            pending: Arc::new(AsyncMutex::new(pending)),
+        });
+
+        Ok(upload_id)
+    }
+
+    /// Looks up a session belonging to the caller
+    pub fn get(&self, identity: &CallerIdentity, upload_id: &str) -> Result<Arc<AsyncMutex<PendingUpload>>, Status> {
+        let sessions = self.sessions.lock().unwrap();
+        let entry = Self::entry(&sessions, identity, upload_id)?;
+        Ok(Arc::clone(&entry.pending))
+    }
+
+    /// Removes a session so it can be finished. Fails while another request
+    /// is still using it.
+    pub fn take(&self, identity: &CallerIdentity, upload_id: &str) -> Result<PendingUpload, Status> {
+        let mut sessions = self.sessions.lock().unwrap();
+        Self::entry(&sessions, identity, upload_id)?;
+
+        let entry = sessions.remove(upload_id).expect("session was just looked up");
+        match Arc::try_unwrap(entry.pending) {
+            Ok(pending) => Ok(pending.into_inner()),
+            Err(pending) => {
+                sessions.insert(upload_id.to_string(), SessionEntry { pending, ..entry });
+                Err(Self::busy())
+            }
+        }
+    }
+
+    /// Seconds until an idle session expires
+    pub fn expires_in(&self, pending: &PendingUpload) -> u64 {
+        self.timeout.saturating_sub(pending.last_activity.elapsed()).as_secs()
+    }
+
+    /// Drops sessions that have been idle for longer than the timeout and
+    /// returns how many were removed. Sessions in use are left alone.
+    pub fn remove_expired(&self) -> usize {
+        let mut sessions = self.sessions.lock().unwrap();
+        let before = sessions.len();
+
+        sessions.retain(|upload_id, entry| {
+            let expired = entry.pending
+                .try_lock()
+                .map(|pending| pending.last_activity.elapsed() >= self.timeout)
+                .unwrap_or(false);
+
+            if expired {
+                tracing::info!("Upload session expired: upload_id='{}'", upload_id);
+            }
+            !expired
+        });
+
+        before - sessions.len()
+    }
+
+    /// Periodically removes expired sessions for as long as the server runs
+    pub fn spawn_reaper(self: &Arc<Self>) {
+        let sessions = Arc::clone(self);
+        let period = (self.timeout / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                sessions.remove_expired();
+            }
+        });
+    }
+
+    pub fn busy() -> Status {
+        Status::aborted("Upload session is in use by another request")
+    }
+
+    fn entry<'a>(
+        sessions: &'a HashMap<String, SessionEntry>,
+        identity: &CallerIdentity,
+        upload_id: &str,
+    ) -> Result<&'a SessionEntry, Status> {
+        let entry = sessions.get(upload_id)
+            .ok_or_else(|| Status::not_found(format!("Upload session '{}' not found", upload_id)))?;
+
+        // Sessions of anonymous callers are only protected by their id
+        if entry.owner.is_some() && entry.owner != Self::owner(identity) {
+            return Err(Status::permission_denied(format!(
+                "Upload session '{}' belongs to another client", upload_id
+            )));
+        }
+
+        Ok(entry)
+    }
+
+    /// Sessions belong to a client rather than an address, since a client
+    /// resuming after a network failure may come back from a new one.
+    fn owner(identity: &CallerIdentity) -> Option<String> {
+        identity.client_name.clone().or_else(|| identity.cert_subject.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_handler::{FileHandler, UploadLimits};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn identity(name: Option<&str>) -> CallerIdentity {
+        CallerIdentity {
+            ip: "127.0.0.1".parse().unwrap(),
+            cert_subject: None,
+            client_name: name.map(str::to_string),
+        }
+    }
+
+    fn test_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("upload_test_{}", uuid::Uuid::now_v7()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    async fn begin(sessions: &UploadSessions, dir: &std::path::Path, owner: Option<&str>) -> String {
+        let upload = FileHandler::new().begin_upload(&dir.join("target.txt"), UploadLimits::default()).await.unwrap();
+        sessions.begin(&identity(owner), "workspace/target.txt".to_string(), upload).unwrap()
+    }
+
+    fn temp_files(dir: &std::path::Path) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    #[tokio::test]
+    async fn test_session_appends_in_order() {
+        let dir = test_dir();
+        let sessions = UploadSessions::new(Duration::from_secs(60), 16);
+        let upload_id = begin(&sessions, &dir, Some("alice")).await;
+
+        let session = sessions.get(&identity(Some("alice")), &upload_id).unwrap();
+        {
+            let mut pending = session.lock().await;
+            pending.append(b"Hello, ").await.unwrap();
+            pending.append(b"World!").await.unwrap();
+            assert_eq!(pending.committed(), 13);
+            assert_eq!(pending.sha256(), hex::encode(Sha256::digest(b"Hello, World!")));
+        }
+        drop(session);
+
+        let pending = sessions.take(&identity(Some("alice")), &upload_id).unwrap();
+        assert_eq!(pending.into_upload().commit().await.unwrap(), 13);
+        assert_eq!(fs::read(dir.join("target.txt")).unwrap(), b"Hello, World!");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_session_belongs_to_its_client() {
+        let dir = test_dir();
+        let sessions = UploadSessions::new(Duration::from_secs(60), 16);
+        let upload_id = begin(&sessions, &dir, Some("alice")).await;
+
+        let err = sessions.get(&identity(Some("bob")), &upload_id).err().unwrap();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        let err = sessions.get(&identity(None), &upload_id).err().unwrap();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        let err = sessions.get(&identity(Some("alice")), "unknown").err().unwrap();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+
+        // Anonymous sessions are open to anyone holding the id
+        let anonymous_id = begin(&sessions, &dir, None).await;
+        assert!(sessions.get(&identity(Some("bob")), &anonymous_id).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_take_fails_while_session_in_use() {
+        let dir = test_dir();
+        let sessions = UploadSessions::new(Duration::from_secs(60), 16);
+        let upload_id = begin(&sessions, &dir, None).await;
+
+        let session = sessions.get(&identity(None), &upload_id).unwrap();
+        let err = sessions.take(&identity(None), &upload_id).err().unwrap();
+        assert_eq!(err.code(), tonic::Code::Aborted);
+
+        // The session is still there once the other request is done with it
+        drop(session);
+        assert!(sessions.take(&identity(None), &upload_id).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expired_sessions_are_removed() {
+        let dir = test_dir();
+        let sessions = UploadSessions::new(Duration::from_millis(50), 16);
+        let idle_id = begin(&sessions, &dir, None).await;
+        let busy_id = begin(&sessions, &dir, None).await;
+        assert_eq!(temp_files(&dir), 2);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let busy = sessions.get(&identity(None), &busy_id).unwrap();
+        let _guard = busy.lock().await;
+        assert_eq!(sessions.remove_expired(), 1);
+
+        let err = sessions.get(&identity(None), &idle_id).err().unwrap();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        assert!(sessions.get(&identity(None), &busy_id).is_ok());
+        assert_eq!(temp_files(&dir), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sessions_per_client_are_limited() {
+        let dir = test_dir();
+        let sessions = UploadSessions::new(Duration::from_secs(60), 2);
+        let first = begin(&sessions, &dir, Some("alice")).await;
+        begin(&sessions, &dir, Some("alice")).await;
+
+        let upload = FileHandler::new().begin_upload(&dir.join("target.txt"), UploadLimits::default()).await.unwrap();
+        let err = sessions.begin(&identity(Some("alice")), "workspace/target.txt".to_string(), upload).err().unwrap();
+        assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+        // The refused upload's temporary file is gone with it
+        assert_eq!(temp_files(&dir), 2);
+
+        // Other clients have sessions of their own, and finishing one frees a slot
+        begin(&sessions, &dir, Some("bob")).await;
+        begin(&sessions, &dir, None).await;
+        sessions.take(&identity(Some("alice")), &first).unwrap();
+        begin(&sessions, &dir, Some("alice")).await;
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,684 @@
+use crate::metadata::{self, OwnerNames};
+use common::{EntryType, FileEntry, FileServerError, FindRequest, ListRequest};
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Entries returned per page when the client does not ask for a size
+pub const DEFAULT_PAGE_SIZE: usize = 1000;
+/// Largest page a client may ask for
+pub const MAX_PAGE_SIZE: usize = 10_000;
+
+/// Glob patterns matched against paths relative to the listed directory.
+/// Patterns without a `/` match the entry's name at any depth.
+struct Patterns {
+    paths: GlobSet,
+    names: GlobSet,
+}
+
+impl Patterns {
+    fn new(patterns: &[String]) -> Result<Self, FileServerError> {
+        let mut paths = GlobSetBuilder::new();
+        let mut names = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            let glob = GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| FileServerError::InvalidPath(format!("Invalid pattern '{}': {}", pattern, e)))?;
+
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+
+        let build = |set: GlobSetBuilder| set.build().map_err(|e| FileServerError::InvalidPath(e.to_string()));
+        Ok(Self { paths: build(paths)?, names: build(names)? })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.paths.is_empty() && self.names.is_empty()
+    }
+
+    fn is_match(&self, relative: &Path) -> bool {
+        self.paths.is_match(relative)
+            || relative.file_name().is_some_and(|name| self.names.is_match(name))
+    }
+}
+
+/// What to list below a directory, and where the previous page ended
+pub struct ListOptions {
+    max_depth: Option<usize>,
+    include: Patterns,
+    exclude: Patterns,
+    page_size: usize,
+    after: Vec<Key>,
+}
+
+impl ListOptions {
+    pub fn from_request(req: &ListRequest) -> Result<Self, FileServerError> {
+        let max_depth = match (req.recursive, req.max_depth) {
+            (false, _) => Some(1),
+            (true, 0) => None,
+            (true, depth) => Some(depth as usize),
+        };
+        let page_size = match req.page_size as usize {
+            0 => DEFAULT_PAGE_SIZE,
+            size => size.min(MAX_PAGE_SIZE),
+        };
+
+        Ok(Self {
+            max_depth,
+            include: Patterns::new(&req.include)?,
+            exclude: Patterns::new(&req.exclude)?,
+            page_size,
+            after: parse_token(&req.page_token)?,
+        })
+    }
+}
+
+pub struct ListPage {
+    pub entries: Vec<FileEntry>,
+    /// Set when there are more entries; pass it back to get the next page
+    pub next_page_token: Option<String>,
+}
+
+/// Sort key of a directory entry: directories first, then by name. Every
+/// directory is listed before its contents, so keys of the path components
+/// give the order of the whole walk.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Key {
+    is_file: bool,
+    name: String,
+}
+
+/// Page tokens are the relative path of the last entry sent, with a
+/// trailing `/` if it was a directory.
+fn parse_token(token: &str) -> Result<Vec<Key>, FileServerError> {
+    if token.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let invalid = || FileServerError::InvalidPath("Invalid page token".to_string());
+    let (path, is_dir) = match token.strip_suffix('/') {
+        Some(path) => (path, true),
+        None => (token, false),
+    };
+
+    let names: Vec<&str> = path.split('/').collect();
+    if names.iter().any(|name| name.is_empty() || *name == "." || *name == "..") {
+        return Err(invalid());
+    }
+
+    let last = names.len() - 1;
+    Ok(names
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| Key { is_file: i == last && !is_dir, name: name.to_string() })
+        .collect())
+}
+
+fn make_token(entry: &FileEntry) -> String {
+    if entry.is_directory {
+        format!("{}/", entry.name)
+    } else {
+        entry.name.clone()
+    }
+}
+
+/// Lists one page of the entries below `root` in a stable order, so a walk
+/// can be resumed from a page token without keeping state on the server.
+/// Only the names of each directory are held in memory; entries are only
+/// stat'ed when they make it into the page. Entries for which `visible`
+/// returns false are skipped together with their contents. Symbolic links
+/// are listed but never followed. The walk is blocking and meant to run on
+/// the blocking thread pool.
+pub fn list(
+    root: &Path,
+    options: &ListOptions,
+    visible: impl Fn(&Path, bool) -> bool,
+) -> Result<ListPage, FileServerError> {
+    let mut walk = Walk {
+        root,
+        options,
+        visible,
+        entries: Vec::new(),
+        full: false,
+        names: OwnerNames::default(),
+    };
+    walk.directory(Path::new(""), 1, Some(&options.after))?;
+
+    let next_page_token = if walk.full { walk.entries.last().map(make_token) } else { None };
+    Ok(ListPage { entries: walk.entries, next_page_token })
+}
+
+struct Walk<'a, F> {
+    root: &'a Path,
+    options: &'a ListOptions,
+    visible: F,
+    entries: Vec<FileEntry>,
+    /// A page's worth of entries was found and there is at least one more
+    full: bool,
+    names: OwnerNames,
+}
+
+impl<F: Fn(&Path, bool) -> bool> Walk<'_, F> {
+    /// Walks the directory at `relative`, whose children are at `depth`.
+    /// `after` holds the rest of the page token while resuming inside it.
+    fn directory(&mut self, relative: &Path, depth: usize, after: Option<&[Key]>) -> Result<(), FileServerError> {
+        let children = read_children(&self.root.join(relative))?;
+        let descend = self.options.max_depth.is_none_or(|max| depth < max);
+        let mut after = after.filter(|after| !after.is_empty());
+
+        for child in children {
+            let path = relative.join(&child.name);
+
+            // Checked before resuming, so a crafted token cannot lead into a
+            // hidden directory
+            if !(self.visible)(&path, !child.is_file) || self.options.exclude.is_match(&path) {
+                continue;
+            }
+
+            if let Some(resume) = after {
+                match child.cmp(&resume[0]) {
+                    Ordering::Less => continue,
+                    // Sent on an earlier page, but the token may lie inside it
+                    Ordering::Equal => {
+                        if !child.is_file && descend {
+                            self.directory(&path, depth + 1, Some(&resume[1..]))?;
+                            if self.full {
+                                return Ok(());
+                            }
+                        }
+                        after = None;
+                        continue;
+                    }
+                    Ordering::Greater => after = None,
+                }
+            }
+
+            if self.options.include.is_empty() || self.options.include.is_match(&path) {
+                if self.entries.len() == self.options.page_size {
+                    self.full = true;
+                    return Ok(());
+                }
+                if let Some(entry) = self.stat(&path) {
+                    self.entries.push(entry);
+                }
+            }
+
+            if !child.is_file && descend {
+                self.directory(&path, depth + 1, None)?;
+                if self.full {
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `None` if the entry disappeared since its directory was read
+    fn stat(&mut self, relative: &Path) -> Option<FileEntry> {
+        let metadata = fs::symlink_metadata(self.root.join(relative)).ok()?;
+        Some(file_entry(self.root, relative, &metadata, &mut self.names))
+    }
+}
+
+/// The names in a directory, in walk order
+fn read_children(dir: &Path) -> Result<Vec<Key>, FileServerError> {
+    let mut children = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        children.push(Key {
+            is_file: !entry.file_type()?.is_dir(),
+            name: entry.file_name().to_string_lossy().to_string(),
+        });
+    }
+    children.sort();
+    Ok(children)
+}
+
+fn file_entry(root: &Path, relative: &Path, metadata: &fs::Metadata, names: &mut OwnerNames) -> FileEntry {
+    let posix = metadata::posix_attributes(&root.join(relative), metadata, names);
+
+    FileEntry {
+        name: relative.to_string_lossy().to_string(),
+        is_directory: metadata.is_dir(),
+        size: metadata.len(),
+        modified_time: modified_time(metadata),
+        permissions: metadata::mode_string(posix.file_type(), posix.mode),
+        posix: Some(posix),
+    }
+}
+
+fn modified_time(metadata: &fs::Metadata) -> i64 {
+    metadata.modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+enum NamePattern {
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+/// What a Find request matches
+pub struct FindCriteria {
+    name: Option<NamePattern>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    modified_after: Option<i64>,
+    modified_before: Option<i64>,
+    entry_type: EntryType,
+    max_depth: Option<usize>,
+    max_results: usize,
+    /// Entries looked at before the search gives up, matching or not
+    max_visited: usize,
+}
+
+impl FindCriteria {
+    /// `limit` caps the number of results, whatever the client asks for
+    pub fn from_request(req: &FindRequest, limit: usize) -> Result<Self, FileServerError> {
+        let invalid = |what: &str, pattern: &str, e: &dyn std::fmt::Display| {
+            FileServerError::InvalidPath(format!("Invalid {} '{}': {}", what, pattern, e))
+        };
+
+        let name = match (req.name_glob.is_empty(), req.name_regex.is_empty()) {
+            (true, true) => None,
+            (false, true) => Some(NamePattern::Glob(
+                Glob::new(&req.name_glob).map_err(|e| invalid("pattern", &req.name_glob, &e))?.compile_matcher()
+            )),
+            (true, false) => Some(NamePattern::Regex(
+                Regex::new(&req.name_regex).map_err(|e| invalid("regex", &req.name_regex, &e))?
+            )),
+            (false, false) => {
+                return Err(FileServerError::InvalidPath("Give either a name glob or a name regex, not both".to_string()));
+            }
+        };
+
+        let max_results = match req.max_results as usize {
+            0 => limit,
+            max => max.min(limit),
+        };
+
+        Ok(Self {
+            name,
+            min_size: req.min_size,
+            max_size: req.max_size,
+            modified_after: req.modified_after,
+            modified_before: req.modified_before,
+            entry_type: req.entry_type(),
+            max_depth: (req.max_depth > 0).then_some(req.max_depth as usize),
+            max_results,
+            max_visited: usize::MAX,
+        })
+    }
+
+    /// Matches the files a grep searches, looking at no more than
+    /// `max_visited` entries on the way
+    pub fn files(name_glob: &str, max_depth: u32, max_visited: usize) -> Result<Self, FileServerError> {
+        let req = FindRequest {
+            name_glob: name_glob.to_string(),
+            entry_type: EntryType::File as i32,
+            max_depth,
+            ..Default::default()
+        };
+        Ok(Self { max_visited, ..Self::from_request(&req, usize::MAX)? })
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        match &self.name {
+            None => true,
+            Some(NamePattern::Glob(glob)) => glob.is_match(name),
+            Some(NamePattern::Regex(regex)) => regex.is_match(name),
+        }
+    }
+
+    fn matches(&self, metadata: &fs::Metadata) -> bool {
+        let file_type = metadata.file_type();
+        let type_matches = match self.entry_type {
+            EntryType::Any => true,
+            EntryType::File => file_type.is_file(),
+            EntryType::Directory => file_type.is_dir(),
+            EntryType::Symlink => file_type.is_symlink(),
+        };
+
+        let has_size_bounds = self.min_size.is_some() || self.max_size.is_some();
+        let size_matches = !(has_size_bounds && file_type.is_dir())
+            && self.min_size.is_none_or(|min| metadata.len() >= min)
+            && self.max_size.is_none_or(|max| metadata.len() <= max);
+
+        let modified = modified_time(metadata);
+        let time_matches = self.modified_after.is_none_or(|after| modified >= after)
+            && self.modified_before.is_none_or(|before| modified <= before);
+
+        type_matches && size_matches && time_matches
+    }
+}
+
+/// Searches below `root` in walk order, calling `found` with each match
+/// until it returns false. Entries for which `visible` returns false are
+/// skipped together with their contents, as are subdirectories that cannot
+/// be read. Returns whether the search stopped at the result limit or the
+/// limit on entries visited. The
+/// search is blocking and meant to run on the blocking thread pool.
+pub fn find(
+    root: &Path,
+    criteria: &FindCriteria,
+    visible: impl Fn(&Path, bool) -> bool,
+    found: impl FnMut(FileEntry) -> bool,
+) -> Result<bool, FileServerError> {
+    let mut search = Search {
+        root,
+        criteria,
+        visible,
+        found,
+        matched: 0,
+        visited: 0,
+        truncated: false,
+        stopped: false,
+        names: OwnerNames::default(),
+    };
+    search.directory(Path::new(""), 1)?;
+    Ok(search.truncated)
+}
+
+struct Search<'a, V, F> {
+    root: &'a Path,
+    criteria: &'a FindCriteria,
+    visible: V,
+    found: F,
+    matched: usize,
+    visited: usize,
+    truncated: bool,
+    stopped: bool,
+    names: OwnerNames,
+}
+
+impl<V: Fn(&Path, bool) -> bool, F: FnMut(FileEntry) -> bool> Search<'_, V, F> {
+    fn directory(&mut self, relative: &Path, depth: usize) -> Result<(), FileServerError> {
+        let children = match read_children(&self.root.join(relative)) {
+            Ok(children) => children,
+            Err(e) if !relative.as_os_str().is_empty() => {
+                tracing::debug!("Skipping unreadable directory '{}': {}", relative.display(), e);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        let descend = self.criteria.max_depth.is_none_or(|max| depth < max);
+
+        for child in children {
+            if self.visited == self.criteria.max_visited {
+                self.truncated = true;
+                self.stopped = true;
+                return Ok(());
+            }
+            self.visited += 1;
+
+            let path = relative.join(&child.name);
+            if !(self.visible)(&path, !child.is_file) {
+                continue;
+            }
+
+            if self.criteria.matches_name(&child.name) {
+                let metadata = fs::symlink_metadata(self.root.join(&path)).ok();
+                if let Some(metadata) = metadata.filter(|m| self.criteria.matches(m)) {
+                    if self.matched == self.criteria.max_results {
+                        self.truncated = true;
+                        self.stopped = true;
+                        return Ok(());
+                    }
+                    self.matched += 1;
+                    if !(self.found)(file_entry(self.root, &path, &metadata, &mut self.names)) {
+                        self.stopped = true;
+                        return Ok(());
+                    }
+                }
+            }
+
+            if !child.is_file && descend {
+                self.directory(&path, depth + 1)?;
+                if self.stopped {
+                    return Ok(());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The symbolic links at or below `path`, never followed, as their paths
+/// relative to `path` along with their targets. The walk is blocking and
+/// meant to run on the blocking thread pool.
+pub fn symlinks(path: &Path) -> Result<Vec<(PathBuf, PathBuf)>, FileServerError> {
+    let metadata = fs::symlink_metadata(path)
+        .map_err(|_| FileServerError::FileNotFound(path.to_string_lossy().to_string()))?;
+    if metadata.file_type().is_symlink() {
+        return Ok(vec![(PathBuf::new(), fs::read_link(path)?)]);
+    }
+
+    let mut links = Vec::new();
+    let mut pending = if metadata.is_dir() { vec![PathBuf::new()] } else { Vec::new() };
+    while let Some(relative) = pending.pop() {
+        for entry in fs::read_dir(path.join(&relative))? {
+            let entry = entry?;
+            let child = relative.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            if file_type.is_symlink() {
+                links.push((child, fs::read_link(entry.path())?));
+            } else if file_type.is_dir() {
+                pending.push(child);
+            }
+        }
+    }
+
+    Ok(links)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("walk_test_{}", uuid::Uuid::now_v7()));
+        fs::create_dir_all(dir.join("src/net")).unwrap();
+        fs::create_dir_all(dir.join("target/debug")).unwrap();
+        fs::write(dir.join("README.md"), "Readme").unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(dir.join("src/net/tcp.rs"), "").unwrap();
+        fs::write(dir.join("src/net/notes.txt"), "").unwrap();
+        fs::write(dir.join("target/debug/app"), "").unwrap();
+        dir
+    }
+
+    fn options(recursive: bool, page_size: u32, token: &str) -> ListOptions {
+        ListOptions::from_request(&ListRequest {
+            recursive,
+            page_size,
+            page_token: token.to_string(),
+            ..Default::default()
+        }).unwrap()
+    }
+
+    fn names(page: &ListPage) -> Vec<&str> {
+        page.entries.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_recursive_walk_order() {
+        let dir = test_dir();
+
+        let page = list(&dir, &options(false, 0, ""), |_, _| true).unwrap();
+        assert_eq!(names(&page), ["src", "target", "README.md"]);
+        assert!(page.next_page_token.is_none());
+
+        let page = list(&dir, &options(true, 0, ""), |_, _| true).unwrap();
+        assert_eq!(names(&page), [
+            "src", "src/net", "src/net/notes.txt", "src/net/tcp.rs", "src/main.rs",
+            "target", "target/debug", "target/debug/app", "README.md",
+        ]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pages_resume_where_the_last_ended() {
+        let dir = test_dir();
+        let all = list(&dir, &options(true, 0, ""), |_, _| true).unwrap();
+
+        for page_size in 1..=4 {
+            let mut token = String::new();
+            let mut walked = Vec::new();
+            loop {
+                let page = list(&dir, &options(true, page_size, &token), |_, _| true).unwrap();
+                assert!(page.entries.len() <= page_size as usize);
+                walked.extend(page.entries.into_iter().map(|e| e.name));
+                match page.next_page_token {
+                    Some(next) => token = next,
+                    None => break,
+                }
+            }
+            assert_eq!(walked, names(&all), "page size {}", page_size);
+        }
+
+        // A token whose entry was deleted still resumes after it
+        let page = list(&dir, &options(true, 2, "src/net/removed.txt"), |_, _| true).unwrap();
+        assert_eq!(names(&page), ["src/net/tcp.rs", "src/main.rs"]);
+        assert_eq!(page.next_page_token.as_deref(), Some("src/main.rs"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_filters_and_depth() {
+        let dir = test_dir();
+        let request = |max_depth: u32, include: &[&str], exclude: &[&str]| ListRequest {
+            recursive: true,
+            max_depth,
+            include: include.iter().map(|p| p.to_string()).collect(),
+            exclude: exclude.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        };
+        let walk = |req: ListRequest| {
+            let page = list(&dir, &ListOptions::from_request(&req).unwrap(), |_, _| true).unwrap();
+            names(&page).into_iter().map(str::to_string).collect::<Vec<_>>()
+        };
+
+        assert_eq!(walk(request(0, &["*.rs"], &[])), ["src/net/tcp.rs", "src/main.rs"]);
+        assert_eq!(walk(request(0, &["src/*.rs"], &[])), ["src/main.rs"]);
+        assert_eq!(walk(request(0, &["*.rs", "*.md"], &["net"])), ["src/main.rs", "README.md"]);
+        assert_eq!(walk(request(2, &[], &["target"])), ["src", "src/net", "src/main.rs", "README.md"]);
+
+        // Hidden directories are skipped with everything below them
+        let page = list(&dir, &options(true, 0, ""), |path, _| path != Path::new("src")).unwrap();
+        assert_eq!(names(&page), ["target", "target/debug", "target/debug/app", "README.md"]);
+        let page = list(&dir, &options(true, 0, "src/net/"), |path, _| path != Path::new("src")).unwrap();
+        assert_eq!(names(&page), ["target", "target/debug", "target/debug/app", "README.md"]);
+
+        assert!(ListOptions::from_request(&request(0, &["a[b"], &[])).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn search(dir: &Path, req: FindRequest, limit: usize) -> (Vec<String>, bool) {
+        let criteria = FindCriteria::from_request(&req, limit).unwrap();
+        let mut names = Vec::new();
+        let truncated = find(dir, &criteria, |_, _| true, |entry| {
+            names.push(entry.name);
+            true
+        }).unwrap();
+        (names, truncated)
+    }
+
+    #[test]
+    fn test_find_by_name_type_and_size() {
+        let dir = test_dir();
+        std::os::unix::fs::symlink("main.rs", dir.join("src/link.rs")).unwrap();
+
+        let (names, _) = search(&dir, FindRequest { name_glob: "*.rs".to_string(), ..Default::default() }, 100);
+        assert_eq!(names, ["src/net/tcp.rs", "src/link.rs", "src/main.rs"]);
+
+        let by_regex = FindRequest { name_regex: "^(tcp|main)\\.rs$".to_string(), ..Default::default() };
+        assert_eq!(search(&dir, by_regex, 100).0, ["src/net/tcp.rs", "src/main.rs"]);
+
+        let links = FindRequest { entry_type: EntryType::Symlink as i32, ..Default::default() };
+        assert_eq!(search(&dir, links, 100).0, ["src/link.rs"]);
+
+        let directories = FindRequest { entry_type: EntryType::Directory as i32, max_depth: 1, ..Default::default() };
+        assert_eq!(search(&dir, directories, 100).0, ["src", "target"]);
+
+        // Size bounds leave out directories and empty files
+        let non_empty = FindRequest { min_size: Some(1), ..Default::default() };
+        assert_eq!(search(&dir, non_empty, 100).0, ["src/link.rs", "src/main.rs", "README.md"]);
+        let small = FindRequest { min_size: Some(1), max_size: Some(7), entry_type: EntryType::File as i32, ..Default::default() };
+        assert_eq!(search(&dir, small, 100).0, ["README.md"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_by_time_and_limit() {
+        let dir = test_dir();
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(10 * 24 * 60 * 60);
+        fs::File::options().write(true).open(dir.join("README.md")).unwrap().set_modified(old).unwrap();
+        let week_ago = modified_time(&fs::metadata(dir.join("src/main.rs")).unwrap()) - 7 * 24 * 60 * 60;
+
+        let recent = FindRequest { entry_type: EntryType::File as i32, modified_after: Some(week_ago), ..Default::default() };
+        let (names, _) = search(&dir, recent, 100);
+        assert!(!names.contains(&"README.md".to_string()));
+        assert_eq!(names.len(), 4);
+
+        let older = FindRequest { modified_before: Some(week_ago), ..Default::default() };
+        assert_eq!(search(&dir, older, 100).0, ["README.md"]);
+
+        let files = || FindRequest { entry_type: EntryType::File as i32, ..Default::default() };
+        let (all, _) = search(&dir, files(), 100);
+        assert_eq!(all.len(), 5);
+        assert_eq!(search(&dir, files(), 5), (all.clone(), false));
+        assert_eq!(search(&dir, files(), 3), (all[..3].to_vec(), true));
+        let asked = FindRequest { max_results: 2, ..files() };
+        assert_eq!(search(&dir, asked, 3), (all[..2].to_vec(), true));
+
+        let both = FindRequest { name_glob: "*".to_string(), name_regex: ".*".to_string(), ..Default::default() };
+        assert!(FindCriteria::from_request(&both, 10).is_err());
+        let bad_regex = FindRequest { name_regex: "(".to_string(), ..Default::default() };
+        assert!(FindCriteria::from_request(&bad_regex, 10).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_entries_visited_are_limited() {
+        let dir = test_dir();
+        let files = |max_visited| {
+            let criteria = FindCriteria::files("", 0, max_visited).unwrap();
+            let mut names = Vec::new();
+            let truncated = find(&dir, &criteria, |_, _| true, |entry| {
+                names.push(entry.name);
+                true
+            }).unwrap();
+            (names.len(), truncated)
+        };
+
+        // Nine entries in all, five of them files
+        assert_eq!(files(9), (5, false));
+        assert_eq!(files(3), (1, true));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_page_tokens() {
+        for token in ["../etc", "a//b", "/", "./a"] {
+            let result = ListOptions::from_request(&ListRequest { page_token: token.to_string(), ..Default::default() });
+            assert!(matches!(result, Err(FileServerError::InvalidPath(_))), "token {:?}", token);
+        }
+    }
+}